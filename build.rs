@@ -1,6 +1,75 @@
-use std::path::PathBuf;
+//! Builds SAF with cmake and generates its bindings, when the `saf` feature
+//! is on.
+//!
+//! SAF leans on a performance library for its linear algebra, picked with
+//! its `SAF_PERFORMANCE_LIB` cmake option. By default we use Apple's
+//! Accelerate framework on Apple platforms, Intel MKL on Windows, and
+//! OpenBLAS with LAPACKE everywhere else. Setting the `SAF_PERFORMANCE_LIB`
+//! environment variable picks a different one, and `SAF_PERFORMANCE_LIB_DIR`
+//! adds a directory to look for it in, if it isn't installed somewhere the
+//! linker already looks. There's no fallback that works without any of
+//! them, SAF needs one.
+//!
+//! - `SAF_USE_APPLE_ACCELERATE` comes with macOS
+//! - `SAF_USE_OPEN_BLAS_AND_LAPACKE` needs OpenBLAS and LAPACKE, such as
+//!   from the `libopenblas-dev` and `liblapacke-dev` packages on Debian
+//! - `SAF_USE_INTEL_MKL_LP64` and `SAF_USE_INTEL_MKL_ILP64` need the custom
+//!   MKL library that SAF's own scripts build, `saf_mkl_custom_lp64` or
+//!   `saf_mkl_custom_ilp64`
 
 fn main() {
+    // Tell cargo to only rerun this script if it changes
+    println!("cargo:rerun-if-changed=build.rs");
+
+    // Without the `saf` feature there's nothing to build or bind to
+    #[cfg(feature = "saf")]
+    build_saf();
+}
+
+/// The performance library SAF uses on `target_os`, unless
+/// `SAF_PERFORMANCE_LIB` says otherwise.
+#[cfg(feature = "saf")]
+fn default_performance_lib(target_os: &str) -> &'static str {
+    match target_os {
+        "macos" | "ios" => "SAF_USE_APPLE_ACCELERATE",
+        "windows" => "SAF_USE_INTEL_MKL_LP64",
+        _ => "SAF_USE_OPEN_BLAS_AND_LAPACKE",
+    }
+}
+
+/// What has to be linked along with SAF for `performance_lib`, since SAF is
+/// built as a static library and doesn't bring it along itself.
+#[cfg(feature = "saf")]
+fn performance_lib_links(performance_lib: &str) -> &'static [&'static str] {
+    match performance_lib {
+        "SAF_USE_APPLE_ACCELERATE" => &["framework=Accelerate"],
+        "SAF_USE_OPEN_BLAS_AND_LAPACKE" => &["dylib=openblas", "dylib=lapacke"],
+        "SAF_USE_INTEL_MKL_LP64" => &["dylib=saf_mkl_custom_lp64"],
+        "SAF_USE_INTEL_MKL_ILP64" => &["dylib=saf_mkl_custom_ilp64"],
+        other => panic!(
+            "unsupported SAF_PERFORMANCE_LIB {other}, pick one of \
+             SAF_USE_APPLE_ACCELERATE, SAF_USE_OPEN_BLAS_AND_LAPACKE, \
+             SAF_USE_INTEL_MKL_LP64, or SAF_USE_INTEL_MKL_ILP64"
+        ),
+    }
+}
+
+/// Builds SAF, links against it, and generates the bindings used by
+/// saf_raw.rs.
+#[cfg(feature = "saf")]
+fn build_saf() {
+    use std::env;
+    use std::path::PathBuf;
+
+    println!("cargo:rerun-if-env-changed=SAF_PERFORMANCE_LIB");
+    println!("cargo:rerun-if-env-changed=SAF_PERFORMANCE_LIB_DIR");
+
+    // Build scripts run on the host, so ask cargo what we're building for
+    let target_os = env::var("CARGO_CFG_TARGET_OS").expect("cargo sets the target OS");
+    let performance_lib = env::var("SAF_PERFORMANCE_LIB")
+        .unwrap_or_else(|_| default_performance_lib(&target_os).to_string());
+    let links = performance_lib_links(&performance_lib);
+
     // Get a full path to the SAF library source tree
     let saf_libdir_path = PathBuf::from("libsaf")
         .canonicalize()
@@ -24,32 +93,41 @@ fn main() {
         .expect("Path is not a valid string");
 
     // Run cmake to build SAF, and record where it was stored
-    let dst = cmake::Config::new("libsaf")
-        .define("SAF_PERFORMANCE_LIB", "SAF_USE_APPLE_ACCELERATE")
+    let mut config = cmake::Config::new("libsaf");
+    config
+        .define("SAF_PERFORMANCE_LIB", &performance_lib)
         .define("SAF_ENABLE_SOFA_READER_MODULE", "1")
-        .define("CMAKE_OSX_ARCHITECTURES", "arm64;x86_64")
         .define("SAF_BUILD_TESTS", "0")
-        .no_build_target(true)
-        .build();
-
-    // Tell cargo where to find the static library files we just built
-    println!(
-        "cargo:rustc-link-search=native={}",
-        dst.join("build").join("framework").display()
-    );
-    println!(
-        "cargo:rustc-link-search=native={}",
-        dst.join("build").join("examples").display()
-    );
-
-    // Tell cargo we want to link against SAF, the binauraliser, and
-    // Apple's Accelerate fast math framework
+        .no_build_target(true);
+    if target_os == "macos" {
+        config.define("CMAKE_OSX_ARCHITECTURES", "arm64;x86_64");
+    }
+    let dst = config.build();
+
+    // Tell cargo where to find the static library files we just built.
+    // Generators that can build several configurations at once, like
+    // Visual Studio's, put each in its own directory
+    for lib_dir in ["framework", "examples"] {
+        let lib_dir = dst.join("build").join(lib_dir);
+        for config_dir in ["Release", "Debug"] {
+            let config_dir = lib_dir.join(config_dir);
+            if config_dir.is_dir() {
+                println!("cargo:rustc-link-search=native={}", config_dir.display());
+            }
+        }
+        println!("cargo:rustc-link-search=native={}", lib_dir.display());
+    }
+    if let Ok(performance_lib_dir) = env::var("SAF_PERFORMANCE_LIB_DIR") {
+        println!("cargo:rustc-link-search=native={}", performance_lib_dir);
+    }
+
+    // Tell cargo we want to link against SAF, the binauraliser, and the
+    // performance library SAF was built with
     println!("cargo:rustc-link-lib=static=saf");
     println!("cargo:rustc-link-lib=static=saf_example_binauraliser_nf");
-    println!("cargo:rustc-link-lib=framework=Accelerate");
-
-    // Tell cargo to rebuild SAF if the library changes
-    println!("cargo:rerun-if-changed=build.rs");
+    for link in links {
+        println!("cargo:rustc-link-lib={}", link);
+    }
 
     // Generate Rust bindings for the header files #included in libsafwrapper.h
     let bindings = bindgen::Builder::default()
@@ -61,7 +139,7 @@ fn main() {
         .expect("Unable to generate bindings");
 
     // Write those bindings to a file that we'll use in saf_raw.rs
-    let out_path = PathBuf::from(std::env::var("OUT_DIR").unwrap()).join("bindings.rs");
+    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap()).join("bindings.rs");
     bindings
         .write_to_file(out_path)
         .expect("Couldn't write bindings!");
@@ -0,0 +1,188 @@
+//! Helpers for finding where the interesting parts of an audio file are, so
+//! that clip boundaries can be chosen without listening through every file,
+//! and for spotting problems with them before they are binauralized.
+
+use crate::audio_decoder::FULL_SCALE;
+
+use std::ops::Range;
+
+/// How close to full scale a sample has to be to count as clipped by
+/// [`clip_ratio`].
+pub const CLIP_LEVEL: f32 = 0.99 * FULL_SCALE;
+
+/// The root-mean-square level of each non-overlapping window of `window`
+/// samples. The last window may be shorter than the rest.
+pub fn rms_envelope(samples: &[f32], window: usize) -> Vec<f32> {
+    assert!(window > 0, "windows must have at least one sample");
+
+    samples
+        .chunks(window)
+        .map(|chunk| (chunk.iter().map(|s| s * s).sum::<f32>() / chunk.len() as f32).sqrt())
+        .collect()
+}
+
+/// The indices of the `n` loudest windows of `window` samples, loudest first.
+/// Windows that are just as loud as each other come out in order. Multiply an
+/// index by `window` to find the sample that the window starts on.
+pub fn peak_windows(samples: &[f32], window: usize, n: usize) -> Vec<usize> {
+    let envelope = rms_envelope(samples, window);
+
+    let mut windows = (0..envelope.len()).collect::<Vec<_>>();
+    // sort_by is stable, so ties stay in order
+    windows.sort_by(|&a, &b| envelope[b].total_cmp(&envelope[a]));
+    windows.truncate(n);
+    windows
+}
+
+/// The fraction of `samples` that are at or near full scale, as decoded by
+/// the [`audio_decoder`](crate::audio_decoder). A clean recording should have
+/// next to none of these, so a high ratio means that the file was already
+/// clipped before we got to it.
+pub fn clip_ratio(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+
+    let clipped = samples.iter().filter(|s| s.abs() >= CLIP_LEVEL).count();
+    clipped as f32 / samples.len() as f32
+}
+
+/// The range of `samples` between the first and last samples louder than
+/// `threshold_db` decibels relative to full scale. Everything outside of it is
+/// silence. Returns [`None`] if the whole buffer is silent.
+pub fn audible_range(samples: &[f32], threshold_db: f32) -> Option<Range<usize>> {
+    let threshold = FULL_SCALE * 10_f32.powf(threshold_db / 20.0);
+    let is_audible = |s: &f32| s.abs() > threshold;
+
+    let start = samples.iter().position(is_audible)?;
+    let end = samples.iter().rposition(is_audible)? + 1;
+    Some(start..end)
+}
+
+/// Cuts the leading and trailing silence, quieter than `threshold_db`, from
+/// each buffer in `sound_data`. If `together` is set, every buffer loses the
+/// same number of samples from the start, the fewest that any of them would
+/// lose on their own, so that they stay lined up with each other. Buffers
+/// that are entirely silent are emptied.
+pub fn trim_silence(sound_data: &mut [Vec<f32>], threshold_db: f32, together: bool) {
+    let ranges = sound_data
+        .iter()
+        .map(|samples| audible_range(samples, threshold_db))
+        .collect::<Vec<_>>();
+
+    let common_start = ranges.iter().flatten().map(|r| r.start).min();
+
+    for (samples, range) in sound_data.iter_mut().zip(ranges) {
+        let Some(range) = range else {
+            samples.clear();
+            continue;
+        };
+        let start = match common_start {
+            Some(common_start) if together => common_start,
+            _ => range.start,
+        };
+        samples.truncate(range.end);
+        samples.drain(..start);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Quiet noise, with a loud burst from sample 400 to 600
+    fn burst() -> Vec<f32> {
+        (0..1000)
+            .map(|i| {
+                let level = if (400..600).contains(&i) {
+                    1000.0
+                } else {
+                    10.0
+                };
+                if i % 2 == 0 {
+                    level
+                } else {
+                    -level
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn envelope_of_burst() {
+        let envelope = rms_envelope(&burst(), 100);
+        assert_eq!(10, envelope.len());
+        for (i, level) in envelope.into_iter().enumerate() {
+            let expected = if i == 4 || i == 5 { 1000.0 } else { 10.0 };
+            assert!((expected - level).abs() < 1e-3, "window {i}: {level}");
+        }
+    }
+
+    #[test]
+    fn short_last_window() {
+        let envelope = rms_envelope(&[3.0, -3.0, 3.0, 4.0], 3);
+        assert_eq!(vec![3.0, 4.0], envelope);
+    }
+
+    #[test]
+    fn clean_tone_is_not_clipped() {
+        let tone = (0..4410)
+            .map(|i| 0.5 * FULL_SCALE * (i as f32 * 0.0628).sin())
+            .collect::<Vec<_>>();
+        assert_eq!(0.0, clip_ratio(&tone));
+        assert_eq!(0.0, clip_ratio(&[]));
+    }
+
+    #[test]
+    fn overdriven_tone_is_clipped() {
+        let tone = (0..4410)
+            .map(|i| (4.0 * FULL_SCALE * (i as f32 * 0.0628).sin()).clamp(-FULL_SCALE, FULL_SCALE))
+            .collect::<Vec<_>>();
+        let ratio = clip_ratio(&tone);
+        assert!(ratio > 0.5, "{ratio}");
+    }
+
+    /// A quiet tone with `before` and `after` samples of silence either side
+    fn padded_tone(before: usize, after: usize) -> Vec<f32> {
+        let mut samples = vec![0.0; before];
+        samples.extend((0..100).map(|i| if i % 2 == 0 { 300.0 } else { -300.0 }));
+        samples.extend(vec![1.0; after]);
+        samples
+    }
+
+    #[test]
+    fn find_audible_range() {
+        // 300 is about -40 dBFS, and 1 is about -90 dBFS
+        assert_eq!(Some(50..150), audible_range(&padded_tone(50, 20), -60.0));
+        assert_eq!(Some(0..100), audible_range(&padded_tone(0, 0), -60.0));
+        assert_eq!(None, audible_range(&padded_tone(50, 20), -30.0));
+        assert_eq!(Some(0..120), audible_range(&padded_tone(0, 20), -100.0));
+        assert_eq!(None, audible_range(&[], -60.0));
+    }
+
+    #[test]
+    fn trim_each_buffer() {
+        let mut sound_data = vec![padded_tone(50, 20), padded_tone(10, 0), vec![0.0; 30]];
+        trim_silence(&mut sound_data, -60.0, false);
+        assert_eq!(vec![padded_tone(0, 0); 2], sound_data[..2]);
+        assert!(sound_data[2].is_empty());
+    }
+
+    #[test]
+    fn trim_buffers_together() {
+        let mut sound_data = vec![padded_tone(50, 20), padded_tone(10, 0), vec![0.0; 30]];
+        trim_silence(&mut sound_data, -60.0, true);
+        assert_eq!(padded_tone(40, 0), sound_data[0]);
+        assert_eq!(padded_tone(0, 0), sound_data[1]);
+        assert!(sound_data[2].is_empty());
+    }
+
+    #[test]
+    fn find_burst() {
+        assert_eq!(vec![4, 5], peak_windows(&burst(), 100, 2));
+        // The quiet windows are all as loud as each other
+        assert_eq!(vec![1, 0, 2], peak_windows(&burst(), 300, 3));
+        assert_eq!(4, peak_windows(&burst(), 250, 10).len());
+        assert!(peak_windows(&[], 100, 3).is_empty());
+    }
+}
@@ -1,6 +1,15 @@
 //! Command line argument parser using [clap](https://docs.rs/clap/latest/clap/) for CyberGrape
 
+use crate::binaural_config::{BinauralConfig, BinauralConfigError};
+use crate::saf::BinauraliserKind;
+
 use clap::{Args, Parser, Subcommand};
+use std::{
+    error::Error,
+    fmt::Display,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 /// The high-level argument struct for CyberGrape
 #[derive(Debug, Parser, Clone)]
@@ -13,6 +22,43 @@ pub struct GrapeArgs {
     /// How often the location of the audio blocks are sampled, in updates per second
     #[arg(short = 'u', long = "update")]
     pub update_rate: usize,
+
+    /// Also write the raw bytes received over serial to this file
+    #[arg(long = "raw-log")]
+    pub raw_log: Option<String>,
+
+    /// Replay hardware messages from a log, like one recorded with
+    /// `--raw-log`, rather than reading them from the serial port
+    #[arg(long = "replay")]
+    pub replay: Option<String>,
+
+    /// How far the listener block is turned to the left of where the
+    /// listener is facing, in degrees. Every source is turned back by this
+    /// much
+    #[arg(
+        long = "listener-yaw",
+        default_value_t = 0.0,
+        allow_negative_numbers = true
+    )]
+    pub listener_yaw: f32,
+
+    /// Also read the yaw of the listener's head from an IMU on this serial
+    /// port, and turn the scene along with it
+    #[arg(long = "head-tracker")]
+    pub head_tracker: Option<String>,
+
+    /// Which binauraliser to mix the sources with
+    #[arg(long = "binauraliser", value_enum, default_value_t)]
+    pub binauraliser: BinauraliserKind,
+
+    /// Directory to write the output to. Without `--out`, the output is
+    /// named after when the run started, like `grape_2024-05-01T12-00-00.wav`
+    #[arg(long = "outdir")]
+    pub outdir: Option<String>,
+
+    /// Overwrite the output file if it already exists
+    #[arg(long = "force")]
+    pub force: bool,
 }
 
 /// A selector for wether we want to binauralize a file or generate positional data
@@ -25,49 +71,998 @@ pub enum CommandTask {
     /// Combine N audio samples into a binauralized WAV file
     #[command(about)]
     Binaural(BinauralCommand),
+
+    /// Binauralize N audio samples using simulated, rather than recorded, motion
+    #[command(about)]
+    Simulate(SimulateCommand),
+
+    /// Binauralize N audio samples along positions recorded by a serial run,
+    /// without any hardware
+    #[command(about)]
+    Render(RenderCommand),
+
+    /// Rewrite a GrapeFile written with an older version of the format in
+    /// the current version
+    #[command(about)]
+    Convert(ConvertCommand),
+
+    /// Check a GrapeFile for problems, and report every one that is found
+    #[command(about)]
+    Validate(ValidateCommand),
 }
 
 /// The arguments needed to generate positional data
 #[derive(Debug, Args, Clone)]
 #[command(version, about)]
 pub struct SerialCommand {
-    /// Filename for serialization output to be written to
+    /// Filename for serialization output to be written to. Defaults to a
+    /// timestamped name in `--outdir`
     #[arg(short = 'o', long = "out")]
-    pub outfile: String,
+    pub outfile: Option<String>,
 
     /// Number of tags to record spatial data from
     #[arg(short)]
     pub num_tags: usize,
+
+    /// Save what has been recorded so far to the output file this often, in
+    /// seconds, so that a capture that dies doesn't lose everything. 0 only
+    /// saves at the end
+    #[arg(long = "checkpoint", value_name = "SECONDS", default_value_t = 30)]
+    pub checkpoint: u64,
+
+    /// Carry on recording at the end of the output file, like one left
+    /// behind by a capture that died, rather than starting a new one
+    #[arg(long = "resume", requires = "outfile")]
+    pub resume: bool,
+}
+
+impl SerialCommand {
+    /// Checks that there is at least one tag to record.
+    pub fn validate(&self) -> Result<(), ArgsError> {
+        if self.num_tags == 0 {
+            return Err(ArgsError::NoSources);
+        }
+        Ok(())
+    }
 }
 
 /// The arguments needed to binauralize sounds
-#[derive(Debug, Args, Clone)]
+#[derive(Debug, Args, Clone, PartialEq)]
 #[command(version, about)]
 pub struct BinauralCommand {
     /// Number of input files to be assigned to audio blocks
-    #[arg(short)]
+    #[arg(
+        short,
+        required_unless_present_any = ["config", "setup"],
+        default_value_t = 0,
+        hide_default_value = true
+    )]
     pub num_files: usize,
 
-    /// Filename for binaural audio data to be written to
+    /// Filename for binaural audio data to be written to. Defaults to a
+    /// timestamped name in `--outdir`
     #[arg(short = 'o', long = "out")]
-    pub outfile: String,
+    pub outfile: Option<String>,
 
-    /// Sample rate of the audio files, in gHz. Defaults to 44100
-    #[arg(short = 's', long = "samp", default_value_t = 44100)]
+    /// No longer used, every file is resampled from its own sample rate to
+    /// the binauraliser's
+    #[arg(short = 's', long = "samp", default_value_t = 44100, hide = true)]
     pub samp_rate: usize,
 
-    /// List of filenames, which should correspond to the number of input files
+    /// List of filenames, which should correspond to the number of input files.
+    /// Add `:left` or `:right` to a stereo file to play just that channel
     #[arg(short = 'f', long = "files")]
     #[clap(num_args = 1..)]
     pub filenames: Vec<String>,
 
-    /// List of gains, which should correspond to the input files given
+    /// List of gains, which should correspond to the input files given.
+    /// Defaults to 1 for every file
     #[arg(short = 'g', long = "gains")]
     #[clap(num_args = 1..)]
-    pub gains: Vec<f32>,
+    pub gains: Option<Vec<f32>>,
+
+    /// Files to play as an ambient bed, like room tone or music. These
+    /// aren't assigned to tags or counted by `-n`, they're mixed into both
+    /// ears equally, without being placed anywhere
+    #[arg(long = "ambient")]
+    #[clap(num_args = 1..)]
+    pub ambient: Vec<String>,
+
+    /// How loud the ambient beds are
+    #[arg(long = "ambient-gain", default_value_t = 1.0, requires = "ambient")]
+    pub ambient_gain: f32,
 
-    /// List of ranges fields, which should correspond to the input files given
+    /// List of ranges fields, which should correspond to the input files given.
+    /// Defaults to 1 for every file
     #[arg(short = 'r', long = "ranges")]
     #[clap(num_args = 1..)]
-    pub ranges: Vec<f32>,
+    pub ranges: Option<Vec<f32>>,
+
+    /// List of tag IDs, which should correspond to the input files given.
+    /// If left out, the files are assigned to the tags in order of ID
+    #[arg(long = "tags")]
+    #[clap(num_args = 1..)]
+    pub tag_ids: Vec<usize>,
+
+    /// Sources to silence, numbered from 1 in the order of the input files
+    #[arg(long = "mute")]
+    #[clap(num_args = 1..)]
+    pub mute: Vec<usize>,
+
+    /// Sources to play on their own, numbered from 1 in the order of the
+    /// input files. Every other source is silenced
+    #[arg(long = "solo")]
+    #[clap(num_args = 1..)]
+    pub solo: Vec<usize>,
+
+    /// List of azimuths, in degrees, to hold each of the input files at,
+    /// rather than following the audio blocks. No positions are captured
+    #[arg(long = "angles", allow_negative_numbers = true)]
+    #[clap(num_args = 1..)]
+    pub angles: Option<Vec<f32>>,
+
+    /// List of elevations, in degrees, to go along with `--angles`. Defaults
+    /// to 0 for every file
+    #[arg(
+        long = "elevations",
+        allow_negative_numbers = true,
+        requires = "angles"
+    )]
+    #[clap(num_args = 1..)]
+    pub elevations: Option<Vec<f32>>,
+
+    /// Read the files, gains, ranges, and tag IDs from a TOML file instead
+    #[arg(
+        long = "config",
+        conflicts_with_all = ["num_files", "filenames", "gains", "ranges", "tag_ids"]
+    )]
+    pub config: Option<String>,
+
+    /// Pick the files, and set their gains and ranges, in an interactive
+    /// form instead
+    #[arg(
+        long = "setup",
+        conflicts_with_all = ["config", "num_files", "filenames", "gains", "ranges", "tag_ids"]
+    )]
+    pub setup: bool,
+
+    /// Smoothly interpolate each source's position between updates, rather
+    /// than jumping from one position to the next
+    #[arg(long = "interpolate")]
+    pub interpolate: bool,
+
+    /// Also play the binauralized audio out of the default output device.
+    /// Needs the `playback` feature
+    #[arg(long = "play")]
+    pub play: bool,
+
+    /// Cut the silence from the start and end of every file before
+    /// binauralizing, where silence is anything quieter than this many
+    /// decibels below full scale. Defaults to -50 dBFS if no level is given
+    #[arg(
+        long = "trim-silence",
+        value_name = "DBFS",
+        num_args = 0..=1,
+        default_missing_value = "-50",
+        allow_negative_numbers = true
+    )]
+    pub trim_silence: Option<f32>,
+
+    /// When trimming silence, cut the same amount from the start of every
+    /// file, so that they stay in time with each other
+    #[arg(long = "trim-together", requires = "trim_silence")]
+    pub trim_together: bool,
+
+    /// Cut this many seconds from the start of the binauralized output, such
+    /// as while the sources were still being placed
+    #[arg(
+        long = "trim-start",
+        value_name = "SECONDS",
+        default_value_t = 0.0,
+        conflicts_with_all = ["live", "mono"]
+    )]
+    pub trim_start: f32,
+
+    /// Give up, rather than writing a file of silence, if no positions have
+    /// come in from the hardware this many seconds after starting. 0 waits
+    /// for as long as the audio lasts, or with `--live`, for as long as it
+    /// takes
+    #[arg(long = "timeout", value_name = "SECONDS", default_value_t = 5)]
+    pub timeout: u64,
+
+    /// Fill in positions that the hardware dropped by gliding each source
+    /// between where it was before and after, rather than holding it where
+    /// it was last heard from
+    #[arg(long = "fill-gaps", conflicts_with_all = ["live", "mono"])]
+    pub fill_gaps: bool,
+
+    /// Binauralize each block of audio as soon as its position arrives, and
+    /// write it out straight away, rather than capturing every position
+    /// first. Can't interpolate, since the next position isn't known yet
+    #[arg(long = "live", conflicts_with_all = ["interpolate", "angles", "play"])]
+    pub live: bool,
+
+    /// Skip binauralizing, and just sum the files, scaled by their gains,
+    /// into a mono file to check the levels. No positions are captured
+    #[arg(long = "mono", conflicts_with = "live")]
+    pub mono: bool,
+}
+
+impl BinauralCommand {
+    /// If a `--config` file was given, fills in the sources from it.
+    pub fn load_config(self) -> Result<Self, BinauralConfigError> {
+        match &self.config {
+            Some(path) => Ok(BinauralConfig::from_path(path)?.apply_to(self)),
+            None => Ok(self),
+        }
+    }
+
+    /// Checks that there is at least one source, and that there is a file
+    /// (and a gain, range, and tag ID, if any of those were given) for each
+    /// of the `num_files` sources.
+    pub fn validate(&self) -> Result<(), ArgsError> {
+        if self.num_files == 0 {
+            return Err(ArgsError::NoSources);
+        }
+
+        let mut counts = vec![("files", self.filenames.len())];
+        if let Some(gains) = &self.gains {
+            counts.push(("gains", gains.len()));
+        }
+        if let Some(ranges) = &self.ranges {
+            counts.push(("ranges", ranges.len()));
+        }
+        if let Some(angles) = &self.angles {
+            counts.push(("angles", angles.len()));
+        }
+        if let Some(elevations) = &self.elevations {
+            counts.push(("elevations", elevations.len()));
+        }
+        if !self.tag_ids.is_empty() {
+            counts.push(("tags", self.tag_ids.len()));
+        }
+
+        for (flag, found) in counts {
+            if found != self.num_files {
+                return Err(ArgsError::CountMismatch {
+                    flag,
+                    expected: self.num_files,
+                    found,
+                });
+            }
+        }
+
+        for (flag, sources) in [("mute", &self.mute), ("solo", &self.solo)] {
+            if let Some(&source) = sources
+                .iter()
+                .find(|&&source| source == 0 || source > self.num_files)
+            {
+                return Err(ArgsError::NoSuchSource {
+                    flag,
+                    source,
+                    num_sources: self.num_files,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Gives every source a gain and range of 1, if they weren't given.
+    pub fn with_default_settings(mut self) -> Self {
+        self.gains.get_or_insert_with(|| vec![1.0; self.num_files]);
+        self.ranges.get_or_insert_with(|| vec![1.0; self.num_files]);
+        self
+    }
+
+    /// The azimuth and elevation, in degrees, that each source is held at, if
+    /// `--angles` was given.
+    pub fn fixed_angles(&self) -> Option<Vec<(f32, f32)>> {
+        let angles = self.angles.as_ref()?;
+        let elevations = self.elevations.clone().unwrap_or(vec![0.0; angles.len()]);
+        Some(angles.iter().copied().zip(elevations).collect())
+    }
+}
+
+/// The ways that the arguments can be inconsistent with each other, in ways
+/// that clap can't check for us.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArgsError {
+    /// A list of per-source values wasn't as long as the number of sources
+    CountMismatch {
+        /// The flag that the list was given with
+        flag: &'static str,
+        /// The number of sources
+        expected: usize,
+        /// How long the list actually was
+        found: usize,
+    },
+    /// There were no sources at all, so there is nothing to record or play
+    NoSources,
+    /// A GrapeFile has positions for a different number of tags than there
+    /// are files to play along them
+    TagCountMismatch {
+        /// The number of tags in the GrapeFile
+        tags: usize,
+        /// The number of files given
+        files: usize,
+    },
+    /// The output file is already there, and `--force` wasn't given to
+    /// overwrite it
+    OutputExists(PathBuf),
+    /// A source was picked by a number that isn't one of the sources
+    NoSuchSource {
+        /// The flag that the source was picked with
+        flag: &'static str,
+        /// The number that was given
+        source: usize,
+        /// The number of sources
+        num_sources: usize,
+    },
+}
+
+impl Display for ArgsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::CountMismatch {
+                flag,
+                expected,
+                found,
+            } => write!(
+                f,
+                "expected {expected} value(s) for --{flag}, one for each of the -n {expected} sources, but got {found}"
+            ),
+            Self::NoSources => write!(f, "expected at least one source, but -n was 0"),
+            Self::TagCountMismatch { tags, files } => write!(
+                f,
+                "the GrapeFile has positions for {tags} tag(s), but {files} file(s) were given"
+            ),
+            Self::OutputExists(path) => write!(
+                f,
+                "{} already exists, pass --force to overwrite it",
+                path.display()
+            ),
+            Self::NoSuchSource {
+                flag,
+                source,
+                num_sources,
+            } => write!(
+                f,
+                "--{flag} {source} isn't one of the sources, which are numbered from 1 to {num_sources}"
+            ),
+        }
+    }
+}
+
+impl Error for ArgsError {}
+
+/// The arguments needed to binauralize sounds along positions that were
+/// already recorded to a GrapeFile. The positions are played back at the
+/// GrapeFile's own sample rate, so `--update` is ignored
+#[derive(Debug, Args, Clone, PartialEq)]
+#[command(version, about)]
+pub struct RenderCommand {
+    /// GrapeFile of positions, like one written by a serial run
+    #[arg(long = "grape")]
+    pub grape: String,
+
+    /// Filename for binaural audio data to be written to. Defaults to a
+    /// timestamped name in `--outdir`
+    #[arg(short = 'o', long = "out")]
+    pub outfile: Option<String>,
+
+    /// List of filenames, one for each tag in the GrapeFile, in order.
+    /// Add `:left` or `:right` to a stereo file to play just that channel
+    #[arg(short = 'f', long = "files", required = true)]
+    #[clap(num_args = 1..)]
+    pub filenames: Vec<String>,
+
+    /// List of gains, which should correspond to the input files given.
+    /// Defaults to 1 for every file
+    #[arg(short = 'g', long = "gains")]
+    #[clap(num_args = 1..)]
+    pub gains: Option<Vec<f32>>,
+
+    /// Smoothly interpolate each source's position between updates, rather
+    /// than jumping from one position to the next
+    #[arg(long = "interpolate")]
+    pub interpolate: bool,
+
+    /// Cut this many seconds from the start of the binauralized output
+    #[arg(long = "trim-start", value_name = "SECONDS", default_value_t = 0.0)]
+    pub trim_start: f32,
+}
+
+/// The arguments needed to convert a GrapeFile to the current version of
+/// the format. `--update` is ignored
+#[derive(Debug, Args, Clone, PartialEq)]
+#[command(version, about)]
+pub struct ConvertCommand {
+    /// GrapeFile to convert, written with any version of the format
+    #[arg(long = "grape")]
+    pub grape: String,
+
+    /// Filename for the converted GrapeFile to be written to. This can be
+    /// the same as `--grape`, along with `--force`
+    #[arg(short = 'o', long = "out")]
+    pub outfile: String,
+}
+
+/// The arguments needed to check a GrapeFile for problems. `--update` is
+/// ignored
+#[derive(Debug, Args, Clone, PartialEq)]
+#[command(version, about)]
+pub struct ValidateCommand {
+    /// GrapeFile to check
+    #[arg(long = "grape")]
+    pub grape: String,
+}
+
+impl RenderCommand {
+    /// Checks that there is a file (and a gain, if they were given) for each
+    /// of the `num_tags` tags in the GrapeFile.
+    pub fn validate(&self, num_tags: usize) -> Result<(), ArgsError> {
+        if self.filenames.len() != num_tags {
+            return Err(ArgsError::TagCountMismatch {
+                tags: num_tags,
+                files: self.filenames.len(),
+            });
+        }
+        if let Some(gains) = &self.gains {
+            if gains.len() != num_tags {
+                return Err(ArgsError::CountMismatch {
+                    flag: "gains",
+                    expected: num_tags,
+                    found: gains.len(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Where to write a run's output, given the `--outdir` and `--out` that it
+/// was started with. A relative `--out` is put in `--outdir`, and without
+/// `--out` the output is named after `now`, the time in UTC, with
+/// `extension`, like `grape_2024-05-01T12-00-00.wav`. If another run already
+/// took that name, a number is added to the end, so nothing is overwritten.
+pub fn output_path(
+    outdir: Option<&str>,
+    outfile: Option<&str>,
+    extension: &str,
+    now: SystemTime,
+) -> PathBuf {
+    let outdir = Path::new(outdir.unwrap_or(""));
+    if let Some(outfile) = outfile {
+        return outdir.join(outfile);
+    }
+
+    let stem = format!("grape_{}", timestamp(now));
+    let mut path = outdir.join(format!("{stem}.{extension}"));
+    let mut copy = 0;
+    while path.exists() {
+        copy += 1;
+        path = outdir.join(format!("{stem}_{copy}.{extension}"));
+    }
+    path
+}
+
+/// Checks that writing the output to `path` won't overwrite anything, unless
+/// `force` says that it may. This is checked before a run starts, rather
+/// than when the output is written at the end, so that a recording isn't
+/// thrown away after the fact.
+pub fn check_overwrite(path: &Path, force: bool) -> Result<(), ArgsError> {
+    if path.exists() && !force {
+        return Err(ArgsError::OutputExists(path.to_path_buf()));
+    }
+    Ok(())
+}
+
+/// Formats `time` in UTC as `2024-05-01T12-00-00`, which is ISO 8601 but for
+/// the colons that aren't allowed in Windows filenames.
+fn timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, secs) = (secs / 86400, secs % 86400);
+
+    // Howard Hinnant's days_from_civil, backwards, counting years from
+    // March so that the leap day falls at the end of the year
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}-{:02}-{:02}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// The arguments needed to binauralize sounds against simulated hardware
+#[derive(Debug, Args, Clone, PartialEq)]
+#[command(version, about)]
+pub struct SimulateCommand {
+    /// The same arguments as a binaural run
+    #[command(flatten)]
+    pub binaural: BinauralCommand,
+
+    /// How far the simulated tags are from the listener
+    #[arg(long = "distance", default_value_t = 1.0)]
+    pub distance: f64,
+
+    /// Noise applied to the simulated antenna measurements, in radians
+    #[arg(long = "noise", default_value_t = 0.01)]
+    pub noise: f64,
+
+    /// How fast the simulated tags orbit the listener, in radians per second
+    #[arg(long = "rotation", default_value_t = 1.0)]
+    pub rotation: f64,
+
+    /// Seconds between simulated antenna measurements
+    #[arg(long = "delay", default_value_t = 0.01)]
+    pub delay: f64,
+}
+
+/// The arguments for the monitor, which localizes updates from a simulated
+/// listener block and plots them against where the sources really are
+#[derive(Debug, Parser, Clone)]
+#[clap(version, about)]
+pub struct MonitorArgs {
+    /// Number of simulated sound sources around the listener
+    #[arg(short = 'n', long = "points", default_value_t = 10)]
+    pub num_points: usize,
+
+    /// How far the simulated sound sources are from the listener
+    #[arg(short = 'r', long = "range", default_value_t = 5.0)]
+    pub range: f64,
+
+    /// Noise applied to the simulated antenna measurements, in radians
+    #[arg(long = "noise", default_value_t = 0.25)]
+    pub noise: f64,
+
+    /// Seconds between simulated antenna measurements
+    #[arg(short = 'd', long = "delay", default_value_t = 0.25)]
+    pub delay: f64,
+
+    /// Record the localized points to this GrapeFile when the monitor quits,
+    /// with an X and a Y stream for each point
+    #[arg(long = "record")]
+    pub record: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn binaural_command(args: &[&str]) -> BinauralCommand {
+        let args = GrapeArgs::try_parse_from(
+            [
+                "cybergrape",
+                "--update",
+                "40",
+                "binaural",
+                "--out",
+                "out.wav",
+            ]
+            .iter()
+            .chain(args),
+        )
+        .unwrap();
+        match args.command {
+            CommandTask::Binaural(command) => command,
+            _ => panic!("expected a binaural command"),
+        }
+    }
+
+    #[test]
+    fn binaural_counts_match() {
+        let command = binaural_command(&[
+            "-n", "2", "--files", "x.wav", "y.wav", "--gains", "1", "1", "--ranges", "3", "4",
+        ]);
+        assert_eq!(Ok(()), command.validate());
+    }
+
+    #[test]
+    fn binaural_counts_mismatch() {
+        let command = binaural_command(&[
+            "-n", "2", "--files", "x.wav", "y.wav", "--gains", "1", "--ranges", "3", "4",
+        ]);
+        assert_eq!(
+            Err(ArgsError::CountMismatch {
+                flag: "gains",
+                expected: 2,
+                found: 1
+            }),
+            command.validate()
+        );
+
+        let command = binaural_command(&[
+            "-n", "1", "--files", "x.wav", "--gains", "1", "--ranges", "3", "--tags", "1", "2",
+        ]);
+        assert_eq!(
+            Err(ArgsError::CountMismatch {
+                flag: "tags",
+                expected: 1,
+                found: 2
+            }),
+            command.validate()
+        );
+    }
+
+    #[test]
+    fn mute_and_solo_existing_sources() {
+        let two = ["-n", "2", "--files", "x.wav", "y.wav"];
+        let command = binaural_command(&[&two[..], &["--mute", "2", "--solo", "1", "2"]].concat());
+        assert_eq!(vec![2], command.mute);
+        assert_eq!(vec![1, 2], command.solo);
+        assert_eq!(Ok(()), command.validate());
+
+        for (flag, source) in [("mute", "3"), ("solo", "0")] {
+            let command = binaural_command(&[&two[..], &[&format!("--{flag}"), source]].concat());
+            assert_eq!(
+                Err(ArgsError::NoSuchSource {
+                    flag,
+                    source: source.parse().unwrap(),
+                    num_sources: 2
+                }),
+                command.validate()
+            );
+        }
+    }
+
+    #[test]
+    fn reject_zero_sources() {
+        let command = binaural_command(&["-n", "0"]);
+        assert_eq!(Err(ArgsError::NoSources), command.validate());
+
+        let args = GrapeArgs::try_parse_from([
+            "cybergrape",
+            "-u",
+            "40",
+            "serial",
+            "-o",
+            "x.grape",
+            "-n",
+            "0",
+        ])
+        .unwrap();
+        let CommandTask::Serial(command) = args.command else {
+            panic!("expected a serial command");
+        };
+        assert_eq!(Err(ArgsError::NoSources), command.validate());
+    }
+
+    #[test]
+    fn render_counts() {
+        let args = GrapeArgs::try_parse_from([
+            "cybergrape",
+            "-u",
+            "40",
+            "render",
+            "--grape",
+            "x.grape",
+            "-o",
+            "out.wav",
+            "--files",
+            "a.wav",
+            "b.wav",
+            "--gains",
+            "1",
+        ])
+        .unwrap();
+        let CommandTask::Render(command) = args.command else {
+            panic!("expected a render command");
+        };
+
+        assert_eq!(
+            Err(ArgsError::TagCountMismatch { tags: 3, files: 2 }),
+            command.validate(3)
+        );
+        assert_eq!(
+            Err(ArgsError::CountMismatch {
+                flag: "gains",
+                expected: 2,
+                found: 1
+            }),
+            command.validate(2)
+        );
+        assert_eq!(0.0, command.trim_start);
+        let command = RenderCommand {
+            gains: None,
+            ..command
+        };
+        assert_eq!(Ok(()), command.validate(2));
+    }
+
+    #[test]
+    fn pick_binauraliser() {
+        let parse = |extra: &[&str]| {
+            let args = ["cybergrape", "-u", "40"]
+                .iter()
+                .chain(extra)
+                .chain(&["convert", "--grape", "x.grape", "-o", "y.grape"]);
+            GrapeArgs::try_parse_from(args).map(|args| args.binauraliser)
+        };
+
+        assert_eq!(BinauraliserKind::NearField, parse(&[]).unwrap());
+        let dummy = parse(&["--binauraliser", "dummy"]).unwrap();
+        assert_eq!(BinauraliserKind::Dummy, dummy);
+        let near_field = parse(&["--binauraliser", "near-field"]).unwrap();
+        assert_eq!(BinauraliserKind::NearField, near_field);
+        let far_field = parse(&["--binauraliser", "far-field"]).unwrap();
+        assert_eq!(BinauraliserKind::FarField, far_field);
+        assert!(parse(&["--binauraliser", "stereo"]).is_err());
+    }
+
+    #[test]
+    fn binaural_default_settings() {
+        let command = binaural_command(&["-n", "2", "--files", "x.wav", "y.wav"]);
+        assert_eq!(None, command.gains);
+        assert_eq!(Ok(()), command.validate());
+
+        let command = command.with_default_settings();
+        assert_eq!(Some(vec![1.0, 1.0]), command.gains);
+        assert_eq!(Some(vec![1.0, 1.0]), command.ranges);
+    }
+
+    #[test]
+    fn binaural_defaults_keep_given_settings() {
+        let command = binaural_command(&["-n", "2", "--files", "x.wav", "y.wav", "-r", "3", "4"])
+            .with_default_settings();
+        assert_eq!(Some(vec![1.0, 1.0]), command.gains);
+        assert_eq!(Some(vec![3.0, 4.0]), command.ranges);
+    }
+
+    #[test]
+    fn binaural_short_settings() {
+        let command = binaural_command(&["-n", "2", "--files", "x.wav", "y.wav", "--ranges", "3"]);
+        assert_eq!(
+            Err(ArgsError::CountMismatch {
+                flag: "ranges",
+                expected: 2,
+                found: 1
+            }),
+            command.validate()
+        );
+    }
+
+    #[test]
+    fn binaural_ambient() {
+        let command = binaural_command(&[
+            "-n",
+            "1",
+            "--files",
+            "x.wav",
+            "--ambient",
+            "room.wav",
+            "--ambient-gain",
+            "0.5",
+        ]);
+        assert_eq!(Ok(()), command.validate());
+        assert_eq!(vec!["room.wav"], command.ambient);
+        assert_eq!(0.5, command.ambient_gain);
+    }
+
+    #[test]
+    fn binaural_trim_silence() {
+        let command = binaural_command(&["-n", "1", "--files", "x.wav"]);
+        assert_eq!(None, command.trim_silence);
+
+        let command = binaural_command(&["-n", "1", "--files", "x.wav", "--trim-silence"]);
+        assert_eq!(Some(-50.0), command.trim_silence);
+        assert!(!command.trim_together);
+
+        let command = binaural_command(&[
+            "-n",
+            "1",
+            "--trim-silence",
+            "-30",
+            "--trim-together",
+            "--files",
+            "x.wav",
+        ]);
+        assert_eq!(Some(-30.0), command.trim_silence);
+        assert!(command.trim_together);
+    }
+
+    #[test]
+    fn binaural_trim_start() {
+        let one = ["-n", "1", "--files", "x.wav"];
+        assert_eq!(0.0, binaural_command(&one).trim_start);
+        let command = binaural_command(&[&one[..], &["--trim-start", "2.5"]].concat());
+        assert_eq!(2.5, command.trim_start);
+
+        // Live runs write the output as they go
+        let live = [
+            "cybergrape",
+            "-u",
+            "40",
+            "binaural",
+            "--live",
+            "--trim-start",
+            "1",
+        ];
+        assert!(GrapeArgs::try_parse_from(live.iter().chain(&one)).is_err());
+    }
+
+    #[test]
+    fn binaural_fill_gaps() {
+        let one = ["-n", "1", "--files", "x.wav"];
+        assert!(!binaural_command(&one).fill_gaps);
+        assert!(binaural_command(&[&one[..], &["--fill-gaps"]].concat()).fill_gaps);
+
+        let args = GrapeArgs::try_parse_from(
+            ["cybergrape", "binaural", "--mono", "--fill-gaps"]
+                .iter()
+                .chain(&one),
+        );
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn binaural_setup() {
+        let command = binaural_command(&["--setup"]);
+        assert!(command.setup);
+        assert!(command.filenames.is_empty());
+
+        let args = GrapeArgs::try_parse_from([
+            "cybergrape",
+            "binaural",
+            "--out",
+            "out.wav",
+            "--setup",
+            "--files",
+            "x.wav",
+        ]);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn binaural_mono() {
+        let command = binaural_command(&["-n", "1", "--files", "x.wav"]);
+        assert!(!command.mono);
+
+        let command = binaural_command(&["-n", "1", "--files", "x.wav", "--mono"]);
+        assert!(command.mono);
+
+        let args = GrapeArgs::try_parse_from([
+            "cybergrape",
+            "binaural",
+            "--out",
+            "out.wav",
+            "-n",
+            "1",
+            "--files",
+            "x.wav",
+            "--mono",
+            "--live",
+        ]);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn binaural_fixed_angles() {
+        let command = binaural_command(&[
+            "-n", "3", "--files", "x.wav", "y.wav", "z.wav", "--angles", "-60", "0", "60.5",
+        ]);
+        assert_eq!(Ok(()), command.validate());
+        assert_eq!(
+            Some(vec![(-60.0, 0.0), (0.0, 0.0), (60.5, 0.0)]),
+            command.fixed_angles()
+        );
+
+        let command = binaural_command(&[
+            "-n",
+            "2",
+            "--files",
+            "x.wav",
+            "y.wav",
+            "--angles",
+            "-20",
+            "20",
+            "--elevations",
+            "10",
+            "-10",
+        ]);
+        assert_eq!(
+            Some(vec![(-20.0, 10.0), (20.0, -10.0)]),
+            command.fixed_angles()
+        );
+
+        let command = binaural_command(&["-n", "1", "--files", "x.wav"]);
+        assert_eq!(None, command.fixed_angles());
+    }
+
+    #[test]
+    fn monitor_defaults() {
+        let args = MonitorArgs::try_parse_from(["monitor"]).unwrap();
+        assert_eq!(10, args.num_points);
+        assert_eq!(5.0, args.range);
+        assert_eq!(0.25, args.noise);
+        assert_eq!(0.25, args.delay);
+    }
+
+    #[test]
+    fn monitor_args() {
+        let args = MonitorArgs::try_parse_from([
+            "monitor", "--points", "3", "-r", "2.5", "--noise", "0", "--delay", "0.1",
+        ])
+        .unwrap();
+        assert_eq!(3, args.num_points);
+        assert_eq!(2.5, args.range);
+        assert_eq!(0.0, args.noise);
+        assert_eq!(0.1, args.delay);
+        assert_eq!(None, args.record);
+
+        let args = MonitorArgs::try_parse_from(["monitor", "--record", "track.grape"]).unwrap();
+        assert_eq!(Some("track.grape".to_string()), args.record);
+    }
+
+    #[test]
+    fn monitor_rejects_bad_points() {
+        assert!(MonitorArgs::try_parse_from(["monitor", "--points", "many"]).is_err());
+    }
+
+    #[test]
+    fn auto_named_output() {
+        // 2024-05-01 at noon, UTC
+        let noon = UNIX_EPOCH + Duration::from_secs(1714564800);
+        assert_eq!("2024-05-01T12-00-00", timestamp(noon));
+        assert_eq!("1970-01-01T00-00-00", timestamp(UNIX_EPOCH));
+        // The leap day, a second before midnight
+        let leap_day = UNIX_EPOCH + Duration::from_secs(951868799);
+        assert_eq!("2000-02-29T23-59-59", timestamp(leap_day));
+
+        let dir = tempfile::tempdir().unwrap();
+        let outdir = dir.path().to_str();
+        let first = output_path(outdir, None, "wav", noon);
+        assert_eq!(dir.path().join("grape_2024-05-01T12-00-00.wav"), first);
+
+        // Another run in the same second doesn't overwrite the first
+        std::fs::write(&first, b"").unwrap();
+        let second = output_path(outdir, None, "wav", noon);
+        assert_eq!(dir.path().join("grape_2024-05-01T12-00-00_1.wav"), second);
+        assert_eq!(
+            dir.path().join("grape_2024-05-01T12-00-00.grape"),
+            output_path(outdir, None, "grape", noon)
+        );
+
+        // A given name is used as it is, in the output directory
+        assert_eq!(
+            dir.path().join("take.wav"),
+            output_path(outdir, Some("take.wav"), "wav", noon)
+        );
+        assert_eq!(
+            PathBuf::from("take.wav"),
+            output_path(None, Some("take.wav"), "wav", noon)
+        );
+    }
+
+    #[test]
+    fn refuse_to_overwrite() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("take.wav");
+        assert_eq!(Ok(()), check_overwrite(&path, false));
+
+        std::fs::write(&path, b"").unwrap();
+        let err = check_overwrite(&path, false).unwrap_err();
+        assert_eq!(ArgsError::OutputExists(path.clone()), err);
+        assert!(err.to_string().contains("--force"));
+        assert_eq!(Ok(()), check_overwrite(&path, true));
+    }
 }
@@ -0,0 +1,566 @@
+//! Reads the audio files that we binauralize. WAV files are always read with
+//! hound. Other formats, like FLAC and MP3, are decoded with
+//! [symphonia](https://docs.rs/symphonia/latest/symphonia/), which is only
+//! built with the `codecs` feature.
+//!
+//! A stereo file can be split into two sources by naming one of its channels
+//! after the file, like `song.wav:left` and `song.wav:right`. Each channel
+//! then gets its own gain, range, and position, as if it were its own file.
+
+use hound::{SampleFormat, WavReader};
+use log::info;
+use std::{error::Error, fmt::Display, path::Path};
+
+/// Decoded samples are scaled so that full scale is that of a 16-bit file,
+/// which is what [`hound_writer`](crate::hound_helpers::hound_writer) writes.
+pub const FULL_SCALE: f32 = 32768.0;
+
+/// The ways that decoding an audio file can fail.
+#[derive(Debug)]
+pub enum DecodeError {
+    /// Errors generated by [`hound`]
+    Hound(hound::Error),
+    /// Errors generated by [`symphonia`]
+    #[cfg(feature = "codecs")]
+    Symphonia(symphonia::core::errors::Error),
+    /// The file isn't in a format that we know how to decode
+    UnsupportedFormat(String),
+    /// One of the sources given to [`decode_source`] couldn't be decoded
+    Source {
+        /// The source, as it was given
+        source: String,
+        /// What went wrong while decoding it
+        error: Box<DecodeError>,
+    },
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Hound(e) => write!(f, "couldn't read the WAV file: {e}"),
+            #[cfg(feature = "codecs")]
+            Self::Symphonia(e) => write!(f, "couldn't decode the audio file: {e}"),
+            Self::UnsupportedFormat(path) if cfg!(feature = "codecs") => {
+                write!(f, "{path} isn't in an audio format that we can decode")
+            }
+            Self::UnsupportedFormat(path) => write!(
+                f,
+                "{path} isn't a WAV file, build with the `codecs` feature to decode other formats"
+            ),
+            Self::Source { source, error } => write!(f, "couldn't decode {source}: {error}"),
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+impl From<hound::Error> for DecodeError {
+    fn from(value: hound::Error) -> Self {
+        Self::Hound(value)
+    }
+}
+
+#[cfg(feature = "codecs")]
+impl From<symphonia::core::errors::Error> for DecodeError {
+    fn from(value: symphonia::core::errors::Error) -> Self {
+        Self::Symphonia(value)
+    }
+}
+
+/// Which of a file's channels a source plays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    /// Every channel, mixed down
+    Mix,
+    /// Just the left channel
+    Left,
+    /// Just the right channel
+    Right,
+}
+
+/// Splits a source, as given on the command line, into the path of its file
+/// and the channel it plays. Only a `:left` or `:right` suffix picks a
+/// channel, anything else is part of the path.
+pub fn parse_source(source: &str) -> (&str, Channel) {
+    if let Some(path) = source.strip_suffix(":left") {
+        (path, Channel::Left)
+    } else if let Some(path) = source.strip_suffix(":right") {
+        (path, Channel::Right)
+    } else {
+        (source, Channel::Mix)
+    }
+}
+
+/// A decoded audio file, before its channels are pulled apart.
+struct Interleaved {
+    samples: Vec<f32>,
+    channels: usize,
+    sample_rate: u32,
+}
+
+/// Decodes the audio file at `path` into mono samples, choosing how to decode
+/// it by its extension. Files with more than one channel are mixed down. The
+/// samples are left at the file's own sample rate.
+pub fn decode_audio(path: impl AsRef<Path>) -> Result<Vec<f32>, DecodeError> {
+    let decoded = decode_interleaved(path.as_ref())?;
+    Ok(mix_down(&decoded.samples, decoded.channels))
+}
+
+/// Decodes the audio file at `path` into its left and right channels. Mono
+/// files play the same samples in both, and channels past the second are
+/// ignored. The samples are left at the file's own sample rate.
+pub fn decode_stereo(path: impl AsRef<Path>) -> Result<(Vec<f32>, Vec<f32>), DecodeError> {
+    let decoded = decode_interleaved(path.as_ref())?;
+    Ok(split_stereo(&decoded.samples, decoded.channels))
+}
+
+/// Decodes a source, as given on the command line, into mono samples at
+/// `sample_rate`, resampling it if the file is at some other rate. See
+/// [`parse_source`]. Errors say which source couldn't be decoded.
+pub fn decode_source(source: &str, sample_rate: u32) -> Result<Vec<f32>, DecodeError> {
+    let (path, channel) = parse_source(source);
+    let decoded = decode_interleaved(Path::new(path)).map_err(|error| match error {
+        // Which already says which file it was
+        DecodeError::UnsupportedFormat(_) => error,
+        error => DecodeError::Source {
+            source: source.to_string(),
+            error: Box::new(error),
+        },
+    })?;
+
+    let samples = match channel {
+        Channel::Mix => mix_down(&decoded.samples, decoded.channels),
+        Channel::Left => split_stereo(&decoded.samples, decoded.channels).0,
+        Channel::Right => split_stereo(&decoded.samples, decoded.channels).1,
+    };
+
+    if decoded.sample_rate == sample_rate {
+        return Ok(samples);
+    }
+
+    info!(
+        "resampling {path} from {} Hz to {sample_rate} Hz",
+        decoded.sample_rate
+    );
+    Ok(resample(&samples, decoded.sample_rate, sample_rate))
+}
+
+/// Decodes each of the sources with [`decode_source`], stopping at the first
+/// one that can't be decoded. Sources at different sample rates all come out
+/// at `sample_rate`, rather than one playing at another's rate.
+pub fn decode_all(sources: &[String], sample_rate: u32) -> Result<Vec<Vec<f32>>, DecodeError> {
+    sources
+        .iter()
+        .map(|s| decode_source(s, sample_rate))
+        .collect()
+}
+
+/// Converts `samples` from the sample rate `from` to the sample rate `to`, by
+/// drawing straight lines between them. The result lasts just as long, give
+/// or take a sample.
+pub fn resample(samples: &[f32], from: u32, to: u32) -> Vec<f32> {
+    if from == to || samples.is_empty() {
+        return samples.to_vec();
+    }
+
+    let len = (samples.len() as u64 * to as u64).div_ceil(from as u64) as usize;
+    let step = from as f64 / to as f64;
+
+    (0..len)
+        .map(|i| {
+            let position = i as f64 * step;
+            let before = position.floor() as usize;
+            let t = (position - before as f64) as f32;
+
+            let a = samples[before];
+            let b = samples.get(before + 1).copied().unwrap_or(a);
+            a + (b - a) * t
+        })
+        .collect()
+}
+
+/// Decodes the audio file at `path` into interleaved samples, choosing how to
+/// decode it by its extension.
+fn decode_interleaved(path: &Path) -> Result<Interleaved, DecodeError> {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "wav" | "wave" => decode_wav(path),
+        #[cfg(feature = "codecs")]
+        _ => decode_with_symphonia(path, &extension),
+        #[cfg(not(feature = "codecs"))]
+        _ => Err(DecodeError::UnsupportedFormat(path.display().to_string())),
+    }
+}
+
+fn decode_wav(path: &Path) -> Result<Interleaved, DecodeError> {
+    let mut reader = WavReader::open(path)?;
+    let spec = reader.spec();
+
+    let samples = match spec.sample_format {
+        SampleFormat::Int => {
+            let scale = FULL_SCALE / (1_i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|s| s.map(|s| s as f32 * scale))
+                .collect::<Result<Vec<_>, _>>()?
+        }
+        SampleFormat::Float => reader
+            .samples::<f32>()
+            .map(|s| s.map(|s| s * FULL_SCALE))
+            .collect::<Result<Vec<_>, _>>()?,
+    };
+
+    Ok(Interleaved {
+        samples,
+        channels: spec.channels as usize,
+        sample_rate: spec.sample_rate,
+    })
+}
+
+#[cfg(feature = "codecs")]
+fn decode_with_symphonia(path: &Path, extension: &str) -> Result<Interleaved, DecodeError> {
+    use symphonia::core::{
+        audio::SampleBuffer, codecs::DecoderOptions, errors::Error as SymphoniaError,
+        formats::FormatOptions, io::MediaSourceStream, meta::MetadataOptions, probe::Hint,
+    };
+
+    let file = std::fs::File::open(path).map_err(SymphoniaError::IoError)?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    hint.with_extension(extension);
+
+    let mut format = symphonia::default::get_probe()
+        .format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )?
+        .format;
+    let track = format
+        .default_track()
+        .ok_or_else(|| DecodeError::UnsupportedFormat(path.display().to_string()))?;
+    let track_id = track.id;
+    let mut decoder =
+        symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let mut samples = vec![];
+    let mut channels = 1;
+    let mut sample_rate = track.codec_params.sample_rate.unwrap_or_default();
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            // This is how symphonia tells us that we've reached the end
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                break
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = decoder.decode(&packet)?;
+        channels = decoded.spec().channels.count();
+        sample_rate = decoded.spec().rate;
+        let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
+        buffer.copy_interleaved_ref(decoded);
+
+        samples.extend(buffer.samples().iter().map(|s| s * FULL_SCALE));
+    }
+
+    Ok(Interleaved {
+        samples,
+        channels,
+        sample_rate,
+    })
+}
+
+/// Pulls the first two channels out of interleaved samples. A single channel
+/// is used for both.
+fn split_stereo(samples: &[f32], channels: usize) -> (Vec<f32>, Vec<f32>) {
+    let channels = channels.max(1);
+
+    let left = samples
+        .iter()
+        .step_by(channels)
+        .copied()
+        .collect::<Vec<_>>();
+    let right = if channels == 1 {
+        left.clone()
+    } else {
+        samples.iter().skip(1).step_by(channels).copied().collect()
+    };
+
+    (left, right)
+}
+
+/// Averages each frame of interleaved samples down to a single sample.
+fn mix_down(samples: &[f32], channels: usize) -> Vec<f32> {
+    if channels <= 1 {
+        return samples.to_vec();
+    }
+
+    samples
+        .chunks(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hound::{WavSpec, WavWriter};
+    use std::f32::consts::PI;
+    use tempfile::Builder;
+
+    /// The same tone as `tests/data/tone.flac`: 800 samples of a 440 Hz sine
+    /// wave at 8 kHz, in 16-bit mono
+    fn write_tone(path: &Path) -> Vec<i16> {
+        let samples = (0..800)
+            .map(|i| (8000.0 * (2.0 * PI * 440.0 * i as f32 / 8000.0).sin()).round() as i16)
+            .collect::<Vec<_>>();
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut writer = WavWriter::create(path, spec).unwrap();
+        for &sample in &samples {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        samples
+    }
+
+    #[test]
+    fn decode_16_bit_wav() {
+        let file = Builder::new().suffix(".wav").tempfile().unwrap();
+        let expected = write_tone(file.path());
+
+        let samples = decode_audio(file.path()).unwrap();
+        let expected = expected.into_iter().map(f32::from).collect::<Vec<_>>();
+        assert_eq!(expected, samples);
+    }
+
+    #[test]
+    fn decode_stereo_float_wav() {
+        let file = Builder::new().suffix(".WAV").tempfile().unwrap();
+        let spec = WavSpec {
+            channels: 2,
+            sample_rate: 8000,
+            bits_per_sample: 32,
+            sample_format: SampleFormat::Float,
+        };
+        let mut writer = WavWriter::create(file.path(), spec).unwrap();
+        for (left, right) in [(0.5, 0.0), (-0.25, -0.25)] {
+            writer.write_sample(left).unwrap();
+            writer.write_sample(right).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        assert_eq!(vec![8192.0, -8192.0], decode_audio(file.path()).unwrap());
+    }
+
+    #[test]
+    fn split_stereo_wav() {
+        let file = Builder::new().suffix(".wav").tempfile().unwrap();
+        let spec = WavSpec {
+            channels: 2,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut writer = WavWriter::create(file.path(), spec).unwrap();
+        for i in 0..100_i16 {
+            writer.write_sample(i).unwrap();
+            writer.write_sample(-i).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let left = (0..100_i16).map(f32::from).collect::<Vec<_>>();
+        let right = left.iter().map(|s| -s).collect::<Vec<_>>();
+        assert_eq!(
+            (left.clone(), right.clone()),
+            decode_stereo(file.path()).unwrap()
+        );
+
+        let path = file.path().display();
+        let sources = [
+            format!("{path}:left"),
+            format!("{path}:right"),
+            path.to_string(),
+        ];
+        assert_eq!(
+            vec![left, right, vec![0.0; 100]],
+            decode_all(&sources, 8000).unwrap()
+        );
+    }
+
+    /// A 1 kHz tone lasting one second
+    fn tone_1k(sample_rate: u32) -> Vec<f32> {
+        (0..sample_rate)
+            .map(|i| (2.0 * PI * 1000.0 * i as f32 / sample_rate as f32).sin())
+            .collect()
+    }
+
+    /// Counts the times that `samples` go from negative to positive
+    fn rising_crossings(samples: &[f32]) -> usize {
+        samples
+            .windows(2)
+            .filter(|w| w[0] < 0.0 && w[1] >= 0.0)
+            .count()
+    }
+
+    #[test]
+    fn resample_keeps_frequency() {
+        let resampled = resample(&tone_1k(48000), 48000, 44100);
+        assert_eq!(44100, resampled.len());
+        let crossings = rising_crossings(&resampled);
+        assert!((998..=1001).contains(&crossings), "{crossings} crossings");
+
+        let resampled = resample(&tone_1k(44100), 44100, 48000);
+        assert_eq!(48000, resampled.len());
+        let crossings = rising_crossings(&resampled);
+        assert!((998..=1001).contains(&crossings), "{crossings} crossings");
+
+        assert_eq!(vec![1.0, 2.0], resample(&[1.0, 2.0], 8000, 8000));
+        assert_eq!(vec![0.0, 0.5, 1.0, 1.0], resample(&[0.0, 1.0], 1, 2));
+    }
+
+    #[test]
+    fn decode_resamples_to_the_binauraliser() {
+        let file = Builder::new().suffix(".wav").tempfile().unwrap();
+        write_tone(file.path());
+
+        let path = file.path().display().to_string();
+        let samples = decode_source(&path, 16000).unwrap();
+        assert_eq!(1600, samples.len());
+        assert_eq!(
+            vec![800],
+            decode_all(&[path], 8000)
+                .unwrap()
+                .iter()
+                .map(Vec::len)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn decode_mixed_sample_rates() {
+        let eight = Builder::new().suffix(".wav").tempfile().unwrap();
+        write_tone(eight.path());
+        let sixteen = Builder::new().suffix(".wav").tempfile().unwrap();
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: 16000,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut writer = WavWriter::create(sixteen.path(), spec).unwrap();
+        for _ in 0..1600 {
+            writer.write_sample(0_i16).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        // Both last a tenth of a second, so they come out just as long,
+        // rather than one playing at the other's rate
+        let sources = [eight.path(), sixteen.path()].map(|p| p.display().to_string());
+        for sample_rate in [8000, 16000, 44100] {
+            let lengths = decode_all(&sources, sample_rate)
+                .unwrap()
+                .iter()
+                .map(Vec::len)
+                .collect::<Vec<_>>();
+            let expected = sample_rate as usize / 10;
+            assert_eq!(vec![expected; 2], lengths, "at {sample_rate} Hz");
+        }
+    }
+
+    #[test]
+    fn decode_names_missing_file() {
+        let good = Builder::new().suffix(".wav").tempfile().unwrap();
+        write_tone(good.path());
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing.wav").display().to_string();
+
+        let sources = [good.path().display().to_string(), format!("{missing}:left")];
+        let err = decode_all(&sources, 8000).unwrap_err();
+        match &err {
+            DecodeError::Source { source, error } => {
+                assert_eq!(&sources[1], source);
+                assert!(matches!(
+                    **error,
+                    DecodeError::Hound(hound::Error::IoError(_))
+                ));
+            }
+            _ => panic!("{err:?} doesn't say which source it was"),
+        }
+        assert!(err.to_string().contains("missing.wav"));
+    }
+
+    #[test]
+    fn mono_is_both_channels() {
+        let file = Builder::new().suffix(".wav").tempfile().unwrap();
+        let samples = write_tone(file.path())
+            .into_iter()
+            .map(f32::from)
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            (samples.clone(), samples),
+            decode_stereo(file.path()).unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_channel_suffixes() {
+        assert_eq!(("x.wav", Channel::Left), parse_source("x.wav:left"));
+        assert_eq!(("x.wav", Channel::Right), parse_source("x.wav:right"));
+        assert_eq!(("C:\\x.wav", Channel::Mix), parse_source("C:\\x.wav"));
+    }
+
+    #[cfg(not(feature = "codecs"))]
+    #[test]
+    fn only_wav_without_codecs() {
+        assert!(matches!(
+            decode_audio("tone.flac"),
+            Err(DecodeError::UnsupportedFormat(_))
+        ));
+    }
+
+    #[cfg(feature = "codecs")]
+    #[test]
+    fn reject_unknown_formats() {
+        let mut file = Builder::new().suffix(".txt").tempfile().unwrap();
+        std::io::Write::write_all(&mut file, b"not audio at all").unwrap();
+        assert!(matches!(
+            decode_audio(file.path()),
+            Err(DecodeError::Symphonia(_))
+        ));
+    }
+
+    #[cfg(feature = "codecs")]
+    #[test]
+    fn flac_matches_wav() {
+        let file = Builder::new().suffix(".wav").tempfile().unwrap();
+        write_tone(file.path());
+
+        let wav = decode_audio(file.path()).unwrap();
+        let flac =
+            decode_audio(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/tone.flac")).unwrap();
+
+        assert_eq!(wav.len(), flac.len());
+        // The fixture was generated with f64s rather than f32s, so the odd
+        // sample rounds the other way
+        for (w, f) in wav.into_iter().zip(flac) {
+            assert!((w - f).abs() <= 1.0, "{w} != {f}");
+        }
+    }
+}
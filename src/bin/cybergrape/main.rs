@@ -2,43 +2,242 @@
 
 use clap::Parser;
 use cybergrape::{
+    analysis::{clip_ratio, trim_silence},
     args::{
-        CommandTask::{Binaural, Serial},
-        GrapeArgs,
+        check_overwrite, output_path, BinauralCommand,
+        CommandTask::{Binaural, Convert, Render, Serial, Simulate, Validate},
+        ConvertCommand, GrapeArgs, RenderCommand, ValidateCommand,
     },
-    gui,
-    hardware_message_decoder::HardwareEvent,
+    audio_decoder::{decode_all, DecodeError},
+    checkpoint::Checkpoint,
+    component::{Component, ComponentError},
+    dummy_hdm::DummyHdm,
+    file_hdm::FileHdm,
+    gui::{self, GainControl},
+    hardware_data_manager::HardwareDataManager,
     hdm::Hdm,
-    hound_helpers::{hound_reader, hound_writer},
-    saf::{Binauraliser, BinauraliserNF, FRAME_SIZE},
-    spatial_data_format::{GrapeFile, GrapeTag},
-    sphericalizer::Sphericalizer,
+    head_tracker::listen_for_head_yaw,
+    hound_helpers::{hound_writer, hound_writer_mono, HoundWriter},
+    pipeline::{self, SharedHdm},
+    saf::{BinauraliserKind, BufferMetadata, FRAME_SIZE, SAMP_RATE},
+    serial_listener::listen_on_port,
+    spatial_data_format::{grape_file_to_metadata, GrapeFile, FORMAT_VERSION},
+    sphericalizer::{HeadYaw, Sphericalizer, BACK_ANTENNA, FRONT_ANTENNA},
     time_domain_buffer::TDBufMeta,
     update_accumulator::UpdateAccumulator,
-    TransposableIter,
 };
 
-use log::{debug, error, info, warn};
+use hound::{SampleFormat, WavSpec};
+use log::{error, info, warn};
 use serial2::SerialPort;
 use spin_sleep::sleep;
 use std::{
-    str::{self, FromStr},
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::PathBuf,
     sync::{Arc, Mutex},
-    thread::spawn,
-    time::Duration,
+    time::{Duration, SystemTime},
 };
 
 const BAUD_RATE: u32 = 115200;
 
+/// Warn about any input with more than this fraction of its samples clipped
+const CLIP_WARNING_RATIO: f32 = 0.01;
+
 // Example:
 // cargo run --bin cybergrape --
-//                            --samp    44100
+//                            --raw-log serial.log
 //                            --update  40 binaural
 //                            -n        2
 //                            --out     outfile.wav
 //                            --gains   1 1
 //                            --ranges  3 4
 //                            --files   x.wav y.wav
+//                            --interpolate
+//
+// or, to write the output as the positions come in, add --live instead of
+// --interpolate
+//
+// or, to try things out without any hardware:
+// cargo run --bin cybergrape -- --update 40 simulate
+//                            -n        1
+//                            --out     outfile.wav
+//                            --files   x.wav
+//                            --rotation 1.0
+//
+// or, to hold a quartet still without any hardware:
+// cargo run --bin cybergrape -- --update 40 binaural -n 4 --out outfile.wav
+//                            --files   a.wav b.wav c.wav d.wav
+//                            --angles  -60 -20 20 60
+//
+// or, to spread the two channels of a stereo file out to either side:
+// cargo run --bin cybergrape -- --update 40 binaural -n 2 --out outfile.wav
+//                            --files   song.wav:left song.wav:right
+//                            --angles  90 -90
+//
+// or, with room tone under the moving sources:
+// cargo run --bin cybergrape -- --update 40 binaural ... --ambient room.wav --ambient-gain 0.5
+//
+// or, with the sources described in a TOML file (see binaural_config):
+// cargo run --bin cybergrape -- --update 40 binaural --out outfile.wav --config sources.toml
+//
+// or, with the listener block mounted a quarter turn to the left:
+// cargo run --bin cybergrape -- --update 40 --listener-yaw 90 binaural ...
+//
+// or, with an IMU on the listener's head:
+// cargo run --bin cybergrape -- --update 40 --head-tracker /dev/ttyUSB1 binaural ...
+//
+// or, to skip the silence at the start and end of each file, keeping the
+// files in time with each other:
+// cargo run --bin cybergrape -- --update 40 binaural ... --trim-silence -50 --trim-together
+//
+// or, to just sum the files into a mono file to check their levels:
+// cargo run --bin cybergrape -- --update 40 binaural ... --mono
+//
+// or, to play files along positions recorded by a serial run, without any
+// hardware:
+// cargo run --bin cybergrape -- --update 40 render --grape positions.grape
+//                            --out     outfile.wav
+//                            --files   x.wav y.wav
+//
+// or, to replay a log recorded with --raw-log:
+// cargo run --bin cybergrape -- --update 40 --replay serial.log binaural ...
+
+/// Everything needed to binauralize a set of audio files, once we know
+/// where the sources are.
+struct AudioSettings {
+    sound_data: Vec<Vec<f32>>,
+    gains: Vec<f32>,
+    ranges: Vec<f32>,
+    tag_ids: Vec<usize>,
+    fixed_angles: Option<Vec<(f32, f32)>>,
+    ambient: Vec<Vec<f32>>,
+    ambient_gain: f32,
+    sample_rate: usize,
+    interpolate: bool,
+    play: bool,
+    live: bool,
+    mono: bool,
+    binauraliser: BinauraliserKind,
+    /// How long to wait for the first position from the hardware
+    timeout: Option<Duration>,
+    /// Whether to glide sources across positions the hardware dropped
+    fill_gaps: bool,
+    /// How many seconds to cut from the start of the output
+    trim_start: f32,
+}
+
+impl TryFrom<BinauralCommand> for AudioSettings {
+    type Error = DecodeError;
+
+    fn try_from(binaural_command: BinauralCommand) -> Result<Self, DecodeError> {
+        let binaural_command = binaural_command.with_default_settings();
+        let mut sound_data = decode_all(&binaural_command.filenames, SAMP_RATE as u32)?;
+
+        for (filename, samples) in binaural_command.filenames.iter().zip(&sound_data) {
+            let ratio = clip_ratio(samples);
+            if ratio > CLIP_WARNING_RATIO {
+                warn!(
+                    "{filename} is already clipped ({:.1}% of samples at full scale)",
+                    ratio * 100.0
+                );
+            }
+        }
+
+        if let Some(threshold_db) = binaural_command.trim_silence {
+            let lengths = sound_data.iter().map(Vec::len).collect::<Vec<_>>();
+            trim_silence(
+                &mut sound_data,
+                threshold_db,
+                binaural_command.trim_together,
+            );
+            for ((filename, samples), length) in binaural_command
+                .filenames
+                .iter()
+                .zip(&sound_data)
+                .zip(lengths)
+            {
+                info!(
+                    "trimmed {} samples of silence from {filename}",
+                    length - samples.len()
+                );
+            }
+        }
+
+        // Sources are numbered from 1 on the command line
+        let to_index = |sources: &[usize]| sources.iter().map(|s| s - 1).collect::<Vec<_>>();
+        pipeline::mute_and_solo(
+            &mut sound_data,
+            &to_index(&binaural_command.mute),
+            &to_index(&binaural_command.solo),
+        );
+
+        Ok(Self {
+            fixed_angles: binaural_command.fixed_angles(),
+            sound_data,
+            ambient: decode_all(&binaural_command.ambient, SAMP_RATE as u32)?,
+            ambient_gain: binaural_command.ambient_gain,
+            gains: binaural_command.gains.unwrap_or_default(),
+            ranges: binaural_command.ranges.unwrap_or_default(),
+            tag_ids: binaural_command.tag_ids,
+            sample_rate: SAMP_RATE,
+            interpolate: binaural_command.interpolate,
+            play: binaural_command.play,
+            live: binaural_command.live,
+            mono: binaural_command.mono,
+            timeout: (binaural_command.timeout > 0)
+                .then(|| Duration::from_secs(binaural_command.timeout)),
+            fill_gaps: binaural_command.fill_gaps,
+            trim_start: binaural_command.trim_start,
+            // Picked on the command line before the subcommand
+            binauraliser: BinauraliserKind::default(),
+        })
+    }
+}
+
+/// Where the sources are while binauralizing.
+enum Positions {
+    /// Follow the audio blocks, as reported by a hardware data manager, from
+    /// the listener's point of view
+    Tracked {
+        hdm: SharedHdm,
+        orientation: Orientation,
+    },
+    /// Hold each source at an azimuth and elevation, in degrees
+    Fixed(Vec<(f32, f32)>),
+    /// Follow positions that were already recorded, one time slice per update
+    Recorded(Vec<Vec<BufferMetadata>>),
+}
+
+/// Which way the listener is facing, relative to the listener block.
+struct Orientation {
+    /// How far the listener block is turned to the left, in radians
+    listener_yaw: f32,
+    /// The listener's head, if it's being tracked
+    head_yaw: Option<HeadYaw>,
+}
+
+impl Orientation {
+    /// Has `sphericalizer` report positions from the listener's point of view.
+    fn apply(&self, sphericalizer: Sphericalizer) -> Sphericalizer {
+        let sphericalizer = sphericalizer.with_listener_yaw(self.listener_yaw);
+        match &self.head_yaw {
+            Some(head_yaw) => sphericalizer.with_head_tracker(head_yaw.clone()),
+            None => sphericalizer,
+        }
+    }
+}
+
+/// The state of a capture over serial, which the user can watch, and tweak
+/// the gains of, while it runs.
+struct LiveCapture {
+    buf: TDBufMeta,
+    checkpoint: Checkpoint,
+    acc: UpdateAccumulator<dyn HardwareDataManager + Send>,
+    sphericalizer: Sphericalizer,
+    gain_control: GainControl,
+    latest: Vec<BufferMetadata>,
+}
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::init();
@@ -46,19 +245,67 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // logic to parse commandline arguments for serial vs binaural
     let update_rate = args.update_rate;
+    let binauraliser = args.binauraliser;
+    let orientation = Orientation {
+        listener_yaw: args.listener_yaw.to_radians(),
+        head_yaw: args.head_tracker.map(open_head_tracker).transpose()?,
+    };
 
-    let cmd = args.command;
+    // Outputs without an `--out` are named after when the run started
+    let started = SystemTime::now();
+    if let Some(outdir) = &args.outdir {
+        fs::create_dir_all(outdir)?;
+    }
+    let outdir = args.outdir.as_deref();
+
+    let cmd = match args.command {
+        Binaural(binaural_command) => {
+            let Some(binaural_command) = run_setup(binaural_command.load_config()?)? else {
+                return Ok(());
+            };
+            binaural_command.validate()?;
+            Binaural(binaural_command)
+        }
+        Simulate(mut simulate_command) => {
+            let Some(binaural_command) = run_setup(simulate_command.binaural.load_config()?)?
+            else {
+                return Ok(());
+            };
+            simulate_command.binaural = binaural_command;
+            simulate_command.binaural.validate()?;
+            Simulate(simulate_command)
+        }
+        Serial(serial_command) => {
+            serial_command.validate()?;
+            Serial(serial_command)
+        }
+        // Recorded positions don't need any hardware at all
+        Render(render_command) => {
+            let outfile = output_path(outdir, render_command.outfile.as_deref(), "wav", started);
+            check_overwrite(&outfile, args.force)?;
+            return render(render_command, binauraliser, outfile);
+        }
+        Convert(convert_command) => {
+            check_overwrite(convert_command.outfile.as_ref(), args.force)?;
+            return convert(convert_command);
+        }
+        Validate(validate_command) => return validate(validate_command),
+    };
 
-    let (num_tags, outfile, audio_settings) = match cmd {
+    // Whether a serial run carries on from an earlier one, and how often it
+    // saves what it has so far
+    let serial_settings = match &cmd {
+        Serial(serial_command) => Some((serial_command.resume, serial_command.checkpoint)),
+        _ => None,
+    };
+    let resume = serial_settings.is_some_and(|(resume, _)| resume);
+
+    let (num_tags, outfile, mut audio_settings, simulation) = match cmd {
         Binaural(binaural_command) => (
             binaural_command.num_files,
-            binaural_command.outfile,
-            Some((
-                hound_reader(binaural_command.filenames),
-                binaural_command.gains,
-                binaural_command.ranges,
-                binaural_command.samp_rate,
-            )),
+            binaural_command.outfile.clone(),
+            Some(AudioSettings::try_from(binaural_command)?),
+            None,
         ),
         Serial(serial_command) => (
             serial_command.num_tags,
@@ -66,9 +313,176 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             serial_command.outfile,
             // the serial command doesn't have any audio samples and doesn't need gain/range info
             None,
+            None,
         ),
+        Simulate(simulate_command) => (
+            simulate_command.binaural.num_files,
+            simulate_command.binaural.outfile.clone(),
+            Some(AudioSettings::try_from(simulate_command.binaural.clone())?),
+            Some(simulate_command),
+        ),
+        Render(_) | Convert(_) | Validate(_) => {
+            unreachable!("render, convert, and validate runs are finished above")
+        }
+    };
+    if let Some(audio_settings) = audio_settings.as_mut() {
+        audio_settings.binauraliser = binauraliser;
+    }
+    let extension = if audio_settings.is_some() {
+        "wav"
+    } else {
+        "grape"
     };
+    let outfile = output_path(outdir, outfile.as_deref(), extension, started);
+    // Resuming carries on at the end of the file, rather than overwriting it
+    check_overwrite(&outfile, args.force || resume)?;
+
+    // Find out that the capture can't be resumed before opening the hardware
+    let checkpoint = match serial_settings {
+        Some((resume, interval)) => {
+            let checkpoint = if resume {
+                let checkpoint = Checkpoint::resume(&outfile, num_tags, update_rate as u64)?;
+                info!(
+                    "carrying on after the {} time slices already in {}",
+                    checkpoint.resumed_len(),
+                    outfile.display()
+                );
+                checkpoint
+            } else {
+                Checkpoint::new(&outfile, num_tags, update_rate as u64)
+            };
+            Some(match interval {
+                0 => checkpoint,
+                seconds => checkpoint.every(Duration::from_secs(seconds)),
+            })
+        }
+        None => None,
+    };
+
+    // Nor does summing to mono, which doesn't place the sources anywhere
+    if audio_settings.as_ref().is_some_and(|a| a.mono) {
+        let audio_settings = audio_settings.expect("only binaural runs are summed to mono");
+        return mono_mixdown(audio_settings, outfile);
+    }
+
+    // Sources held at fixed angles don't need any hardware at all
+    if let Some(fixed_angles) = audio_settings.as_mut().and_then(|a| a.fixed_angles.take()) {
+        let audio_settings = audio_settings.expect("only binaural runs have fixed angles");
+        return binauralize(
+            Positions::Fixed(fixed_angles),
+            audio_settings,
+            num_tags,
+            update_rate,
+            outfile,
+        );
+    }
+
+    // Figure out where our updates are coming from. We hold on to the
+    // DummyHdm, if there is one, so that we can stop it when we're done.
+    let (hdm, dummy_hdm): (SharedHdm, _) = if let Some(simulate_command) = simulation {
+        let dummy_hdm = Arc::new(Mutex::new(
+            DummyHdm::builder()
+                .num_points(num_tags)
+                .range(simulate_command.distance)
+                .noise(simulate_command.noise)
+                .rotation(simulate_command.rotation)
+                .delay(simulate_command.delay)
+                .antennas(BACK_ANTENNA, FRONT_ANTENNA)
+                .build(),
+        ));
+        (dummy_hdm.clone(), Some(dummy_hdm))
+    } else if let Some(path) = args.replay {
+        (
+            Arc::new(Mutex::new(FileHdm::from_path(path)?.paced())),
+            None,
+        )
+    } else {
+        match open_serial(args.raw_log)? {
+            Some(hdm) => (hdm, None),
+            None => return Ok(()),
+        }
+    };
+
+    if let Some(audio_settings) = audio_settings {
+        if audio_settings.live {
+            binauralize_live(hdm, audio_settings, orientation, update_rate, outfile)?;
+        } else {
+            binauralize(
+                Positions::Tracked { hdm, orientation },
+                audio_settings,
+                num_tags,
+                update_rate,
+                outfile,
+            )?;
+        }
+    } else {
+        let th_hdm = hdm.clone();
+        let time_delta = Duration::from_secs(1).div_f64(update_rate as f64);
 
+        let capture = LiveCapture {
+            buf: TDBufMeta::new(num_tags),
+            checkpoint: checkpoint.expect("serial runs always have a checkpoint"),
+            acc: UpdateAccumulator::new(th_hdm),
+            sphericalizer: orientation.apply(Sphericalizer::new(vec![(1.0, 1.0); num_tags])),
+            gain_control: GainControl::new(),
+            latest: vec![],
+        };
+
+        let mut capture = gui::fold_until_stop_with_meter(
+            capture,
+            move |mut capture| {
+                if let Some(update) = capture.sphericalizer.query(&mut capture.acc) {
+                    capture.latest.clone_from(&update);
+                    capture.buf.add(update)
+                }
+                if let Err(e) = capture.checkpoint.save_if_due(&capture.buf) {
+                    warn!("couldn't save a checkpoint of the capture: {e}");
+                }
+                sleep(time_delta);
+                capture
+            },
+            |capture| capture.latest.clone(),
+            |capture, key| {
+                capture
+                    .gain_control
+                    .handle_key(key, &mut capture.sphericalizer)
+            },
+        )?;
+
+        capture.checkpoint.save(&capture.buf)?;
+    }
+
+    if let Some(dummy_hdm) = dummy_hdm {
+        dummy_hdm.lock().unwrap().stop();
+    }
+
+    Ok(())
+}
+
+/// If `--setup` was given, asks the user for the sources in the setup form,
+/// and fills them in. Returns `None` if the user quit the form.
+fn run_setup(
+    binaural_command: BinauralCommand,
+) -> Result<Option<BinauralCommand>, Box<dyn std::error::Error>> {
+    if !binaural_command.setup {
+        return Ok(Some(binaural_command));
+    }
+
+    match gui::setup_form(".")? {
+        Some(config) => Ok(Some(BinauralCommand {
+            setup: false,
+            ..config.apply_to(binaural_command)
+        })),
+        None => {
+            error!("Setup cancelled");
+            Ok(None)
+        }
+    }
+}
+
+/// Asks the user which serial port the listener block is on, then starts
+/// listening to it. Returns `None` if the user didn't pick a port.
+fn open_serial(raw_log: Option<String>) -> Result<Option<SharedHdm>, Box<dyn std::error::Error>> {
     // Figure out what serial port our antena box is on
     let available_ports = SerialPort::available_ports()?;
     let selected_port_opt = gui::device_selector(available_ports)?;
@@ -76,7 +490,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         Some(port) => port,
         None => {
             error!("Port selection failed");
-            return Ok(());
+            return Ok(None);
         }
     };
 
@@ -86,159 +500,368 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     port.set_read_timeout(std::time::Duration::MAX)
         .expect("Failed to set read timeout");
 
-    let hdm = Arc::new(Mutex::new(Hdm::new()));
-
-    listen_on_port(port, hdm.clone());
-
-    if let Some((mut sound_data, gains, ranges, sample_rate)) = audio_settings {
-        let sphericalizer = Sphericalizer::new(gains.into_iter().zip(ranges).collect());
-
-        let total_samples = sound_data
-            .iter()
-            .map(|v| v.len())
-            .max()
-            .expect("should have some files");
-        let seconds = total_samples.div_ceil(sample_rate);
+    let raw_log: Box<dyn Write + Send> = match raw_log {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::sink()),
+    };
 
-        let num_updates_needed = seconds * update_rate;
-        let samples_per_update = sample_rate / update_rate;
-        let new_samples_per_update = samples_per_update.div_ceil(FRAME_SIZE) * FRAME_SIZE;
-        let total_new_samples = new_samples_per_update * num_updates_needed;
+    let hdm = Arc::new(Mutex::new(Hdm::new()));
 
-        for samples in sound_data.iter_mut() {
-            samples.resize(total_new_samples, 0.0);
-        }
+    listen_on_port(port, hdm.clone(), raw_log);
 
-        let mut td_buf = TDBufMeta::new(num_tags);
-        let time_delta = Duration::from_secs(1).div_f64(update_rate as f64);
+    Ok(Some(hdm))
+}
 
-        info!("DERIVED SETTINGS FOR THIS RUN:");
-        info!("total_samples: {:#?}", total_samples);
-        info!("seconds: {:#?}", seconds);
-        info!("num_updates_needed: {:#?}", num_updates_needed);
-        info!("samples_per_update: {:#?}", samples_per_update);
-        info!("new_samples_per_update: {:#?}", new_samples_per_update);
-        info!("total_new_samples: {:#?}", total_new_samples);
-        info!("time_delta: {:#?}", time_delta);
+/// Opens the serial port that the head tracker is on, and starts keeping
+/// track of the listener's head.
+fn open_head_tracker(path: String) -> Result<HeadYaw, Box<dyn std::error::Error>> {
+    let mut port = SerialPort::open(path, BAUD_RATE)?;
+    port.set_read_timeout(Duration::MAX)?;
 
-        info!("gathering data");
+    let head_yaw = HeadYaw::default();
+    listen_for_head_yaw(port, head_yaw.clone());
 
-        let mut accumulator = UpdateAccumulator::new(hdm.clone());
+    Ok(head_yaw)
+}
 
-        // wait for the accumulator to load with some data
-        sleep(Duration::from_secs_f32(0.1));
+/// Rewrites the GrapeFile of `convert_command` in the current version of the
+/// format.
+fn convert(convert_command: ConvertCommand) -> Result<(), Box<dyn std::error::Error>> {
+    let grape_file = GrapeFile::from_path(&convert_command.grape)?;
+    info!(
+        "Converting {} from version {} to {}",
+        convert_command.grape,
+        grape_file.version(),
+        FORMAT_VERSION
+    );
+    grape_file.upgrade().to_path(&convert_command.outfile)?;
+    Ok(())
+}
 
-        for _ in 0..num_updates_needed {
-            if let Some(update) = sphericalizer.query(&mut accumulator) {
-                td_buf.add(update)
-            } else {
-                warn!("we lost an update from the sphericalizer");
+/// Checks the GrapeFile of `validate_command`, printing every problem with
+/// it, and fails if there were any.
+fn validate(validate_command: ValidateCommand) -> Result<(), Box<dyn std::error::Error>> {
+    match GrapeFile::validate_path(&validate_command.grape) {
+        Ok(()) => {
+            println!("{}: ok", validate_command.grape);
+            Ok(())
+        }
+        Err(problems) => {
+            for problem in &problems {
+                println!("{}: {}", validate_command.grape, problem);
             }
-            sleep(time_delta);
+            Err(format!(
+                "found {} problem(s) with {}",
+                problems.len(),
+                validate_command.grape
+            )
+            .into())
         }
-        let spatial_data = td_buf.dump();
-        let mut binauraliser = BinauraliserNF::new();
+    }
+}
+
+/// Binauralizes the audio files of `render_command` along the positions in
+/// its GrapeFile, at the GrapeFile's own sample rate, and writes it to
+/// `outfile`.
+fn render(
+    render_command: RenderCommand,
+    binauraliser: BinauraliserKind,
+    outfile: PathBuf,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let grape_file = GrapeFile::from_path(&render_command.grape)?;
+    let num_tags = grape_file.num_tag_groups();
+    render_command.validate(num_tags)?;
+
+    let spatial_data = grape_file_to_metadata(&grape_file);
+    if spatial_data.is_empty() {
+        return Err(format!("{} has no positions in it", render_command.grape).into());
+    }
+    let (update_rate, _) = grape_file.streams_native_sample_rate();
+
+    let audio_settings = AudioSettings {
+        sound_data: decode_all(&render_command.filenames, SAMP_RATE as u32)?,
+        gains: render_command.gains.unwrap_or_else(|| vec![1.0; num_tags]),
+        ranges: vec![1.0; num_tags],
+        tag_ids: vec![],
+        fixed_angles: None,
+        ambient: vec![],
+        ambient_gain: 1.0,
+        sample_rate: SAMP_RATE,
+        interpolate: render_command.interpolate,
+        play: false,
+        live: false,
+        mono: false,
+        binauraliser,
+        timeout: None,
+        fill_gaps: false,
+        trim_start: render_command.trim_start,
+    };
 
-        let mut out_left = Vec::with_capacity(total_new_samples);
-        let mut out_right = Vec::with_capacity(total_new_samples);
+    binauralize(
+        Positions::Recorded(spatial_data),
+        audio_settings,
+        num_tags,
+        update_rate as usize,
+        outfile,
+    )
+}
 
-        info!("post processing");
+/// Records the positions of the tags for as long as the audio lasts (unless
+/// they're held still, or were recorded already), then binauralizes the
+/// audio and writes it to `outfile`.
+fn binauralize(
+    positions: Positions,
+    audio_settings: AudioSettings,
+    num_tags: usize,
+    update_rate: usize,
+    outfile: PathBuf,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let AudioSettings {
+        mut sound_data,
+        gains,
+        ranges,
+        tag_ids,
+        ambient,
+        ambient_gain,
+        sample_rate,
+        interpolate,
+        play,
+        binauraliser,
+        timeout,
+        fill_gaps,
+        trim_start,
+        ..
+    } = audio_settings;
+
+    let tag_settings: Vec<_> = gains.into_iter().zip(ranges).collect();
+
+    let total_samples = sound_data
+        .iter()
+        .map(|v| v.len())
+        .max()
+        .expect("should have some files");
+    let seconds = total_samples.div_ceil(sample_rate);
+
+    let num_updates_needed = seconds * update_rate;
+    // The audio is rendered a frame at a time, each at the position the
+    // sources had when it starts, which needn't line up with the updates
+    let num_frames = total_samples.div_ceil(FRAME_SIZE);
+
+    for samples in sound_data.iter_mut() {
+        samples.resize(num_frames * FRAME_SIZE, 0.0);
+    }
 
-        for (i, metadata) in spatial_data.into_iter().enumerate() {
-            let sound_start = i * new_samples_per_update;
-            let sound_stop = (i + 1) * new_samples_per_update;
-            let sound_slices = sound_data
-                .iter()
-                .map(|v| &v[sound_start..sound_stop])
-                .collect::<Vec<_>>();
+    let time_delta = Duration::from_secs(1).div_f64(update_rate as f64);
 
-            assert_eq!(metadata.len(), sound_slices.len());
+    info!("DERIVED SETTINGS FOR THIS RUN:");
+    info!("total_samples: {:#?}", total_samples);
+    info!("seconds: {:#?}", seconds);
+    info!("num_updates_needed: {:#?}", num_updates_needed);
+    info!("num_frames: {:#?}", num_frames);
+    info!("time_delta: {:#?}", time_delta);
 
-            let tagged_buffers = metadata.into_iter().zip(sound_slices).collect::<Vec<_>>();
+    let (spatial_data, times) = match positions {
+        Positions::Tracked { hdm, orientation } => {
+            info!("gathering data");
 
-            let (mut new_left, mut new_right) = binauraliser.process(&tagged_buffers[..]);
+            let mut sphericalizer = orientation.apply(Sphericalizer::new(tag_settings));
+            if !tag_ids.is_empty() {
+                sphericalizer = sphericalizer.with_tag_ids(tag_ids);
+            }
 
-            out_left.append(&mut new_left);
-            out_right.append(&mut new_right);
+            let mut accumulator = UpdateAccumulator::new(hdm);
+
+            // wait for the accumulator to load with some data
+            sleep(Duration::from_secs_f32(0.1));
+
+            let (spatial_data, timestamps) = pipeline::capture_timed(
+                &mut accumulator,
+                &sphericalizer,
+                num_tags,
+                num_updates_needed,
+                time_delta,
+                timeout,
+                fill_gaps,
+                pipeline::stderr_progress("gathering data"),
+            )?;
+            // Sleeping is never quite exact, so go by when each query was
+            // actually made
+            let times = timestamps.iter().map(Duration::as_secs_f64).collect();
+            (spatial_data, times)
         }
+        Positions::Fixed(angles) => (pipeline::hold_still(&angles, &tag_settings, 1), vec![0.0]),
+        Positions::Recorded(mut spatial_data) => {
+            for metadata in spatial_data.iter_mut() {
+                for (tag, &(gain, _range)) in metadata.iter_mut().zip(&tag_settings) {
+                    tag.gain *= gain;
+                }
+            }
+            // The last position is held if the audio outlasts the recording
+            let times = pipeline::update_times(spatial_data.len(), update_rate);
+            (spatial_data, times)
+        }
+    };
+    let spatial_data =
+        pipeline::positions_per_frame(&spatial_data, &times, sample_rate, num_frames, interpolate);
+
+    info!("post processing");
+
+    // Find out here, rather than on a worker thread, if SAF can't be set up
+    binauraliser.build()?;
+    let (mut out_left, mut out_right) = pipeline::render_parallel(
+        || {
+            binauraliser
+                .build()
+                .expect("SAF set up a binauraliser a moment ago")
+        },
+        &spatial_data,
+        &sound_data,
+        FRAME_SIZE,
+        // Already placed between the updates, frame by frame
+        false,
+        pipeline::stderr_progress("post processing"),
+    );
+
+    out_left.truncate(total_samples);
+    out_right.truncate(total_samples);
+
+    pipeline::mix_ambient(&mut out_left, &mut out_right, &ambient, 0, ambient_gain);
+
+    let (out_left, out_right) =
+        pipeline::trim_start(&out_left, &out_right, trim_start, sample_rate);
+    if trim_start > 0.0 {
+        info!("trimmed {} seconds from the start", trim_start);
+    }
 
-        out_left.truncate(total_samples);
-        out_right.truncate(total_samples);
+    if play {
+        info!("playing the output");
+        #[cfg(feature = "playback")]
+        cybergrape::playback::play(&out_left, &out_right, sample_rate as u32)?;
+        #[cfg(not(feature = "playback"))]
+        error!("can't play the output, CyberGrape was built without the `playback` feature");
+    }
 
-        info!("writing the output file");
+    info!("writing the output file");
 
-        hound_writer(out_left, out_right, outfile);
-    } else {
-        let th_hdm = hdm.clone();
-        let sphericalizer = Sphericalizer::new(vec![(1.0, 1.0); num_tags]);
+    hound_writer(out_left.to_vec(), out_right.to_vec(), outfile);
 
-        let td_buf = TDBufMeta::new(num_tags);
-        let time_delta = Duration::from_secs(1).div_f64(update_rate as f64);
+    Ok(())
+}
 
-        let accumulator = UpdateAccumulator::new(th_hdm);
+/// Sums the audio, and the ambient beds, into one channel without
+/// binauralizing it, and writes it to `outfile`.
+fn mono_mixdown(
+    audio_settings: AudioSettings,
+    outfile: PathBuf,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let AudioSettings {
+        sound_data,
+        gains,
+        ambient,
+        ambient_gain,
+        sample_rate,
+        play,
+        ..
+    } = audio_settings;
+
+    info!("summing to mono");
+
+    let mut mono = pipeline::mono_sum(&sound_data, &gains);
+    let beds = pipeline::mono_sum(&ambient, &vec![ambient_gain; ambient.len()]);
+    for (sample, bed) in mono.iter_mut().zip(beds) {
+        *sample += bed;
+    }
 
-        let (buf, _) = gui::fold_until_stop((td_buf, accumulator), move |(mut buf, mut acc)| {
-            if let Some(update) = sphericalizer.query(&mut acc) {
-                buf.add(update)
-            }
-            sleep(time_delta);
-            (buf, acc)
-        })?;
+    if play {
+        info!("playing the output");
+        #[cfg(feature = "playback")]
+        cybergrape::playback::play(&mono, &mono, sample_rate as u32)?;
+        #[cfg(not(feature = "playback"))]
+        error!("can't play the output, CyberGrape was built without the `playback` feature");
+    }
 
-        let data = buf.dump();
+    info!("writing the output file");
 
-        let grape_file_builder = GrapeFile::builder().set_samplerate(update_rate as u64);
+    hound_writer_mono(mono, sample_rate as u32, outfile);
 
-        let grape_file = data
-            .transpose()
-            .fold(grape_file_builder, |b, v| {
-                let azms: Vec<f32> = v.iter().map(|e| e.azimuth).collect();
-                let elvs: Vec<f32> = v.iter().map(|e| e.elevation).collect();
-                b.add_stream(&azms, GrapeTag::Azimuth)
-                    .add_stream(&elvs, GrapeTag::Elevation)
-            })
-            .build()?;
+    Ok(())
+}
 
-        grape_file.to_path(outfile)?;
+/// Binauralizes the audio block by block as the positions of the tags come
+/// in, appending each block to `outfile` as soon as it's rendered.
+fn binauralize_live(
+    hdm: SharedHdm,
+    audio_settings: AudioSettings,
+    orientation: Orientation,
+    update_rate: usize,
+    outfile: PathBuf,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let AudioSettings {
+        sound_data,
+        gains,
+        ranges,
+        tag_ids,
+        ambient,
+        ambient_gain,
+        sample_rate,
+        binauraliser,
+        timeout,
+        ..
+    } = audio_settings;
+
+    let samples_per_update = (sample_rate / update_rate).div_ceil(FRAME_SIZE) * FRAME_SIZE;
+    // Blocks are rounded up to whole frames, so wait as long as they last
+    let time_delta = Duration::from_secs_f64(samples_per_update as f64 / sample_rate as f64);
+
+    info!("samples_per_update: {:#?}", samples_per_update);
+    info!("time_delta: {:#?}", time_delta);
+
+    let mut sphericalizer =
+        orientation.apply(Sphericalizer::new(gains.into_iter().zip(ranges).collect()));
+    if !tag_ids.is_empty() {
+        sphericalizer = sphericalizer.with_tag_ids(tag_ids);
     }
 
-    Ok(())
-}
+    let mut accumulator = UpdateAccumulator::new(hdm);
+
+    // HoundWriter writes the samples as they are, so they have to be floats
+    // between -1 and 1
+    let mut writer = HoundWriter::new(
+        outfile,
+        WavSpec {
+            channels: 2,
+            sample_rate: sample_rate as u32,
+            bits_per_sample: 32,
+            sample_format: SampleFormat::Float,
+        },
+    );
+    let to_float = |samples: Vec<f32>| samples.into_iter().map(|s| s / 32768.0).collect();
+
+    info!("binauralizing live");
+
+    let mut binauraliser = binauraliser.build()?;
+    let mut mixed = 0;
+    pipeline::render_live(
+        &mut accumulator,
+        &sphericalizer,
+        &mut binauraliser,
+        &sound_data,
+        samples_per_update,
+        time_delta,
+        timeout,
+        |mut left, mut right| {
+            pipeline::mix_ambient(&mut left, &mut right, &ambient, mixed, ambient_gain);
+            mixed += left.len();
+            writer
+                .convert((to_float(left), to_float(right)))
+                .map_err(Box::<dyn std::error::Error>::from)
+        },
+        pipeline::stderr_progress("binauralizing"),
+    )?;
+
+    writer
+        .finalize()
+        .map_err(|ComponentError::HoundError(e)| e)?;
 
-fn listen_on_port(port: SerialPort, hdm: Arc<Mutex<Hdm>>) {
-    let _hdm_thread = spawn(move || {
-        // Read from the port and print the received data
-        let mut buffer = [0; 256];
-        let mut read_buf = Vec::new();
-
-        loop {
-            let read_len = port.read(&mut buffer).expect("Device disconnected");
-
-            for &c in buffer.iter().take(read_len) {
-                read_buf.push(c);
-                if c == b'\n' {
-                    match str::from_utf8(&read_buf) {
-                        Ok(s) => match HardwareEvent::from_str(s) {
-                            Ok(HardwareEvent::UUDFEvent(e)) => {
-                                debug!("Received {:#?}, adding to HDM", e);
-                                hdm.lock().unwrap().add_update(e);
-                            }
-                            Ok(HardwareEvent::UUDFPEvent(ep)) => {
-                                debug!("Received {:#?}", ep);
-                            }
-                            Err(e) => {
-                                warn!("Was unable to parse hardware message: {}", e);
-                            }
-                        },
-                        // Often happens at the beginning of transmission when
-                        // there is still garbage in the hardware buffer
-                        Err(e) => {
-                            warn!("Failed to decode utf-8: {:?}", e);
-                        }
-                    }
-                    read_buf.clear();
-                }
-            }
-        }
-    });
+    Ok(())
 }
@@ -2,20 +2,26 @@
 //! show how good/bad our localization algorithm is.
 
 use crossterm::{
+    cursor::Show,
     event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
+use cybergrape::gui::TerminalGuard;
 use cybergrape::localizer::Point;
 use ratatui::{
     backend::{Backend, CrosstermBackend},
-    style::{Color, Style},
+    style::Color,
     symbols,
-    widgets::{Axis, Block, Chart, Dataset, GraphType},
+    widgets::{
+        canvas::{Canvas, Line as CanvasLine, Points},
+        Block,
+    },
     Frame, Terminal,
 };
 use std::{
     error::Error,
+    f64::consts::TAU,
     io,
     time::{Duration, Instant},
 };
@@ -34,7 +40,21 @@ use std::{
 // contains anything that implements the FunMut() -> Vec<Point> trait. It needs to
 // be in a Box because the function itself could be of a variable size, so it must
 // be allocated on the heap, hence the Box.
-type PointGenerator = Box<dyn FnMut() -> Vec<Point>>;
+pub type PointGenerator = Box<dyn FnMut() -> Vec<Point>>;
+
+/// Like a [`PointGenerator`], but each point comes with the signal strength,
+/// in dBm, of the measurements it was localized from, if we know it.
+pub type RatedPointGenerator = Box<dyn FnMut() -> Vec<(Point, Option<i32>)>>;
+
+/// Signals this weak or weaker are drawn as the least confident
+const RSSI_WEAK: i32 = -90;
+
+/// Signals this strong or stronger are drawn as the most confident
+const RSSI_STRONG: i32 = -40;
+
+/// How often the GUI asks for new points, so also how often a recording of
+/// them has a sample.
+pub const TICK_RATE: Duration = Duration::from_millis(250);
 
 /// This struct contains function pointers that generate original/debug points
 /// and the new/calculated points that come out of the localization algorithm.
@@ -43,18 +63,24 @@ type PointGenerator = Box<dyn FnMut() -> Vec<Point>>;
 /// want to run the localization algorithm on every re-draw.
 struct App {
     orig_points_generator: PointGenerator,
-    new_points_generator: PointGenerator,
+    new_points_generator: RatedPointGenerator,
     orig_points: Vec<(f64, f64)>,
     new_points: Vec<(f64, f64)>,
+    /// The signal strength behind each of the `new_points`
+    confidence: Vec<Option<i32>>,
 }
 
 impl App {
-    fn new(orig_points_generator: PointGenerator, new_points_generator: PointGenerator) -> App {
+    fn new(
+        orig_points_generator: PointGenerator,
+        new_points_generator: RatedPointGenerator,
+    ) -> App {
         App {
             orig_points_generator,
             new_points_generator,
             orig_points: vec![],
             new_points: vec![],
+            confidence: vec![],
         }
     }
 
@@ -65,37 +91,40 @@ impl App {
             .iter()
             .map(|&Point { x, y }| (x, y))
             .collect();
-        self.new_points = (self.new_points_generator)()
+        (self.new_points, self.confidence) = (self.new_points_generator)()
             .iter()
-            .map(|&Point { x, y }| (x, y))
-            .collect();
+            .map(|&(Point { x, y }, rssi)| ((x, y), rssi))
+            .unzip();
     }
 }
 
 pub fn engage_gui(
     orig_points_generator: PointGenerator,
-    new_points_generator: PointGenerator,
+    new_points_generator: RatedPointGenerator,
 ) -> Result<(), Box<dyn Error>> {
-    // setup terminal
+    // setup terminal. The guard restores it once we're done, even if the
+    // app panics, so that the user isn't left with a garbled terminal
     enable_raw_mode()?;
+    let guard = TerminalGuard::new(|| {
+        let _ = disable_raw_mode();
+        let _ = execute!(
+            io::stdout(),
+            LeaveAlternateScreen,
+            DisableMouseCapture,
+            Show
+        );
+    });
     let mut stdout = io::stdout();
     execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
     // create app and run it
-    let tick_rate = Duration::from_millis(250);
     let app = App::new(orig_points_generator, new_points_generator);
-    let res = run_app(&mut terminal, app, tick_rate);
+    let res = run_app(&mut terminal, app, TICK_RATE);
 
     // restore terminal
-    disable_raw_mode()?;
-    execute!(
-        terminal.backend_mut(),
-        LeaveAlternateScreen,
-        DisableMouseCapture
-    )?;
-    terminal.show_cursor()?;
+    drop(guard);
 
     if let Err(err) = res {
         println!("{:?}", err)
@@ -135,41 +164,216 @@ fn run_app<B: Backend>(
     }
 }
 
+/// How many points each range ring is drawn with
+const RING_POINTS: usize = 180;
+
+/// How many spokes the polar grid has, evenly spaced around the origin
+const SPOKES: usize = 8;
+
 fn ui(f: &mut Frame, app: &mut App) {
     // Padding added to the bounds of the chart
     let padding = 2.0;
 
-    // Collect all the x and y values from orig_points and new_points
-    let all_x = app.orig_points.iter().map(|(x, _)| x);
-    let all_y = app.orig_points.iter().map(|(_, y)| y);
-
-    // Compute lower and upper bounds for the chart
-    let x_bounds = [
-        all_x.clone().fold(f64::INFINITY, |a, b| a.min(*b)) - padding,
-        all_x.clone().fold(f64::NEG_INFINITY, |a, b| a.max(*b)) + padding,
-    ];
-    let y_bounds = [
-        all_y.clone().fold(f64::INFINITY, |a, b| a.min(*b)) - padding,
-        all_y.clone().fold(f64::NEG_INFINITY, |a, b| a.max(*b)) + padding,
-    ];
-
-    let chart = Chart::new(vec![
-        Dataset::default()
-            .name("Original")
-            .marker(symbols::Marker::Dot)
-            .graph_type(GraphType::Scatter)
-            .style(Style::default().fg(Color::Cyan))
-            .data(&app.orig_points),
-        Dataset::default()
-            .name("Calculated")
-            .marker(symbols::Marker::Dot)
-            .graph_type(GraphType::Scatter)
-            .style(Style::default().fg(Color::Red))
-            .data(&app.new_points),
-    ])
-    .block(Block::default().title("Chart"))
-    .x_axis(Axis::default().bounds(x_bounds))
-    .y_axis(Axis::default().bounds(y_bounds));
-
-    f.render_widget(chart, f.size());
+    // The chart is centred on the listener at the origin, and reaches out
+    // far enough to show every point, whichever side of the listener it's on
+    let extent = app
+        .orig_points
+        .iter()
+        .chain(&app.new_points)
+        .map(|(x, y)| x.abs().max(y.abs()))
+        .fold(0.0, f64::max)
+        + padding;
+
+    // Rings at round distances, about four of them
+    let ring_step = (extent / 4.0).ceil().max(1.0);
+    let rings = (1..)
+        .map(|i| i as f64 * ring_step)
+        .take_while(|&r| r <= extent)
+        .map(|r| ring(r, RING_POINTS))
+        .collect::<Vec<_>>();
+
+    let canvas = Canvas::default()
+        .block(Block::default().title("Chart"))
+        .marker(symbols::Marker::Braille)
+        .x_bounds([-extent, extent])
+        .y_bounds([-extent, extent])
+        .paint(|ctx| {
+            for ring in &rings {
+                ctx.draw(&Points {
+                    coords: ring,
+                    color: Color::DarkGray,
+                });
+            }
+            for (x, y) in ring(extent, SPOKES) {
+                ctx.draw(&CanvasLine {
+                    x1: 0.0,
+                    y1: 0.0,
+                    x2: x,
+                    y2: y,
+                    color: Color::DarkGray,
+                });
+            }
+            // Draw the grid underneath the points
+            ctx.layer();
+            ctx.draw(&Points {
+                coords: &app.orig_points,
+                color: Color::Cyan,
+            });
+            for (point, &rssi) in app.new_points.iter().zip(&app.confidence) {
+                ctx.draw(&Points {
+                    coords: std::slice::from_ref(point),
+                    color: rssi_color(rssi),
+                });
+            }
+        });
+
+    f.render_widget(canvas, f.size());
+}
+
+/// `n` points evenly spaced around a circle of `radius` about the origin,
+/// starting straight ahead (along the x axis) and going round to the left.
+fn ring(radius: f64, n: usize) -> Vec<(f64, f64)> {
+    (0..n)
+        .map(|i| {
+            let angle = i as f64 * TAU / n as f64;
+            (radius * angle.cos(), radius * angle.sin())
+        })
+        .collect()
+}
+
+/// The colour to draw a point localized from measurements with signal
+/// strength `rssi`: red for weak signals through yellow to green for strong
+/// ones. Points we don't know the signal strength of are magenta.
+fn rssi_color(rssi: Option<i32>) -> Color {
+    let Some(rssi) = rssi else {
+        return Color::Magenta;
+    };
+    let confidence = (rssi - RSSI_WEAK) as f64 / (RSSI_STRONG - RSSI_WEAK) as f64;
+    let confidence = confidence.clamp(0.0, 1.0);
+
+    // Red fades out over the top half, green fades in over the bottom half
+    let red = (2.0 * (1.0 - confidence)).min(1.0) * 255.0;
+    let green = (2.0 * confidence).min(1.0) * 255.0;
+    Color::Rgb(red.round() as u8, green.round() as u8, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{point_generators, start_hdm, Recording};
+    use clap::Parser;
+    use cybergrape::args::MonitorArgs;
+    use cybergrape::dummy_hdm::DummyHdm;
+    use ratatui::backend::TestBackend;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn setup_draws_chart() {
+        let hdm = Arc::new(Mutex::new(
+            DummyHdm::builder().num_points(4).delay(0.001).build(),
+        ));
+        let (orig_points_generator, new_points_generator) = point_generators(&hdm, None);
+        let mut app = App::new(orig_points_generator, new_points_generator);
+
+        std::thread::sleep(Duration::from_millis(20));
+        app.on_tick();
+        hdm.lock().unwrap().stop();
+        // The four sources, plus the listener at the origin
+        assert_eq!(5, app.orig_points.len());
+
+        let mut terminal = Terminal::new(TestBackend::new(40, 20)).unwrap();
+        terminal.draw(|f| ui(f, &mut app)).unwrap();
+        let drawn = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect::<String>();
+        assert!(drawn.contains("Chart"));
+    }
+
+    #[test]
+    fn runs_without_noise() {
+        let args = MonitorArgs::try_parse_from([
+            "monitor", "--points", "3", "--noise", "0", "--delay", "0.001",
+        ])
+        .unwrap();
+        let hdm = Arc::new(Mutex::new(start_hdm(&args)));
+        let (orig_points_generator, new_points_generator) = point_generators(&hdm, None);
+        let mut app = App::new(orig_points_generator, new_points_generator);
+
+        // The updates keep coming, and are exact enough to localize
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while app.new_points.len() < 3 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(5));
+            app.on_tick();
+        }
+        hdm.lock().unwrap().stop();
+        assert_eq!(4, app.orig_points.len());
+        assert_eq!(3, app.new_points.len());
+    }
+
+    #[test]
+    fn records_points_by_tag() {
+        let hdm = Arc::new(Mutex::new(
+            DummyHdm::builder().num_points(3).delay(0.001).build(),
+        ));
+        let recording = Recording::default();
+        let (orig_points_generator, new_points_generator) =
+            point_generators(&hdm, Some(recording.clone()));
+        let mut app = App::new(orig_points_generator, new_points_generator);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while app.new_points.len() < 3 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(5));
+            app.on_tick();
+        }
+        hdm.lock().unwrap().stop();
+
+        // Every frame is recorded, with the tags always in the same order
+        let recording = recording.borrow();
+        let last = recording.last().unwrap();
+        assert_eq!(
+            vec![1, 2, 3],
+            last.iter().map(|&(id, _)| id).collect::<Vec<_>>()
+        );
+        for (&(_, point), &(x, y)) in last.iter().zip(&app.new_points) {
+            assert_eq!((point.x, point.y), (x, y));
+        }
+    }
+
+    #[test]
+    fn ring_is_round() {
+        let points = ring(2.5, 8);
+        assert_eq!(8, points.len());
+        for &(x, y) in &points {
+            assert!((x.hypot(y) - 2.5).abs() < 1e-9);
+        }
+        // Straight ahead, then a quarter of the way round to the left
+        assert!((points[0].0 - 2.5).abs() < 1e-9 && points[0].1.abs() < 1e-9);
+        assert!(points[2].0.abs() < 1e-9 && (points[2].1 - 2.5).abs() < 1e-9);
+        assert!(ring(1.0, 0).is_empty());
+    }
+
+    #[test]
+    fn rssi_gradient() {
+        assert_eq!(Color::Magenta, rssi_color(None));
+        assert_eq!(Color::Rgb(255, 0, 0), rssi_color(Some(-120)));
+        assert_eq!(Color::Rgb(255, 0, 0), rssi_color(Some(RSSI_WEAK)));
+        assert_eq!(Color::Rgb(255, 255, 0), rssi_color(Some(-65)));
+        assert_eq!(Color::Rgb(0, 255, 0), rssi_color(Some(RSSI_STRONG)));
+        assert_eq!(Color::Rgb(0, 255, 0), rssi_color(Some(-10)));
+
+        // Stronger signals are never drawn redder, or less green
+        let colors = (RSSI_WEAK..=RSSI_STRONG)
+            .map(|rssi| match rssi_color(Some(rssi)) {
+                Color::Rgb(r, g, 0) => (r, g),
+                other => panic!("{other:?} isn't on the gradient"),
+            })
+            .collect::<Vec<_>>();
+        assert!(colors
+            .windows(2)
+            .all(|w| w[1].0 <= w[0].0 && w[1].1 >= w[0].1));
+    }
 }
@@ -5,20 +5,29 @@
 
 mod gui;
 
+use std::cell::RefCell;
+use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 
+use clap::Parser;
+use cybergrape::args::MonitorArgs;
 use cybergrape::dummy_hdm::DummyHdm;
-use cybergrape::localizer::localize_points;
+use cybergrape::hardware_data_manager::Id;
+use cybergrape::localizer::{localize_points, Point};
+use cybergrape::spatial_data_format::points_to_grape_file;
 use cybergrape::update_accumulator::UpdateAccumulator;
-use gui::engage_gui;
+use gui::{engage_gui, PointGenerator, RatedPointGenerator, TICK_RATE};
+use log::error;
+
+/// Every batch of localized points that the GUI asks for, each paired with
+/// the tag it was localized for. The GUI and `main` both need to get at it,
+/// and it never leaves this thread, so an Rc<RefCell<T>> will do.
+type Recording = Rc<RefCell<Vec<Vec<(Id, Point)>>>>;
 
 fn main() {
-    // Configure, instantiate, and start the dummy HDM.
-    let hdm = DummyHdm::builder()
-        .num_points(10)
-        .range(5.0)
-        .noise(0.25)
-        .build();
+    env_logger::init();
+    let args = MonitorArgs::parse();
+    let hdm = start_hdm(&args);
 
     // We're going to need a few references active to this HDM at once, so we
     // wrap it in a RefCell to indicate that we want to enforce the borrow checking
@@ -32,6 +41,42 @@ fn main() {
     // we don't accidentially do something funky with the original thing.
     let hdm = hdm_rf.clone();
 
+    // If we're recording, the points are kept here as well as drawn
+    let recording = args.record.as_ref().map(|_| Recording::default());
+    let (orig_points_generator, new_points_generator) =
+        point_generators(&hdm_rf, recording.clone());
+    let _ = engage_gui(orig_points_generator, new_points_generator);
+
+    // Once the gui terminates, we take a mutable referene to the hdm and stop it.
+    // .borrow_mut() takes the Rc<RefCell<T>> and turns it into an &mut T.
+    hdm.lock().unwrap().stop();
+
+    if let (Some(path), Some(recording)) = (&args.record, recording) {
+        let sample_rate = (1.0 / TICK_RATE.as_secs_f64()).round() as u64;
+        let track = points_to_grape_file(&recording.borrow(), sample_rate);
+        if let Err(e) = track.to_path(path) {
+            error!("couldn't write the recording to {path}: {e}");
+        }
+    }
+}
+
+/// Configures, instantiates, and starts the dummy HDM.
+fn start_hdm(args: &MonitorArgs) -> DummyHdm {
+    DummyHdm::builder()
+        .num_points(args.num_points)
+        .range(args.range)
+        .noise(args.noise)
+        .delay(args.delay)
+        .build()
+}
+
+/// Builds the functions that the GUI calls to get the original points that the
+/// `hdm` is simulating, and the points that we localize from its updates. If
+/// there's a `recording`, the localized points are added to it too.
+fn point_generators(
+    hdm_rf: &Arc<Mutex<DummyHdm>>,
+    recording: Option<Recording>,
+) -> (PointGenerator, RatedPointGenerator) {
     // Instantiate an UpdateAccumulator with a pointer to the HDM.
     let update_acc_hdm_handle = hdm_rf.clone();
     let mut update_acc = UpdateAccumulator::new(update_acc_hdm_handle);
@@ -39,7 +84,7 @@ fn main() {
     let debug_hdm_handle = hdm_rf.clone();
 
     // Ok now this is the wonky bit. We're going to define closures to pass into
-    // this function. The || indicates that this is a closure that takes no arguments
+    // the GUI. The || indicates that this is a closure that takes no arguments
     // and move indicates that captured variables will be _moved_ into the scope
     // of the function, rather than being borrowed.
     //
@@ -49,12 +94,27 @@ fn main() {
     //
     // Remember that those closures are **not** being run immediately, they are
     // instead run roughly every quarter second by the GUI.
-    let _ = engage_gui(
+    (
         Box::new(move || debug_hdm_handle.lock().unwrap().get_debug_locations()),
-        Box::new(move || localize_points(&update_acc.get_status())),
-    );
-
-    // Once the gui terminates, we take a mutable referene to the hdm and stop it.
-    // .borrow_mut() takes the Rc<RefCell<T>> and turns it into an &mut T.
-    hdm.lock().unwrap().stop();
+        Box::new(move || {
+            // localize_points gives us a point for each measurement from the
+            // listener, in order, so we can match them back up with their
+            // tags and signal strengths. The accumulator hands them over in
+            // no particular order, so put the tags in order first
+            let mut updates = update_acc.get_status();
+            updates.sort_by_key(|u| (u.src, u.dst));
+            let from_listener = updates.iter().filter(|u| u.src == 0);
+            let points = localize_points(&updates);
+            if let Some(recording) = &recording {
+                let ids = from_listener.clone().map(|u| u.dst);
+                recording
+                    .borrow_mut()
+                    .push(ids.zip(points.iter().copied()).collect());
+            }
+            points
+                .into_iter()
+                .zip(from_listener.map(|u| u.rssi))
+                .collect()
+        }),
+    )
 }
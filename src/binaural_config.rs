@@ -0,0 +1,266 @@
+//! A [TOML](https://toml.io) file that describes the sources of a binaural
+//! run, so that they don't all have to be spelled out on the command line.
+//!
+//! Each source gets its own table:
+//!
+//! ```toml
+//! [[source]]
+//! file = "x.wav"
+//! gain = 1.0
+//! range = 3.0
+//! tag = 118875764010724
+//!
+//! [[source]]
+//! file = "y.wav"
+//! range = 4.0
+//! tag = 118875764011634
+//! ```
+//!
+//! `gain` and `range` default to 1. `tag` is the ID of the audio block that
+//! moves the source around. It can be left out, in which case the sources are
+//! matched up with the tags in order of ID, but then it must be left out of
+//! every source.
+
+use crate::args::BinauralCommand;
+use crate::hardware_data_manager::Id;
+
+use serde::Deserialize;
+use std::{error::Error, fmt::Display, fs, io, path::Path};
+
+/// The sources of a binaural run, read from a config file.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BinauralConfig {
+    /// The sources, in the order they should be mixed
+    #[serde(rename = "source", default)]
+    pub sources: Vec<SourceConfig>,
+}
+
+/// A single audio source in a [`BinauralConfig`].
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SourceConfig {
+    /// The audio file to play from this source
+    pub file: String,
+    /// The gain of this source
+    #[serde(default = "one")]
+    pub gain: f32,
+    /// How far this source is from the listener
+    #[serde(default = "one")]
+    pub range: f32,
+    /// The ID of the audio block that moves this source around
+    pub tag: Option<Id>,
+}
+
+fn one() -> f32 {
+    1.0
+}
+
+/// The ways that loading a [`BinauralConfig`] can fail.
+#[derive(Debug)]
+pub enum BinauralConfigError {
+    /// The config file couldn't be read
+    Io(io::Error),
+    /// The config file isn't valid TOML, or has fields that we don't expect
+    Parse(toml::de::Error),
+    /// The config file has no sources in it
+    NoSources,
+    /// Only some of the sources have a tag
+    MissingTags,
+}
+
+impl Display for BinauralConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "couldn't read the config file: {e}"),
+            Self::Parse(e) => write!(f, "couldn't parse the config file: {e}"),
+            Self::NoSources => write!(f, "the config file doesn't have any sources"),
+            Self::MissingTags => write!(
+                f,
+                "either every source in the config file needs a tag, or none of them do"
+            ),
+        }
+    }
+}
+
+impl Error for BinauralConfigError {}
+
+impl From<io::Error> for BinauralConfigError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<toml::de::Error> for BinauralConfigError {
+    fn from(value: toml::de::Error) -> Self {
+        Self::Parse(value)
+    }
+}
+
+impl BinauralConfig {
+    /// Read a config from the path provided.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, BinauralConfigError> {
+        Self::from_toml(&fs::read_to_string(path)?)
+    }
+
+    /// Parse a config, checking that it describes its sources consistently.
+    pub fn from_toml(toml: &str) -> Result<Self, BinauralConfigError> {
+        let config: Self = toml::from_str(toml)?;
+
+        if config.sources.is_empty() {
+            return Err(BinauralConfigError::NoSources);
+        }
+
+        let num_tags = config.sources.iter().filter(|s| s.tag.is_some()).count();
+        if num_tags != 0 && num_tags != config.sources.len() {
+            return Err(BinauralConfigError::MissingTags);
+        }
+
+        Ok(config)
+    }
+
+    /// Fills in the sources of `command` from this config, exactly as if they
+    /// had been given on the command line.
+    pub fn apply_to(self, mut command: BinauralCommand) -> BinauralCommand {
+        command.num_files = self.sources.len();
+        command.filenames = self.sources.iter().map(|s| s.file.clone()).collect();
+        command.gains = Some(self.sources.iter().map(|s| s.gain).collect());
+        command.ranges = Some(self.sources.iter().map(|s| s.range).collect());
+        command.tag_ids = self.sources.iter().filter_map(|s| s.tag).collect();
+        command.config = None;
+        command
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::{CommandTask, GrapeArgs};
+    use clap::Parser;
+
+    const SAMPLE_CONFIG: &str = r#"
+        [[source]]
+        file = "x.wav"
+        gain = 0.5
+        range = 3.0
+        tag = 118875764010724
+
+        [[source]]
+        file = "y.wav"
+        range = 4.0
+        tag = 118875764011634
+    "#;
+
+    fn binaural_command(args: &[&str]) -> BinauralCommand {
+        let args = GrapeArgs::try_parse_from(
+            ["cybergrape", "--update", "40", "binaural"]
+                .iter()
+                .chain(args),
+        )
+        .unwrap();
+        match args.command {
+            CommandTask::Binaural(command) => command,
+            _ => panic!("expected a binaural command"),
+        }
+    }
+
+    #[test]
+    fn deserialize_sample() {
+        let config = BinauralConfig::from_toml(SAMPLE_CONFIG).unwrap();
+        assert_eq!(
+            vec![
+                SourceConfig {
+                    file: "x.wav".to_string(),
+                    gain: 0.5,
+                    range: 3.0,
+                    tag: Some(118875764010724),
+                },
+                SourceConfig {
+                    file: "y.wav".to_string(),
+                    gain: 1.0,
+                    range: 4.0,
+                    tag: Some(118875764011634),
+                },
+            ],
+            config.sources
+        );
+    }
+
+    #[test]
+    fn config_matches_cli() {
+        let from_config = binaural_command(&["--out", "out.wav", "--config", "sources.toml"]);
+        assert_eq!(Some("sources.toml".to_string()), from_config.config);
+        let from_config = BinauralConfig::from_toml(SAMPLE_CONFIG)
+            .unwrap()
+            .apply_to(from_config);
+
+        let from_cli = binaural_command(&[
+            "-n",
+            "2",
+            "--out",
+            "out.wav",
+            "--gains",
+            "0.5",
+            "1",
+            "--ranges",
+            "3",
+            "4",
+            "--files",
+            "x.wav",
+            "y.wav",
+            "--tags",
+            "118875764010724",
+            "118875764011634",
+        ]);
+
+        assert_eq!(from_cli, from_config);
+    }
+
+    #[test]
+    fn config_conflicts_with_cli_sources() {
+        let args = GrapeArgs::try_parse_from([
+            "cybergrape",
+            "--update",
+            "40",
+            "binaural",
+            "-n",
+            "2",
+            "--out",
+            "out.wav",
+            "--config",
+            "sources.toml",
+        ]);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn reject_inconsistent_configs() {
+        assert!(matches!(
+            BinauralConfig::from_toml(""),
+            Err(BinauralConfigError::NoSources)
+        ));
+        assert!(matches!(
+            BinauralConfig::from_toml(
+                r#"
+                [[source]]
+                file = "x.wav"
+                tag = 1
+
+                [[source]]
+                file = "y.wav"
+                "#
+            ),
+            Err(BinauralConfigError::MissingTags)
+        ));
+        assert!(matches!(
+            BinauralConfig::from_toml(
+                r#"
+                [[source]]
+                file = "x.wav"
+                volume = 11
+                "#
+            ),
+            Err(BinauralConfigError::Parse(_))
+        ));
+    }
+}
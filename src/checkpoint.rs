@@ -0,0 +1,360 @@
+//! Saves a serial capture to its GrapeFile every so often while it runs, so
+//! that a capture that dies partway through only loses what came in since
+//! the last checkpoint. A capture can then be resumed, carrying on at the end
+//! of the file it left behind.
+
+use crate::spatial_data_format::{metadata_to_grape_file, GrapeFile, GrapeFileError};
+use crate::time_domain_buffer::TDBufMeta;
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Writes everything captured so far to a GrapeFile, after whatever was
+/// already in the file when the capture was resumed.
+///
+/// There's no way to add to the end of a GrapeFile in place, since the
+/// header and checksum cover every sample, so each checkpoint rewrites the
+/// whole file. That happens on a thread of its own, which keeps its own copy
+/// of the capture and is only sent the time slices added since the last
+/// checkpoint, so that the capture doesn't stall while the file is written.
+/// It's written next to the file first, and then moved over it, so dying
+/// partway through a checkpoint leaves the last one intact.
+pub struct Checkpoint {
+    path: PathBuf,
+    num_tags: usize,
+    sample_rate: u64,
+    earlier: Option<GrapeFile>,
+    resumed_len: usize,
+    interval: Option<Duration>,
+    last_saved: Instant,
+    sent: usize,
+    saver: Option<Saver>,
+}
+
+/// The thread that writes the checkpoints, and how to talk to it. The
+/// results are behind a [Mutex] only so that a capture holding a
+/// [Checkpoint] can be shared between threads.
+struct Saver {
+    tails: Sender<TDBufMeta>,
+    results: Mutex<Receiver<Result<(), GrapeFileError>>>,
+    in_flight: usize,
+}
+
+impl Checkpoint {
+    /// Instantiates a `Checkpoint` that saves a capture of `num_tags` tags,
+    /// at `sample_rate` time slices per second, to a new file at `path`.
+    pub fn new(path: impl AsRef<Path>, num_tags: usize, sample_rate: u64) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            num_tags,
+            sample_rate,
+            earlier: None,
+            resumed_len: 0,
+            interval: None,
+            last_saved: Instant::now(),
+            sent: 0,
+            saver: None,
+        }
+    }
+
+    /// Instantiates a `Checkpoint` that carries on after what is already
+    /// saved in the file at `path`. The file has to have been captured with
+    /// the same number of tags and sample rate, otherwise
+    /// [GrapeFileError::MismatchedTags] or
+    /// [GrapeFileError::MismatchedSampleRates] is returned.
+    pub fn resume(
+        path: impl AsRef<Path>,
+        num_tags: usize,
+        sample_rate: u64,
+    ) -> Result<Self, GrapeFileError> {
+        let earlier = GrapeFile::from_path(&path)?;
+        // Check that the capture will fit on the end before it starts
+        earlier.append_time(&metadata_to_grape_file(&[], num_tags, sample_rate))?;
+
+        Ok(Self {
+            resumed_len: earlier.num_samples_per_stream(),
+            earlier: Some(earlier),
+            ..Self::new(path, num_tags, sample_rate)
+        })
+    }
+
+    /// Has [Checkpoint::save_if_due] save the capture every `interval`.
+    pub fn every(mut self, interval: Duration) -> Self {
+        self.interval = Some(interval);
+        self
+    }
+
+    /// The number of time slices saved before this capture was resumed.
+    pub fn resumed_len(&self) -> usize {
+        self.resumed_len
+    }
+
+    /// Saves everything in `buf` to the file, after anything that was there
+    /// when the capture was resumed, and waits for it to be written.
+    pub fn save(&mut self, buf: &TDBufMeta) -> Result<(), GrapeFileError> {
+        self.last_saved = Instant::now();
+        self.send(buf)?;
+
+        let saver = self.saver.as_mut().expect("the saver was just started");
+        let mut result = Ok(());
+        while saver.in_flight > 0 {
+            result = saver.wait();
+        }
+        result
+    }
+
+    /// Starts saving `buf` in the background, like [Checkpoint::save], if
+    /// the interval given to [Checkpoint::every] has passed since the last
+    /// save, and the last one has been written. Returns whether it started
+    /// a save, or the error from the last one if it failed. A failed save
+    /// still counts, so it is tried again after another interval rather than
+    /// straight away.
+    pub fn save_if_due(&mut self, buf: &TDBufMeta) -> Result<bool, GrapeFileError> {
+        let in_flight = match &mut self.saver {
+            Some(saver) => {
+                saver.finished()?;
+                saver.in_flight
+            }
+            None => 0,
+        };
+
+        match self.interval {
+            Some(interval) if in_flight == 0 && self.last_saved.elapsed() >= interval => {
+                self.last_saved = Instant::now();
+                self.send(buf)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Sends the saver whatever has been added to `buf` since last time,
+    /// starting it if it isn't running yet.
+    fn send(&mut self, buf: &TDBufMeta) -> Result<(), GrapeFileError> {
+        let tail = buf.tail(self.sent);
+        self.sent = buf.len();
+
+        let saver = self.saver.get_or_insert_with(|| {
+            Saver::start(
+                self.path.clone(),
+                self.num_tags,
+                self.sample_rate,
+                self.earlier.take(),
+            )
+        });
+        saver.tails.send(tail).map_err(|_| stopped())?;
+        saver.in_flight += 1;
+        Ok(())
+    }
+}
+
+impl Saver {
+    /// Starts the thread that writes the checkpoints to `path`, after
+    /// `earlier` if there is anything earlier.
+    fn start(path: PathBuf, num_tags: usize, sample_rate: u64, earlier: Option<GrapeFile>) -> Self {
+        let (tails, tails_rx) = mpsc::channel::<TDBufMeta>();
+        let (results_tx, results) = mpsc::channel();
+
+        thread::spawn(move || {
+            let mut captured: Option<TDBufMeta> = None;
+            for tail in tails_rx {
+                let captured = match &mut captured {
+                    Some(captured) => {
+                        captured.append(tail);
+                        captured
+                    }
+                    None => captured.insert(tail),
+                };
+
+                let result = write(&path, num_tags, sample_rate, earlier.as_ref(), captured);
+                if results_tx.send(result).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            tails,
+            results: Mutex::new(results),
+            in_flight: 0,
+        }
+    }
+
+    /// Waits for the oldest save still being written.
+    fn wait(&mut self) -> Result<(), GrapeFileError> {
+        self.in_flight -= 1;
+        self.results
+            .get_mut()
+            .unwrap()
+            .recv()
+            .map_err(|_| stopped())?
+    }
+
+    /// Hears back from any saves that have been written since last time,
+    /// without waiting, returning the first failure.
+    fn finished(&mut self) -> Result<(), GrapeFileError> {
+        let mut result = Ok(());
+        while self.in_flight > 0 {
+            match self.results.get_mut().unwrap().try_recv() {
+                Ok(saved) => {
+                    self.in_flight -= 1;
+                    result = result.and(saved);
+                }
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    self.in_flight = 0;
+                    return Err(stopped());
+                }
+            }
+        }
+        result
+    }
+}
+
+impl Drop for Saver {
+    /// Waits for a save that is still being written, so that it isn't cut
+    /// off partway through.
+    fn drop(&mut self) {
+        while self.in_flight > 0 {
+            let _ = self.wait();
+        }
+    }
+}
+
+/// Writes `captured` to the file at `path`, after `earlier`.
+fn write(
+    path: &Path,
+    num_tags: usize,
+    sample_rate: u64,
+    earlier: Option<&GrapeFile>,
+    captured: &TDBufMeta,
+) -> Result<(), GrapeFileError> {
+    let captured = metadata_to_grape_file(&captured.clone().dump(), num_tags, sample_rate);
+    let file = match earlier {
+        Some(earlier) => earlier.append_time(&captured)?,
+        None => captured,
+    };
+
+    let partial = path.with_extension("checkpoint");
+    file.to_path(&partial)?;
+    fs::rename(&partial, path).map_err(GrapeFileError::IoError)
+}
+
+/// The error for when the saver's thread has gone away.
+fn stopped() -> GrapeFileError {
+    GrapeFileError::IoError(io::Error::other("the checkpoint thread stopped"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::saf::BufferMetadata;
+    use crate::spatial_data_format::grape_file_to_metadata;
+
+    fn slice(azimuth: f32) -> Vec<BufferMetadata> {
+        vec![
+            BufferMetadata {
+                azimuth,
+                elevation: 0.1,
+                range: 1.0,
+                gain: 1.0,
+            },
+            BufferMetadata {
+                azimuth: -azimuth,
+                elevation: 0.2,
+                range: 1.0,
+                gain: 1.0,
+            },
+        ]
+    }
+
+    #[test]
+    fn resume_after_interruption() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("capture.grape");
+
+        let mut buf = TDBufMeta::new(2);
+        let mut checkpoint = Checkpoint::new(&path, 2, 20).every(Duration::ZERO);
+        for azimuth in [0.1, 0.2, 0.3] {
+            buf.add(slice(azimuth));
+        }
+        assert!(checkpoint.save_if_due(&buf).unwrap());
+        // Then the capture dies before it can save this one
+        buf.add(slice(0.4));
+        drop(checkpoint);
+
+        let mut buf = TDBufMeta::new(2);
+        let mut checkpoint = Checkpoint::resume(&path, 2, 20).unwrap();
+        assert_eq!(3, checkpoint.resumed_len());
+        assert!(!checkpoint.save_if_due(&buf).unwrap());
+        for azimuth in [0.5, 0.6] {
+            buf.add(slice(azimuth));
+        }
+        checkpoint.save(&buf).unwrap();
+
+        let saved = grape_file_to_metadata(&GrapeFile::from_path(&path).unwrap());
+        let expected = [0.1, 0.2, 0.3, 0.5, 0.6].map(slice).to_vec();
+        assert_eq!(expected, saved);
+        assert!(!path.with_extension("checkpoint").exists());
+    }
+
+    #[test]
+    fn failed_saves_still_count() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("missing").join("capture.grape");
+
+        let mut buf = TDBufMeta::new(2);
+        buf.add(slice(0.1));
+        let mut checkpoint = Checkpoint::new(&path, 2, 20).every(Duration::from_secs(3600));
+        assert!(!checkpoint.save_if_due(&buf).unwrap());
+        assert!(matches!(
+            checkpoint.save(&buf),
+            Err(GrapeFileError::IoError(_))
+        ));
+        // Rather than trying again on every tick
+        assert!(!checkpoint.save_if_due(&buf).unwrap());
+
+        let mut checkpoint = Checkpoint::new(&path, 2, 20).every(Duration::ZERO);
+        assert!(checkpoint.save_if_due(&buf).unwrap());
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let failed = loop {
+            match checkpoint.save_if_due(&buf) {
+                Err(e) => break e,
+                Ok(_) if Instant::now() < deadline => thread::yield_now(),
+                Ok(_) => panic!("the save never failed"),
+            }
+        };
+        assert!(matches!(failed, GrapeFileError::IoError(_)));
+    }
+
+    #[test]
+    fn resume_needs_the_same_capture() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("capture.grape");
+        Checkpoint::new(&path, 2, 20)
+            .save(&TDBufMeta::new(2))
+            .unwrap();
+
+        assert!(Checkpoint::resume(&path, 2, 20).is_ok());
+        assert!(matches!(
+            Checkpoint::resume(&path, 3, 20),
+            Err(GrapeFileError::MismatchedTags)
+        ));
+        assert!(matches!(
+            Checkpoint::resume(&path, 2, 40),
+            Err(GrapeFileError::MismatchedSampleRates)
+        ));
+        assert!(matches!(
+            Checkpoint::resume(dir.path().join("missing.grape"), 2, 20),
+            Err(GrapeFileError::IoError(_))
+        ));
+    }
+}
@@ -1,14 +1,16 @@
 //! A dummy implementation of a [`HardwareDataManager`] that pretends
-//! that there is a static circle of sound sources around the listener
+//! that there is a static circle of sound sources around the listener, and
+//! a [`ScriptedHdm`] that just plays back a list of updates
 
 use crate::hardware_data_manager::*;
 use crate::localizer::Point;
+use crate::sphericalizer::Sphericalizer;
 use rand::prelude::*;
 use std::collections::VecDeque;
 use std::f64::consts::PI;
 use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 /// Manages a thread that produces updates as if there is a circle of sound
 /// sources around the listener.
@@ -16,7 +18,7 @@ pub struct DummyHdm {
     handle: Option<thread::JoinHandle<()>>,
     tx: mpsc::Sender<Signal>,
     msgs: Arc<Mutex<VecDeque<Update>>>,
-    debug_coordinates: Vec<Point>,
+    debug_coordinates: Arc<Mutex<Vec<Point>>>,
 }
 
 /// A utility struct that enables configuration of the `DummyHdm`
@@ -25,6 +27,8 @@ pub struct DummyHdmBuilder {
     noise: f64,
     range: f64,
     delay: f64,
+    rotation: f64,
+    antennas: Option<(Id, Id)>,
 }
 
 impl DummyHdmBuilder {
@@ -35,6 +39,8 @@ impl DummyHdmBuilder {
             noise: f64::MIN_POSITIVE,
             range: 1.0,
             delay: 0.25,
+            rotation: 0.0,
+            antennas: None,
         }
     }
 
@@ -44,7 +50,8 @@ impl DummyHdmBuilder {
         self
     }
 
-    /// Sets the "noise" in the simulated measurements of angles
+    /// Sets the "noise" in the simulated measurements of angles. A noise of
+    /// 0 makes the measurements exact.
     pub fn noise(mut self, noise: f64) -> Self {
         self.noise = noise;
         self
@@ -62,6 +69,21 @@ impl DummyHdmBuilder {
         self
     }
 
+    /// Sets how fast the ring of sound sources spins around the listener, in
+    /// radians per second.
+    pub fn rotation(mut self, rotation: f64) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Rather than reporting the azimuth between every pair of points, report
+    /// what the `back` and `front` antennas of a listener block at the origin
+    /// would measure for each sound source, as consumed by a [`Sphericalizer`].
+    pub fn antennas(mut self, back: Id, front: Id) -> Self {
+        self.antennas = Some((back, front));
+        self
+    }
+
     /// Consumes the builder, instantiating and starting a new `DummyHdm`.
     pub fn build(self) -> DummyHdm {
         DummyHdm::new_from_builder(self)
@@ -119,18 +141,20 @@ impl DummyHdm {
         // passed into the thread
         let th_msgs = Arc::clone(&msgs);
 
-        // Generate the true coordinates of the objects once
-        let debug_coordinates = generate_circular_points(b.num_points, b.range);
+        // Generate the starting coordinates of the objects once
+        let start_coordinates = generate_circular_points(b.num_points, b.range);
+        let debug_coordinates = Arc::new(Mutex::new(start_coordinates.clone()));
 
         // We need to make a clone because it is going to be moved in to the thread,
         // and we need to have access for debug purposes out here in the struct
-        let th_debug_coords = debug_coordinates.clone();
+        let th_debug_coords = Arc::clone(&debug_coordinates);
 
         // The closure passed in to `thread::spawn` is going to run in its own
         // thread! `move` means that the closure is going to take ownership of
         // every value that is captured (th_debug_coords, b.noise, and b.delay)
         // rather than trying to borrow them.
         let handle = thread::spawn(move || {
+            let start = Instant::now();
             let mut running = true;
             while running {
                 // if we receive a Signal::Stop, stop looping
@@ -139,13 +163,21 @@ impl DummyHdm {
                         Signal::Stop => running = false,
                     }
                 }
+
+                // spin the ring of sources to wherever it should be by now
+                let angle = b.rotation * start.elapsed().as_secs_f64();
+                let coords = rotate_points(&start_coordinates, angle);
+
+                let mut updates = match b.antennas {
+                    Some((back, front)) => generate_antenna_updates(&coords, back, front, b.noise),
+                    None => generate_flat_updates(&coords, b.noise),
+                };
+                *th_debug_coords.lock().unwrap() = coords;
+
                 // insert a fresh batch of updates into the update queue we need
                 // to take the lock on the queue so that no one can muck with it
                 // while we are appending to it
-                th_msgs
-                    .lock()
-                    .unwrap()
-                    .append(&mut generate_flat_updates(&th_debug_coords, b.noise));
+                th_msgs.lock().unwrap().append(&mut updates);
 
                 thread::sleep(Duration::from_secs_f64(b.delay));
             }
@@ -180,7 +212,7 @@ impl DummyHdm {
 
     /// Returns the **true** locations of the objects in the dummy HDM.
     pub fn get_debug_locations(&self) -> Vec<Point> {
-        self.debug_coordinates.clone()
+        self.debug_coordinates.lock().unwrap().clone()
     }
 }
 
@@ -231,14 +263,15 @@ fn generate_flat_updates(points: &[Point], noise: f64) -> VecDeque<Update> {
                 .filter(|(j, _)| i != *j)
                 // for all Point pairs &(p1, &p2), where p1 != p2
                 .map(|(j, &p2)| -> Update {
-                    let dx = p2.x - p1.x + rng.gen_range(-noise..noise);
-                    let dy = p2.y - p1.y + rng.gen_range(-noise..noise);
+                    let dx = p2.x - p1.x + jitter(&mut rng, noise);
+                    let dy = p2.y - p1.y + jitter(&mut rng, noise);
                     let azimuth = dy.atan2(dx);
                     Update {
                         src: i,
                         dst: j,
                         elv: 0.0, // working in a flat 2D plane, for now
                         azm: azimuth,
+                        rssi: None,
                     }
                 })
                 .collect()
@@ -249,6 +282,93 @@ fn generate_flat_updates(points: &[Point], noise: f64) -> VecDeque<Update> {
         .collect()
 }
 
+/// A random offset of up to `noise` either way, or none at all if `noise`
+/// is 0, since there's nothing to pick from an empty range.
+fn jitter(rng: &mut impl Rng, noise: f64) -> f64 {
+    let noise = noise.abs();
+    if noise > 0.0 {
+        rng.gen_range(-noise..noise)
+    } else {
+        0.0
+    }
+}
+
+/// Rotate a slice of [`Point`]s about the origin by `angle` radians.
+fn rotate_points(points: &[Point], angle: Radian) -> Vec<Point> {
+    let (sin, cos) = angle.sin_cos();
+    points
+        .iter()
+        .map(|p| Point {
+            x: p.x * cos - p.y * sin,
+            y: p.x * sin + p.y * cos,
+        })
+        .collect()
+}
+
+/// Given a slice of [`Point`]s, where the first is the listener, generate the
+/// [`Update`]s that the `back` and `front` antennas of the listener would
+/// report for every other point (with some noise).
+fn generate_antenna_updates(points: &[Point], back: Id, front: Id, noise: f64) -> VecDeque<Update> {
+    let mut rng = thread_rng();
+    let listener = points[0];
+    points
+        .iter()
+        .enumerate()
+        .skip(1)
+        .flat_map(|(i, &p)| {
+            let azimuth = (p.y - listener.y).atan2(p.x - listener.x);
+            let (back_azm, front_azm) = Sphericalizer::antenna_azimuths(azimuth as f32);
+            let back_azm = back_azm as f64 + jitter(&mut rng, noise);
+            [(back, back_azm), (front, front_azm as f64)]
+                .into_iter()
+                .map(move |(src, azm)| Update {
+                    src,
+                    dst: i,
+                    elv: 0.0,
+                    azm,
+                    rssi: None,
+                })
+        })
+        .collect()
+}
+
+/// A [`HardwareDataManager`] that hands over a scripted list of updates, one
+/// per call to `next()`, and then runs dry. There's no thread and no
+/// randomness, so the same script always plays out the same way, which
+/// makes it handy for testing whatever consumes the updates.
+#[derive(Debug, Clone, Default)]
+pub struct ScriptedHdm {
+    script: VecDeque<Update>,
+}
+
+impl ScriptedHdm {
+    /// Instantiates a `ScriptedHdm` that will hand over `script` in order.
+    pub fn new(script: Vec<Update>) -> Self {
+        Self {
+            script: script.into(),
+        }
+    }
+
+    /// Adds `update` to the end of the script.
+    pub fn push(&mut self, update: Update) {
+        self.script.push_back(update);
+    }
+}
+
+impl HardwareDataManager for ScriptedHdm {
+    /// Throw away the rest of the script
+    fn clear(&mut self) {
+        self.script.clear();
+    }
+}
+
+impl Iterator for ScriptedHdm {
+    type Item = Update;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.script.pop_front()
+    }
+}
+
 /// Take a slice of [`Update`]s and apply a bit of vertical noise so that they
 /// are no longer pinned to the x/y plane.
 #[allow(dead_code)]
@@ -260,7 +380,7 @@ fn unflatten_updates(updates: &[Update], noise: f64) -> VecDeque<Update> {
             // Here we are using the "struct update syntax" to create a new
             // update with the same src, dst, and azm; but with a nonzero elv.
             Update {
-                elv: rng.gen_range(-noise..noise),
+                elv: jitter(&mut rng, noise),
                 ..u.clone()
             }
         })
@@ -272,6 +392,31 @@ fn unflatten_updates(updates: &[Update], noise: f64) -> VecDeque<Update> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn scripted_hdm_follows_the_script() {
+        let script = (0..4)
+            .map(|i| Update {
+                src: 0,
+                dst: i,
+                elv: 0.0,
+                azm: i as f64,
+                rssi: None,
+            })
+            .collect::<Vec<_>>();
+
+        let mut hdm = ScriptedHdm::new(script.clone());
+        assert_eq!(Some(script[0].clone()), hdm.next());
+        assert_eq!(script[1..].to_vec(), hdm.by_ref().collect::<Vec<_>>());
+        assert_eq!(None, hdm.next());
+
+        hdm.push(script[3].clone());
+        assert_eq!(Some(script[3].clone()), hdm.next());
+
+        let mut hdm = ScriptedHdm::new(script);
+        hdm.clear();
+        assert_eq!(None, hdm.next());
+    }
+
     #[test]
     fn generate_some_points() {
         let generated_points = generate_circular_points(4, 1.0);
@@ -290,4 +435,35 @@ mod tests {
                 assert!(other.abs_dist(gen) < 0.0001);
             });
     }
+
+    #[test]
+    fn rotate_a_quarter_turn() {
+        let rotated = rotate_points(&[Point { x: 1.0, y: 0.0 }], PI / 2.0);
+        assert!(rotated[0].abs_dist(&Point { x: 0.0, y: 1.0 }) < 0.0001);
+    }
+
+    #[test]
+    fn antenna_updates_for_each_source() {
+        let points = generate_circular_points(3, 1.0);
+        let updates = generate_antenna_updates(&points, 10, 20, f64::MIN_POSITIVE);
+
+        assert_eq!(6, updates.len());
+        for (i, pair) in updates.iter().collect::<Vec<_>>().chunks(2).enumerate() {
+            assert_eq!((10, i + 1), (pair[0].src, pair[0].dst));
+            assert_eq!((20, i + 1), (pair[1].src, pair[1].dst));
+        }
+    }
+
+    #[test]
+    fn updates_without_noise() {
+        let points = generate_circular_points(2, 1.0);
+        for noise in [0.0, -0.1] {
+            assert_eq!(4, generate_antenna_updates(&points, 10, 20, noise).len());
+            assert_eq!(6, generate_flat_updates(&points, noise).len());
+        }
+
+        let exact = generate_flat_updates(&points, 0.0);
+        assert_eq!(exact, generate_flat_updates(&points, 0.0));
+        assert_eq!(exact, unflatten_updates(&Vec::from(exact.clone()), 0.0));
+    }
 }
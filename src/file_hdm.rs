@@ -0,0 +1,178 @@
+//! A [`HardwareDataManager`] that replays a previously recorded log of
+//! hardware messages, so that the rest of the pipeline can be developed
+//! without the physical listener block.
+
+use crate::hardware_data_manager::{HardwareDataManager, Update};
+use crate::hardware_message_decoder::HardwareEvent;
+
+use log::warn;
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+/// Replays [`Update`]s from a newline-delimited log. Each line is either a
+/// raw `+UUDF:` message, exactly as it came over serial, or an [`Update`]
+/// serialized with [ron]. Heartbeats, blank lines, and lines that can't be
+/// parsed are skipped.
+#[derive(Debug)]
+pub struct FileHdm {
+    /// The updates left to replay, with the hardware timestamp (in
+    /// milliseconds) of the message they came from, if there was one
+    msgs: VecDeque<(Option<u32>, Update)>,
+    /// Whether to hold back updates until their timestamp comes around
+    paced: bool,
+    /// The wall-clock time and hardware timestamp of the first paced update
+    epoch: Option<(Instant, u32)>,
+}
+
+impl FileHdm {
+    /// Read a log from the path provided.
+    pub fn from_path(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::open(path)?;
+        Self::from_reader(BufReader::new(file))
+    }
+
+    /// Read a log from the [BufRead]able object provided.
+    pub fn from_reader(reader: impl BufRead) -> io::Result<Self> {
+        let mut msgs = VecDeque::new();
+
+        for (line_no, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            match parse_line(line) {
+                Some(msg) => msgs.push_back(msg),
+                None => warn!("Skipping line {} of the replay log", line_no + 1),
+            }
+        }
+
+        Ok(Self {
+            msgs,
+            paced: false,
+            epoch: None,
+        })
+    }
+
+    /// Replay the updates in real time, using the timestamps embedded in the
+    /// `+UUDF:` messages. Updates without a timestamp are emitted immediately.
+    pub fn paced(mut self) -> Self {
+        self.paced = true;
+        self
+    }
+
+    /// Whether the update at the front of the queue should be emitted yet.
+    fn front_is_due(&mut self) -> bool {
+        let Some((Some(timestamp), _)) = self.msgs.front() else {
+            return !self.msgs.is_empty();
+        };
+
+        if !self.paced {
+            return true;
+        }
+
+        let (start, first) = *self.epoch.get_or_insert((Instant::now(), *timestamp));
+        let offset = Duration::from_millis(timestamp.saturating_sub(first) as u64);
+        start.elapsed() >= offset
+    }
+}
+
+/// Turns one line of a replay log into an [`Update`], if it holds one.
+fn parse_line(line: &str) -> Option<(Option<u32>, Update)> {
+    if line.starts_with('+') {
+        match HardwareEvent::from_str(line) {
+            Ok(HardwareEvent::UUDFEvent(event)) => {
+                Some((Some(event.timestamp), Update::from(&event)))
+            }
+            _ => None,
+        }
+    } else {
+        ron::de::from_str::<Update>(line)
+            .ok()
+            .map(|update| (None, update))
+    }
+}
+
+impl Iterator for FileHdm {
+    type Item = Update;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.front_is_due() {
+            self.msgs.pop_front().map(|(_, update)| update)
+        } else {
+            None
+        }
+    }
+}
+
+impl HardwareDataManager for FileHdm {
+    /// Skips every update that is already due, leaving the ones that are
+    /// still in the future.
+    fn clear(&mut self) {
+        while self.next().is_some() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+    use std::io::Cursor;
+
+    const LOG: &str = "\
++UUDF:CCF9578E0D8A,-42,90,0,-43,37,\"CCF9578E0D89\",\"\",15869,23
++UUDFP:6C3DEBAFAEE4,19FF1500000050F80C0065000900052A0D001F000000D0030000
+
++UUDF:CCF9578E0D8B,-41,-45,4,-42,38,\"CCF9578E0D89\",\"\",16869,24
+(src:1,dst:2,elv:0.5,azm:0.25)
+";
+
+    #[test]
+    fn replays_log() {
+        let hdm = FileHdm::from_reader(Cursor::new(LOG)).unwrap();
+        let updates: Vec<Update> = hdm.collect();
+
+        assert_eq!(3, updates.len());
+
+        assert_eq!(0xCCF9578E0D89, updates[0].src);
+        assert_eq!(0xCCF9578E0D8A, updates[0].dst);
+        assert!((updates[0].azm - PI / 2.0).abs() < 1e-9);
+
+        assert_eq!(0xCCF9578E0D8B, updates[1].dst);
+        assert!((updates[1].azm + PI / 4.0).abs() < 1e-9);
+
+        assert_eq!(
+            Update {
+                src: 1,
+                dst: 2,
+                elv: 0.5,
+                azm: 0.25,
+                rssi: None,
+            },
+            updates[2]
+        );
+    }
+
+    #[test]
+    fn paced_replay_waits() {
+        let mut hdm = FileHdm::from_reader(Cursor::new(LOG)).unwrap().paced();
+
+        // The second message is a full second after the first
+        assert!(hdm.next().is_some());
+        assert!(hdm.next().is_none());
+    }
+
+    #[test]
+    fn clear_skips_due_updates() {
+        let mut hdm = FileHdm::from_reader(Cursor::new(LOG)).unwrap();
+        hdm.clear();
+        assert!(hdm.next().is_none());
+    }
+}
@@ -1,12 +1,8 @@
 use std::{io::stdout, path::PathBuf};
 
-use crate::gui::error::GrapeGuiError;
+use crate::gui::{error::GrapeGuiError, terminal_guard::TerminalGuard};
 
-use crossterm::{
-    event::{self, KeyCode, KeyEventKind},
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
-    ExecutableCommand,
-};
+use crossterm::event::{self, KeyCode, KeyEventKind};
 use ratatui::{
     prelude::*,
     widgets::{
@@ -17,28 +13,40 @@ use ratatui::{
 };
 
 /// Generates a TUI that allows the user to select which serial
-/// device is attached to the u-blox antenna.
+/// device is attached to the u-blox antenna. Typing narrows the list down to
+/// the ports whose names contain what was typed.
 pub fn device_selector(
     mut available_ports: Vec<PathBuf>,
 ) -> Result<Option<PathBuf>, GrapeGuiError> {
-    enable_raw_mode()?;
-    stdout().execute(EnterAlternateScreen)?;
+    let guard = TerminalGuard::enter()?;
     let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
     terminal.clear()?;
 
     let mut cursor = 0;
     let mut list_state = ListState::default().with_selected(Some(cursor));
-    let n_ports = available_ports.len();
+    let mut query = String::new();
     let mut selected_port = None;
+    // How many ports fit on the screen, as of the last draw
+    let mut page = 1;
     loop {
-        let title = Title::from(" Device Selector ".magenta().bold());
+        // The indices into `available_ports` of the ports that are shown
+        let shown = filter_ports(&available_ports, &query);
+        let n_ports = shown.len();
+
+        let mut title = vec![" Device Selector ".magenta().bold()];
+        if !query.is_empty() {
+            title.extend([" Filter: ".into(), query.as_str().bold(), " ".into()]);
+        }
+        let title = Title::from(Line::from(title));
         let instructions = Title::from(Line::from(vec![
             " Navigate ".into(),
-            "<Up>/<Down>".magenta().bold(),
+            "<Up>/<Down>/<PgUp>/<PgDn>".magenta().bold(),
+            " Filter ".into(),
+            "<Type>".magenta().bold(),
             " Select ".into(),
             "<Enter>".magenta().bold(),
             " Quit ".into(),
-            "<Q> ".magenta().bold(),
+            "<Esc> ".magenta().bold(),
         ]));
         let block = Block::default()
             .title(title.alignment(Alignment::Center))
@@ -48,16 +56,19 @@ pub fn device_selector(
                     .position(Position::Bottom),
             )
             .borders(Borders::ALL);
-        let port_names = available_ports.iter().map(|p| p.to_string_lossy());
+        let port_names = shown.iter().map(|&i| available_ports[i].to_string_lossy());
         let list = List::new(port_names)
             .style(Style::default().fg(Color::White))
             .highlight_symbol(">>")
             .highlight_style(Style::default().fg(Color::Magenta))
             .block(block);
-        list_state.select(Some(cursor));
+        list_state.select((n_ports > 0).then_some(cursor));
         terminal
             .draw(|frame| {
                 let area = frame.size();
+                // Leave room for the borders
+                page = (area.height as usize).saturating_sub(2).max(1);
+                *list_state.offset_mut() = scroll_offset(cursor, list_state.offset(), page);
                 frame.render_stateful_widget(list, area, &mut list_state);
             })
             .unwrap();
@@ -65,17 +76,35 @@ pub fn device_selector(
             if let event::Event::Key(key) = event::read().unwrap() {
                 if key.kind == KeyEventKind::Press {
                     match key.code {
+                        KeyCode::Esc => break,
+                        KeyCode::Char(c) => {
+                            query.push(c);
+                            cursor = 0;
+                        }
+                        KeyCode::Backspace => {
+                            query.pop();
+                            cursor = 0;
+                        }
+                        // Nothing to move through, or to pick
+                        _ if n_ports == 0 => {}
                         KeyCode::Down => {
                             cursor = (cursor + 1) % n_ports;
                         }
                         KeyCode::Up => {
                             cursor = (cursor + n_ports - 1) % n_ports;
                         }
+                        // Paging stops at the ends of the list, rather
+                        // than wrapping round
+                        KeyCode::PageDown => {
+                            cursor = (cursor + page).min(n_ports - 1);
+                        }
+                        KeyCode::PageUp => {
+                            cursor = cursor.saturating_sub(page);
+                        }
                         KeyCode::Enter => {
-                            selected_port = Some(cursor);
+                            selected_port = Some(shown[cursor]);
                             break;
                         }
-                        KeyCode::Char('q') => break,
                         _ => {}
                     }
                 }
@@ -83,8 +112,69 @@ pub fn device_selector(
         }
     }
 
-    disable_raw_mode()?;
-    stdout().execute(LeaveAlternateScreen)?;
+    drop(guard);
 
     Ok(selected_port.map(|i| available_ports.swap_remove(i)))
 }
+
+/// The indices of the `ports` whose names contain `query`, ignoring case, in
+/// the order that they're given. An empty `query` matches every port.
+fn filter_ports(ports: &[PathBuf], query: &str) -> Vec<usize> {
+    let query = query.to_lowercase();
+    ports
+        .iter()
+        .enumerate()
+        .filter(|(_, port)| port.to_string_lossy().to_lowercase().contains(&query))
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Where a list showing `height` items at a time should start, so that the
+/// `selected` item is on screen. The list only scrolls as far as it has to
+/// from where it started, at `offset`.
+fn scroll_offset(selected: usize, offset: usize, height: usize) -> usize {
+    if selected < offset {
+        selected
+    } else if selected >= offset + height {
+        selected + 1 - height
+    } else {
+        offset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scroll_to_selection() {
+        // Already on screen
+        assert_eq!(0, scroll_offset(3, 0, 5));
+        assert_eq!(4, scroll_offset(6, 4, 5));
+        // Just off the bottom, so the selection ends up on the last line
+        assert_eq!(1, scroll_offset(5, 0, 5));
+        assert_eq!(16, scroll_offset(20, 0, 5));
+        // Off the top, so the selection ends up on the first line
+        assert_eq!(2, scroll_offset(2, 10, 5));
+        // Wrapping round from the bottom back to the top
+        assert_eq!(0, scroll_offset(0, 16, 5));
+    }
+
+    #[test]
+    fn filter_by_name() {
+        let ports = [
+            "/dev/ttyS0",
+            "/dev/ttyUSB0",
+            "/dev/ttyUSB1",
+            "/dev/cu.usbmodem1101",
+        ]
+        .map(PathBuf::from);
+
+        assert_eq!(vec![0, 1, 2, 3], filter_ports(&ports, ""));
+        assert_eq!(vec![1, 2], filter_ports(&ports, "ttyUSB"));
+        // Case doesn't matter
+        assert_eq!(vec![1, 2, 3], filter_ports(&ports, "usb"));
+        assert_eq!(vec![2], filter_ports(&ports, "usb1"));
+        assert!(filter_ports(&ports, "COM3").is_empty());
+    }
+}
@@ -1,21 +1,22 @@
 use std::{io::stdout, sync::mpsc, thread::spawn};
 
-use crate::gui::error::GrapeGuiError;
+use crate::gui::{error::GrapeGuiError, tag_meter::tag_meter, terminal_guard::TerminalGuard};
+use crate::saf::BufferMetadata;
 
-use crossterm::{
-    event::{self, KeyEventKind},
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
-    ExecutableCommand,
-};
+use crossterm::event::{self, KeyCode, KeyEventKind};
 
 use ratatui::{
     prelude::*,
-    widgets::{block::Title, *},
+    widgets::{
+        block::{Position, Title},
+        *,
+    },
     Terminal,
 };
 
 enum ThreadMessage {
     Stop,
+    Key(KeyCode),
 }
 
 /// Generates a gui that runs a function until the user provides input.
@@ -29,45 +30,124 @@ where
     F: Fn(T) -> T + Send + Sync + 'static,
     T: Send + Sync + 'static,
 {
-    enable_raw_mode()?;
-    stdout().execute(EnterAlternateScreen)?;
+    run(init, f, |_| vec![], |_, _| {}, true)
+}
+
+/// Like [`fold_until_stop`], but after each step of the fold, `positions` is
+/// called on the new state and the tag positions it returns are drawn live,
+/// so that we can see that the hardware is tracking.
+///
+/// Only `<Q>` or `<Esc>` stop the fold. Any other key press is handed to
+/// `on_key`, along with the state, between steps of the fold.
+pub fn fold_until_stop_with_meter<F, P, K, T>(
+    init: T,
+    f: F,
+    positions: P,
+    on_key: K,
+) -> Result<T, GrapeGuiError>
+where
+    F: Fn(T) -> T + Send + Sync + 'static,
+    P: Fn(&T) -> Vec<BufferMetadata> + Send + Sync + 'static,
+    K: Fn(&mut T, KeyCode) + Send + Sync + 'static,
+    T: Send + Sync + 'static,
+{
+    run(init, f, positions, on_key, false)
+}
+
+fn run<F, P, K, T>(
+    init: T,
+    f: F,
+    positions: P,
+    on_key: K,
+    stop_on_any_key: bool,
+) -> Result<T, GrapeGuiError>
+where
+    F: Fn(T) -> T + Send + Sync + 'static,
+    P: Fn(&T) -> Vec<BufferMetadata> + Send + Sync + 'static,
+    K: Fn(&mut T, KeyCode) + Send + Sync + 'static,
+    T: Send + Sync + 'static,
+{
+    let guard = TerminalGuard::enter()?;
     let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
     terminal.clear()?;
 
     let (stop_tx, stop_rx) = mpsc::channel();
     let (res_tx, res_rx) = mpsc::channel();
+    let (pos_tx, pos_rx) = mpsc::channel();
 
     let th = spawn(move || {
         let mut val = init;
 
         loop {
             val = f(val);
-            if let Ok(ThreadMessage::Stop) = stop_rx.try_recv() {
+            // The gui may have already hung up, in which case nobody is
+            // looking at the positions anyway
+            let _ = pos_tx.send(positions(&val));
+            let mut stop = false;
+            for msg in stop_rx.try_iter() {
+                match msg {
+                    ThreadMessage::Key(code) => on_key(&mut val, code),
+                    ThreadMessage::Stop => stop = true,
+                }
+            }
+            if stop {
                 res_tx.send(val).unwrap();
                 break;
             }
         }
     });
 
+    let stop_hint = if stop_on_any_key {
+        " Press any key to stop "
+    } else {
+        " Press <Q> to stop "
+    };
+    let mut latest_positions = vec![];
     loop {
         let title = Title::from(" Monitoring Tag Positions... ".magenta().bold());
-        let text = Paragraph::new(Line::from(vec![
-            " Things are happening! ".into(),
-            " Press any key to stop ".into(),
-        ]));
-        let block = Block::default()
+        let mut block = Block::default()
             .title(title.alignment(Alignment::Center))
             .borders(Borders::ALL);
+        if !stop_on_any_key {
+            let instructions = Title::from(Line::from(vec![
+                " Select Tag ".into(),
+                "<1-9>".magenta().bold(),
+                " Gain ".into(),
+                "<Up>/<Down>".magenta().bold(),
+                " Quit ".into(),
+                "<Q> ".magenta().bold(),
+            ]));
+            block = block.title(
+                instructions
+                    .alignment(Alignment::Center)
+                    .position(Position::Bottom),
+            );
+        }
+        if let Some(positions) = pos_rx.try_iter().last() {
+            latest_positions = positions;
+        }
         terminal
             .draw(|frame| {
                 let area = frame.size();
+                let text = if latest_positions.is_empty() {
+                    Paragraph::new(Line::from(vec![
+                        " Things are happening! ".into(),
+                        stop_hint.into(),
+                    ]))
+                } else {
+                    tag_meter(&latest_positions, area.width as usize)
+                };
                 frame.render_widget(text.block(block), area);
             })
             .unwrap();
         if event::poll(std::time::Duration::from_millis(16)).unwrap() {
             if let event::Event::Key(key) = event::read().unwrap() {
                 if key.kind == KeyEventKind::Press {
-                    break;
+                    match key.code {
+                        _ if stop_on_any_key => break,
+                        KeyCode::Char('q') | KeyCode::Esc => break,
+                        code => stop_tx.send(ThreadMessage::Key(code))?,
+                    }
                 }
             }
         }
@@ -76,8 +156,7 @@ where
     stop_tx.send(ThreadMessage::Stop)?;
     let res = res_rx.recv()?;
     th.join().map_err(|_| GrapeGuiError::JoinError)?;
-    disable_raw_mode()?;
-    stdout().execute(LeaveAlternateScreen)?;
+    drop(guard);
 
     Ok(res)
 }
@@ -0,0 +1,105 @@
+use crate::sphericalizer::Sphericalizer;
+
+use crossterm::event::KeyCode;
+
+/// How much a single arrow key press changes the gain by
+const GAIN_STEP: f32 = 0.1;
+
+/// Lets the user adjust the gain of each tag while capturing. The number keys
+/// select a tag, and the arrow keys nudge its gain up or down.
+#[derive(Debug, Clone, Default)]
+pub struct GainControl {
+    selected: usize,
+}
+
+impl GainControl {
+    /// Instantiates a new `GainControl` with the first tag selected.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The index of the tag whose gain the arrow keys will change.
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    /// Updates the selected tag, or the selected tag's gain in `sphericalizer`,
+    /// in response to a key press. Keys that don't mean anything here, or
+    /// that select a tag that doesn't exist, are ignored.
+    pub fn handle_key(&mut self, key: KeyCode, sphericalizer: &mut Sphericalizer) {
+        let step = match key {
+            KeyCode::Char(c) => {
+                // Tags are numbered from 1 on screen
+                if let Some(tag) = c.to_digit(10).and_then(|d| (d as usize).checked_sub(1)) {
+                    if tag < sphericalizer.num_tags() {
+                        self.selected = tag;
+                    }
+                }
+                return;
+            }
+            KeyCode::Up | KeyCode::Right => GAIN_STEP,
+            KeyCode::Down | KeyCode::Left => -GAIN_STEP,
+            _ => return,
+        };
+
+        if let Some(gain) = sphericalizer.gain(self.selected) {
+            sphericalizer.set_gain(self.selected, (gain + step).max(0.0));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(keys: &[KeyCode]) -> (GainControl, Sphericalizer) {
+        let mut control = GainControl::new();
+        let mut sphericalizer = Sphericalizer::new(vec![(1.0, 1.0), (0.5, 2.0)]);
+        for &key in keys {
+            control.handle_key(key, &mut sphericalizer);
+        }
+        (control, sphericalizer)
+    }
+
+    fn assert_gains(sphericalizer: &Sphericalizer, expected: [f32; 2]) {
+        for (tag, expected) in expected.into_iter().enumerate() {
+            let gain = sphericalizer.gain(tag).unwrap();
+            assert!((gain - expected).abs() < 1e-6, "{gain} != {expected}");
+        }
+    }
+
+    #[test]
+    fn arrows_adjust_first_tag() {
+        let (control, sphericalizer) = press(&[KeyCode::Up, KeyCode::Up, KeyCode::Down]);
+        assert_eq!(0, control.selected());
+        assert_gains(&sphericalizer, [1.1, 0.5]);
+    }
+
+    #[test]
+    fn number_keys_select_tag() {
+        let (control, sphericalizer) = press(&[KeyCode::Char('2'), KeyCode::Right]);
+        assert_eq!(1, control.selected());
+        assert_gains(&sphericalizer, [1.0, 0.6]);
+    }
+
+    #[test]
+    fn unknown_tags_are_ignored() {
+        let (control, sphericalizer) = press(&[
+            KeyCode::Char('2'),
+            KeyCode::Char('3'),
+            KeyCode::Char('0'),
+            KeyCode::Char('x'),
+            KeyCode::Up,
+        ]);
+        assert_eq!(1, control.selected());
+        assert_gains(&sphericalizer, [1.0, 0.6]);
+    }
+
+    #[test]
+    fn gain_never_goes_negative() {
+        let mut keys = vec![KeyCode::Char('2')];
+        keys.extend([KeyCode::Left; 10]);
+        let (_, sphericalizer) = press(&keys);
+        assert_gains(&sphericalizer, [1.0, 0.0]);
+    }
+}
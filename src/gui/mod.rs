@@ -4,7 +4,14 @@
 mod device_selector;
 mod error;
 mod fold_until_stop;
+mod gain_control;
+mod setup_form;
+mod tag_meter;
+mod terminal_guard;
 
 pub use device_selector::device_selector;
 pub use error::GrapeGuiError;
-pub use fold_until_stop::fold_until_stop;
+pub use fold_until_stop::{fold_until_stop, fold_until_stop_with_meter};
+pub use gain_control::GainControl;
+pub use setup_form::setup_form;
+pub use terminal_guard::TerminalGuard;
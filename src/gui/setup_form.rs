@@ -0,0 +1,567 @@
+use std::{
+    fs, io,
+    io::stdout,
+    path::{Path, PathBuf},
+};
+
+use crate::binaural_config::{BinauralConfig, SourceConfig};
+use crate::gui::{error::GrapeGuiError, terminal_guard::TerminalGuard};
+
+use crossterm::event::{self, KeyCode, KeyEventKind};
+use ratatui::{
+    prelude::*,
+    widgets::{
+        block::{Position, Title},
+        *,
+    },
+    Terminal,
+};
+
+/// Generates a TUI form for setting up the sources of a binaural run. The
+/// user adds sources, picking a file for each from a file browser that starts
+/// in `start_dir`, and sets their gains and ranges. Returns the sources as a
+/// [`BinauralConfig`] once the user confirms them, or `None` if they quit.
+pub fn setup_form(start_dir: impl AsRef<Path>) -> Result<Option<BinauralConfig>, GrapeGuiError> {
+    let mut form = SetupForm::new(fs::canonicalize(start_dir)?);
+
+    let guard = TerminalGuard::enter()?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
+    terminal.clear()?;
+
+    let outcome = loop {
+        terminal.draw(|frame| form.render(frame))?;
+        if event::poll(std::time::Duration::from_millis(16))? {
+            if let event::Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press {
+                    match form.handle_key(key.code) {
+                        Outcome::Continue => {}
+                        outcome => break outcome,
+                    }
+                }
+            }
+        }
+    };
+
+    drop(guard);
+
+    Ok((outcome == Outcome::Confirmed).then(|| form.into_config()))
+}
+
+/// What the form wants to happen after a key press
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Outcome {
+    /// Keep showing the form
+    Continue,
+    /// The user is happy with the sources
+    Confirmed,
+    /// The user gave up
+    Cancelled,
+}
+
+/// The settings of a source that can be typed in
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Field {
+    Gain,
+    Range,
+}
+
+impl Field {
+    fn name(self) -> &'static str {
+        match self {
+            Field::Gain => "gain",
+            Field::Range => "range",
+        }
+    }
+}
+
+/// What the form is showing
+#[derive(Debug, PartialEq)]
+enum Mode {
+    /// The list of sources
+    Sources,
+    /// A file browser, picking a file either for a new source or, if
+    /// `replace` is set, for the selected one
+    Browsing { browser: FileBrowser, replace: bool },
+    /// A prompt for a new value of one of the selected source's settings
+    Editing { field: Field, text: String },
+}
+
+/// The state of the setup form, kept apart from the terminal so that it can
+/// be driven by key presses alone.
+#[derive(Debug)]
+struct SetupForm {
+    sources: Vec<SourceConfig>,
+    /// The selected source
+    cursor: usize,
+    mode: Mode,
+    /// Where the file browser opens, which is wherever the last file was
+    /// picked from
+    dir: PathBuf,
+    /// A message for the user about their last key press
+    message: Option<String>,
+}
+
+impl SetupForm {
+    fn new(dir: PathBuf) -> Self {
+        Self {
+            sources: vec![],
+            cursor: 0,
+            mode: Mode::Sources,
+            dir,
+            message: None,
+        }
+    }
+
+    fn into_config(self) -> BinauralConfig {
+        BinauralConfig {
+            sources: self.sources,
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyCode) -> Outcome {
+        self.message = None;
+        match std::mem::replace(&mut self.mode, Mode::Sources) {
+            Mode::Sources => return self.handle_sources_key(key),
+            Mode::Browsing { browser, replace } => self.handle_browsing_key(key, browser, replace),
+            Mode::Editing { field, text } => self.handle_editing_key(key, field, text),
+        }
+        Outcome::Continue
+    }
+
+    fn handle_sources_key(&mut self, key: KeyCode) -> Outcome {
+        let n_sources = self.sources.len();
+        match key {
+            KeyCode::Esc | KeyCode::Char('q') => return Outcome::Cancelled,
+            KeyCode::Enter if n_sources == 0 => {
+                self.message = Some("Add a source first".to_string());
+            }
+            KeyCode::Enter => return Outcome::Confirmed,
+            KeyCode::Char('a') => self.browse(false),
+            // Everything else needs a source to work on
+            _ if n_sources == 0 => {}
+            KeyCode::Down => self.cursor = (self.cursor + 1) % n_sources,
+            KeyCode::Up => self.cursor = (self.cursor + n_sources - 1) % n_sources,
+            KeyCode::Char('f') => self.browse(true),
+            KeyCode::Char('g') => self.edit(Field::Gain),
+            KeyCode::Char('r') => self.edit(Field::Range),
+            KeyCode::Char('d') | KeyCode::Delete => {
+                self.sources.remove(self.cursor);
+                self.cursor = self.cursor.min(self.sources.len().saturating_sub(1));
+            }
+            _ => {}
+        }
+        Outcome::Continue
+    }
+
+    fn handle_browsing_key(&mut self, key: KeyCode, mut browser: FileBrowser, replace: bool) {
+        let n_entries = browser.entries.len();
+        match key {
+            KeyCode::Esc => return,
+            _ if n_entries == 0 => {}
+            KeyCode::Down => browser.cursor = (browser.cursor + 1) % n_entries,
+            KeyCode::Up => browser.cursor = (browser.cursor + n_entries - 1) % n_entries,
+            KeyCode::Enter => {
+                let entry = &browser.entries[browser.cursor];
+                if entry.is_dir {
+                    match FileBrowser::open(&entry.path) {
+                        Ok(inner) => browser = inner,
+                        Err(e) => self.message = Some(format!("Couldn't open {}: {e}", entry.name)),
+                    }
+                } else {
+                    let file = entry.path.to_string_lossy().into_owned();
+                    if replace {
+                        self.sources[self.cursor].file = file;
+                    } else {
+                        self.sources.push(SourceConfig {
+                            file,
+                            gain: 1.0,
+                            range: 1.0,
+                            tag: None,
+                        });
+                        self.cursor = self.sources.len() - 1;
+                    }
+                    self.dir = browser.dir;
+                    return;
+                }
+            }
+            _ => {}
+        }
+        self.mode = Mode::Browsing { browser, replace };
+    }
+
+    fn handle_editing_key(&mut self, key: KeyCode, field: Field, mut text: String) {
+        match key {
+            KeyCode::Esc => return,
+            KeyCode::Char(c) if c.is_ascii_digit() || c == '.' => text.push(c),
+            KeyCode::Backspace => {
+                text.pop();
+            }
+            KeyCode::Enter => match text.parse::<f32>() {
+                Ok(value) if value.is_finite() && (value > 0.0 || field == Field::Gain) => {
+                    let source = &mut self.sources[self.cursor];
+                    match field {
+                        Field::Gain => source.gain = value,
+                        Field::Range => source.range = value,
+                    }
+                    return;
+                }
+                _ => self.message = Some(format!("{text:?} isn't a valid {}", field.name())),
+            },
+            _ => {}
+        }
+        self.mode = Mode::Editing { field, text };
+    }
+
+    /// Opens the file browser where the last file was picked from.
+    fn browse(&mut self, replace: bool) {
+        match FileBrowser::open(&self.dir) {
+            Ok(browser) => self.mode = Mode::Browsing { browser, replace },
+            Err(e) => self.message = Some(format!("Couldn't open {}: {e}", self.dir.display())),
+        }
+    }
+
+    /// Starts typing in a new value for `field` of the selected source.
+    fn edit(&mut self, field: Field) {
+        let source = &self.sources[self.cursor];
+        let value = match field {
+            Field::Gain => source.gain,
+            Field::Range => source.range,
+        };
+        self.mode = Mode::Editing {
+            field,
+            text: value.to_string(),
+        };
+    }
+
+    fn render(&self, frame: &mut Frame) {
+        let (title, instructions, items, cursor) = match &self.mode {
+            Mode::Browsing { browser, .. } => (
+                format!(" {} ", browser.dir.display()),
+                vec![
+                    ("Navigate", "<Up>/<Down>"),
+                    ("Open", "<Enter>"),
+                    ("Back", "<Esc>"),
+                ],
+                browser.entries.iter().map(|e| e.name.clone()).collect(),
+                browser.cursor,
+            ),
+            Mode::Sources | Mode::Editing { .. } => (
+                " Setup ".to_string(),
+                vec![
+                    ("Navigate", "<Up>/<Down>"),
+                    ("Add", "<a>"),
+                    ("File", "<f>"),
+                    ("Gain", "<g>"),
+                    ("Range", "<r>"),
+                    ("Delete", "<d>"),
+                    ("Done", "<Enter>"),
+                    ("Quit", "<Esc>"),
+                ],
+                self.sources
+                    .iter()
+                    .map(|s| format!("{}  gain {:.2}  range {:.2}", s.file, s.gain, s.range))
+                    .collect::<Vec<_>>(),
+                self.cursor,
+            ),
+        };
+
+        let instructions = instructions
+            .into_iter()
+            .flat_map(|(action, key)| [format!(" {action} ").into(), key.magenta().bold()])
+            .collect::<Vec<_>>();
+        let block = Block::default()
+            .title(Title::from(title.magenta().bold()).alignment(Alignment::Center))
+            .title(
+                Title::from(Line::from(instructions))
+                    .alignment(Alignment::Center)
+                    .position(Position::Bottom),
+            )
+            .borders(Borders::ALL);
+
+        // A line at the bottom for prompts and messages
+        let [list_area, status_area] = {
+            let areas =
+                Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).split(frame.size());
+            [areas[0], areas[1]]
+        };
+
+        let n_items = items.len();
+        let list = List::new(items)
+            .style(Style::default().fg(Color::White))
+            .highlight_symbol(">>")
+            .highlight_style(Style::default().fg(Color::Magenta))
+            .block(block);
+        let mut list_state = ListState::default().with_selected((n_items > 0).then_some(cursor));
+        frame.render_stateful_widget(list, list_area, &mut list_state);
+
+        let status = match (&self.mode, &self.message) {
+            (_, Some(message)) => message.clone().red(),
+            (Mode::Editing { field, text }, None) => {
+                format!("New {}: {text}_", field.name()).bold()
+            }
+            _ => "".into(),
+        };
+        frame.render_widget(Paragraph::new(status), status_area);
+    }
+}
+
+/// Something in the directory being browsed
+#[derive(Debug, PartialEq, Clone)]
+struct Entry {
+    name: String,
+    path: PathBuf,
+    is_dir: bool,
+}
+
+/// The contents of a directory, for picking a file out of
+#[derive(Debug, PartialEq)]
+struct FileBrowser {
+    dir: PathBuf,
+    /// The parent directory, as `..`, then the directories and then the
+    /// files in `dir`, each sorted by name
+    entries: Vec<Entry>,
+    /// The selected entry
+    cursor: usize,
+}
+
+impl FileBrowser {
+    fn open(dir: &Path) -> io::Result<Self> {
+        let mut entries = fs::read_dir(dir)?
+            .filter_map(Result::ok)
+            .map(|e| Entry {
+                name: e.file_name().to_string_lossy().into_owned(),
+                path: e.path(),
+                is_dir: e.path().is_dir(),
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+
+        if let Some(parent) = dir.parent() {
+            entries.insert(
+                0,
+                Entry {
+                    name: "..".to_string(),
+                    path: parent.to_path_buf(),
+                    is_dir: true,
+                },
+            );
+        }
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            entries,
+            cursor: 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory with a couple of sound files and a folder with another
+    fn sounds() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("b.wav"), "").unwrap();
+        fs::write(dir.path().join("a.wav"), "").unwrap();
+        fs::create_dir(dir.path().join("more")).unwrap();
+        fs::write(dir.path().join("more").join("c.wav"), "").unwrap();
+        dir
+    }
+
+    fn press(form: &mut SetupForm, keys: &[KeyCode]) -> Outcome {
+        keys.iter()
+            .map(|&key| form.handle_key(key))
+            .last()
+            .unwrap_or(Outcome::Continue)
+    }
+
+    fn browsed(form: &SetupForm) -> Vec<&str> {
+        match &form.mode {
+            Mode::Browsing { browser, .. } => {
+                browser.entries.iter().map(|e| e.name.as_str()).collect()
+            }
+            mode => panic!("expected to be browsing, not {mode:?}"),
+        }
+    }
+
+    #[test]
+    fn add_sources_from_the_browser() {
+        let dir = sounds();
+        let mut form = SetupForm::new(dir.path().to_path_buf());
+
+        press(&mut form, &[KeyCode::Char('a')]);
+        assert_eq!(vec!["..", "more", "a.wav", "b.wav"], browsed(&form));
+
+        // Into the folder, and pick the file in there
+        press(&mut form, &[KeyCode::Down, KeyCode::Enter]);
+        assert_eq!(vec!["..", "c.wav"], browsed(&form));
+        press(&mut form, &[KeyCode::Down, KeyCode::Enter]);
+        assert_eq!(Mode::Sources, form.mode);
+        assert_eq!(1, form.sources.len());
+
+        // The browser opens where we left it, so back out to get b.wav
+        press(&mut form, &[KeyCode::Char('a'), KeyCode::Enter]);
+        assert_eq!(vec!["..", "more", "a.wav", "b.wav"], browsed(&form));
+        press(&mut form, &[KeyCode::Up, KeyCode::Enter]);
+        assert_eq!(1, form.cursor);
+
+        let files = form
+            .sources
+            .iter()
+            .map(|s| PathBuf::from(&s.file))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                dir.path().join("more").join("c.wav"),
+                dir.path().join("b.wav")
+            ],
+            files
+        );
+        assert!(form.sources.iter().all(|s| s.gain == 1.0 && s.range == 1.0));
+    }
+
+    #[test]
+    fn back_out_of_the_browser() {
+        let dir = sounds();
+        let mut form = SetupForm::new(dir.path().to_path_buf());
+        press(&mut form, &[KeyCode::Char('a'), KeyCode::Esc]);
+        assert_eq!(Mode::Sources, form.mode);
+        assert!(form.sources.is_empty());
+    }
+
+    #[test]
+    fn replace_a_file() {
+        let dir = sounds();
+        let mut form = SetupForm::new(dir.path().to_path_buf());
+        let a = [KeyCode::Down, KeyCode::Down, KeyCode::Enter];
+        press(&mut form, &[KeyCode::Char('a')]);
+        press(&mut form, &a);
+        press(
+            &mut form,
+            &[KeyCode::Char('g'), KeyCode::Backspace, KeyCode::Char('2')],
+        );
+        press(
+            &mut form,
+            &[
+                KeyCode::Enter,
+                KeyCode::Char('f'),
+                KeyCode::Up,
+                KeyCode::Enter,
+            ],
+        );
+
+        assert_eq!(1, form.sources.len());
+        assert_eq!(
+            dir.path().join("b.wav"),
+            PathBuf::from(&form.sources[0].file)
+        );
+        // The rest of the source is left alone
+        assert_eq!(2.0, form.sources[0].gain);
+    }
+
+    #[test]
+    fn edit_gain_and_range() {
+        let dir = sounds();
+        let mut form = SetupForm::new(dir.path().to_path_buf());
+        press(
+            &mut form,
+            &[KeyCode::Char('a'), KeyCode::Up, KeyCode::Enter],
+        );
+
+        press(&mut form, &[KeyCode::Char('g')]);
+        assert_eq!(
+            Mode::Editing {
+                field: Field::Gain,
+                text: "1".to_string()
+            },
+            form.mode
+        );
+        // Letters are ignored
+        let keys = [KeyCode::Backspace, KeyCode::Char('0'), KeyCode::Char('x')];
+        press(&mut form, &keys);
+        press(
+            &mut form,
+            &[KeyCode::Char('.'), KeyCode::Char('5'), KeyCode::Enter],
+        );
+        assert_eq!(Mode::Sources, form.mode);
+        assert_eq!(0.5, form.sources[0].gain);
+
+        // Not a number, so we're still editing, until we give up
+        let keys = [KeyCode::Backspace, KeyCode::Char('.'), KeyCode::Enter];
+        press(&mut form, &[KeyCode::Char('r')]);
+        press(&mut form, &keys);
+        assert!(matches!(
+            form.mode,
+            Mode::Editing {
+                field: Field::Range,
+                ..
+            }
+        ));
+        assert!(form.message.is_some());
+        press(&mut form, &[KeyCode::Esc]);
+        assert_eq!(Mode::Sources, form.mode);
+        assert_eq!(1.0, form.sources[0].range);
+
+        // A source can be silent, but it can't be right on top of the listener
+        press(&mut form, &[KeyCode::Char('g'), KeyCode::Backspace]);
+        press(&mut form, &[KeyCode::Backspace, KeyCode::Backspace]);
+        press(
+            &mut form,
+            &[KeyCode::Backspace, KeyCode::Char('0'), KeyCode::Enter],
+        );
+        assert_eq!(0.0, form.sources[0].gain);
+        press(&mut form, &[KeyCode::Char('r'), KeyCode::Backspace]);
+        press(&mut form, &[KeyCode::Char('0'), KeyCode::Enter]);
+        assert!(matches!(
+            form.mode,
+            Mode::Editing {
+                field: Field::Range,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn confirm_or_cancel() {
+        let dir = sounds();
+        let mut form = SetupForm::new(dir.path().to_path_buf());
+
+        // There's nothing to confirm yet
+        assert_eq!(Outcome::Continue, press(&mut form, &[KeyCode::Enter]));
+        assert!(form.message.is_some());
+        // Nothing to edit or delete either
+        press(&mut form, &[KeyCode::Char('g'), KeyCode::Char('d')]);
+        assert_eq!(Mode::Sources, form.mode);
+
+        press(
+            &mut form,
+            &[KeyCode::Char('a'), KeyCode::Up, KeyCode::Enter],
+        );
+        press(
+            &mut form,
+            &[KeyCode::Char('a'), KeyCode::Up, KeyCode::Up, KeyCode::Enter],
+        );
+        press(&mut form, &[KeyCode::Char('d')]);
+        assert_eq!(0, form.cursor);
+        assert_eq!(Outcome::Confirmed, press(&mut form, &[KeyCode::Enter]));
+
+        let config = form.into_config();
+        assert_eq!(
+            vec![SourceConfig {
+                file: dir.path().join("b.wav").to_string_lossy().into_owned(),
+                gain: 1.0,
+                range: 1.0,
+                tag: None,
+            }],
+            config.sources
+        );
+
+        let mut form = SetupForm::new(dir.path().to_path_buf());
+        press(
+            &mut form,
+            &[KeyCode::Char('a'), KeyCode::Up, KeyCode::Enter],
+        );
+        assert_eq!(Outcome::Cancelled, press(&mut form, &[KeyCode::Esc]));
+    }
+}
@@ -0,0 +1,60 @@
+use std::f32::consts::TAU;
+
+use crate::saf::BufferMetadata;
+
+use ratatui::{prelude::*, widgets::*};
+
+/// Maps an azimuth, in radians, onto one of `width` cells of a bar, where the
+/// first cell is straight ahead and the bar wraps all the way around the
+/// listener.
+pub fn azimuth_to_bar(azimuth: f32, width: usize) -> usize {
+    let turns = azimuth.rem_euclid(TAU) / TAU;
+    ((turns * width as f32) as usize).min(width.saturating_sub(1))
+}
+
+/// Draws one bar per tag, with a marker at the tag's current azimuth.
+pub fn tag_meter<'a>(positions: &[BufferMetadata], width: usize) -> Paragraph<'a> {
+    // Leave room for the label in front of the bar and the angle after it
+    let bar_width = width.saturating_sub(34);
+
+    let lines = positions
+        .iter()
+        .enumerate()
+        .map(|(i, position)| {
+            let marker = azimuth_to_bar(position.azimuth, bar_width);
+            let bar = (0..bar_width)
+                .map(|cell| if cell == marker { '●' } else { '─' })
+                .collect::<String>();
+            Line::from(vec![
+                format!(" Tag {:<3}", i + 1).into(),
+                bar.magenta(),
+                format!(" {:>6.1}° ", position.azimuth.to_degrees()).into(),
+                format!(" gain {:.1} ", position.gain).into(),
+            ])
+        })
+        .collect::<Vec<_>>();
+
+    Paragraph::new(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    #[test]
+    fn azimuths_map_onto_bar() {
+        assert_eq!(0, azimuth_to_bar(0.0, 36));
+        assert_eq!(9, azimuth_to_bar(PI / 2.0, 36));
+        assert_eq!(18, azimuth_to_bar(PI, 36));
+        assert_eq!(27, azimuth_to_bar(-PI / 2.0, 36));
+        assert_eq!(35, azimuth_to_bar(TAU - 0.001, 36));
+        assert_eq!(0, azimuth_to_bar(TAU, 36));
+    }
+
+    #[test]
+    fn empty_bar() {
+        assert_eq!(0, azimuth_to_bar(PI, 0));
+        assert_eq!(0, azimuth_to_bar(PI, 1));
+    }
+}
@@ -0,0 +1,135 @@
+use std::{
+    io::{self, stdout},
+    panic,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Once,
+    },
+};
+
+use crossterm::{
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    ExecutableCommand,
+};
+
+/// Whether the terminal is in the state that [`TerminalGuard::enter`] puts it
+/// in.
+static ENTERED: AtomicBool = AtomicBool::new(false);
+
+/// Installs the panic hook, the first time a guard is entered.
+static PANIC_HOOK: Once = Once::new();
+
+/// Puts the terminal back the way it was when it's dropped, so that a TUI
+/// that returns early, or panics, doesn't leave the user's terminal in raw
+/// mode on the alternate screen, with no echo.
+pub struct TerminalGuard {
+    restore: Option<Box<dyn FnOnce()>>,
+}
+
+impl TerminalGuard {
+    /// Switches the terminal to raw mode on the alternate screen, returning a
+    /// guard that switches it back. A panic switches it back too, before the
+    /// panic message is printed, since the message would be lost on the
+    /// alternate screen.
+    pub fn enter() -> io::Result<Self> {
+        PANIC_HOOK.call_once(install_panic_hook);
+        enable_raw_mode()?;
+        ENTERED.store(true, Ordering::SeqCst);
+        let guard = Self::new(|| restore_once(&ENTERED, restore_terminal));
+        stdout().execute(EnterAlternateScreen)?;
+        Ok(guard)
+    }
+
+    /// Returns a guard that calls `restore` when it's dropped, for TUIs that
+    /// set up the terminal in some other way.
+    pub fn new(restore: impl FnOnce() + 'static) -> Self {
+        Self {
+            restore: Some(Box::new(restore)),
+        }
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        if let Some(restore) = self.restore.take() {
+            restore();
+        }
+    }
+}
+
+/// Restores the terminal when a guard is entered and something panics, and
+/// then carries on to whatever hook was there before, which prints the
+/// message. The guard is only dropped after that, as the panic unwinds.
+fn install_panic_hook() {
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        restore_once(&ENTERED, restore_terminal);
+        previous(info);
+    }));
+}
+
+/// Calls `restore` if `entered` is set, clearing it, so that the terminal is
+/// only restored once, by whichever of the panic hook and the guard gets
+/// there first.
+fn restore_once(entered: &AtomicBool, restore: impl FnOnce()) {
+    if entered.swap(false, Ordering::SeqCst) {
+        restore();
+    }
+}
+
+/// Undoes [`TerminalGuard::enter`]. There's nothing to be done if this
+/// fails, so the errors are ignored.
+fn restore_terminal() {
+    let _ = disable_raw_mode();
+    let _ = stdout().execute(LeaveAlternateScreen);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::panic::catch_unwind;
+    use std::sync::{atomic::AtomicUsize, Arc};
+
+    /// A guard that counts how many times it restores the terminal
+    fn counting_guard(restored: &Arc<AtomicUsize>) -> TerminalGuard {
+        let restored = restored.clone();
+        TerminalGuard::new(move || {
+            restored.fetch_add(1, Ordering::SeqCst);
+        })
+    }
+
+    #[test]
+    fn restores_once_when_dropped() {
+        let restored = Arc::new(AtomicUsize::new(0));
+        let guard = counting_guard(&restored);
+        assert_eq!(0, restored.load(Ordering::SeqCst));
+        drop(guard);
+        assert_eq!(1, restored.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn restores_on_panic() {
+        let restored = Arc::new(AtomicUsize::new(0));
+        let result = catch_unwind(|| {
+            let _guard = counting_guard(&restored);
+            panic!("the TUI fell over");
+        });
+        assert!(result.is_err());
+        assert_eq!(1, restored.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn restores_once_after_the_panic_hook() {
+        let entered = AtomicBool::new(true);
+        let restored = AtomicUsize::new(0);
+        let restore = || {
+            restored.fetch_add(1, Ordering::SeqCst);
+        };
+
+        // The hook gets there first, and then the guard is dropped
+        restore_once(&entered, restore);
+        restore_once(&entered, restore);
+        assert_eq!(1, restored.load(Ordering::SeqCst));
+        assert!(!entered.load(Ordering::SeqCst));
+    }
+}
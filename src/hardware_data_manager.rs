@@ -1,5 +1,8 @@
 //! An interface definition for the hardware/software barrier.
 
+use serde::{Deserialize, Serialize};
+use std::f64::consts::TAU;
+
 // This is just like the typedef you've seen in C. `pub` means that someone
 // who imports this module (everything in this file) will have access to those
 // type aliases.
@@ -22,7 +25,7 @@ pub type Id = usize;
 // to work.
 
 /// This struct represents a radial measurement taken from `src` to `dst`.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Update {
     /// The identifier of the object that we are measuring from
     pub src: Id,
@@ -32,6 +35,49 @@ pub struct Update {
     pub elv: Radian,
     /// The radians of the angle from `src` to `dst` in the x/y plane, which we call the "azimuth".
     pub azm: Radian,
+    /// The received signal strength of the measurement in dBm, if the
+    /// hardware told us. Stronger (closer to zero) means more confident.
+    #[serde(default)]
+    pub rssi: Option<i32>,
+}
+
+/// Turns the whole degrees that the antenna reports its `angle_1` and
+/// `angle_2` in into radians, giving the azimuth and elevation, in that
+/// order.
+pub fn angles_to_radians(angle_1: i32, angle_2: i32) -> (Radian, Radian) {
+    ((angle_1 as f64).to_radians(), (angle_2 as f64).to_radians())
+}
+
+/// Brings an azimuth into the range that the crate keeps azimuths in, from 0
+/// up to (but not including) 2π. Azimuths are measured in radians from
+/// straight ahead, the positive x axis, turning to the left, so a sound at
+/// π/2 is on the listener's left and one at 3π/2 (or -π/2, before it's
+/// normalized) is on their right.
+///
+/// The one exception is the raw `azm` of an [`Update`] straight from an
+/// antenna, which is a signed angle either side of where that antenna is
+/// pointing. The [`Sphericalizer`](crate::sphericalizer::Sphericalizer)
+/// turns those into azimuths around the listener.
+pub fn normalize_azimuth(azimuth: Radian) -> Radian {
+    let azimuth = azimuth.rem_euclid(TAU);
+    // Tiny negative angles round up to a whole turn
+    if azimuth >= TAU {
+        0.0
+    } else {
+        azimuth
+    }
+}
+
+/// [normalize_azimuth] for the `f32` azimuths that the rest of the pipeline
+/// works in. An azimuth just below 2π can round up to a whole turn when it's
+/// cast down, so that is caught after the cast.
+pub fn normalize_azimuth_f32(azimuth: f32) -> f32 {
+    let azimuth = normalize_azimuth(azimuth.into()) as f32;
+    if azimuth >= std::f32::consts::TAU {
+        0.0
+    } else {
+        azimuth
+    }
 }
 
 // A `trait` is like an Interface in Java or an abstract Class in C++. It
@@ -47,3 +93,67 @@ pub trait HardwareDataManager: Iterator<Item = Update> {
     /// with the `Update`s and wants to skip forward to the most recent items.
     fn clear(&mut self);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn update_serde_round_trip() {
+        let update = Update {
+            src: 0,
+            dst: 1,
+            elv: 0.25,
+            azm: -1.5,
+            rssi: Some(-42),
+        };
+
+        let text = ron::ser::to_string(&update).unwrap();
+        let read: Update = ron::de::from_str(&text).unwrap();
+        assert_eq!(update, read);
+    }
+
+    #[test]
+    fn degrees_to_radians() {
+        use std::f64::consts::{FRAC_PI_2, FRAC_PI_4};
+
+        let (azm, elv) = angles_to_radians(90, -45);
+        assert!((azm - FRAC_PI_2).abs() < 1e-12);
+        assert!((elv + FRAC_PI_4).abs() < 1e-12);
+        assert_eq!((0.0, 0.0), angles_to_radians(0, 0));
+    }
+
+    #[test]
+    fn azimuths_normalize_into_one_turn() {
+        use std::f64::consts::{FRAC_PI_2, PI};
+
+        let cases = [
+            (0.0, 0.0),
+            (FRAC_PI_2, FRAC_PI_2),
+            (-FRAC_PI_2, 3.0 * FRAC_PI_2),
+            (TAU, 0.0),
+            (-PI, PI),
+            (5.0 * PI, PI),
+            (-1e-18, 0.0),
+        ];
+        for (azimuth, expected) in cases {
+            let normalized = normalize_azimuth(azimuth);
+            assert!((0.0..TAU).contains(&normalized), "{}", azimuth);
+            assert!((normalized - expected).abs() < 1e-12, "{}", azimuth);
+            assert_eq!(normalized, normalize_azimuth(normalized));
+        }
+    }
+
+    #[test]
+    fn f32_azimuths_normalize_into_one_turn() {
+        use std::f32::consts::TAU;
+
+        for azimuth in [-1e-8, -1e-30, TAU, 2.0 * TAU - 1e-7, 0.0, 1.0] {
+            let normalized = normalize_azimuth_f32(azimuth);
+            assert!((0.0..TAU).contains(&normalized), "{}", azimuth);
+            assert_eq!(normalized, normalize_azimuth_f32(normalized));
+        }
+        assert_eq!(0.0, normalize_azimuth_f32(-1e-8));
+        assert_eq!(1.0, normalize_azimuth_f32(1.0));
+    }
+}
@@ -11,6 +11,8 @@ use nom::{
     Finish, IResult,
 };
 
+use crate::hardware_data_manager::{angles_to_radians, Update};
+
 use std::str::FromStr;
 
 /// The various kinds of messages that can be sent from the u-blox antenna.
@@ -72,10 +74,13 @@ pub struct UUDFEvent {
     pub angle_1: i32,
     /// Elevation to tag
     pub angle_2: i32,
-    /// u-blox won't say what they use this for...maybe curing cancer.
-    reserved: i32,
-    /// TODO what does it mean "channel", is this which Bluetooth channel?
-    /// Completely unclear from the u-blox documentation.
+    /// u-blox won't say what they use this for...maybe curing cancer. In the
+    /// readings we've seen, it tracks the signal strength within a dB or so.
+    /// Nothing in the pipeline relies on it.
+    pub reserved: i32,
+    /// The Bluetooth LE advertising channel (37, 38, or 39) that the reading
+    /// was taken on. The pipeline doesn't use it, but it shows whether one
+    /// channel is noisier than the others.
     pub channel: u32,
     /// The ID of the antenna
     pub anchor_id: u64,
@@ -101,6 +106,32 @@ impl FromStr for UUDFEvent {
     }
 }
 
+impl From<&UUDFEvent> for Update {
+    /// The measurement from the antenna to the tag, with its angles turned
+    /// from degrees into radians.
+    fn from(event: &UUDFEvent) -> Self {
+        let (azm, elv) = angles_to_radians(event.angle_1, event.angle_2);
+        Update {
+            src: event.anchor_id as usize,
+            dst: event.tag_id as usize,
+            azm,
+            elv,
+            rssi: Some(event.rssi),
+        }
+    }
+}
+
+impl TryFrom<HardwareEvent> for Update {
+    /// Events without a measurement are handed back
+    type Error = HardwareEvent;
+    fn try_from(event: HardwareEvent) -> Result<Self, Self::Error> {
+        match event {
+            HardwareEvent::UUDFEvent(e) => Ok(Update::from(&e)),
+            other => Err(other),
+        }
+    }
+}
+
 fn parse_id(s: &str) -> IResult<&str, u64> {
     map_res(hex_digit1, |d: &str| {
         if d.len() == 12 {
@@ -282,6 +313,15 @@ mod tests {
         );
     }
 
+    #[test]
+    fn diagnostic_fields_are_readable() {
+        let s = "+UUDF:CCF9578E0D8A,-42,20,0,-43,38,\"CCF9578E0D89\",\"\",15869,23";
+
+        let event = UUDFEvent::from_str(s).unwrap();
+        assert_eq!(-43, event.reserved);
+        assert_eq!(38, event.channel);
+    }
+
     #[test]
     fn hardware_event_uufdp_test() {
         let s = "+UUDFP:6C3DEBAFAEE4,19FF1500000050F80C0065000900052A0D001F000000D0030000";
@@ -294,4 +334,24 @@ mod tests {
             })
         );
     }
+
+    #[test]
+    fn uudf_to_update() {
+        let s = "+UUDF:CCF9578E0D8B,-41,-45,90,-42,38,\"CCF9578E0D89\",\"\",15892,24";
+        let event = UUDFEvent::from_str(s).unwrap();
+
+        let update = Update::from(&event);
+        assert_eq!(0xCCF9578E0D89, update.src);
+        assert_eq!(0xCCF9578E0D8B, update.dst);
+        assert!((update.azm + std::f64::consts::FRAC_PI_4).abs() < 1e-12);
+        assert!((update.elv - std::f64::consts::FRAC_PI_2).abs() < 1e-12);
+        assert_eq!(Some(-41), update.rssi);
+
+        assert_eq!(
+            Ok(update),
+            Update::try_from(HardwareEvent::UUDFEvent(event))
+        );
+        let heartbeat = HardwareEvent::UUDFPEvent(UUDFPEvent { tag_id: 1 });
+        assert_eq!(Err(heartbeat.clone()), Update::try_from(heartbeat));
+    }
 }
@@ -4,36 +4,187 @@ use crate::hardware_data_manager::{HardwareDataManager, Update};
 use crate::hardware_message_decoder::UUDFEvent;
 
 use std::{
-    collections::VecDeque,
-    f64::consts::PI,
+    collections::{HashMap, VecDeque},
     sync::{Arc, Mutex},
 };
 
+/// How many of the latest sequence numbers we remember for each antenna and
+/// tag, to spot readings that were sent twice.
+const RECENT_SEQUENCES: usize = 16;
+
+/// What an [`Hdm`] does with a new reading when its buffer is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// Make room by throwing away the oldest reading
+    #[default]
+    DropOldest,
+    /// Throw away the new reading
+    DropNewest,
+    /// Keep every reading anyway, ignoring the capacity
+    Grow,
+}
+
+/// What became of a reading given to [`Hdm::add_update`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddStatus {
+    /// The reading was added
+    Accepted,
+    /// The reading was added, and the oldest one was dropped to make room
+    /// for it
+    Evicted,
+    /// The buffer was full, so the reading was dropped
+    Full,
+    /// The reading was a repeat of a recent one, so it was dropped
+    Duplicate,
+}
+
+impl AddStatus {
+    /// Whether the reading made it into the buffer.
+    pub fn is_accepted(self) -> bool {
+        matches!(self, Self::Accepted | Self::Evicted)
+    }
+}
+
+/// A utility struct that enables configuration of the [`Hdm`]
+#[derive(Debug, Default)]
+pub struct HdmBuilder {
+    capacity: Option<usize>,
+    overflow: OverflowPolicy,
+}
+
+impl HdmBuilder {
+    /// Sets how many readings the [`Hdm`] holds before it starts following
+    /// its [`OverflowPolicy`]. By default there is no limit.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// Sets what the [`Hdm`] does with readings that come in while it's
+    /// full. Defaults to [`OverflowPolicy::DropOldest`].
+    pub fn overflow(mut self, overflow: OverflowPolicy) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Consumes the builder, instantiating a new [`Hdm`].
+    pub fn build(self) -> Hdm {
+        Hdm {
+            msgs: Arc::new(Mutex::new(VecDeque::with_capacity(
+                self.capacity.unwrap_or_default(),
+            ))),
+            recent: Mutex::new(HashMap::new()),
+            capacity: self.capacity,
+            overflow: self.overflow,
+        }
+    }
+}
+
 /// A [`HardwareDataManager`] that simply acts as a thread-safe buffer where
 /// we can store [`UUDFEvent`]s from the antennas.
 #[derive(Debug, Default)]
 pub struct Hdm {
     msgs: Arc<Mutex<VecDeque<Update>>>,
+    recent: Mutex<HashMap<(u64, u64), VecDeque<u32>>>,
+    capacity: Option<usize>,
+    overflow: OverflowPolicy,
 }
 
 impl Hdm {
     /// Instantiae a new [`Hdm`] with a thread-safe buffer.
     pub fn new() -> Self {
-        Hdm {
-            msgs: Arc::new(Mutex::new(VecDeque::new())),
+        Self::builder().build()
+    }
+
+    /// Emits a Builder that allows a user to configure a bounded [`Hdm`].
+    /// Call `.build()` on the resulting object to instantiate it.
+    pub fn builder() -> HdmBuilder {
+        HdmBuilder::default()
+    }
+
+    /// Insert a new [`UUDFEvent`] into the thread-safe buffer. The listener
+    /// block sometimes sends the same reading twice, so readings with the
+    /// same antenna, tag, and sequence number as a recent one are dropped.
+    /// If the buffer is full, its [`OverflowPolicy`] decides what to drop.
+    /// Only readings that are stored count as recent, so one dropped because
+    /// the buffer was full can still be sent again. Returns what became of
+    /// the reading.
+    pub fn add_update(&self, event: UUDFEvent) -> AddStatus {
+        let mut recent = self.recent.lock().unwrap();
+        let sequences = recent.entry((event.anchor_id, event.tag_id)).or_default();
+        if sequences.contains(&event.sequence) {
+            return AddStatus::Duplicate;
+        }
+
+        let new_update = Update::from(&event);
+
+        let mut msgs = self.msgs.lock().unwrap();
+        let mut status = AddStatus::Accepted;
+        if self.capacity.is_some_and(|capacity| msgs.len() >= capacity) {
+            match self.overflow {
+                // The newest readings are at the front
+                OverflowPolicy::DropOldest => {
+                    msgs.pop_back();
+                    status = AddStatus::Evicted;
+                }
+                OverflowPolicy::DropNewest => return AddStatus::Full,
+                OverflowPolicy::Grow => {}
+            }
         }
+        msgs.push_front(new_update);
+
+        if sequences.len() == RECENT_SEQUENCES {
+            sequences.pop_front();
+        }
+        sequences.push_back(event.sequence);
+        status
+    }
+}
+
+/// Watches the `sequence` numbers of each tag's [`UUDFEvent`]s, to find the
+/// readings that never made it to us over serial.
+#[derive(Debug, Default)]
+pub struct SequenceTracker {
+    last: HashMap<u64, u32>,
+    missed: HashMap<u64, u64>,
+}
+
+impl SequenceTracker {
+    /// Instantiate a new [`SequenceTracker`] that hasn't seen any readings.
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    /// Insert a new [`UUDFEvent`] into the thread-safe buffer.
-    pub fn add_update(&self, event: UUDFEvent) {
-        let new_update = Update {
-            src: event.anchor_id as usize,
-            dst: event.tag_id as usize,
-            azm: event.angle_1 as f64 * (PI / 180.0),
-            elv: event.angle_2 as f64 * (PI / 180.0),
+    /// Records a reading from `tag_id`, returning how many of its readings
+    /// were missed just before this one. Readings that repeat or come before
+    /// the latest one aren't gaps, and count as 0.
+    pub fn observe(&mut self, tag_id: u64, sequence: u32) -> u32 {
+        let Some(last) = self.last.get_mut(&tag_id) else {
+            self.last.insert(tag_id, sequence);
+            return 0;
         };
 
-        self.msgs.lock().unwrap().push_front(new_update);
+        // Differences past halfway round are readings from the past, rather
+        // than ones from far in the future
+        let step = sequence.wrapping_sub(*last);
+        if step == 0 || step > u32::MAX / 2 {
+            return 0;
+        }
+
+        *last = sequence;
+        let gap = step - 1;
+        *self.missed.entry(tag_id).or_default() += gap as u64;
+        gap
+    }
+
+    /// How many of `tag_id`'s readings have been missed so far.
+    pub fn missed(&self, tag_id: u64) -> u64 {
+        self.missed.get(&tag_id).copied().unwrap_or(0)
+    }
+
+    /// How many readings have been missed so far, from every tag.
+    pub fn total_missed(&self) -> u64 {
+        self.missed.values().sum()
     }
 }
 
@@ -50,3 +201,147 @@ impl HardwareDataManager for Hdm {
         self.msgs.lock().unwrap().clear();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(anchor_id: u64, sequence: u32) -> UUDFEvent {
+        UUDFEvent {
+            tag_id: 7,
+            rssi: -42,
+            angle_1: 20,
+            angle_2: 0,
+            reserved: -43,
+            channel: 37,
+            anchor_id,
+            user_defined: String::new(),
+            timestamp: 15869,
+            sequence,
+        }
+    }
+
+    #[test]
+    fn drop_repeated_readings() {
+        let mut hdm = Hdm::new();
+        assert_eq!(AddStatus::Accepted, hdm.add_update(event(1, 23)));
+        assert_eq!(AddStatus::Duplicate, hdm.add_update(event(1, 23)));
+        assert_eq!(1, hdm.by_ref().count());
+
+        // Another antenna hearing the same reading isn't a repeat
+        hdm.add_update(event(1, 24));
+        hdm.add_update(event(2, 24));
+        assert_eq!(2, hdm.by_ref().count());
+
+        // Only recent readings are remembered
+        for sequence in 25..25 + RECENT_SEQUENCES as u32 {
+            hdm.add_update(event(1, sequence));
+        }
+        hdm.add_update(event(1, 23));
+        hdm.add_update(event(1, 30));
+        assert_eq!(RECENT_SEQUENCES + 1, hdm.by_ref().count());
+    }
+
+    /// The azimuths, in whole degrees, of the readings left in `hdm`
+    fn azimuths(hdm: &mut Hdm) -> Vec<i32> {
+        hdm.map(|u| u.azm.to_degrees().round() as i32).collect()
+    }
+
+    /// Adds five readings to `hdm`, at 1 to 5 degrees, returning what
+    /// became of each
+    fn add_five(hdm: &Hdm) -> Vec<AddStatus> {
+        (1..=5)
+            .map(|i| {
+                hdm.add_update(UUDFEvent {
+                    angle_1: i,
+                    ..event(1, i as u32)
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn capacity_drops_oldest() {
+        let mut hdm = Hdm::builder()
+            .capacity(3)
+            .overflow(OverflowPolicy::DropOldest)
+            .build();
+        use AddStatus::*;
+        assert_eq!(
+            vec![Accepted, Accepted, Accepted, Evicted, Evicted],
+            add_five(&hdm)
+        );
+        assert_eq!(vec![5, 4, 3], azimuths(&mut hdm));
+    }
+
+    #[test]
+    fn capacity_drops_newest() {
+        let mut hdm = Hdm::builder()
+            .capacity(3)
+            .overflow(OverflowPolicy::DropNewest)
+            .build();
+        use AddStatus::*;
+        assert_eq!(
+            vec![Accepted, Accepted, Accepted, Full, Full],
+            add_five(&hdm)
+        );
+        assert!(!Full.is_accepted());
+        assert_eq!(vec![3, 2, 1], azimuths(&mut hdm));
+
+        // The readings that didn't fit weren't stored, so they aren't
+        // repeats when they're sent again
+        let again = |i| {
+            hdm.add_update(UUDFEvent {
+                angle_1: i,
+                ..event(1, i as u32)
+            })
+        };
+        assert_eq!(Accepted, again(4));
+        assert_eq!(Duplicate, again(4));
+        assert_eq!(Duplicate, again(1));
+        assert_eq!(vec![4], azimuths(&mut hdm));
+    }
+
+    #[test]
+    fn capacity_can_grow() {
+        let mut hdm = Hdm::builder()
+            .capacity(3)
+            .overflow(OverflowPolicy::Grow)
+            .build();
+        assert!(add_five(&hdm).into_iter().all(|s| s == AddStatus::Accepted));
+        assert_eq!(vec![5, 4, 3, 2, 1], azimuths(&mut hdm));
+    }
+
+    #[test]
+    fn find_a_gap() {
+        let mut tracker = SequenceTracker::new();
+        let gaps = [1, 2, 4, 5]
+            .into_iter()
+            .map(|sequence| tracker.observe(7, sequence))
+            .collect::<Vec<_>>();
+
+        assert_eq!(vec![0, 0, 1, 0], gaps);
+        assert_eq!(1, tracker.missed(7));
+        assert_eq!(0, tracker.missed(8));
+    }
+
+    #[test]
+    fn gaps_are_per_tag() {
+        let mut tracker = SequenceTracker::new();
+        tracker.observe(1, 10);
+        tracker.observe(2, 50);
+        assert_eq!(0, tracker.observe(1, 11));
+        assert_eq!(3, tracker.observe(2, 54));
+
+        // Repeats and stragglers aren't gaps
+        assert_eq!(0, tracker.observe(2, 54));
+        assert_eq!(0, tracker.observe(2, 52));
+        assert_eq!(0, tracker.observe(2, 55));
+
+        // Nor is wrapping round
+        tracker.observe(3, u32::MAX);
+        assert_eq!(0, tracker.observe(3, 0));
+
+        assert_eq!(3, tracker.total_missed());
+    }
+}
@@ -0,0 +1,58 @@
+//! Reads the yaw of the listener's head off of a second serial port, from an
+//! IMU, so that the [`Sphericalizer`](crate::sphericalizer::Sphericalizer)
+//! can turn the scene against the listener's head.
+//!
+//! The IMU sends one reading per line, the yaw of the head in degrees to the
+//! left, like `12.5\n`.
+
+use crate::sphericalizer::HeadYaw;
+
+use log::warn;
+use serial2::SerialPort;
+use std::{str, thread::spawn};
+
+/// Spawns a thread that reads from `port` forever, keeping `head_yaw` up to
+/// date with the latest reading, in radians.
+pub fn listen_for_head_yaw(port: SerialPort, head_yaw: HeadYaw) {
+    let _head_thread = spawn(move || {
+        let mut buffer = [0; 256];
+        let mut read_buf = Vec::new();
+
+        loop {
+            let read_len = port.read(&mut buffer).expect("Head tracker disconnected");
+
+            for &c in buffer.iter().take(read_len) {
+                read_buf.push(c);
+                if c == b'\n' {
+                    match parse_yaw(&read_buf) {
+                        Some(yaw) => *head_yaw.lock().unwrap() = yaw,
+                        None => warn!("Was unable to parse head tracker reading"),
+                    }
+                    read_buf.clear();
+                }
+            }
+        }
+    });
+}
+
+/// Parses one newline-terminated line from the head tracker into a yaw in
+/// radians.
+pub fn parse_yaw(line: &[u8]) -> Option<f32> {
+    let degrees: f32 = str::from_utf8(line).ok()?.trim().parse().ok()?;
+    degrees.is_finite().then(|| degrees.to_radians())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    #[test]
+    fn parse_readings() {
+        assert_eq!(Some(PI / 2.0), parse_yaw(b"90\n"));
+        assert_eq!(Some(-PI / 4.0), parse_yaw(b" -45.0\r\n"));
+        assert_eq!(None, parse_yaw(b"\xFF\xFE\n"));
+        assert_eq!(None, parse_yaw(b"yaw\n"));
+        assert_eq!(None, parse_yaw(b"NaN\n"));
+    }
+}
@@ -1,9 +1,12 @@
 //! A wrapper for the hound library that writes binauralized audio to
 //! the user-speciifed output file.
 
+use crate::audio_decoder::FULL_SCALE;
 use crate::component::{Component, ComponentError};
 use hound::{Error as HoundError, SampleFormat, WavReader, WavSpec, WavWriter};
 
+use std::error::Error;
+use std::fmt::Display;
 use std::fs::File;
 use std::io::BufWriter;
 use std::path::Path;
@@ -61,50 +64,162 @@ impl Component for HoundWriter {
     }
 }
 
+/// An error from [`hound_reader`], along with the file that caused it.
+#[derive(Debug)]
+pub struct HoundReadError {
+    /// The file that couldn't be read
+    pub filename: String,
+    /// What went wrong while reading it
+    pub error: HoundError,
+}
+
+impl Display for HoundReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "couldn't read {}: {}", self.filename, self.error)
+    }
+}
+
+impl Error for HoundReadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
 /// This function, given a Vec of filenames, uses hound to read the audio
-/// data into a 2D Vec, where each Vec represents the audio file data.
+/// data into a 2D Vec, where each Vec represents the audio file data. Stops
+/// at the first file that can't be read.
 ///
 /// IMPORTANT NOTE:
 ///
 /// This function does not meaningfully handle audio data
 /// with multiple channels. Only use mono files!
-pub fn hound_reader(filenames: Vec<String>) -> Vec<Vec<f32>> {
+pub fn hound_reader(filenames: Vec<String>) -> Result<Vec<Vec<f32>>, HoundReadError> {
     let mut all_samples: Vec<Vec<f32>> = vec![];
 
     for file in filenames {
-        let mut reader = WavReader::open(file).unwrap();
-
-        // collect wav file data into Vec of interleaved f32 samples
-        let samples = reader
-            .samples::<i32>()
-            .map(|x| x.unwrap() as f32)
-            .collect::<Vec<_>>();
+        let read = || -> Result<Vec<f32>, HoundError> {
+            let mut reader = WavReader::open(&file)?;
+
+            // collect wav file data into Vec of interleaved f32 samples
+            reader
+                .samples::<i32>()
+                .map(|x| x.map(|x| x as f32))
+                .collect()
+        };
+        let samples = read().map_err(|error| HoundReadError {
+            filename: file.clone(),
+            error,
+        })?;
 
         all_samples.push(samples);
     }
 
-    all_samples
+    Ok(all_samples)
+}
+
+/// How [`hound_writer_with_layout`] lays out the WAV file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WavLayout {
+    /// A plain 16-bit stereo file, which anything can play
+    #[default]
+    Plain,
+    /// A 24-bit file with a WAVE_FORMAT_EXTENSIBLE header, whose channel
+    /// mask says that the channels are front left and front right. Some VR
+    /// tools want this before they'll treat the file as binaural.
+    Extensible,
+}
+
+impl WavLayout {
+    /// The spec of a stereo file at `sample_rate` with this layout. Hound
+    /// writes a WAVE_FORMAT_EXTENSIBLE header, with the stereo channel mask,
+    /// for anything over 16 bits, so the same goes for a [`HoundWriter`]
+    /// given a 32-bit float spec.
+    pub fn spec(self, sample_rate: u32) -> WavSpec {
+        let bits_per_sample = match self {
+            Self::Plain => 16,
+            Self::Extensible => 24,
+        };
+        WavSpec {
+            channels: 2,
+            sample_rate,
+            bits_per_sample,
+            sample_format: SampleFormat::Int,
+        }
+    }
 }
 
 /// Writes two vectors of samples to a file on the disk in WAV format
 pub fn hound_writer(left_samps: Vec<f32>, right_samps: Vec<f32>, out_file: impl AsRef<Path>) {
+    hound_writer_with_layout(left_samps, right_samps, out_file, WavLayout::Plain);
+}
+
+/// Writes two vectors of samples, at the scale of a 16-bit file, to a file
+/// on the disk in WAV format, laid out as `layout`.
+pub fn hound_writer_with_layout(
+    left_samps: Vec<f32>,
+    right_samps: Vec<f32>,
+    out_file: impl AsRef<Path>,
+    layout: WavLayout,
+) {
+    let spec = layout.spec(44100);
+
+    let mut writer = WavWriter::create(out_file, spec).unwrap();
+
+    for (left, right) in std::iter::zip(left_samps, right_samps) {
+        match layout {
+            WavLayout::Plain => {
+                writer.write_sample(f32_to_i16(left / FULL_SCALE)).unwrap();
+                writer.write_sample(f32_to_i16(right / FULL_SCALE)).unwrap();
+            }
+            // Another 8 bits below the 16 that the samples are scaled to
+            WavLayout::Extensible => {
+                writer.write_sample(to_24_bit(left)).unwrap();
+                writer.write_sample(to_24_bit(right)).unwrap();
+            }
+        }
+    }
+
+    writer.finalize().unwrap();
+}
+
+/// Writes one vector of samples to a file on the disk as a mono WAV file at
+/// `sample_rate`.
+pub fn hound_writer_mono(samps: Vec<f32>, sample_rate: u32, out_file: impl AsRef<Path>) {
     let spec = WavSpec {
-        channels: 2,
-        sample_rate: 44100,
-        bits_per_sample: 16,
-        sample_format: SampleFormat::Int,
+        channels: 1,
+        ..WavLayout::Plain.spec(sample_rate)
     };
 
     let mut writer = WavWriter::create(out_file, spec).unwrap();
 
-    for (left, right) in std::iter::zip(left_samps, right_samps) {
-        writer.write_sample(left as i16).unwrap();
-        writer.write_sample(right as i16).unwrap();
+    for sample in samps {
+        writer
+            .write_sample(f32_to_i16(sample / FULL_SCALE))
+            .unwrap();
     }
 
     writer.finalize().unwrap();
 }
 
+/// Converts a sample between -1.0 and 1.0 to a 16-bit one, rounded to the
+/// nearest step. Anything louder is clipped, rather than wrapping around, and
+/// NaN is silence.
+pub fn f32_to_i16(sample: f32) -> i16 {
+    if sample.is_nan() {
+        return 0;
+    }
+    // -1.0 is exactly i16::MIN, and 1.0 is one step past i16::MAX
+    (sample.clamp(-1.0, 1.0) * FULL_SCALE)
+        .round()
+        .min(i16::MAX as f32) as i16
+}
+
+/// Rescales a sample at the scale of a 16-bit file to a 24-bit one, clipping
+/// like [`f32_to_i16`] does.
+fn to_24_bit(sample: f32) -> i32 {
+    (sample * 256.0).clamp(-(1 << 23) as f32, ((1 << 23) - 1) as f32) as i32
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -238,4 +353,83 @@ mod tests {
 
         assert!(remove_file(file_name).is_ok());
     }
+
+    #[test]
+    fn hound_reader_names_missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let good = dir.path().join("good.wav");
+        hound_writer(vec![1.0, 2.0], vec![3.0, 4.0], &good);
+        let missing = dir.path().join("missing.wav");
+
+        let read = hound_reader(vec![good.display().to_string()]).unwrap();
+        assert_eq!(vec![vec![1.0, 3.0, 2.0, 4.0]], read);
+
+        let err = hound_reader(vec![
+            good.display().to_string(),
+            missing.display().to_string(),
+        ])
+        .unwrap_err();
+        assert_eq!(missing.display().to_string(), err.filename);
+        assert!(matches!(err.error, HoundError::IoError(_)));
+        assert!(err.to_string().contains("missing.wav"));
+    }
+
+    #[test]
+    fn f32_to_i16_clamps_and_rounds() {
+        assert_eq!(i16::MAX, f32_to_i16(1.0));
+        assert_eq!(i16::MIN, f32_to_i16(-1.0));
+        assert_eq!(i16::MAX, f32_to_i16(1.5));
+        assert_eq!(i16::MIN, f32_to_i16(-40.0));
+        assert_eq!(i16::MAX, f32_to_i16(f32::INFINITY));
+        assert_eq!(0, f32_to_i16(f32::NAN));
+        assert_eq!(0, f32_to_i16(0.0));
+
+        // Rounded to the nearest step, not truncated toward zero
+        assert_eq!(2, f32_to_i16(1.6 / FULL_SCALE));
+        assert_eq!(-2, f32_to_i16(-1.6 / FULL_SCALE));
+        assert_eq!(16384, f32_to_i16(0.5));
+    }
+
+    #[test]
+    fn plain_layout_rounds_and_clips() {
+        let path = NamedTempFile::new().unwrap();
+        hound_writer(vec![1.6, 40000.0], vec![-2.7, -40000.0], &path);
+
+        let mut reader = WavReader::open(&path).unwrap();
+        let samples = reader
+            .samples::<i16>()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(vec![2, -3, i16::MAX, i16::MIN], samples);
+    }
+
+    #[test]
+    fn extensible_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("binaural.wav");
+        hound_writer_with_layout(
+            vec![1.0, -32768.0],
+            vec![0.5, 40000.0],
+            &path,
+            WavLayout::Extensible,
+        );
+
+        let bytes = std::fs::read(&path).unwrap();
+        // WAVE_FORMAT_EXTENSIBLE, and a channel mask of front left and
+        // front right
+        assert_eq!([0xFE, 0xFF], bytes[20..22]);
+        assert_eq!(3_u32.to_le_bytes(), bytes[40..44]);
+
+        let mut reader = WavReader::open(&path).unwrap();
+        assert_eq!(WavLayout::Extensible.spec(44100), reader.spec());
+        let samples = reader
+            .samples::<i32>()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(vec![256, 128, -(1 << 23), (1 << 23) - 1], samples);
+
+        hound_writer(vec![1.0], vec![2.0], &path);
+        let reader = WavReader::open(&path).unwrap();
+        assert_eq!(WavLayout::Plain.spec(44100), reader.spec());
+    }
 }
@@ -16,18 +16,31 @@
 //! Thompson.
 
 #![warn(missing_docs)]
+pub mod analysis;
 pub mod args;
+pub mod audio_decoder;
+pub mod binaural_config;
+pub mod checkpoint;
 pub mod component;
 pub mod dummy_hdm;
+pub mod file_hdm;
 pub mod gui;
 pub mod hardware_data_manager;
 pub mod hardware_message_decoder;
 pub mod hdm;
+pub mod head_tracker;
 pub mod hound_helpers;
 pub mod localizer;
+pub mod pipeline;
+#[cfg(feature = "plot")]
+pub mod plot;
+#[cfg(feature = "playback")]
+pub mod playback;
 pub mod report;
 pub mod saf;
+#[cfg(feature = "saf")]
 mod saf_raw;
+pub mod serial_listener;
 pub mod spatial_data_format;
 pub mod sphericalizer;
 pub mod time_domain_buffer;
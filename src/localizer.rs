@@ -1,7 +1,6 @@
 //! Converts radial points into cartesian points
 
-use crate::hardware_data_manager::Update;
-use std::f64::consts::PI;
+use crate::hardware_data_manager::{normalize_azimuth, Update};
 
 /// A simple x/y cartesian point
 #[allow(missing_docs)]
@@ -21,6 +20,26 @@ impl Point {
     pub fn new(x: f64, y: f64) -> Self {
         Point { x, y }
     }
+
+    /// The point at `azimuth` radians to the left of straight ahead (the x
+    /// axis), `elevation` radians up and `range` away from the origin,
+    /// flattened onto the x/y plane.
+    pub fn from_spherical(azimuth: f64, elevation: f64, range: f64) -> Self {
+        let flat = range * elevation.cos();
+        Point {
+            x: flat * azimuth.cos(),
+            y: flat * azimuth.sin(),
+        }
+    }
+
+    /// The (azimuth, elevation, range) of the point as seen from the origin,
+    /// like a [`BufferMetadata`](crate::saf::BufferMetadata): the azimuth is
+    /// in radians to the left of straight ahead, from 0 to 2PI. Points are
+    /// flat, so the elevation is always 0.
+    pub fn to_spherical(&self) -> (f64, f64, f64) {
+        let azimuth = normalize_azimuth(self.y.atan2(self.x));
+        (azimuth, 0.0, self.x.hypot(self.y))
+    }
 }
 
 impl std::fmt::Display for Point {
@@ -43,10 +62,7 @@ pub fn localize_points(measurements: &[Update]) -> Vec<Point> {
         .filter(|m| m.src == 0)
         .map(|m| {
             // working in the 2D plan, elv is 0 for now
-            let elv = PI / 2.0 - m.elv;
-            let x = range * m.azm.cos() * elv.sin();
-            let y = range * m.azm.sin() * elv.sin();
-            Point { x, y }
+            Point::from_spherical(m.azm, m.elv, range)
         })
         .collect()
 }
@@ -54,6 +70,7 @@ pub fn localize_points(measurements: &[Update]) -> Vec<Point> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::f64::consts::PI;
 
     #[test]
     fn test1() {
@@ -63,15 +80,40 @@ mod tests {
                 dst: 1,
                 elv: 0.0,
                 azm: 0.0,
+                rssi: None,
             }, // block 1 is straight ahead of the listener
             Update {
                 src: 0,
                 dst: 2,
                 elv: 0.0,
                 azm: 1.57,
+                rssi: None,
             }, // block 2 is to the right of the listener
         ];
         let points = localize_points(&updates);
         eprintln!("{:?}", points);
     }
+
+    #[test]
+    fn spherical_round_trip() {
+        let points = [
+            (Point::new(1.0, 0.0), 0.0),
+            (Point::new(0.0, 2.0), PI / 2.0),
+            (Point::new(-3.0, 0.0), PI),
+            (Point::new(0.0, -0.5), 1.5 * PI),
+            (Point::new(1.0, -1.0), 1.75 * PI),
+            (Point::new(-4.0, 3.0), PI - 0.75_f64.atan()),
+        ];
+        for (point, expected_azimuth) in points {
+            let (azimuth, elevation, range) = point.to_spherical();
+            assert!((azimuth - expected_azimuth).abs() < 1e-9, "{point}");
+            assert_eq!(0.0, elevation);
+            assert!((range - point.abs_dist(&Point::new(0.0, 0.0))).abs() < 1e-9);
+
+            let back = Point::from_spherical(azimuth, elevation, range);
+            assert!(back.abs_dist(&point) < 1e-9, "{point} came back as {back}");
+        }
+
+        assert_eq!((0.0, 0.0, 0.0), Point::new(0.0, 0.0).to_spherical());
+    }
 }
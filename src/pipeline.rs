@@ -0,0 +1,1030 @@
+//! The stages of the binaural pipeline, from a [`HardwareDataManager`] all
+//! the way to mixed stereo audio. These work over any source of updates, so
+//! the real hardware, a replayed log, or a simulation can all drive them.
+
+use crate::hardware_data_manager::{normalize_azimuth_f32, HardwareDataManager};
+use crate::saf::{Binauraliser, BufferMetadata, FRAME_SIZE};
+use crate::sphericalizer::Sphericalizer;
+use crate::time_domain_buffer::TDBufMeta;
+use crate::update_accumulator::UpdateAccumulator;
+
+use log::{info, warn};
+use rayon::prelude::*;
+use spin_sleep::sleep;
+use std::{
+    error::Error,
+    fmt::Display,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// A shared handle to any [`HardwareDataManager`], chosen at runtime.
+pub type SharedHdm = Arc<Mutex<dyn HardwareDataManager + Send>>;
+
+/// The ways that capturing positions can fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureError {
+    /// Not one query came back with a position for every tag in this long,
+    /// which usually means that the hardware has stopped sending anything
+    NoData(Duration),
+}
+
+impl Display for CaptureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoData(waited) => write!(
+                f,
+                "no positions were received from the hardware in {:.1} seconds, is it running?",
+                waited.as_secs_f64()
+            ),
+        }
+    }
+}
+
+impl Error for CaptureError {}
+
+/// Returns a progress hook for [`capture`] or [`render`] that prints how far
+/// along we are to stderr, whenever the percentage changes.
+pub fn stderr_progress(label: &'static str) -> impl FnMut(usize, usize) {
+    let mut last_percent = None;
+    move |done, total| {
+        let percent = (done * 100).checked_div(total).unwrap_or(100);
+        if last_percent != Some(percent) {
+            last_percent = Some(percent);
+            eprint!("\r{label}: {percent:>3}%");
+            if done == total {
+                eprintln!();
+            }
+        }
+    }
+}
+
+/// Queries the [`Sphericalizer`] for the position of every tag
+/// `num_updates` times, waiting `time_delta` between each query. Queries
+/// that come back empty are filled in by interpolating between the queries
+/// on either side.
+///
+/// If `timeout` is given, and no query has come back with a position within
+/// `timeout` of starting, gives up with [`CaptureError::NoData`] rather than
+/// capturing nothing at all.
+///
+/// A tag missing from a query holds its last position, or if `fill_gaps` is
+/// set, glides between its positions on either side of the gap, see
+/// [`TDBufMeta::with_interpolation`].
+///
+/// After each query, `progress` is called with the number of queries made so
+/// far and `num_updates`.
+#[allow(clippy::too_many_arguments)]
+pub fn capture<H>(
+    acc: &mut UpdateAccumulator<H>,
+    sphericalizer: &Sphericalizer,
+    num_tags: usize,
+    num_updates: usize,
+    time_delta: Duration,
+    timeout: Option<Duration>,
+    fill_gaps: bool,
+    progress: impl FnMut(usize, usize),
+) -> Result<Vec<Vec<BufferMetadata>>, CaptureError>
+where
+    H: HardwareDataManager + ?Sized,
+{
+    capture_timed(
+        acc,
+        sphericalizer,
+        num_tags,
+        num_updates,
+        time_delta,
+        timeout,
+        fill_gaps,
+        progress,
+    )
+    .map(|(spatial_data, _timestamps)| spatial_data)
+}
+
+/// Does the same as [`capture`], but also returns when each query was made,
+/// measured from the first one. Sleeping is never quite exact, so the queries
+/// drift away from being `time_delta` apart; [`positions_per_frame`] puts
+/// them back where they belong, given the timestamps in seconds.
+#[allow(clippy::too_many_arguments)]
+pub fn capture_timed<H>(
+    acc: &mut UpdateAccumulator<H>,
+    sphericalizer: &Sphericalizer,
+    num_tags: usize,
+    num_updates: usize,
+    time_delta: Duration,
+    timeout: Option<Duration>,
+    fill_gaps: bool,
+    mut progress: impl FnMut(usize, usize),
+) -> Result<(Vec<Vec<BufferMetadata>>, Vec<Duration>), CaptureError>
+where
+    H: HardwareDataManager + ?Sized,
+{
+    let mut td_buf = TDBufMeta::new(num_tags);
+    if fill_gaps {
+        td_buf = td_buf.with_interpolation();
+    }
+    let mut timestamps = Vec::with_capacity(num_updates);
+    let start = Instant::now();
+    let mut received = false;
+
+    for i in 0..num_updates {
+        timestamps.push(start.elapsed());
+        if let Some(update) = sphericalizer.query(acc) {
+            received = true;
+            td_buf.add(update)
+        } else {
+            warn!("we lost an update from the sphericalizer");
+            td_buf.drop_slice();
+            if let Some(timeout) =
+                timeout.filter(|&timeout| !received && start.elapsed() >= timeout)
+            {
+                return Err(CaptureError::NoData(timeout));
+            }
+        }
+        progress(i + 1, num_updates);
+        sleep(time_delta);
+    }
+
+    for (tag, stats) in td_buf.stats().iter().enumerate() {
+        info!("tag {tag}: {stats}");
+    }
+
+    Ok((td_buf.dump(), timestamps))
+}
+
+/// Gives each of `num_frames` frames of [`FRAME_SIZE`] samples, of audio at
+/// `sample_rate`, the position of every source at the moment the frame
+/// starts. Each time slice of `spatial_data` was taken at the matching time
+/// in `times`, in seconds from the start of the audio. A frame holds the
+/// latest slice taken by the time it starts or, if `interpolate` is set, is
+/// placed between that slice and the next by how far it is between them.
+/// Before the first slice, the sources wait where it puts them.
+///
+/// The slices don't have to line up with the frames at all, so however the
+/// update rate divides into the sample rate, the positions don't drift away
+/// from the audio over a long file. The result is ready for [`render`], with
+/// a `samples_per_update` of [`FRAME_SIZE`].
+pub fn positions_per_frame(
+    spatial_data: &[Vec<BufferMetadata>],
+    times: &[f64],
+    sample_rate: usize,
+    num_frames: usize,
+    interpolate: bool,
+) -> Vec<Vec<BufferMetadata>> {
+    if spatial_data.is_empty() {
+        return vec![];
+    }
+    let times = &times[..times.len().min(spatial_data.len())];
+
+    (0..num_frames)
+        .map(|frame| {
+            let start = (frame * FRAME_SIZE) as f64 / sample_rate as f64;
+            // The number of slices taken by the time this frame starts
+            let taken = times.partition_point(|&time| time <= start);
+            match taken {
+                0 => spatial_data[0].clone(),
+                _ if interpolate && taken < times.len() => {
+                    let (before, after) = (taken - 1, taken);
+                    let t = (start - times[before]) / (times[after] - times[before]);
+                    spatial_data[before]
+                        .iter()
+                        .zip(&spatial_data[after])
+                        .map(|(from, to)| from.lerp(to, t as f32))
+                        .collect()
+                }
+                _ => spatial_data[taken - 1].clone(),
+            }
+        })
+        .collect()
+}
+
+/// The times, in seconds, of `num_updates` updates taken at `update_rate`
+/// updates per second from the start of the audio, for
+/// [`positions_per_frame`].
+pub fn update_times(num_updates: usize, update_rate: usize) -> Vec<f64> {
+    (0..num_updates)
+        .map(|i| i as f64 / update_rate as f64)
+        .collect()
+}
+
+/// Holds every source still for `num_updates` updates, at the azimuth and
+/// elevation (in degrees) given for it in `angles`, with the matching gain
+/// and range from `tag_settings`. The azimuths are normalized, so `-90`
+/// is the same as `270`.
+pub fn hold_still(
+    angles: &[(f32, f32)],
+    tag_settings: &[(f32, f32)],
+    num_updates: usize,
+) -> Vec<Vec<BufferMetadata>> {
+    let positions = angles
+        .iter()
+        .zip(tag_settings)
+        .map(|(&(azimuth, elevation), &(gain, range))| BufferMetadata {
+            azimuth: normalize_azimuth_f32(azimuth.to_radians()),
+            elevation: elevation.to_radians(),
+            range,
+            gain,
+        })
+        .collect::<Vec<_>>();
+
+    let mut td_buf = TDBufMeta::new(positions.len());
+    for _ in 0..num_updates {
+        td_buf.add(positions.clone());
+    }
+
+    td_buf.dump()
+}
+
+/// Binauralizes `sound_data`, where each time slice of `spatial_data` gives
+/// the position of every source for the next `samples_per_update` samples.
+/// If `interpolate` is set, each source glides towards the position it has in
+/// the next time slice, rather than jumping there.
+///
+/// Each audio buffer must be at least `samples_per_update` times as long as
+/// `spatial_data`, and `samples_per_update` must be a multiple of
+/// [`FRAME_SIZE`](crate::saf::FRAME_SIZE).
+///
+/// After each time slice, `progress` is called with the number of slices
+/// rendered so far and the total number of slices.
+pub fn render<B>(
+    binauraliser: &mut B,
+    spatial_data: &[Vec<BufferMetadata>],
+    sound_data: &[Vec<f32>],
+    samples_per_update: usize,
+    interpolate: bool,
+    mut progress: impl FnMut(usize, usize),
+) -> (Vec<f32>, Vec<f32>)
+where
+    B: Binauraliser + ?Sized,
+{
+    let total_samples = spatial_data.len() * samples_per_update;
+    let mut out_left = Vec::with_capacity(total_samples);
+    let mut out_right = Vec::with_capacity(total_samples);
+
+    for i in 0..spatial_data.len() {
+        let (mut new_left, mut new_right) = render_slice(
+            binauraliser,
+            spatial_data,
+            sound_data,
+            i,
+            samples_per_update,
+            interpolate,
+        );
+
+        out_left.append(&mut new_left);
+        out_right.append(&mut new_right);
+
+        progress(i + 1, spatial_data.len());
+    }
+
+    (out_left, out_right)
+}
+
+/// How much audio before each chunk [`render_parallel`] renders and throws
+/// away. SAF's filterbank holds on to 12 frames of [`FRAME_SIZE`], and its
+/// HRIRs are a few hundred samples long, so this is comfortably longer than
+/// anything a binauraliser remembers.
+pub const WARM_UP_SAMPLES: usize = 32 * FRAME_SIZE;
+
+/// Does the same as [`render`], but splits the time slices into one chunk
+/// for each of rayon's threads and renders the chunks at the same time. SAF
+/// binaurialisers can't be shared between threads, so each chunk gets its
+/// own from `new_binauraliser`.
+///
+/// A binauraliser carries audio over from one frame to the next, so each
+/// chunk's binauraliser first renders at least [`WARM_UP_SAMPLES`] before the
+/// chunk, and throws them away, to pick up where the last chunk left off.
+///
+/// `progress` is called just as it is for [`render`], from whichever thread
+/// finished the slice.
+pub fn render_parallel<B>(
+    new_binauraliser: impl Fn() -> B + Sync,
+    spatial_data: &[Vec<BufferMetadata>],
+    sound_data: &[Vec<f32>],
+    samples_per_update: usize,
+    interpolate: bool,
+    progress: impl FnMut(usize, usize) + Send,
+) -> (Vec<f32>, Vec<f32>)
+where
+    B: Binauraliser,
+{
+    let num_slices = spatial_data.len();
+    let chunk_size = num_slices.div_ceil(rayon::current_num_threads()).max(1);
+    let warm_up_slices = WARM_UP_SAMPLES.div_ceil(samples_per_update.max(1));
+
+    let done = AtomicUsize::new(0);
+    let progress = Mutex::new(progress);
+
+    let chunks = (0..num_slices)
+        .step_by(chunk_size)
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .map(|chunk_start| {
+            let mut binauraliser = new_binauraliser();
+            for i in chunk_start.saturating_sub(warm_up_slices)..chunk_start {
+                render_slice(
+                    &mut binauraliser,
+                    spatial_data,
+                    sound_data,
+                    i,
+                    samples_per_update,
+                    interpolate,
+                );
+            }
+
+            let chunk_stop = num_slices.min(chunk_start + chunk_size);
+            let mut out_left = Vec::with_capacity((chunk_stop - chunk_start) * samples_per_update);
+            let mut out_right = Vec::with_capacity((chunk_stop - chunk_start) * samples_per_update);
+
+            for i in chunk_start..chunk_stop {
+                let (mut new_left, mut new_right) = render_slice(
+                    &mut binauraliser,
+                    spatial_data,
+                    sound_data,
+                    i,
+                    samples_per_update,
+                    interpolate,
+                );
+                out_left.append(&mut new_left);
+                out_right.append(&mut new_right);
+
+                let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+                (progress.lock().unwrap())(done, num_slices);
+            }
+
+            (out_left, out_right)
+        })
+        .collect::<Vec<_>>();
+
+    let (left, right): (Vec<_>, Vec<_>) = chunks.into_iter().unzip();
+    (left.concat(), right.concat())
+}
+
+/// Binauralizes the `i`th time slice of `sound_data`, for [`render`] and
+/// [`render_parallel`].
+fn render_slice<B>(
+    binauraliser: &mut B,
+    spatial_data: &[Vec<BufferMetadata>],
+    sound_data: &[Vec<f32>],
+    i: usize,
+    samples_per_update: usize,
+    interpolate: bool,
+) -> (Vec<f32>, Vec<f32>)
+where
+    B: Binauraliser + ?Sized,
+{
+    let metadata = &spatial_data[i];
+    let sound_start = i * samples_per_update;
+    let sound_stop = (i + 1) * samples_per_update;
+    let sound_slices = sound_data
+        .iter()
+        .map(|v| &v[sound_start..sound_stop])
+        .collect::<Vec<_>>();
+
+    assert_eq!(metadata.len(), sound_slices.len());
+
+    // When interpolating, each source glides towards the position
+    // it has at the next update (the last update just holds still)
+    let next_metadata = if interpolate {
+        spatial_data.get(i + 1).unwrap_or(metadata)
+    } else {
+        metadata
+    };
+
+    let tagged_buffers = metadata
+        .iter()
+        .zip(next_metadata)
+        .zip(sound_slices)
+        .map(|((&start, &end), samples)| (start, end, samples))
+        .collect::<Vec<_>>();
+
+    binauraliser.process_interpolated(&tagged_buffers[..])
+}
+
+/// Mixes each of the `ambient` beds into `left` and `right` equally, scaled
+/// by `gain`, without placing them anywhere. `left` and `right` are taken to
+/// start `offset` samples into the beds, and beds that have already ended
+/// add nothing.
+pub fn mix_ambient(
+    left: &mut [f32],
+    right: &mut [f32],
+    ambient: &[Vec<f32>],
+    offset: usize,
+    gain: f32,
+) {
+    for bed in ambient {
+        let bed = bed.get(offset..).unwrap_or(&[]);
+        for ((l, r), &sample) in left.iter_mut().zip(right.iter_mut()).zip(bed) {
+            *l += sample * gain;
+            *r += sample * gain;
+        }
+    }
+}
+
+/// Cuts `seconds` from the start of the rendered `left` and `right`
+/// channels, at `sample_rate` samples per second, such as while the sources
+/// were still being placed. Cutting more than there is leaves nothing.
+pub fn trim_start<'a>(
+    left: &'a [f32],
+    right: &'a [f32],
+    seconds: f32,
+    sample_rate: usize,
+) -> (&'a [f32], &'a [f32]) {
+    let skip = (seconds as f64 * sample_rate as f64).round() as usize;
+    (
+        left.get(skip..).unwrap_or_default(),
+        right.get(skip..).unwrap_or_default(),
+    )
+}
+
+/// Sums `sound_data` into one channel, scaling each source by its gain in
+/// `gains`, without placing the sources anywhere. The sum is as long as the
+/// longest source.
+pub fn mono_sum(sound_data: &[Vec<f32>], gains: &[f32]) -> Vec<f32> {
+    let len = sound_data.iter().map(Vec::len).max().unwrap_or(0);
+    let mut sum = vec![0.0; len];
+    for (samples, &gain) in sound_data.iter().zip(gains) {
+        for (s, &sample) in sum.iter_mut().zip(samples) {
+            *s += sample * gain;
+        }
+    }
+    sum
+}
+
+/// Silences every source in `mute`, and, if `solo` isn't empty, every
+/// source that isn't in `solo`, before the sources are mixed. Sources are
+/// picked by their index in `sound_data`. Silenced sources keep their
+/// length, so nothing else moves out of time.
+pub fn mute_and_solo(sound_data: &mut [Vec<f32>], mute: &[usize], solo: &[usize]) {
+    for (i, samples) in sound_data.iter_mut().enumerate() {
+        if mute.contains(&i) || (!solo.is_empty() && !solo.contains(&i)) {
+            samples.fill(0.0);
+        }
+    }
+}
+
+/// Folds `channels` down to stereo, adding each channel into the left and
+/// right by its pair of coefficients in `coeffs`. The stereo pair is as long
+/// as the longest channel. [`default_downmix`] has coefficients for the
+/// common layouts.
+pub fn downmix_to_stereo(channels: &[Vec<f32>], coeffs: &[(f32, f32)]) -> (Vec<f32>, Vec<f32>) {
+    let (left_gains, right_gains): (Vec<_>, Vec<_>) = coeffs.iter().copied().unzip();
+    (
+        mono_sum(channels, &left_gains),
+        mono_sum(channels, &right_gains),
+    )
+}
+
+/// The left and right coefficients that [`downmix_to_stereo`] folds
+/// `num_channels` channels down with, for the usual channel orders:
+///
+/// - 1: mono, down the middle at -3 dB
+/// - 2: stereo, left and right
+/// - 4: quad, front left, front right, back left, back right
+/// - 6: 5.1, front left, front right, center, LFE, surround left, surround
+///   right, with the center and surrounds at -3 dB and the LFE dropped
+///
+/// Any other number of channels alternates between left and right, like
+/// [`DummyBinauraliser`](crate::saf::DummyBinauraliser) does.
+pub fn default_downmix(num_channels: usize) -> Vec<(f32, f32)> {
+    use std::f32::consts::FRAC_1_SQRT_2 as HALF_POWER;
+    match num_channels {
+        1 => vec![(HALF_POWER, HALF_POWER)],
+        4 => vec![(1.0, 0.0), (0.0, 1.0), (HALF_POWER, 0.0), (0.0, HALF_POWER)],
+        6 => vec![
+            (1.0, 0.0),
+            (0.0, 1.0),
+            (HALF_POWER, HALF_POWER),
+            (0.0, 0.0),
+            (HALF_POWER, 0.0),
+            (0.0, HALF_POWER),
+        ],
+        _ => (0..num_channels)
+            .map(|i| if i % 2 == 0 { (1.0, 0.0) } else { (0.0, 1.0) })
+            .collect(),
+    }
+}
+
+/// Binauralizes `sound_data` as the updates come in, rather than capturing
+/// them all first. Each block of `samples_per_update` samples is rendered at
+/// the latest position the [`Sphericalizer`] has for every source, and handed
+/// to `output` straight away. If a query comes back empty, the sources hold
+/// their last position. Waits `time_delta` between blocks, and doesn't start
+/// until the first position arrives.
+///
+/// If `timeout` is given, and the first position hasn't arrived within
+/// `timeout` of starting, gives up with [`CaptureError::NoData`] rather than
+/// waiting forever.
+///
+/// `samples_per_update` must be a multiple of
+/// [`FRAME_SIZE`](crate::saf::FRAME_SIZE). The audio buffers don't need to be
+/// padded, the last block is cut short to the length of the longest one.
+///
+/// After each block, `progress` is called with the number of blocks rendered
+/// so far and the total number of blocks. Stops at the first error from
+/// `output`.
+#[allow(clippy::too_many_arguments)]
+pub fn render_live<H, B, E>(
+    acc: &mut UpdateAccumulator<H>,
+    sphericalizer: &Sphericalizer,
+    binauraliser: &mut B,
+    sound_data: &[Vec<f32>],
+    samples_per_update: usize,
+    time_delta: Duration,
+    timeout: Option<Duration>,
+    mut output: impl FnMut(Vec<f32>, Vec<f32>) -> Result<(), E>,
+    mut progress: impl FnMut(usize, usize),
+) -> Result<(), E>
+where
+    H: HardwareDataManager + ?Sized,
+    B: Binauraliser + ?Sized,
+    E: From<CaptureError>,
+{
+    let total_samples = sound_data.iter().map(Vec::len).max().unwrap_or(0);
+    let num_blocks = total_samples.div_ceil(samples_per_update);
+
+    let mut latest = None;
+    let mut block = vec![vec![0.0; samples_per_update]; sound_data.len()];
+
+    let start = Instant::now();
+    for i in 0..num_blocks {
+        let mut update = sphericalizer.query(acc);
+        while update.is_none() && latest.is_none() {
+            if let Some(timeout) = timeout.filter(|&timeout| start.elapsed() >= timeout) {
+                return Err(CaptureError::NoData(timeout).into());
+            }
+            sleep(time_delta);
+            update = sphericalizer.query(acc);
+        }
+        if update.is_some() {
+            latest = update;
+        }
+        let metadata = latest.as_ref().expect("we waited for the first position");
+
+        let block_start = i * samples_per_update;
+        let block_stop = total_samples.min(block_start + samples_per_update);
+
+        // Copy into a padded block, so that short files fall silent
+        for (buf, samples) in block.iter_mut().zip(sound_data) {
+            let end = block_stop.min(samples.len());
+            let samples = samples.get(block_start..end).unwrap_or(&[]);
+            buf[..samples.len()].copy_from_slice(samples);
+            buf[samples.len()..].fill(0.0);
+        }
+
+        assert_eq!(metadata.len(), block.len());
+
+        let tagged_buffers = metadata
+            .iter()
+            .copied()
+            .zip(block.iter().map(Vec::as_slice))
+            .collect::<Vec<_>>();
+
+        let (mut left, mut right) = binauraliser.process(&tagged_buffers);
+        left.truncate(block_stop - block_start);
+        right.truncate(block_stop - block_start);
+
+        output(left, right)?;
+        progress(i + 1, num_blocks);
+        sleep(time_delta);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dummy_hdm::{DummyHdm, ScriptedHdm};
+    use crate::hardware_data_manager::Update;
+    use crate::sphericalizer::{BACK_ANTENNA, FRONT_ANTENNA};
+    use std::collections::VecDeque;
+
+    /// Passes the first source straight through to both ears
+    struct PassThrough;
+
+    impl Binauraliser for PassThrough {
+        fn process_frame(&mut self, buffers: &[(BufferMetadata, &[f32])]) -> (Vec<f32>, Vec<f32>) {
+            (buffers[0].1.to_vec(), buffers[0].1.to_vec())
+        }
+    }
+
+    #[test]
+    fn pipeline_over_dummy_hdm() {
+        let dummy = Arc::new(Mutex::new(
+            DummyHdm::builder()
+                .num_points(2)
+                .delay(0.001)
+                .antennas(BACK_ANTENNA, FRONT_ANTENNA)
+                .build(),
+        ));
+        let hdm: SharedHdm = dummy.clone();
+
+        let mut acc = UpdateAccumulator::new(hdm);
+        let sphericalizer = Sphericalizer::new(vec![(1.0, 1.0); 2]);
+
+        sleep(Duration::from_millis(20));
+        let mut capture_calls = vec![];
+        let spatial_data = capture(
+            &mut acc,
+            &sphericalizer,
+            2,
+            4,
+            Duration::from_millis(5),
+            None,
+            false,
+            |done, total| capture_calls.push((done, total)),
+        )
+        .unwrap();
+        dummy.lock().unwrap().stop();
+
+        assert_eq!(4, spatial_data.len());
+        assert_eq!(vec![(1, 4), (2, 4), (3, 4), (4, 4)], capture_calls);
+
+        let sound_data = vec![vec![0.5; FRAME_SIZE * 4]; 2];
+        let mut render_calls = 0;
+        let (left, right) = render(
+            &mut PassThrough,
+            &spatial_data,
+            &sound_data,
+            FRAME_SIZE,
+            true,
+            |_, _| render_calls += 1,
+        );
+        assert_eq!(FRAME_SIZE * 4, left.len());
+        assert_eq!(left, right);
+        assert_eq!(4, render_calls);
+    }
+
+    #[test]
+    fn capture_gives_up_on_silent_hardware() {
+        let silent: SharedHdm = Arc::new(Mutex::new(ScriptedHdm::new(vec![])));
+        let mut acc = UpdateAccumulator::new(silent);
+        let sphericalizer = Sphericalizer::new(vec![(1.0, 1.0)]);
+
+        let timeout = Duration::from_millis(20);
+        let start = Instant::now();
+        let result = capture(
+            &mut acc,
+            &sphericalizer,
+            1,
+            1000,
+            Duration::from_millis(5),
+            Some(timeout),
+            false,
+            |_, _| {},
+        );
+        assert_eq!(Err(CaptureError::NoData(timeout)), result);
+        // Well before all 1000 updates would have been waited for
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        // Once a position has come in, the timeout no longer applies
+        let script = [BACK_ANTENNA, FRONT_ANTENNA]
+            .map(|src| Update {
+                src,
+                dst: 1,
+                elv: 0.0,
+                azm: 0.5,
+                rssi: None,
+            })
+            .to_vec();
+        let mut acc = UpdateAccumulator::new(Arc::new(Mutex::new(ScriptedHdm::new(script))));
+        let spatial_data = capture(
+            &mut acc,
+            &sphericalizer,
+            1,
+            8,
+            Duration::from_millis(5),
+            Some(Duration::ZERO),
+            false,
+            |_, _| {},
+        )
+        .unwrap();
+        assert_eq!(8, spatial_data.len());
+    }
+
+    #[test]
+    fn long_capture_stays_aligned() {
+        // 44100 / 40 isn't a whole number of samples, let alone frames. Each
+        // slice's range says when it was taken, in updates
+        let (sample_rate, update_rate) = (44100, 40);
+        let minutes = 10;
+        let num_updates = minutes * 60 * update_rate;
+        let spatial_data = (0..num_updates)
+            .map(|i| {
+                vec![BufferMetadata {
+                    azimuth: 0.0,
+                    elevation: 0.0,
+                    range: i as f32,
+                    gain: 1.0,
+                }]
+            })
+            .collect::<Vec<_>>();
+        let times = update_times(num_updates, update_rate);
+        let num_frames = (minutes * 60 * sample_rate).div_ceil(FRAME_SIZE);
+
+        // Where the frame starting at `frame` ought to be, in updates
+        let expected =
+            |frame: usize| (frame * FRAME_SIZE * update_rate) as f64 / sample_rate as f64;
+
+        // The last few frames come after the last update, and hold it
+        let frames = [0, 1, num_frames / 2, num_frames - 20];
+        let held = positions_per_frame(&spatial_data, &times, sample_rate, num_frames, false);
+        assert_eq!(num_frames, held.len());
+        for frame in frames {
+            assert_eq!(expected(frame).floor() as f32, held[frame][0].range);
+        }
+
+        let glided = positions_per_frame(&spatial_data, &times, sample_rate, num_frames, true);
+        for frame in frames {
+            let range = glided[frame][0].range as f64;
+            assert!((expected(frame) - range).abs() < 0.01, "frame {frame}");
+        }
+
+        // Before the first slice, and after the last, the sources hold still
+        let late = update_times(3, update_rate)
+            .iter()
+            .map(|t| t + 1.0)
+            .collect::<Vec<_>>();
+        let held = positions_per_frame(&spatial_data[..3], &late, sample_rate, num_frames, true);
+        assert_eq!(0.0, held[0][0].range);
+        assert_eq!(2.0, held[num_frames - 1][0].range);
+        assert!(positions_per_frame(&[], &[], sample_rate, 3, true).is_empty());
+    }
+
+    #[test]
+    fn mute_and_solo_sources() {
+        let sources = vec![vec![1.0; 4], vec![2.0; 4], vec![3.0; 3]];
+        let mix = |mute: &[usize], solo: &[usize]| {
+            let mut sound_data = sources.clone();
+            mute_and_solo(&mut sound_data, mute, solo);
+            mono_sum(&sound_data, &[1.0; 3])
+        };
+
+        assert_eq!(vec![6.0, 6.0, 6.0, 3.0], mix(&[], &[]));
+        // Muting the second source takes away just its contribution
+        assert_eq!(vec![4.0, 4.0, 4.0, 1.0], mix(&[1], &[]));
+        // Soloing the third leaves nothing but it
+        assert_eq!(vec![3.0, 3.0, 3.0, 0.0], mix(&[], &[2]));
+        assert_eq!(vec![2.0; 4], mix(&[0], &[0, 1]));
+    }
+
+    #[test]
+    fn downmix_quad() {
+        let channels = vec![
+            vec![1.0, 2.0],
+            vec![10.0, 20.0],
+            vec![100.0, 200.0],
+            vec![1000.0],
+        ];
+        let coeffs = [(1.0, 0.0), (0.0, 1.0), (0.5, 0.25), (0.1, 0.2)];
+        let (left, right) = downmix_to_stereo(&channels, &coeffs);
+        assert_eq!(vec![1.0 + 50.0 + 100.0, 2.0 + 100.0], left);
+        assert_eq!(vec![10.0 + 25.0 + 200.0, 20.0 + 50.0], right);
+
+        let (left, right) = downmix_to_stereo(&channels[..2], &default_downmix(2));
+        assert_eq!((channels[0].clone(), channels[1].clone()), (left, right));
+        // Each side of a quad render comes out as loud as the other
+        let quad = vec![vec![1.0]; 4];
+        let (left, right) = downmix_to_stereo(&quad, &default_downmix(4));
+        assert_eq!(left, right);
+        assert_eq!(6, default_downmix(6).len());
+        assert_eq!(vec![(1.0, 0.0), (0.0, 1.0), (1.0, 0.0)], default_downmix(3));
+    }
+
+    #[test]
+    fn trim_the_start() {
+        let left: Vec<f32> = (0..10).map(|i| i as f32).collect();
+        let right: Vec<f32> = (0..10).map(|i| -i as f32).collect();
+
+        let (l, r) = trim_start(&left, &right, 0.25, 8);
+        assert_eq!(&left[2..], l);
+        assert_eq!(&right[2..], r);
+        assert_eq!((&left[..], &right[..]), trim_start(&left, &right, 0.0, 8));
+        assert_eq!((&[][..], &[][..]), trim_start(&left, &right, 2.0, 8));
+    }
+
+    #[test]
+    fn mono_sum_of_two() {
+        let sound_data = vec![vec![1.0, 2.0, 3.0], vec![10.0, -20.0]];
+        assert_eq!(
+            vec![1.0 + 5.0, 2.0 - 10.0, 3.0],
+            mono_sum(&sound_data, &[1.0, 0.5])
+        );
+        assert!(mono_sum(&[], &[]).is_empty());
+    }
+
+    /// How many frames later [`Echo`] repeats each frame
+    const ECHO_FRAMES: usize = 8;
+
+    /// Leaks half of every frame into the one [`ECHO_FRAMES`] later, like
+    /// the long tail of a filter
+    #[derive(Default)]
+    struct Echo {
+        past: VecDeque<Vec<f32>>,
+    }
+
+    impl Binauraliser for Echo {
+        fn process_frame(&mut self, buffers: &[(BufferMetadata, &[f32])]) -> (Vec<f32>, Vec<f32>) {
+            let mixed = (0..FRAME_SIZE)
+                .map(|i| buffers.iter().map(|(m, b)| b[i] * m.gain).sum::<f32>())
+                .collect::<Vec<_>>();
+            let tail = if self.past.len() == ECHO_FRAMES {
+                self.past.pop_front()
+            } else {
+                None
+            };
+            let out = mixed
+                .iter()
+                .enumerate()
+                .map(|(i, s)| s + tail.as_ref().map_or(0.0, |tail| tail[i] / 2.0))
+                .collect::<Vec<_>>();
+            self.past.push_back(mixed);
+            (out.clone(), out.iter().map(|s| -s).collect())
+        }
+    }
+
+    #[test]
+    fn parallel_matches_serial() {
+        // One frame per slice, as the binaural run renders them, so the echo
+        // reaches back over several slices
+        let num_slices = 97;
+        let spatial_data = (0..num_slices)
+            .map(|i| {
+                (0..3)
+                    .map(|tag| BufferMetadata {
+                        azimuth: 0.1 * i as f32,
+                        elevation: 0.0,
+                        range: 1.0,
+                        gain: 1.0 + (i * tag) as f32 / 10.0,
+                    })
+                    .collect()
+            })
+            .collect::<Vec<_>>();
+        let sound_data = (0..3)
+            .map(|tag| {
+                (0..num_slices * FRAME_SIZE)
+                    .map(|i| ((i * (tag + 1)) % 17) as f32)
+                    .collect()
+            })
+            .collect::<Vec<_>>();
+
+        let serial = render(
+            &mut Echo::default(),
+            &spatial_data,
+            &sound_data,
+            FRAME_SIZE,
+            true,
+            |_, _| {},
+        );
+
+        let mut calls = vec![];
+        let parallel = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build()
+            .unwrap()
+            .install(|| {
+                render_parallel(
+                    Echo::default,
+                    &spatial_data,
+                    &sound_data,
+                    FRAME_SIZE,
+                    true,
+                    |done, total| calls.push((done, total)),
+                )
+            });
+
+        assert_eq!(serial, parallel);
+        assert_eq!(num_slices, calls.len());
+        assert_eq!(Some(&(num_slices, num_slices)), calls.last());
+    }
+
+    #[test]
+    fn live_over_dummy_hdm() {
+        let dummy = Arc::new(Mutex::new(
+            DummyHdm::builder()
+                .num_points(2)
+                .delay(0.001)
+                .antennas(BACK_ANTENNA, FRONT_ANTENNA)
+                .build(),
+        ));
+        let hdm: SharedHdm = dummy.clone();
+
+        let mut acc = UpdateAccumulator::new(hdm);
+        let sphericalizer = Sphericalizer::new(vec![(1.0, 1.0); 2]);
+
+        // Files of different lengths, neither of which fills the last block
+        let sound_data = vec![vec![0.5; FRAME_SIZE * 3 + 50], vec![0.25; FRAME_SIZE]];
+        let (mut left, mut right) = (vec![], vec![]);
+        let mut blocks = vec![];
+        render_live(
+            &mut acc,
+            &sphericalizer,
+            &mut PassThrough,
+            &sound_data,
+            FRAME_SIZE,
+            Duration::from_millis(5),
+            None,
+            |mut new_left, mut new_right| {
+                blocks.push(new_left.len());
+                left.append(&mut new_left);
+                right.append(&mut new_right);
+                Ok::<_, CaptureError>(())
+            },
+            |_, _| {},
+        )
+        .unwrap();
+        dummy.lock().unwrap().stop();
+
+        assert_eq!(vec![FRAME_SIZE, FRAME_SIZE, FRAME_SIZE, 50], blocks);
+        assert_eq!(sound_data[0], left);
+        assert_eq!(left, right);
+    }
+
+    #[test]
+    fn live_gives_up_without_data() {
+        let mut acc = UpdateAccumulator::new(Arc::new(Mutex::new(ScriptedHdm::new(vec![]))));
+        let sphericalizer = Sphericalizer::new(vec![(1.0, 1.0)]);
+        let timeout = Duration::from_millis(20);
+
+        let mut blocks = 0;
+        let result = render_live(
+            &mut acc,
+            &sphericalizer,
+            &mut PassThrough,
+            &[vec![0.5; FRAME_SIZE * 2]],
+            FRAME_SIZE,
+            Duration::from_millis(5),
+            Some(timeout),
+            |_, _| {
+                blocks += 1;
+                Ok(())
+            },
+            |_, _| {},
+        );
+
+        assert_eq!(Err(CaptureError::NoData(timeout)), result);
+        assert_eq!(0, blocks);
+    }
+
+    #[test]
+    fn ambient_is_centred() {
+        let mut left = vec![1.0, -1.0, 0.5, 0.0];
+        let mut right = vec![0.0; 4];
+        let ambient = vec![vec![0.0, 1.0, 2.0, 3.0, 4.0], vec![10.0; 3]];
+
+        mix_ambient(&mut left, &mut right, &ambient, 1, 0.5);
+
+        assert_eq!(vec![1.0 + 5.5, -1.0 + 6.0, 0.5 + 1.5, 2.0], left);
+        assert_eq!(vec![5.5, 6.0, 1.5, 2.0], right);
+
+        // The ambient bed alone comes out the same on both sides
+        let mut left = vec![0.0; 4];
+        let mut right = vec![0.0; 4];
+        mix_ambient(&mut left, &mut right, &ambient, 0, 1.0);
+        assert_eq!(left, right);
+    }
+
+    #[test]
+    fn hold_sources_still() {
+        let spatial_data = hold_still(&[(-90.0, 0.0), (90.0, 10.0)], &[(1.0, 2.0), (0.5, 3.0)], 3);
+
+        let expected = vec![
+            BufferMetadata {
+                // On the right, the same as 270 degrees
+                azimuth: 270.0_f32.to_radians(),
+                elevation: 0.0,
+                range: 2.0,
+                gain: 1.0,
+            },
+            BufferMetadata {
+                azimuth: 90.0_f32.to_radians(),
+                elevation: 10.0_f32.to_radians(),
+                range: 3.0,
+                gain: 0.5,
+            },
+        ];
+        assert_eq!(vec![expected; 3], spatial_data);
+    }
+
+    #[test]
+    fn held_azimuths_are_normalized() {
+        let angles = (-48..=48)
+            .map(|i| (i as f32 * 15.0, 0.0))
+            .chain([(-1e-6, 0.0), (360.0 - 1e-5, 0.0)])
+            .collect::<Vec<_>>();
+        let spatial_data = hold_still(&angles, &vec![(1.0, 1.0); angles.len()], 1);
+
+        for (metadata, (degrees, _)) in spatial_data[0].iter().zip(&angles) {
+            let azimuth = metadata.azimuth;
+            assert!(
+                (0.0..std::f32::consts::TAU).contains(&azimuth),
+                "{degrees} became {azimuth}"
+            );
+            assert_eq!(azimuth, normalize_azimuth_f32(azimuth), "{degrees}");
+        }
+    }
+}
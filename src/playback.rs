@@ -0,0 +1,132 @@
+//! Plays binauralized audio out of the default output device with
+//! [cpal](https://docs.rs/cpal/latest/cpal/), so that we can hear a run
+//! without opening the output file. Only built with the `playback` feature.
+
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    BufferSize, BuildStreamError, PlayStreamError, SampleRate, Stream, StreamConfig,
+};
+use log::error;
+use std::{error::Error, fmt::Display, sync::mpsc, thread, time::Duration};
+
+/// Binauralized samples are at the scale of a 16-bit file, but cpal wants
+/// them between -1 and 1.
+const FULL_SCALE: f32 = 32768.0;
+
+/// The ways that playing audio can fail.
+#[derive(Debug)]
+pub enum PlaybackError {
+    /// There is nothing to play the audio out of
+    NoOutputDevice,
+    /// Errors generated when cpal can't set up a stream to the device
+    BuildStream(BuildStreamError),
+    /// Errors generated when cpal can't start the stream
+    PlayStream(PlayStreamError),
+}
+
+impl Display for PlaybackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoOutputDevice => write!(f, "there is no audio output device"),
+            Self::BuildStream(e) => write!(f, "couldn't open the audio output: {e}"),
+            Self::PlayStream(e) => write!(f, "couldn't play the audio output: {e}"),
+        }
+    }
+}
+
+impl Error for PlaybackError {}
+
+impl From<BuildStreamError> for PlaybackError {
+    fn from(value: BuildStreamError) -> Self {
+        Self::BuildStream(value)
+    }
+}
+
+impl From<PlayStreamError> for PlaybackError {
+    fn from(value: PlayStreamError) -> Self {
+        Self::PlayStream(value)
+    }
+}
+
+/// Sets up, but doesn't start, a stereo stream to the default output device
+/// that plays `left` and `right` through once. The returned receiver hears
+/// from the stream once it has run out of samples.
+pub fn build_stream(
+    left: &[f32],
+    right: &[f32],
+    sample_rate: u32,
+) -> Result<(Stream, mpsc::Receiver<()>), PlaybackError> {
+    let device = cpal::default_host()
+        .default_output_device()
+        .ok_or(PlaybackError::NoOutputDevice)?;
+
+    let config = StreamConfig {
+        channels: 2,
+        sample_rate: SampleRate(sample_rate),
+        buffer_size: BufferSize::Default,
+    };
+
+    let mut samples = left
+        .iter()
+        .zip(right)
+        .map(|(&l, &r)| (l / FULL_SCALE, r / FULL_SCALE))
+        .collect::<Vec<_>>()
+        .into_iter();
+    let (done_tx, done_rx) = mpsc::channel();
+    let mut done_tx = Some(done_tx);
+
+    let stream = device.build_output_stream(
+        &config,
+        move |data: &mut [f32], _| {
+            for frame in data.chunks_mut(2) {
+                match samples.next() {
+                    Some((l, r)) => {
+                        frame[0] = l;
+                        frame[1] = r;
+                    }
+                    None => {
+                        frame.fill(0.0);
+                        if let Some(done_tx) = done_tx.take() {
+                            // Nobody may be waiting on us anymore
+                            let _ = done_tx.send(());
+                        }
+                    }
+                }
+            }
+        },
+        |e| error!("audio output failed: {e}"),
+        None,
+    )?;
+
+    Ok((stream, done_rx))
+}
+
+/// Plays `left` and `right` out of the default output device, and waits for
+/// them to finish.
+pub fn play(left: &[f32], right: &[f32], sample_rate: u32) -> Result<(), PlaybackError> {
+    let (stream, done_rx) = build_stream(left, right, sample_rate)?;
+    stream.play()?;
+
+    // If the stream dies, the sender is dropped along with it
+    let _ = done_rx.recv();
+    // Give the device a moment to drain its buffer
+    thread::sleep(Duration::from_millis(200));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stream_builds() {
+        match build_stream(&[0.0; 128], &[0.0; 128], 44100) {
+            Ok(_) => {}
+            Err(PlaybackError::NoOutputDevice) => {
+                eprintln!("no audio output device, skipping");
+            }
+            Err(e) => panic!("{e}"),
+        }
+    }
+}
@@ -0,0 +1,73 @@
+//! Draws a track of positions, like the ones captured by
+//! [`pipeline::capture`](crate::pipeline::capture) or read from a
+//! [`GrapeFile`](crate::spatial_data_format::GrapeFile), as a PNG, to show
+//! how the sources moved during a capture. Only built with the `plot`
+//! feature.
+
+use crate::hardware_data_manager::normalize_azimuth_f32;
+use crate::saf::BufferMetadata;
+use plotters::prelude::*;
+use std::{error::Error, f32::consts::TAU, path::Path};
+
+/// How big the images are, in pixels.
+const SIZE: (u32, u32) = (1024, 512);
+
+/// Plots the azimuth of each tag in `track` over time to the PNG at `path`,
+/// one line for each tag, with time along the x axis and azimuth, from 0 to
+/// 2PI, up the y axis. Each time slice of `track` is one step along the x
+/// axis.
+pub fn plot_azimuths(
+    track: &[Vec<BufferMetadata>],
+    path: impl AsRef<Path>,
+) -> Result<(), Box<dyn Error>> {
+    let root = BitMapBackend::new(path.as_ref(), SIZE).into_drawing_area();
+    root.fill(&WHITE)?;
+
+    let mut chart = ChartBuilder::on(&root)
+        .margin(10)
+        .build_cartesian_2d(0..track.len().max(1), 0_f32..TAU)?;
+
+    let num_tags = track.iter().map(Vec::len).max().unwrap_or(0);
+    for tag in 0..num_tags {
+        let points = track.iter().enumerate().filter_map(|(i, slice)| {
+            slice
+                .get(tag)
+                .map(|m| (i, normalize_azimuth_f32(m.azimuth)))
+        });
+        chart.draw_series(LineSeries::new(
+            points,
+            Palette99::pick(tag).stroke_width(2),
+        ))?;
+    }
+
+    root.present()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plot_is_a_png() {
+        let track = (0..50)
+            .map(|i| {
+                (0..3)
+                    .map(|tag| BufferMetadata {
+                        azimuth: i as f32 * 0.1 + tag as f32,
+                        elevation: 0.0,
+                        range: 1.0,
+                        gain: 1.0,
+                    })
+                    .collect()
+            })
+            .collect::<Vec<_>>();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("azimuths.png");
+
+        plot_azimuths(&track, &path).unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        assert!(bytes.starts_with(b"\x89PNG\r\n\x1a\n"));
+    }
+}
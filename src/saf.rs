@@ -1,21 +1,73 @@
 //! A safe api into the Spatial Audio Framework.
+//!
+//! SAF itself, and [`BinauraliserNF`] with it, is only built with the `saf`
+//! feature, which is on by default. Without it, the rest of this module is
+//! still here, and [`DummyBinauraliser`] is the only binauraliser.
 
+use crate::hardware_data_manager::normalize_azimuth_f32;
+#[cfg(feature = "saf")]
 use crate::saf_raw;
+#[cfg(feature = "saf")]
 use libc::c_void;
-use std::ptr::{addr_of_mut, null, null_mut};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::f32::consts::{PI, TAU};
+#[cfg(feature = "saf")]
+use std::ffi::CStr;
+use std::fmt::Display;
+#[cfg(feature = "saf")]
+use std::ptr::{addr_of_mut, null_mut};
 
 // Sets all audio channel distances to 1 meter—— stretch goal to specify per channel
-const SAMP_RATE: usize = 44100;
+/// The sample rate that [`BinauraliserNF`] runs at. Audio at any other rate
+/// has to be resampled first.
+pub const SAMP_RATE: usize = 44100;
+#[cfg(feature = "saf")]
 const NUM_OUT_CHANNELS: usize = 2;
 
 /// The number of samples that can be processed in one frame by a [`Binauraliser`].
 pub const FRAME_SIZE: usize = 128;
 
-const RAD_TO_DEGREE: f32 = 180.0 / std::f32::consts::PI;
+#[cfg(feature = "saf")]
+const RAD_TO_DEGREE: f32 = 180.0 / PI;
+
+/// The ways that setting up a binauraliser can fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SafError {
+    /// SAF didn't allocate a binauraliser of this mode, such as when it ran
+    /// out of memory
+    CreateFailed(BinauraliserMode),
+    /// CyberGrape was built without the `saf` feature, so SAF's
+    /// binauralisers aren't there to be created
+    Unavailable,
+}
+
+impl Display for SafError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::CreateFailed(BinauraliserMode::NearField) => {
+                write!(f, "SAF couldn't create its near field binauraliser")
+            }
+            Self::CreateFailed(BinauraliserMode::FarField) => {
+                write!(f, "SAF couldn't create its far field binauraliser")
+            }
+            Self::Unavailable => write!(
+                f,
+                "CyberGrape was built without the `saf` feature, only the dummy binauraliser is available"
+            ),
+        }
+    }
+}
+
+impl Error for SafError {}
 
 /// A Binauraliser is anything that can take an array of sound buffers, paired
 /// with their associated metadata, and return a pair of freshly allocated
 /// buffers representing the mixed stereo audio.
+///
+/// Building a binauraliser isn't part of the trait, so that one can be kept
+/// behind a `Box<dyn Binauraliser>`. [`BinauraliserKind::build`] picks one
+/// at runtime.
 pub trait Binauraliser {
     /// Takes a slice of audio data tuples for each sound source. Each tuple
     /// contains 128 frames of float sound data and a [`BufferMetadata`],
@@ -30,14 +82,48 @@ pub trait Binauraliser {
     /// Takes a slice of audio data tuples for each sound source. Each tuple
     /// contains float sound data and a BufferMetadata, which encodes the
     /// sound source's location, range, and gain over that frame period.
+    ///
+    /// With no sources at all, there is nothing to mix, so both vectors are
+    /// empty.
     fn process(&mut self, buffers: &[(BufferMetadata, &[f32])]) -> (Vec<f32>, Vec<f32>) {
+        if buffers.is_empty() {
+            return (Vec::new(), Vec::new());
+        }
+
+        let buffers = buffers
+            .iter()
+            .map(|&(metadata, samples)| (metadata, metadata, samples))
+            .collect::<Vec<_>>();
+
+        self.process_interpolated(&buffers)
+    }
+
+    /// Takes a slice of audio data tuples for each sound source. Each tuple
+    /// contains the source's [`BufferMetadata`] at the start of the buffer,
+    /// its [`BufferMetadata`] at the end of the buffer, and the float sound
+    /// data.
+    ///
+    /// Each frame of [`FRAME_SIZE`] samples is rendered at a position
+    /// linearly interpolated between the start and end metadata, so that
+    /// fast-moving sources glide between updates rather than jumping.
+    ///
+    /// With no sources at all, there is nothing to mix, so both vectors are
+    /// empty.
+    fn process_interpolated(
+        &mut self,
+        buffers: &[(BufferMetadata, BufferMetadata, &[f32])],
+    ) -> (Vec<f32>, Vec<f32>) {
+        if buffers.is_empty() {
+            return (Vec::new(), Vec::new());
+        }
+
         let len = buffers
             .iter()
-            .map(|(_tag, samples)| samples.len())
+            .map(|(_start, _end, samples)| samples.len())
             .max()
             .unwrap_or(0);
 
-        for (_tag, samples) in buffers.iter() {
+        for (_start, _end, samples) in buffers.iter() {
             debug_assert_eq!(0, samples.len() % FRAME_SIZE);
         }
 
@@ -47,10 +133,11 @@ pub trait Binauraliser {
         for i in (0..len).step_by(FRAME_SIZE) {
             let buf_lo = i;
             let buf_hi = i + FRAME_SIZE;
+            let t = buf_lo as f32 / len as f32;
 
             let frame = buffers
                 .iter()
-                .map(|(metadata, samples)| (*metadata, &samples[buf_lo..buf_hi]))
+                .map(|(start, end, samples)| (start.lerp(end, t), &samples[buf_lo..buf_hi]))
                 .collect::<Vec<_>>();
 
             let (mut left_vec, mut right_vec) = self.process_frame(&frame);
@@ -63,14 +150,68 @@ pub trait Binauraliser {
     }
 }
 
+/// A boxed binauraliser, such as one from [`BinauraliserKind::build`], is a
+/// binauraliser too, so it can go anywhere that the one inside it could.
+impl<B: Binauraliser + ?Sized> Binauraliser for Box<B> {
+    fn process_frame(&mut self, buffers: &[(BufferMetadata, &[f32])]) -> (Vec<f32>, Vec<f32>) {
+        (**self).process_frame(buffers)
+    }
+
+    fn process(&mut self, buffers: &[(BufferMetadata, &[f32])]) -> (Vec<f32>, Vec<f32>) {
+        (**self).process(buffers)
+    }
+
+    fn process_interpolated(
+        &mut self,
+        buffers: &[(BufferMetadata, BufferMetadata, &[f32])],
+    ) -> (Vec<f32>, Vec<f32>) {
+        (**self).process_interpolated(buffers)
+    }
+}
+
+/// The binaurialisers that can be picked from at runtime, such as from the
+/// command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum BinauraliserKind {
+    /// SAF's near field binauraliser, [`BinauraliserNF`]
+    #[default]
+    NearField,
+    /// SAF's plain far field binauraliser, a [`BinauraliserNF`] in
+    /// [`BinauraliserMode::FarField`]
+    FarField,
+    /// A [`DummyBinauraliser`], which doesn't place the sources at all
+    Dummy,
+}
+
+impl BinauraliserKind {
+    /// Creates a new binauraliser of this kind, or returns a [`SafError`] if
+    /// SAF couldn't set one up, or wasn't built at all.
+    pub fn build(self) -> Result<Box<dyn Binauraliser>, SafError> {
+        Ok(match self {
+            #[cfg(feature = "saf")]
+            BinauraliserKind::NearField => Box::new(BinauraliserNF::new()?),
+            #[cfg(feature = "saf")]
+            BinauraliserKind::FarField => {
+                Box::new(BinauraliserNF::with_mode(BinauraliserMode::FarField)?)
+            }
+            #[cfg(not(feature = "saf"))]
+            BinauraliserKind::NearField | BinauraliserKind::FarField => {
+                return Err(SafError::Unavailable)
+            }
+            BinauraliserKind::Dummy => Box::new(DummyBinauraliser),
+        })
+    }
+}
+
 /// The metadata associated with an audio stream. Includes the buffer's angular
 /// position, range, and gain.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub struct BufferMetadata {
-    /// The azimuth of the sound from the listener, in degrees, with 0.0 being
-    /// directly in front of the listener
+    /// The azimuth of the sound from the listener, in radians, with 0.0 being
+    /// directly in front of the listener. See
+    /// [`normalize_azimuth`](crate::hardware_data_manager::normalize_azimuth)
     pub azimuth: f32,
-    /// The elevation of the sound from the listener, in degrees, with 0.0
+    /// The elevation of the sound from the listener, in radians, with 0.0
     /// indicating that the sound is level with the listener's head
     pub elevation: f32,
     /// The distance of the sound from the listener
@@ -79,56 +220,190 @@ pub struct BufferMetadata {
     pub gain: f32,
 }
 
-/// Implementation of [`Binauraliser`] that uses SAF's BinauraliserNF (Near Field)
+impl BufferMetadata {
+    /// Linearly interpolates between `self` and `other`, where `t == 0.0`
+    /// yields `self` and `t == 1.0` yields `other`. The azimuth is
+    /// interpolated along the shortest way around the circle, so moving from
+    /// just below 2π to just above 0 does not sweep through the whole scene,
+    /// and comes out normalized.
+    pub fn lerp(&self, other: &Self, t: f32) -> Self {
+        let azm_delta = (other.azimuth - self.azimuth + PI).rem_euclid(TAU) - PI;
+
+        BufferMetadata {
+            azimuth: normalize_azimuth_f32(self.azimuth + azm_delta * t),
+            elevation: self.elevation + (other.elevation - self.elevation) * t,
+            range: self.range + (other.range - self.range) * t,
+            gain: self.gain + (other.gain - self.gain) * t,
+        }
+    }
+}
+
+/// Which of SAF's binauralisers a [`BinauraliserNF`] runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BinauraliserMode {
+    /// Filters each source by its distance, so that close sources sound
+    /// close
+    #[default]
+    NearField,
+    /// Ignores the distance of each source, which is cheaper, and all that's
+    /// needed when every source is a few meters away or more
+    FarField,
+}
+
+/// The name SAF gives the set of HRIRs that it ships with, which is used
+/// unless a SOFA file is loaded instead.
+#[cfg(feature = "saf")]
+pub const DEFAULT_HRIRS: &str = "SAF default";
+
+/// The set of HRIRs (head related impulse responses) that a
+/// [`BinauraliserNF`] places the sources with.
+#[cfg(feature = "saf")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HrirInfo {
+    /// Where the set came from, either [`DEFAULT_HRIRS`] or the path of the
+    /// SOFA file it was loaded from
+    pub name: String,
+    /// How many directions the set has an HRIR for
+    pub num_directions: usize,
+    /// How many samples long each HRIR is
+    pub length: usize,
+    /// The sample rate that the HRIRs were measured at
+    pub sample_rate: usize,
+}
+
+/// Implementation of [`Binauraliser`] that uses SAF's BinauraliserNF (Near
+/// Field), or its plain far field binauraliser
+#[cfg(feature = "saf")]
 pub struct BinauraliserNF {
     /// stores C-style BinauraliserNF object, for use in libsaf
     h_bin: *mut c_void,
+    /// which binauraliser `h_bin` is
+    mode: BinauraliserMode,
+    /// scratch space for the pointers to each source's samples, kept between
+    /// frames so that we don't allocate it every time
+    input_ptrs: Vec<*const f32>,
 }
 
+#[cfg(feature = "saf")]
 impl BinauraliserNF {
-    /// Creates a new [`BinauraliserNF`]
-    pub fn new() -> Self {
+    /// Creates a new [`BinauraliserNF`], or returns a [`SafError`] if SAF
+    /// couldn't allocate one.
+    pub fn new() -> Result<Self, SafError> {
+        Self::with_mode(BinauraliserMode::NearField)
+    }
+
+    /// Creates a new [`BinauraliserNF`] that runs SAF's binauraliser for
+    /// `mode`, or returns a [`SafError`] if SAF couldn't allocate one.
+    pub fn with_mode(mode: BinauraliserMode) -> Result<Self, SafError> {
         let mut h_bin = null_mut();
         unsafe {
-            saf_raw::binauraliserNF_create(addr_of_mut!(h_bin));
+            match mode {
+                BinauraliserMode::NearField => saf_raw::binauraliserNF_create(addr_of_mut!(h_bin)),
+                BinauraliserMode::FarField => saf_raw::binauraliser_create(addr_of_mut!(h_bin)),
+            }
+        }
 
+        // SAF leaves the handle null when it fails, and every other call
+        // would dereference it
+        if h_bin.is_null() {
+            return Err(SafError::CreateFailed(mode));
+        }
+
+        // From here on, dropping the binauraliser frees the handle
+        let binauraliser = BinauraliserNF {
+            h_bin,
+            mode,
+            input_ptrs: Vec::new(),
+        };
+
+        unsafe {
             // initialize sample rate
-            saf_raw::binauraliserNF_init(h_bin, SAMP_RATE as i32);
+            match mode {
+                BinauraliserMode::NearField => {
+                    saf_raw::binauraliserNF_init(h_bin, SAMP_RATE as i32)
+                }
+                BinauraliserMode::FarField => saf_raw::binauraliser_init(h_bin, SAMP_RATE as i32),
+            }
             saf_raw::binauraliser_setUseDefaultHRIRsflag(h_bin, 1);
         }
 
-        BinauraliserNF { h_bin }
+        Ok(binauraliser)
     }
-}
 
-impl Binauraliser for BinauraliserNF {
-    fn process_frame(&mut self, buffers: &[(BufferMetadata, &[f32])]) -> (Vec<f32>, Vec<f32>) {
+    /// Which binauraliser this is running.
+    pub fn mode(&self) -> BinauraliserMode {
+        self.mode
+    }
+
+    /// Describes the set of HRIRs this is running with. SAF only loads the
+    /// set once the codec is initialized, so this does that first if it
+    /// hasn't happened yet.
+    pub fn hrir_info(&mut self) -> HrirInfo {
+        unsafe {
+            match self.mode {
+                BinauraliserMode::NearField => saf_raw::binauraliserNF_initCodec(self.h_bin),
+                BinauraliserMode::FarField => saf_raw::binauraliser_initCodec(self.h_bin),
+            }
+
+            let path = saf_raw::binauraliser_getSofaFilePath(self.h_bin);
+            let name = if saf_raw::binauraliser_getUseDefaultHRIRsflag(self.h_bin) != 0
+                || path.is_null()
+            {
+                DEFAULT_HRIRS.to_string()
+            } else {
+                CStr::from_ptr(path).to_string_lossy().into_owned()
+            };
+
+            HrirInfo {
+                name,
+                num_directions: saf_raw::binauraliser_getNDirs(self.h_bin) as usize,
+                length: saf_raw::binauraliser_getHRIRlength(self.h_bin) as usize,
+                sample_rate: saf_raw::binauraliser_getHRIRsamplerate(self.h_bin) as usize,
+            }
+        }
+    }
+
+    /// Does the same as [`Binauraliser::process_frame`], but writes the mixed
+    /// audio into `left` and `right`, which must each be [`FRAME_SIZE`]
+    /// samples long, rather than allocating new vectors for it. Nothing is
+    /// allocated at all once the number of sources stops growing.
+    pub fn process_frame_into(
+        &mut self,
+        buffers: &[(BufferMetadata, &[f32])],
+        left: &mut [f32],
+        right: &mut [f32],
+    ) {
         for (_, b) in buffers {
             debug_assert_eq!(b.len(), FRAME_SIZE);
         }
-        // convert each slice in buffers to a raw pointer
-        let num_channels: usize = buffers.len();
-
-        // allocate input and output buffers for process() call
-        let mut raw_input_ptrs: Vec<*const f32> = vec![null(); num_channels];
+        assert_eq!(left.len(), FRAME_SIZE);
+        assert_eq!(right.len(), FRAME_SIZE);
+
+        // SAF can't mix zero sources, so we mix them into silence ourselves
+        if buffers.is_empty() {
+            left.fill(0.0);
+            right.fill(0.0);
+            return;
+        }
 
-        let mut output_vec_1 = vec![0.0; FRAME_SIZE];
-        let mut output_vec_2 = vec![0.0; FRAME_SIZE];
+        let num_channels: usize = buffers.len();
 
-        let mut raw_output_ptrs: Vec<*mut f32> = vec![null_mut(); NUM_OUT_CHANNELS];
+        // convert each slice in buffers to a raw pointer
+        self.input_ptrs.clear();
+        self.input_ptrs
+            .extend(buffers.iter().map(|(_, audio_data)| audio_data.as_ptr()));
 
-        raw_output_ptrs[0] = output_vec_1.as_mut_ptr();
-        raw_output_ptrs[1] = output_vec_2.as_mut_ptr();
+        let raw_output_ptrs: [*mut f32; NUM_OUT_CHANNELS] = [left.as_mut_ptr(), right.as_mut_ptr()];
 
         unsafe {
             saf_raw::binauraliser_setNumSources(self.h_bin, num_channels as i32);
 
-            for (i, &(metadata, audio_data)) in buffers.iter().enumerate() {
-                // store raw pointer for channel in raw_data_ptrs
-                raw_input_ptrs[i] = audio_data.as_ptr();
-
-                // set distance, azimuth, and elevation for each channel
-                saf_raw::binauraliserNF_setSourceDist_m(self.h_bin, i as i32, metadata.range);
+            for (i, &(metadata, _)) in buffers.iter().enumerate() {
+                // set distance, azimuth, and elevation for each channel.
+                // The far field binauraliser has no idea of distance
+                if self.mode == BinauraliserMode::NearField {
+                    saf_raw::binauraliserNF_setSourceDist_m(self.h_bin, i as i32, metadata.range);
+                }
                 saf_raw::binauraliser_setSourceAzi_deg(
                     self.h_bin,
                     i as i32,
@@ -143,49 +418,83 @@ impl Binauraliser for BinauraliserNF {
             }
 
             // note: must initialize codec variables after setting positional
-            // data for each of the sound sources
-            saf_raw::binauraliserNF_initCodec(self.h_bin);
-
-            // call process() to convert to binaural audio
-            saf_raw::binauraliserNF_process(
-                self.h_bin,
-                raw_input_ptrs.as_ptr(),  // N inputs x K samples
-                raw_output_ptrs.as_ptr(), // N inputs x K samples
-                num_channels as i32,      // N inputs
-                NUM_OUT_CHANNELS as i32,  // N outputs
-                FRAME_SIZE as i32,        // K samples
-            );
+            // data for each of the sound sources, then call process() to
+            // convert to binaural audio
+            match self.mode {
+                BinauraliserMode::NearField => {
+                    saf_raw::binauraliserNF_initCodec(self.h_bin);
+                    saf_raw::binauraliserNF_process(
+                        self.h_bin,
+                        self.input_ptrs.as_ptr(), // N inputs x K samples
+                        raw_output_ptrs.as_ptr(), // N inputs x K samples
+                        num_channels as i32,      // N inputs
+                        NUM_OUT_CHANNELS as i32,  // N outputs
+                        FRAME_SIZE as i32,        // K samples
+                    );
+                }
+                BinauraliserMode::FarField => {
+                    saf_raw::binauraliser_initCodec(self.h_bin);
+                    saf_raw::binauraliser_process(
+                        self.h_bin,
+                        self.input_ptrs.as_ptr(),
+                        raw_output_ptrs.as_ptr(),
+                        num_channels as i32,
+                        NUM_OUT_CHANNELS as i32,
+                        FRAME_SIZE as i32,
+                    );
+                }
+            }
         }
-
-        (output_vec_1, output_vec_2)
     }
 }
 
-impl Default for BinauraliserNF {
-    fn default() -> Self {
-        Self::new()
+#[cfg(feature = "saf")]
+impl Binauraliser for BinauraliserNF {
+    fn process_frame(&mut self, buffers: &[(BufferMetadata, &[f32])]) -> (Vec<f32>, Vec<f32>) {
+        let mut output_vec_1 = vec![0.0; FRAME_SIZE];
+        let mut output_vec_2 = vec![0.0; FRAME_SIZE];
+
+        self.process_frame_into(buffers, &mut output_vec_1, &mut output_vec_2);
+
+        (output_vec_1, output_vec_2)
     }
 }
 
 /// Frees memory associated with BinauraliserNF struct
+#[cfg(feature = "saf")]
 impl Drop for BinauraliserNF {
     fn drop(&mut self) {
         unsafe {
-            saf_raw::binauraliserNF_destroy(addr_of_mut!(self.h_bin));
+            match self.mode {
+                BinauraliserMode::NearField => {
+                    saf_raw::binauraliserNF_destroy(addr_of_mut!(self.h_bin))
+                }
+                BinauraliserMode::FarField => {
+                    saf_raw::binauraliser_destroy(addr_of_mut!(self.h_bin))
+                }
+            }
         }
     }
 }
 
-#[allow(dead_code)]
-struct DummyBinauraliser;
+/// Implementation of [`Binauraliser`] that doesn't place the sources at all,
+/// which is handy for checking levels, or running without SAF. The first
+/// source goes to the left ear, the second to the right, the third to the
+/// left again and so on, each scaled by its gain.
+#[derive(Debug, Default)]
+pub struct DummyBinauraliser;
 
 impl Binauraliser for DummyBinauraliser {
     fn process_frame(&mut self, buffers: &[(BufferMetadata, &[f32])]) -> (Vec<f32>, Vec<f32>) {
-        for (_, b) in buffers {
-            debug_assert_eq!(b.len(), FRAME_SIZE);
+        let mut ears = [vec![0.0; FRAME_SIZE], vec![0.0; FRAME_SIZE]];
+        for (i, (metadata, samples)) in buffers.iter().enumerate() {
+            debug_assert_eq!(samples.len(), FRAME_SIZE);
+            for (out, sample) in ears[i % 2].iter_mut().zip(*samples) {
+                *out += sample * metadata.gain;
+            }
         }
-        assert!(buffers.len() == 2);
-        (buffers[0].1.to_vec(), buffers[1].1.to_vec())
+        let [left, right] = ears;
+        (left, right)
     }
 }
 
@@ -193,6 +502,8 @@ impl Binauraliser for DummyBinauraliser {
 mod tests {
     use super::*;
 
+    use crate::audio_decoder::FULL_SCALE;
+    use crate::hound_helpers::f32_to_i16;
     use hound::{SampleFormat, WavSpec, WavWriter};
     use std::f32::consts::PI;
 
@@ -210,6 +521,7 @@ mod tests {
         gain: 1.0,
     };
 
+    #[cfg(feature = "saf")]
     const RIGHT_METADATA: BufferMetadata = BufferMetadata {
         azimuth: 90.0,
         elevation: 0.0,
@@ -217,9 +529,12 @@ mod tests {
         gain: 1.0,
     };
 
+    #[cfg(feature = "saf")]
     const C: f32 = 261.61;
+    #[cfg(feature = "saf")]
     const G: f32 = 392.00;
 
+    #[cfg(feature = "saf")]
     fn create_sine_wave(len: usize, note: f32) -> Vec<f32> {
         let snapped_len = len.div_ceil(FRAME_SIZE) * FRAME_SIZE;
 
@@ -241,20 +556,21 @@ mod tests {
         let mut writer = WavWriter::create(out_file, spec).unwrap();
 
         for (left, right) in std::iter::zip(left_samps, right_samps) {
-            writer.write_sample(left as i16).unwrap();
-            writer.write_sample(right as i16).unwrap();
+            writer.write_sample(f32_to_i16(left / FULL_SCALE)).unwrap();
+            writer.write_sample(f32_to_i16(right / FULL_SCALE)).unwrap();
         }
 
         writer.finalize().unwrap();
     }
 
     #[test]
+    #[cfg(feature = "saf")]
     ///
     /// Validate that runnning process_frame() doesn't segfault on mono
     /// audio data
     ///
     fn test_mono_single_frame() {
-        let mut binauraliser_nf = BinauraliserNF::new();
+        let mut binauraliser_nf = BinauraliserNF::new().unwrap();
 
         // 1 frame of audio (128 samples)
         let c_note_vec: Vec<f32> = create_sine_wave(FRAME_SIZE, C);
@@ -267,12 +583,13 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "saf")]
     ///
     /// Validate that runnning process_frame() doesn't segfault on stereo
     /// audio data
     ///
     fn test_stereo_single_frame() {
-        let mut binauraliser_nf = BinauraliserNF::new();
+        let mut binauraliser_nf = BinauraliserNF::new().unwrap();
 
         // 1 frame of audio (128 samples)
         let c_note_vec: Vec<f32> = create_sine_wave(FRAME_SIZE, C);
@@ -289,9 +606,292 @@ mod tests {
         assert!(right_samps.into_iter().all(|x| x != 0.0));
     }
 
+    /// Remembers the metadata of every frame it is asked to process
+    struct RecordingBinauraliser {
+        seen: Vec<BufferMetadata>,
+    }
+
+    impl Binauraliser for RecordingBinauraliser {
+        fn process_frame(&mut self, buffers: &[(BufferMetadata, &[f32])]) -> (Vec<f32>, Vec<f32>) {
+            self.seen
+                .extend(buffers.iter().map(|(metadata, _)| *metadata));
+            (vec![0.0; FRAME_SIZE], vec![0.0; FRAME_SIZE])
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "saf")]
+    fn test_backends_behind_trait_objects() {
+        let c_note_vec: Vec<f32> = create_sine_wave(FRAME_SIZE, C);
+        let g_note_vec: Vec<f32> = create_sine_wave(FRAME_SIZE, G);
+        let frame_slice = [
+            (LEFT_METADATA, c_note_vec.as_slice()),
+            (RIGHT_METADATA, g_note_vec.as_slice()),
+        ];
+
+        let mut binaurialisers = [BinauraliserKind::NearField, BinauraliserKind::Dummy]
+            .map(|kind| kind.build().unwrap());
+        let [near_field, dummy] = binaurialisers
+            .each_mut()
+            .map(|binauraliser| binauraliser.process_frame(&frame_slice));
+
+        // Each one is what we'd get from the backend itself
+        assert_eq!(
+            BinauraliserNF::new().unwrap().process_frame(&frame_slice),
+            near_field
+        );
+        assert_eq!((c_note_vec.clone(), g_note_vec.clone()), dummy);
+    }
+
+    #[test]
+    fn test_dummy_mixes_alternate_ears() {
+        let ones = vec![1.0; FRAME_SIZE];
+        let quiet = BufferMetadata {
+            gain: 0.5,
+            ..MOCK_METADATA
+        };
+        let frame_slice = [
+            (MOCK_METADATA, ones.as_slice()),
+            (MOCK_METADATA, ones.as_slice()),
+            (quiet, ones.as_slice()),
+        ];
+
+        let (left, right) = DummyBinauraliser.process_frame(&frame_slice);
+        assert_eq!(vec![1.5; FRAME_SIZE], left);
+        assert_eq!(vec![1.0; FRAME_SIZE], right);
+        assert_eq!(
+            (vec![0.0; FRAME_SIZE], vec![0.0; FRAME_SIZE]),
+            DummyBinauraliser.process_frame(&[])
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "saf")]
+    fn test_construction_result() {
+        for mode in [BinauraliserMode::NearField, BinauraliserMode::FarField] {
+            let binauraliser = BinauraliserNF::with_mode(mode);
+            assert!(binauraliser.is_ok_and(|binauraliser| binauraliser.mode() == mode));
+        }
+        for kind in [
+            BinauraliserKind::NearField,
+            BinauraliserKind::FarField,
+            BinauraliserKind::Dummy,
+        ] {
+            assert!(kind.build().is_ok());
+        }
+
+        let error = SafError::CreateFailed(BinauraliserMode::FarField);
+        assert_eq!(
+            "SAF couldn't create its far field binauraliser",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "saf")]
+    fn test_default_hrir_info() {
+        for mode in [BinauraliserMode::NearField, BinauraliserMode::FarField] {
+            let info = BinauraliserNF::with_mode(mode).unwrap().hrir_info();
+            assert_eq!(DEFAULT_HRIRS, info.name);
+            assert!(info.num_directions > 0);
+            assert!(info.length > 0);
+            assert!(info.sample_rate > 0);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "saf")]
+    fn test_near_and_far_field_modes() {
+        // SAF only filters sources by distance when they're inside its far
+        // field threshold, so put this one right by the listener's ear
+        let close = BufferMetadata {
+            range: 0.5,
+            ..LEFT_METADATA
+        };
+        let c_note_vec: Vec<f32> = create_sine_wave(FRAME_SIZE, C);
+        let frame_slice = [(close, c_note_vec.as_slice())];
+
+        let mut near_field = BinauraliserNF::with_mode(BinauraliserMode::NearField).unwrap();
+        let mut far_field = BinauraliserNF::with_mode(BinauraliserMode::FarField).unwrap();
+        assert_eq!(BinauraliserMode::NearField, near_field.mode());
+        assert_eq!(BinauraliserMode::FarField, far_field.mode());
+        assert_eq!(
+            BinauraliserMode::NearField,
+            BinauraliserNF::new().unwrap().mode()
+        );
+
+        let near = near_field.process_frame(&frame_slice);
+        let far = far_field.process_frame(&frame_slice);
+        assert!(near.0.iter().chain(&near.1).any(|&x| x != 0.0));
+        assert!(far.0.iter().chain(&far.1).any(|&x| x != 0.0));
+        assert_ne!(near, far);
+    }
+
+    #[test]
+    #[cfg(feature = "saf")]
+    ///
+    /// Validate that rendering in parallel chunks gives the same audio as
+    /// rendering serially, once each chunk's filterbank has warmed up
+    ///
+    fn test_parallel_matches_serial() {
+        use crate::pipeline::{render, render_parallel};
+
+        let num_frames = 200;
+        let spatial_data = (0..num_frames)
+            .map(|i| {
+                vec![
+                    BufferMetadata {
+                        azimuth: 0.05 * i as f32,
+                        ..LEFT_METADATA
+                    },
+                    BufferMetadata {
+                        elevation: 0.01 * i as f32,
+                        ..RIGHT_METADATA
+                    },
+                ]
+            })
+            .collect::<Vec<_>>();
+        let sound_data = vec![
+            create_sine_wave(num_frames * FRAME_SIZE, C),
+            create_sine_wave(num_frames * FRAME_SIZE, G),
+        ];
+
+        let serial = render(
+            &mut BinauraliserNF::new().unwrap(),
+            &spatial_data,
+            &sound_data,
+            FRAME_SIZE,
+            false,
+            |_, _| {},
+        );
+        let parallel = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build()
+            .unwrap()
+            .install(|| {
+                render_parallel(
+                    || BinauraliserNF::new().unwrap(),
+                    &spatial_data,
+                    &sound_data,
+                    FRAME_SIZE,
+                    false,
+                    |_, _| {},
+                )
+            });
+
+        let peak = serial
+            .0
+            .iter()
+            .chain(&serial.1)
+            .fold(0.0f32, |peak, x| peak.max(x.abs()));
+        assert!(peak > 0.0);
+        for (serial, parallel) in [(serial.0, parallel.0), (serial.1, parallel.1)] {
+            assert_eq!(serial.len(), parallel.len());
+            for (s, p) in serial.iter().zip(&parallel) {
+                assert!((s - p).abs() <= peak * 1e-4, "{s} and {p} differ");
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "saf"))]
+    fn test_built_without_saf() {
+        for kind in [BinauraliserKind::NearField, BinauraliserKind::FarField] {
+            assert!(matches!(kind.build(), Err(SafError::Unavailable)));
+        }
+        assert!(BinauraliserKind::Dummy.build().is_ok());
+        assert!(SafError::Unavailable.to_string().contains("`saf` feature"));
+    }
+
+    #[test]
+    fn test_metadata_serde_round_trip() {
+        let text = ron::ser::to_string(&LEFT_METADATA).unwrap();
+        let read: BufferMetadata = ron::de::from_str(&text).unwrap();
+        assert_eq!(LEFT_METADATA, read);
+    }
+
+    #[test]
+    fn test_interpolated_moves_smoothly() {
+        let mut binauraliser = RecordingBinauraliser { seen: vec![] };
+        let samples = vec![0.0; FRAME_SIZE * 4];
+        let end = BufferMetadata {
+            azimuth: 1.0,
+            elevation: 0.5,
+            range: 3.0,
+            gain: 1.0,
+        };
+
+        binauraliser.process_interpolated(&[(MOCK_METADATA, end, &samples)]);
+
+        assert_eq!(4, binauraliser.seen.len());
+        for (i, metadata) in binauraliser.seen.iter().enumerate() {
+            let t = i as f32 / 4.0;
+            assert!((metadata.azimuth - t).abs() < 1e-5);
+            assert!((metadata.range - (1.0 + 2.0 * t)).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_no_sources() {
+        let mut binauraliser = RecordingBinauraliser { seen: vec![] };
+
+        assert_eq!((vec![], vec![]), binauraliser.process(&[]));
+        assert_eq!((vec![], vec![]), binauraliser.process_interpolated(&[]));
+        assert!(binauraliser.seen.is_empty());
+    }
+
+    #[test]
+    fn test_lerp_wraps_azimuth() {
+        let start = BufferMetadata {
+            azimuth: 2.0 * PI - 0.2,
+            ..MOCK_METADATA
+        };
+        let end = BufferMetadata {
+            azimuth: 0.2,
+            ..MOCK_METADATA
+        };
+
+        // Straight ahead, which is always just above 0 rather than 2π
+        let halfway = start.lerp(&end, 0.5).azimuth;
+        assert!((0.0..TAU).contains(&halfway), "{halfway}");
+        assert!(halfway.min(TAU - halfway) < 1e-5, "{halfway}");
+
+        let nearly = start.lerp(&end, 0.25);
+        assert!((nearly.azimuth - (2.0 * PI - 0.1)).abs() < 1e-5);
+        assert!((start.lerp(&end, 0.75).azimuth - 0.1).abs() < 1e-5);
+    }
+
+    #[test]
+    #[cfg(feature = "saf")]
+    fn test_in_place_matches_allocating() {
+        let mut allocating = BinauraliserNF::new().unwrap();
+        let mut in_place = BinauraliserNF::new().unwrap();
+
+        let c_note_vec: Vec<f32> = create_sine_wave(FRAME_SIZE * 8, C);
+        let g_note_vec: Vec<f32> = create_sine_wave(FRAME_SIZE * 8, G);
+
+        let mut left = vec![0.0; FRAME_SIZE];
+        let mut right = vec![0.0; FRAME_SIZE];
+
+        for (c_frame, g_frame) in c_note_vec
+            .chunks(FRAME_SIZE)
+            .zip(g_note_vec.chunks(FRAME_SIZE))
+        {
+            let frame_slice = [(LEFT_METADATA, c_frame), (RIGHT_METADATA, g_frame)];
+
+            let (expected_left, expected_right) = allocating.process_frame(&frame_slice);
+            in_place.process_frame_into(&frame_slice, &mut left, &mut right);
+
+            let bits = |samples: &[f32]| samples.iter().map(|s| s.to_bits()).collect::<Vec<_>>();
+            assert_eq!(bits(&expected_left), bits(&left));
+            assert_eq!(bits(&expected_right), bits(&right));
+        }
+    }
+
     #[test]
+    #[cfg(feature = "saf")]
     fn test_stereo_multi_frame() {
-        let mut binauraliser_nf = BinauraliserNF::new();
+        let mut binauraliser_nf = BinauraliserNF::new().unwrap();
 
         const THREE_SEC: usize = SAMP_RATE * 3;
         // 1 frame of audio (128 samples)
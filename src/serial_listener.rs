@@ -0,0 +1,212 @@
+//! Reads hardware messages off of the serial port and feeds them into an
+//! [`Hdm`].
+//!
+//! The radio details of every reading, which the pipeline doesn't otherwise
+//! use, are logged to the [`RF_DIAGNOSTICS`] target. Turn them on with
+//! `RUST_LOG=cybergrape::rf=debug`.
+//!
+//! Every line logged about a reading carries the [`event_fields`] of that
+//! reading, as `key=value` pairs, so that one tag's readings can be picked
+//! out with something like `grep tag_id=CCF9578E0D8A`.
+
+use crate::hardware_message_decoder::{HardwareEvent, UUDFEvent};
+use crate::hdm::{AddStatus, Hdm, SequenceTracker};
+
+use log::{debug, warn};
+use serial2::SerialPort;
+use std::{
+    io::{self, Write},
+    str::{self, FromStr},
+    sync::{Arc, Mutex},
+    thread::spawn,
+    time::{Duration, Instant},
+};
+
+/// The log target for the radio details of every reading.
+pub const RF_DIAGNOSTICS: &str = "cybergrape::rf";
+
+/// How often the raw log is flushed. The listener thread never finishes, so
+/// we can't count on the log being flushed when it is dropped, but flushing
+/// every line would undo any buffering.
+const RAW_LOG_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The fields that say which reading `event` is, formatted as `key=value`
+/// pairs for the start of a log line.
+pub fn event_fields(event: &UUDFEvent) -> String {
+    format!(
+        "tag_id={:012X} anchor_id={:012X} sequence={}",
+        event.tag_id, event.anchor_id, event.sequence
+    )
+}
+
+/// Spawns a thread that reads from `port` forever, adding every measurement
+/// it receives to `hdm`. Every line is also copied, byte for byte, into
+/// `raw_log`, which is flushed every [`RAW_LOG_FLUSH_INTERVAL`]; pass
+/// [`io::sink`] to skip logging. Readings that went missing along the way,
+/// and trouble writing the log, are logged as warnings.
+pub fn listen_on_port(port: SerialPort, hdm: Arc<Mutex<Hdm>>, mut raw_log: Box<dyn Write + Send>) {
+    let _hdm_thread = spawn(move || {
+        // Read from the port and print the received data
+        let mut buffer = [0; 256];
+        let mut read_buf = Vec::new();
+        let mut sequences = SequenceTracker::new();
+        let mut last_flush = Instant::now();
+
+        loop {
+            let read_len = port.read(&mut buffer).expect("Device disconnected");
+
+            for &c in buffer.iter().take(read_len) {
+                read_buf.push(c);
+                if c == b'\n' {
+                    if let Err(e) = handle_line(&read_buf, &hdm, &mut sequences, &mut raw_log) {
+                        warn!("Failed to write to the raw log: {}", e);
+                    }
+                    read_buf.clear();
+                }
+            }
+
+            if last_flush.elapsed() >= RAW_LOG_FLUSH_INTERVAL {
+                if let Err(e) = raw_log.flush() {
+                    warn!("Failed to flush the raw log: {}", e);
+                }
+                last_flush = Instant::now();
+            }
+        }
+    });
+}
+
+/// Parses one newline-terminated line from the serial port and adds any
+/// measurement it contains to `hdm`, checking with `sequences` that no
+/// readings were dropped before it, then logs the line to `raw_log`. Only
+/// the error from writing the log is returned, by which time the
+/// measurement is already in `hdm`.
+pub fn handle_line(
+    line: &[u8],
+    hdm: &Mutex<Hdm>,
+    sequences: &mut SequenceTracker,
+    raw_log: &mut impl Write,
+) -> io::Result<()> {
+    add_line(line, hdm, sequences);
+
+    // Log the raw bytes whether or not they could be decoded, so that
+    // garbage that isn't valid utf-8 still makes it into the log
+    raw_log.write_all(line)
+}
+
+/// Parses `line` and adds any measurement it contains to `hdm`, for
+/// [`handle_line`].
+fn add_line(line: &[u8], hdm: &Mutex<Hdm>, sequences: &mut SequenceTracker) {
+    match str::from_utf8(line) {
+        Ok(s) => match HardwareEvent::from_str(s) {
+            Ok(HardwareEvent::UUDFEvent(e)) => {
+                let fields = event_fields(&e);
+                debug!(
+                    target: RF_DIAGNOSTICS,
+                    "{fields} rssi={} reserved={} channel={}",
+                    e.rssi,
+                    e.reserved,
+                    e.channel
+                );
+                let missed = sequences.observe(e.tag_id, e.sequence);
+                if missed > 0 {
+                    warn!(
+                        "{fields} Dropped {} reading(s) before this one, {} so far",
+                        missed,
+                        sequences.missed(e.tag_id)
+                    );
+                }
+                debug!("{fields} Received {:#?}, adding to HDM", e);
+                match hdm.lock().unwrap().add_update(e) {
+                    AddStatus::Accepted => {}
+                    AddStatus::Evicted => {
+                        warn!("{fields} The HDM is full, dropped its oldest reading to make room")
+                    }
+                    AddStatus::Full => warn!("{fields} The HDM is full, dropped this reading"),
+                    AddStatus::Duplicate => debug!("{fields} Dropped repeated reading"),
+                }
+            }
+            Ok(HardwareEvent::UUDFPEvent(ep)) => {
+                debug!("tag_id={:012X} Received {:#?}", ep.tag_id, ep);
+            }
+            Err(e) => {
+                warn!("Was unable to parse hardware message: {}", e);
+            }
+        },
+        // Often happens at the beginning of transmission when
+        // there is still garbage in the hardware buffer
+        Err(e) => {
+            warn!("Failed to decode utf-8: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn logs_raw_lines() {
+        let hdm = Mutex::new(Hdm::new());
+        let mut sequences = SequenceTracker::new();
+        let mut log = Vec::new();
+
+        let garbage = b"\xFF\xFE\x00garbage\n";
+        let line = b"+UUDF:CCF9578E0D8A,-42,20,0,-43,37,\"CCF9578E0D89\",\"\",15869,23\n";
+
+        handle_line(garbage, &hdm, &mut sequences, &mut log).unwrap();
+        handle_line(line, &hdm, &mut sequences, &mut log).unwrap();
+
+        assert_eq!([&garbage[..], &line[..]].concat(), log);
+        assert_eq!(1, hdm.lock().unwrap().by_ref().count());
+    }
+
+    #[test]
+    fn track_sequences() {
+        let hdm = Mutex::new(Hdm::new());
+        let mut sequences = SequenceTracker::new();
+
+        for sequence in [23, 24, 27] {
+            let line = format!(
+                "+UUDF:CCF9578E0D8A,-42,20,0,-43,37,\"CCF9578E0D89\",\"\",15869,{sequence}\n"
+            );
+            handle_line(line.as_bytes(), &hdm, &mut sequences, &mut io::sink()).unwrap();
+        }
+
+        assert_eq!(2, sequences.missed(0xCCF9578E0D8A));
+    }
+
+    /// A raw log that can't be written to
+    struct BrokenLog;
+
+    impl Write for BrokenLog {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("disk full"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn keeps_readings_when_the_log_fails() {
+        let hdm = Mutex::new(Hdm::new());
+        let mut sequences = SequenceTracker::new();
+        let line = b"+UUDF:CCF9578E0D8A,-42,20,0,-43,37,\"CCF9578E0D89\",\"\",15869,23\n";
+
+        assert!(handle_line(line, &hdm, &mut sequences, &mut BrokenLog).is_err());
+        assert_eq!(1, hdm.lock().unwrap().by_ref().count());
+    }
+
+    #[test]
+    fn fields_of_an_event() {
+        let line = "+UUDF:CCF9578E0D8A,-42,20,0,-43,37,\"CCF9578E0D89\",\"\",15869,23\n";
+        let Ok(HardwareEvent::UUDFEvent(event)) = HardwareEvent::from_str(line) else {
+            panic!("expected a UUDF event");
+        };
+        assert_eq!(
+            "tag_id=CCF9578E0D8A anchor_id=CCF9578E0D89 sequence=23",
+            event_fields(&event)
+        );
+    }
+}
@@ -6,25 +6,47 @@
 //!   - The sample rate of the file (samples per second)
 //!   - The number of data streams
 //!   - An array of tags for the data streams, indicating a cartesian dimenson,
-//!     a spherical dimension, or a angular dimension; see [GrapeTag].
+//!     a spherical dimension, a angular dimension, a gain, or a time; see
+//!     [GrapeTag].
 //! - Then there is a seperator, which is a byte of all 1s; `0xFF`.
-//! - Finally, the samples, which are `f32`s, interpolated from each stream
-//!   in order.
+//! - Then the samples, which are `f32`s, interpolated from each stream
+//!   in order. If the streams have their own sample rates, each stream's
+//!   samples come one after another instead, a whole stream at a time.
+//! - Finally, if the header says so, a big-endian CRC32 of the sample bytes,
+//!   so that a file can be checked for damage when it is read.
 //!
 //! More concretely, the header is encoded using [serde] and [ron]. In the file,
 //! it appears as follows:
 //!
 //! ```text
-//! (n_streams:A,sample_rate:B,tags:[C, D,...])
+//! (version:V,n_streams:A,sample_rate:B,tags:[C, D,...],checksum:true)
 //! ```
 //!
 //! Where:
 //!
+//! - `V` is the version of the format that the file was written with. Files
+//!   from before versioning leave it out, and are version 1
 //! - `A` is the number of streams contained in the file
 //! - `B` is the sample rate in samples per second
 //! - `[C, D,...]` are tags, each associated with one stream
+//! - `checksum:true` is only there if the samples are followed by a checksum
+//!
+//! From version 3, the header can also give each stream its own sample rate,
+//! along with how many samples it has, which lets slowly changing streams
+//! take up less space:
+//!
+//! ```text
+//! (version:3,n_streams:2,sample_rate:B,tags:[C, D],stream_rates:[B, E],stream_lens:[F, G])
+//! ```
+//!
+//! A stream with its own rate still lasts as long as the others, and is
+//! resampled to whatever rate it is read at, like the rest.
 
 #![allow(unused)]
+use crate::hardware_data_manager::Id;
+use crate::localizer::Point;
+use crate::saf::BufferMetadata;
+
 use serde::{Deserialize, Serialize};
 use std::{
     borrow::Cow,
@@ -33,6 +55,8 @@ use std::{
     fs::File,
     io::{Read, Write},
     path::Path,
+    sync::OnceLock,
+    time::Duration,
 };
 
 /// This struct contains the header and samples associated with a GrapeFile
@@ -40,14 +64,66 @@ use std::{
 pub struct GrapeFile {
     header: GrapeFileHeader,
     samples: Vec<f32>,
+    /// The file with every stream resampled to its sample rate, for files
+    /// whose streams have their own, worked out the first time it's needed.
+    uniform: Cached<Box<GrapeFile>>,
+}
+
+/// A value worked out from the rest of a struct the first time it's needed.
+/// It's left out when comparing, since it only ever holds what could be
+/// worked out again.
+#[derive(Debug, Clone)]
+struct Cached<T>(OnceLock<T>);
+
+impl<T> Default for Cached<T> {
+    fn default() -> Self {
+        Cached(OnceLock::new())
+    }
 }
 
+impl<T> PartialEq for Cached<T> {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+/// How many samples [GrapeFile::to_file()] encodes at a time.
+const WRITE_CHUNK_SAMPLES: usize = 4096;
+
+/// The version of the format that this module writes. Version 2 added the
+/// optional checksum, version 3 the optional per-stream sample rates, and
+/// version 4 the [GrapeTag::Gain] and [GrapeTag::Timestamp] tags.
+pub const FORMAT_VERSION: u64 = 4;
+
+/// The first version of the format that can give streams their own sample
+/// rates.
+const STREAM_RATES_VERSION: u64 = 3;
+
 /// This struct contains the header data for a [GrapeFile].
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 struct GrapeFileHeader {
+    #[serde(default = "GrapeFileHeader::unversioned")]
+    version: u64,
     n_streams: u64,
     sample_rate: u64,
     tags: Vec<GrapeTag>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    checksum: bool,
+    /// The sample rate of each stream, or empty if they all share
+    /// `sample_rate`. If not, the samples are stored a stream at a time
+    /// rather than interleaved.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    stream_rates: Vec<u64>,
+    /// How many samples each stream has, alongside `stream_rates`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    stream_lens: Vec<u64>,
+}
+
+impl GrapeFileHeader {
+    /// Files written before the header had a version are version 1.
+    fn unversioned() -> u64 {
+        1
+    }
 }
 
 /// The [GrapeTag] identifies the _kind_ of spatial data contained within a
@@ -72,6 +148,26 @@ pub enum GrapeTag {
     Yaw,
     /// Roll in angular direction
     Roll,
+    /// Gain applied to a source, which can change over time, like a fade
+    Gain,
+    /// When each sample was taken, in seconds since the start of the
+    /// recording, for streams that weren't sampled quite evenly
+    Timestamp,
+}
+
+/// Which streams of a [GrapeFile] describe one source, as found by
+/// [GrapeFile::logical_sources()]. Each is the index of a stream in the
+/// file, or `None` if the source doesn't have that stream.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SourceStreams {
+    /// The source's [GrapeTag::Azimuth] stream
+    pub azimuth: Option<usize>,
+    /// The source's [GrapeTag::Elevation] stream
+    pub elevation: Option<usize>,
+    /// The source's [GrapeTag::Range] stream
+    pub range: Option<usize>,
+    /// The source's [GrapeTag::Gain] stream
+    pub gain: Option<usize>,
 }
 
 /// A nice little error that we can return if things go wrong throughout
@@ -79,9 +175,37 @@ pub enum GrapeTag {
 #[derive(Debug)]
 pub enum GrapeFileError {
     /// Returned when trying to build a [GrapeFile] using [GrapeFileBuilder::build()]
-    /// and the sample buffers are of unequal lengths.
+    /// and the sample buffers are of unequal lengths, or by
+    /// [GrapeFile::validate()] when the samples can't be shared evenly
+    /// between the streams.
     UnequalSampleBufferLengths,
 
+    /// Returned when trying to combine [GrapeFile]s that were recorded at
+    /// different sample rates.
+    MismatchedSampleRates,
+
+    /// Returned when trying to combine [GrapeFile]s whose streams are not
+    /// tagged identically.
+    MismatchedTags,
+
+    /// Returned by [GrapeFileBuilder::add_tagged_streams()] or
+    /// [GrapeFile::validate()] when there isn't exactly one tag for every
+    /// stream.
+    TagCountMismatch,
+
+    /// Returned when reading a [GrapeFile] whose checksum doesn't match its
+    /// samples, because the file was damaged after it was written.
+    ChecksumMismatch,
+
+    /// Returned when reading a [GrapeFile] written with a newer version of
+    /// the format than [FORMAT_VERSION]. Carries the version of the file.
+    UnsupportedVersion(u64),
+
+    /// Returned when reading a [GrapeFile] whose per-stream sample rates
+    /// don't agree with the rest of the file, or that has per-stream sample
+    /// rates but was written with a version from before they were added.
+    InvalidStreamRates,
+
     /// Returned when trying to read a [GrapeFile], but are not able to find
     /// the delimiter between the header and sample binary.
     NoDelimiter,
@@ -89,6 +213,16 @@ pub enum GrapeFileError {
     /// Returned when somehow we fail to turn four bytes into a f32 when reading.
     TryInto,
 
+    /// Returned when reading a [GrapeFile] whose samples don't take up a
+    /// whole number of `f32`s, usually because the file was cut short.
+    TruncatedSamples,
+
+    /// Returned when building a [GrapeFile] with
+    /// [GrapeFileBuilder::reject_non_finite()] set, or by
+    /// [GrapeFile::validate()], when one of the streams contains a NaN or
+    /// infinite sample. Carries the tag of that stream.
+    NonFiniteSample(GrapeTag),
+
     /// Returned when io fails when reading or writing files.
     IoError(std::io::Error),
 
@@ -104,8 +238,21 @@ impl fmt::Display for GrapeFileError {
         use GrapeFileError as GFE;
         let msg = match self {
             GFE::UnequalSampleBufferLengths => Cow::from("unequal sample buffer lengths"),
+            GFE::MismatchedSampleRates => Cow::from("mismatched sample rates"),
+            GFE::MismatchedTags => Cow::from("mismatched stream tags"),
+            GFE::TagCountMismatch => Cow::from("number of tags and streams differ"),
+            GFE::ChecksumMismatch => Cow::from("checksum does not match the samples"),
+            GFE::UnsupportedVersion(version) => Cow::from(format!(
+                "GrapeFile version {} is newer than {}",
+                version, FORMAT_VERSION
+            )),
+            GFE::InvalidStreamRates => Cow::from("per-stream sample rates don't match the file"),
             GFE::NoDelimiter => Cow::from("no delimiter in GrapeFile"),
             GFE::TryInto => Cow::from("something went wrong while parsing f32s"),
+            GFE::TruncatedSamples => Cow::from("samples are not a whole number of f32s"),
+            GFE::NonFiniteSample(tag) => {
+                Cow::from(format!("non-finite sample in {:?} stream", tag))
+            }
             GFE::IoError(error) => Cow::from(format!("io error: {}", error)),
             GFE::RonError(error) => Cow::from(format!("ron error: {}", error)),
             GFE::RonSpannedError(error) => Cow::from(format!("ron spanning error: {}", error)),
@@ -130,18 +277,38 @@ impl GrapeFile {
         self.to_file(&mut handle)
     }
 
-    /// Write out a [GrapeFile] to the [Write]able object provided.
+    /// Write out a [GrapeFile] to the [Write]able object provided. The
+    /// samples are encoded a chunk at a time, so that a big file isn't held
+    /// in memory twice over while it's written.
     pub fn to_file(&self, file: &mut impl Write) -> Result<(), GrapeFileError> {
         let h_str = ron::ser::to_string(&self.header).map_err(GrapeFileError::RonError)?;
-        let h_buf = h_str.as_bytes();
+        file.write_all(h_str.as_bytes())
+            .and_then(|_| file.write_all(&[0xFF]))
+            .map_err(GrapeFileError::IoError)?;
 
-        file.write_all(h_buf).map_err(GrapeFileError::IoError)?;
+        let mut crc = CRC32_INIT;
+        let mut buf = Vec::with_capacity(WRITE_CHUNK_SAMPLES.min(self.samples.len()) * 4);
+        for chunk in self.samples.chunks(WRITE_CHUNK_SAMPLES) {
+            buf.clear();
+            buf.extend(chunk.iter().flat_map(|f| f.to_be_bytes()));
+            crc = crc32_update(crc, &buf);
+            file.write_all(&buf).map_err(GrapeFileError::IoError)?;
+        }
 
-        file.write_all(&[0xFF]).map_err(GrapeFileError::IoError)?;
+        if self.header.checksum {
+            file.write_all(&(!crc).to_be_bytes())
+                .map_err(GrapeFileError::IoError)?;
+        }
 
-        let s_buf: Vec<u8> = self.samples.iter().flat_map(|f| f.to_be_bytes()).collect();
+        Ok(())
+    }
 
-        file.write_all(&s_buf).map_err(GrapeFileError::IoError)
+    /// Encode a [GrapeFile] into memory, exactly as it would be written out
+    /// by [GrapeFile::to_file()].
+    pub fn to_bytes(&self) -> Result<Vec<u8>, GrapeFileError> {
+        let mut buf = Vec::with_capacity(self.samples.len() * 4 + 64);
+        self.to_file(&mut buf)?;
+        Ok(buf)
     }
 
     /// Read a [GrapeFile] from the path provided.
@@ -156,6 +323,12 @@ impl GrapeFile {
         file.read_to_end(&mut raw_text)
             .map_err(GrapeFileError::IoError)?;
 
+        Self::from_bytes(&raw_text)
+    }
+
+    /// Read a [GrapeFile] that is already in memory, such as one produced by
+    /// [GrapeFile::to_bytes()].
+    pub fn from_bytes(raw_text: &[u8]) -> Result<Self, GrapeFileError> {
         let delim_idx = raw_text
             .iter()
             .position(|e| *e == 0xFF)
@@ -167,8 +340,46 @@ impl GrapeFile {
         let header = ron::de::from_bytes::<GrapeFileHeader>(header_buf)
             .map_err(GrapeFileError::RonSpannedError)?;
 
+        if header.version > FORMAT_VERSION {
+            return Err(GrapeFileError::UnsupportedVersion(header.version));
+        }
+
+        let samples_buf = if header.checksum {
+            let split = samples_buf
+                .len()
+                .checked_sub(4)
+                .ok_or(GrapeFileError::TruncatedSamples)?;
+            let (samples_buf, crc) = samples_buf.split_at(split);
+            let crc = u32::from_be_bytes(crc.try_into().map_err(|_| GrapeFileError::TryInto)?);
+            if crc != crc32(samples_buf) {
+                return Err(GrapeFileError::ChecksumMismatch);
+            }
+            samples_buf
+        } else {
+            samples_buf
+        };
+
+        if samples_buf.len() % 4 != 0 {
+            return Err(GrapeFileError::TruncatedSamples);
+        }
+
+        // Files without per-stream rates leave both lists out
+        let (rates, lens) = (&header.stream_rates, &header.stream_lens);
+        let has_stream_rates = !rates.is_empty() || !lens.is_empty();
+        if has_stream_rates
+            && (header.version < STREAM_RATES_VERSION
+                || rates.len() as u64 != header.n_streams
+                || lens.len() as u64 != header.n_streams
+                || lens.iter().sum::<u64>() != samples_buf.len() as u64 / 4
+                || !rates
+                    .iter()
+                    .all(|&rate| rates_fit(rate, header.sample_rate)))
+        {
+            return Err(GrapeFileError::InvalidStreamRates);
+        }
+
         let samples: Vec<f32> = samples_buf
-            .chunks(4)
+            .chunks_exact(4)
             .map(|bs| {
                 let four_bytes: [u8; 4] =
                     bs[0..4].try_into().map_err(|_| GrapeFileError::TryInto)?;
@@ -176,13 +387,254 @@ impl GrapeFile {
             })
             .collect::<Result<Vec<f32>, GrapeFileError>>()?;
 
-        Ok(GrapeFile { header, samples })
+        Ok(GrapeFile::new(header, samples))
+    }
+
+    /// Check the [GrapeFile] at the path provided for problems, like
+    /// [GrapeFile::validate_bytes()].
+    pub fn validate_path(path: impl AsRef<Path>) -> Result<(), Vec<GrapeFileError>> {
+        let raw_text = std::fs::read(path).map_err(|e| vec![GrapeFileError::IoError(e)])?;
+        Self::validate_bytes(&raw_text)
+    }
+
+    /// Check a [GrapeFile] that is still encoded for problems. Where
+    /// [GrapeFile::from_bytes()] stops at the first problem, this carries on
+    /// reading as much of the file as it can, and returns every problem it
+    /// finds, including those that [GrapeFile::validate()] looks for. Only a
+    /// missing delimiter or a header that can't be parsed at all stop it
+    /// early.
+    pub fn validate_bytes(raw_text: &[u8]) -> Result<(), Vec<GrapeFileError>> {
+        let delim_idx = raw_text
+            .iter()
+            .position(|e| *e == 0xFF)
+            .ok_or_else(|| vec![GrapeFileError::NoDelimiter])?;
+
+        let (header_buf, samples_buf) = raw_text.split_at(delim_idx);
+        let samples_buf = &samples_buf[1..];
+
+        let header = ron::de::from_bytes::<GrapeFileHeader>(header_buf)
+            .map_err(|e| vec![GrapeFileError::RonSpannedError(e)])?;
+
+        let mut problems = Vec::new();
+        let samples_buf = if header.checksum {
+            match samples_buf.len().checked_sub(4) {
+                Some(split) => {
+                    let (samples_buf, crc) = samples_buf.split_at(split);
+                    let crc = u32::from_be_bytes([crc[0], crc[1], crc[2], crc[3]]);
+                    if crc != crc32(samples_buf) {
+                        problems.push(GrapeFileError::ChecksumMismatch);
+                    }
+                    samples_buf
+                }
+                None => {
+                    problems.push(GrapeFileError::TruncatedSamples);
+                    &[]
+                }
+            }
+        } else {
+            samples_buf
+        };
+
+        if samples_buf.len() % 4 != 0 {
+            problems.push(GrapeFileError::TruncatedSamples);
+        }
+
+        // Whatever is left over after the last whole f32 is left out
+        let samples = samples_buf
+            .chunks_exact(4)
+            .map(|bs| f32::from_be_bytes([bs[0], bs[1], bs[2], bs[3]]))
+            .collect();
+
+        if let Err(more) = GrapeFile::new(header, samples).validate() {
+            problems.extend(more);
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+
+    /// Check a [GrapeFile] for problems: a version newer than
+    /// [FORMAT_VERSION], a different number of tags than streams, per-stream
+    /// sample rates that don't agree with the rest of the file or can't be
+    /// resampled to its sample rate, samples that
+    /// can't be shared evenly between the streams, and NaN or infinite
+    /// samples. Every problem is returned, rather than just the first, with
+    /// one [GrapeFileError::NonFiniteSample] for each stream that has any.
+    pub fn validate(&self) -> Result<(), Vec<GrapeFileError>> {
+        let header = &self.header;
+        let n_streams = header.n_streams as usize;
+        let mut problems = Vec::new();
+
+        if header.version > FORMAT_VERSION {
+            problems.push(GrapeFileError::UnsupportedVersion(header.version));
+        }
+
+        if header.tags.len() != n_streams {
+            problems.push(GrapeFileError::TagCountMismatch);
+        }
+
+        let (rates, lens) = (&header.stream_rates, &header.stream_lens);
+        let has_stream_rates = !rates.is_empty() || !lens.is_empty();
+        let lens_fit = lens.iter().sum::<u64>() == self.samples.len() as u64;
+        let shared_evenly = match n_streams {
+            0 => self.samples.is_empty(),
+            n_streams => self.samples.len().is_multiple_of(n_streams),
+        };
+        if has_stream_rates {
+            if header.version < STREAM_RATES_VERSION
+                || rates.len() != n_streams
+                || lens.len() != n_streams
+                || !lens_fit
+                || !rates
+                    .iter()
+                    .all(|&rate| rates_fit(rate, header.sample_rate))
+            {
+                problems.push(GrapeFileError::InvalidStreamRates);
+            }
+        } else if !shared_evenly {
+            problems.push(GrapeFileError::UnequalSampleBufferLengths);
+        }
+
+        // Work out which stream each sample belongs to, as far as we can
+        let stream_idxs: Box<dyn Iterator<Item = usize>> = if has_stream_rates && lens_fit {
+            Box::new(
+                lens.iter()
+                    .enumerate()
+                    .flat_map(|(i, &len)| std::iter::repeat_n(i, len as usize)),
+            )
+        } else {
+            Box::new((0..n_streams).cycle())
+        };
+        let mut non_finite = vec![false; n_streams.max(lens.len())];
+        for (i, sample) in stream_idxs.zip(&self.samples) {
+            non_finite[i] |= !sample.is_finite();
+        }
+        problems.extend(
+            header
+                .tags
+                .iter()
+                .zip(non_finite)
+                .filter(|(_, non_finite)| *non_finite)
+                .map(|(tag, _)| GrapeFileError::NonFiniteSample(*tag)),
+        );
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+
+    /// Combine several [GrapeFile]s into one file containing all of their
+    /// streams, in order. This is useful when each tag was recorded in its
+    /// own session. All files must share a sample rate, otherwise
+    /// [GrapeFileError::MismatchedSampleRates] is returned, and all streams
+    /// must be the same length, otherwise
+    /// [GrapeFileError::UnequalSampleBufferLengths] is returned. The merged
+    /// file has a checksum if any of the files did.
+    pub fn merge(files: &[GrapeFile]) -> Result<GrapeFile, GrapeFileError> {
+        let mut builder = GrapeFile::builder();
+
+        if let Some(first) = files.first() {
+            builder = builder.set_samplerate(first.header.sample_rate);
+        }
+        if files.iter().any(|file| file.header.checksum) {
+            builder = builder.with_checksum();
+        }
+
+        for file in files {
+            if file.header.sample_rate != builder.sample_rate {
+                return Err(GrapeFileError::MismatchedSampleRates);
+            }
+
+            let (_, streams) = file.streams_native_sample_rate();
+            for (tag, stream) in streams {
+                builder = builder.add_stream(&stream, tag);
+            }
+        }
+
+        builder.build()
+    }
+
+    /// Split a [GrapeFile] into several files of `group_size` streams each,
+    /// in order, such as one file per tag when each tag was recorded as an
+    /// azimuth and elevation stream. The last file holds whatever streams are
+    /// left over, if there aren't a whole number of groups. Every file keeps
+    /// the sample rate of this one, and its checksum if it has one, and
+    /// [GrapeFile::merge()] puts them back together again.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `group_size` is 0.
+    pub fn split_by_tag_groups(&self, group_size: usize) -> Vec<GrapeFile> {
+        assert!(group_size > 0, "groups must have at least one stream");
+
+        let (sample_rate, streams) = self.streams_native_sample_rate();
+        let mut builder = GrapeFile::builder().set_samplerate(sample_rate);
+        if self.header.checksum {
+            builder = builder.with_checksum();
+        }
+        streams
+            .chunks(group_size)
+            .map(|group| {
+                group
+                    .iter()
+                    .fold(builder.clone().clear_streams(), |builder, (tag, stream)| {
+                        builder.add_stream(stream, *tag)
+                    })
+                    .build_truncate()
+            })
+            .collect()
+    }
+
+    /// Stitch `other` onto the end of this [GrapeFile] in time, producing a
+    /// file whose samples are those of `self` followed by those of `other`.
+    /// Both files must have identical tags, otherwise
+    /// [GrapeFileError::MismatchedTags] is returned, and the same sample
+    /// rate, otherwise [GrapeFileError::MismatchedSampleRates] is returned.
+    /// Streams with their own sample rates are resampled to the file's.
+    pub fn append_time(&self, other: &GrapeFile) -> Result<GrapeFile, GrapeFileError> {
+        let (this, other) = (self.uniform(), other.uniform());
+        if this.header.tags != other.header.tags {
+            return Err(GrapeFileError::MismatchedTags);
+        }
+
+        if this.header.sample_rate != other.header.sample_rate {
+            return Err(GrapeFileError::MismatchedSampleRates);
+        }
+
+        // Samples are stored one time step after another, so concatenating
+        // the interleaved buffers concatenates every stream at once.
+        let mut samples = Vec::with_capacity(this.samples.len() + other.samples.len());
+        samples.extend_from_slice(&this.samples);
+        samples.extend_from_slice(&other.samples);
+
+        Ok(GrapeFile::new(this.header.clone(), samples))
+    }
+
+    /// The version of the format that this file was read as, or
+    /// [FORMAT_VERSION] if it was built rather than read.
+    pub fn version(&self) -> u64 {
+        self.header.version
+    }
+
+    /// The same file, to be written out with the current [FORMAT_VERSION].
+    /// Every version so far only adds to the format, so the sample rate,
+    /// tags, and samples carry over exactly.
+    pub fn upgrade(mut self) -> Self {
+        self.header.version = FORMAT_VERSION;
+        self.uniform = Cached::default();
+        self
     }
 
     /// Extract the streams from a [GrapeFile], also returns the sample rate
-    /// because the streams can be encoded at any sample rate.
+    /// because the streams can be encoded at any sample rate. Streams with
+    /// their own sample rates are resampled to the file's.
     pub fn streams_native_sample_rate(&self) -> (u64, Vec<(GrapeTag, Vec<f32>)>) {
-        let sample_vecs = self.get_raw_streams();
+        let sample_vecs = self.uniform().get_raw_streams();
 
         let res_vecs = Self::attach_tags(&self.header.tags, sample_vecs);
 
@@ -190,13 +642,114 @@ impl GrapeFile {
     }
 
     /// Extracts the streams from a [GrapeFile], interpolating or quantizing
-    /// the streams to produce datapoints at the requested sample rate.
+    /// each stream from its own sample rate to produce datapoints at the
+    /// requested sample rate.
     pub fn streams_with_sample_rate(&self, sample_rate: u64) -> Vec<(GrapeTag, Vec<f32>)> {
-        match sample_rate.cmp(&self.header.sample_rate) {
-            Ordering::Equal => self.streams_native_sample_rate().1,
-            Ordering::Less => self.streams_quantized(sample_rate),
-            Ordering::Greater => self.streams_interpolated(sample_rate),
+        let streams = self
+            .get_raw_streams()
+            .into_iter()
+            .zip(self.stream_sample_rates())
+            .map(|(stream, native)| resample(stream, native, sample_rate))
+            .collect();
+        Self::attach_tags(&self.header.tags, streams)
+    }
+
+    /// The sample rate of each stream, which is the file's sample rate unless
+    /// the streams were given their own.
+    pub fn stream_sample_rates(&self) -> Vec<u64> {
+        if self.header.stream_rates.is_empty() {
+            vec![self.header.sample_rate; self.header.n_streams as usize]
+        } else {
+            self.header.stream_rates.clone()
+        }
+    }
+
+    /// Walks the file one time step at a time, yielding each stream's
+    /// [GrapeTag] paired with its value at that time step. Streams with
+    /// their own sample rates are resampled to the file's.
+    pub fn rows(&self) -> impl Iterator<Item = Vec<(GrapeTag, f32)>> + '_ {
+        let file = self.uniform();
+        // A file with no streams has no samples either, but `chunks` refuses
+        // a chunk size of zero, so we ask for chunks of one from nothing.
+        let n_streams = (self.header.n_streams as usize).max(1);
+        (0..file.samples.len() / n_streams).map(move |i| {
+            let row = &file.samples[i * n_streams..(i + 1) * n_streams];
+            file.header
+                .tags
+                .iter()
+                .cloned()
+                .zip(row.iter().cloned())
+                .collect()
+        })
+    }
+
+    /// How many samples each stream has at the file's sample rate. A file
+    /// with no streams has none.
+    pub fn num_samples_per_stream(&self) -> usize {
+        let file = self.uniform();
+        match file.header.n_streams as usize {
+            0 => 0,
+            n_streams => file.samples.len() / n_streams,
+        }
+    }
+
+    /// How long the streams last at the file's sample rate. A file with no
+    /// streams, or a sample rate of 0, lasts no time at all.
+    pub fn duration(&self) -> Duration {
+        match self.header.sample_rate {
+            0 => Duration::ZERO,
+            sample_rate => {
+                Duration::from_secs_f64(self.num_samples_per_stream() as f64 / sample_rate as f64)
+            }
+        }
+    }
+
+    /// The number of tags whose streams are in this file, where each tag's
+    /// streams follow one another, as read by [grape_file_to_metadata()].
+    pub fn num_tag_groups(&self) -> usize {
+        self.tag_groups().last().copied().unwrap_or(0)
+    }
+
+    /// Which streams belong to each source in this file, in order. Each
+    /// source's streams are expected to follow one another, in any order,
+    /// like the azimuth and elevation streams written for each tag by a
+    /// serial run. A new source starts whenever a stream repeats a tag that
+    /// the current source already has, so
+    /// `[Azimuth, Elevation, Azimuth, Range]` is two sources, the second of
+    /// which has no elevation. This is how [grape_file_to_metadata()] reads
+    /// them.
+    ///
+    /// Streams that don't describe a source, like [GrapeTag::X], are left
+    /// out, though they still take part in deciding where sources start.
+    pub fn logical_sources(&self) -> Vec<SourceStreams> {
+        let mut sources = vec![SourceStreams::default(); self.num_tag_groups()];
+        for (i, (tag, tag_idx)) in self.header.tags.iter().zip(self.tag_groups()).enumerate() {
+            let source = &mut sources[tag_idx - 1];
+            match tag {
+                GrapeTag::Azimuth => source.azimuth = Some(i),
+                GrapeTag::Elevation => source.elevation = Some(i),
+                GrapeTag::Range => source.range = Some(i),
+                GrapeTag::Gain => source.gain = Some(i),
+                _ => {}
+            }
+        }
+        sources
+    }
+
+    /// Which tag each stream belongs to, counting from 1. A new tag starts
+    /// whenever a stream repeats a tag that the current tag already has.
+    fn tag_groups(&self) -> Vec<usize> {
+        let mut tag_idxs = Vec::with_capacity(self.header.tags.len());
+        let mut seen = Vec::new();
+        for tag in &self.header.tags {
+            if seen.contains(tag) {
+                seen.clear();
+            }
+            seen.push(*tag);
+            let tag_idx = tag_idxs.last().copied().unwrap_or(0) + usize::from(seen.len() == 1);
+            tag_idxs.push(tag_idx);
         }
+        tag_idxs
     }
 
     /// Take a slice of [GrapeTag]s and sample vectors and zip them.
@@ -205,8 +758,60 @@ impl GrapeFile {
         tags.iter().cloned().zip(samples).collect()
     }
 
-    /// Returns a cloned, de-interleaved version of the samples in the file.
+    /// This file, or if its streams have their own sample rates, a copy with
+    /// every stream resampled to the file's sample rate and stored
+    /// interleaved, with the shorter streams held at their last sample. The
+    /// copy is only made once, and kept for next time.
+    fn uniform(&self) -> &GrapeFile {
+        if self.header.stream_rates.is_empty() {
+            return self;
+        }
+
+        self.uniform.0.get_or_init(|| {
+            let builder = GrapeFile::builder().set_samplerate(self.header.sample_rate);
+            let builder = if self.header.checksum {
+                builder.with_checksum()
+            } else {
+                builder
+            };
+            let file = self
+                .streams_with_sample_rate(self.header.sample_rate)
+                .into_iter()
+                .fold(builder, |builder, (tag, stream)| {
+                    builder.add_stream(&stream, tag)
+                })
+                .build_extend();
+            Box::new(file)
+        })
+    }
+
+    /// A file with the given header and samples, that hasn't been resampled
+    /// yet.
+    fn new(header: GrapeFileHeader, samples: Vec<f32>) -> Self {
+        GrapeFile {
+            header,
+            samples,
+            uniform: Cached::default(),
+        }
+    }
+
+    /// Returns a cloned, de-interleaved version of the samples in the file,
+    /// each at its own sample rate.
     fn get_raw_streams(&self) -> Vec<Vec<f32>> {
+        if !self.header.stream_lens.is_empty() {
+            let mut rest = self.samples.as_slice();
+            return self
+                .header
+                .stream_lens
+                .iter()
+                .map(|&len| {
+                    let (stream, tail) = rest.split_at(len as usize);
+                    rest = tail;
+                    stream.to_vec()
+                })
+                .collect();
+        }
+
         let n_streams = self.header.n_streams as usize;
         (0..n_streams)
             .map(|i| {
@@ -219,50 +824,75 @@ impl GrapeFile {
             })
             .collect()
     }
+}
 
-    /// Extracts the streams from the file, and interpolates data points to
-    /// produce data points at the requrested sample_rate.
-    ///
-    /// Right now, this function only really works if the requested sample rate
-    /// is a multiple of the native sample rate. This needs some work.
-    fn streams_interpolated(&self, sample_rate: u64) -> Vec<(GrapeTag, Vec<f32>)> {
-        debug_assert!(sample_rate > self.header.sample_rate);
-        let samples_per_pt = sample_rate as usize / self.header.sample_rate as usize;
-        let raw_streams = self.get_raw_streams();
-        let interpolated_streams = raw_streams
-            .into_iter()
-            .map(|v| {
-                v.windows(2)
-                    .flat_map(|w| {
-                        let step = (w[1] - w[0]) / samples_per_pt as f32;
-                        (0..samples_per_pt)
-                            .map(|i| w[0] + i as f32 * step)
-                            .collect::<Vec<_>>()
-                    })
-                    .collect()
-            })
-            .collect();
-        Self::attach_tags(&self.header.tags, interpolated_streams)
+/// Whether a stream recorded at `stream_rate` can be stored in a file with
+/// the given `sample_rate`. Neither can be 0, and one has to be a whole
+/// multiple of the other, since [interpolate()] and [quantize()] can't
+/// resample by anything else without warping time.
+fn rates_fit(stream_rate: u64, sample_rate: u64) -> bool {
+    let (low, high) = if stream_rate < sample_rate {
+        (stream_rate, sample_rate)
+    } else {
+        (sample_rate, stream_rate)
+    };
+    low > 0 && high.is_multiple_of(low)
+}
+
+/// Interpolates or quantizes a `stream` recorded at `native` samples per
+/// second to produce data points at `sample_rate`.
+fn resample(stream: Vec<f32>, native: u64, sample_rate: u64) -> Vec<f32> {
+    match sample_rate.cmp(&native) {
+        Ordering::Equal => stream,
+        Ordering::Less => quantize(&stream, native, sample_rate),
+        Ordering::Greater => interpolate(&stream, native, sample_rate),
     }
+}
 
-    /// Extracts the streams from the file, and quantizes data points to
-    /// produce data points at the requested sample_rate.
-    ///
-    /// Right now, this function only really works if the requested sample rate
-    /// is a factor of the native sample rate. This needs some work.
-    fn streams_quantized(&self, sample_rate: u64) -> Vec<(GrapeTag, Vec<f32>)> {
-        debug_assert!(sample_rate < self.header.sample_rate);
-        let pts_per_sample = self.header.sample_rate as usize / sample_rate as usize;
-        let raw_streams = self.get_raw_streams();
-        let quantized_streams = raw_streams
-            .into_iter()
-            .map(|v| {
-                v.chunks(pts_per_sample)
-                    .map(|c| c.iter().sum::<f32>() / c.len() as f32)
-                    .collect()
-            })
-            .collect();
-        Self::attach_tags(&self.header.tags, quantized_streams)
+/// Interpolates data points in a stream recorded at `native` samples per
+/// second to produce data points at the requrested sample_rate.
+///
+/// The requested sample rate has to be a whole multiple of the native sample
+/// rate, which [rates_fit()] checks for the streams in a file.
+fn interpolate(stream: &[f32], native: u64, sample_rate: u64) -> Vec<f32> {
+    debug_assert!(sample_rate > native);
+    let samples_per_pt = sample_rate as usize / native as usize;
+    stream
+        .windows(2)
+        .flat_map(|w| {
+            let step = (w[1] - w[0]) / samples_per_pt as f32;
+            (0..samples_per_pt)
+                .map(|i| w[0] + i as f32 * step)
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Quantizes data points in a stream recorded at `native` samples per
+/// second to produce data points at the requested sample_rate.
+///
+/// The requested sample rate has to be a factor of the native sample rate,
+/// which [rates_fit()] checks for the streams in a file.
+fn quantize(stream: &[f32], native: u64, sample_rate: u64) -> Vec<f32> {
+    debug_assert!(sample_rate < native);
+    let pts_per_sample = native as usize / sample_rate as usize;
+    stream
+        .chunks(pts_per_sample)
+        .map(|c| c.iter().sum::<f32>() / c.len() as f32)
+        .collect()
+}
+
+impl fmt::Display for GrapeFile {
+    /// Summarises the header and the length of the file, leaving out the
+    /// samples themselves, like `GrapeFile { 1000 Hz, 2 streams [X, Y], 4 samples each }`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let n_streams = self.header.n_streams as usize;
+        let per_stream = self.num_samples_per_stream();
+        write!(
+            f,
+            "GrapeFile {{ {} Hz, {} streams {:?}, {} samples each }}",
+            self.header.sample_rate, n_streams, self.header.tags, per_stream
+        )
     }
 }
 
@@ -271,6 +901,10 @@ impl GrapeFile {
 pub struct GrapeFileBuilder {
     sample_rate: u64,
     streams: Vec<(GrapeTag, Vec<f32>)>,
+    /// The sample rate of each stream, if it was given its own
+    stream_rates: Vec<Option<u64>>,
+    reject_non_finite: bool,
+    checksum: bool,
 }
 
 impl Default for GrapeFileBuilder {
@@ -286,6 +920,9 @@ impl GrapeFileBuilder {
         GrapeFileBuilder {
             sample_rate: 1000,
             streams: Vec::new(),
+            stream_rates: Vec::new(),
+            reject_non_finite: false,
+            checksum: false,
         }
     }
 
@@ -301,18 +938,85 @@ impl GrapeFileBuilder {
     pub fn add_stream(mut self, stream: &[f32], tag: GrapeTag) -> Self {
         let stream: Vec<f32> = stream.to_vec();
         self.streams.push((tag, stream));
+        self.stream_rates.push(None);
+        self
+    }
+
+    /// Adds a tagged stream with its own sample rate to the builder, such as
+    /// a slowly changing range alongside a quickly changing azimuth. If its
+    /// rate differs from the builder's, the [GrapeFile] stores every stream
+    /// as it is given, without truncating or extending any of them.
+    ///
+    /// Returns [GrapeFileError::InvalidStreamRates] if the rate is 0, or if
+    /// neither it nor the builder's sample rate is a whole multiple of the
+    /// other, since the stream couldn't be resampled to the file's rate.
+    pub fn add_stream_with_rate(
+        mut self,
+        stream: &[f32],
+        tag: GrapeTag,
+        sample_rate: u64,
+    ) -> Result<Self, GrapeFileError> {
+        if !rates_fit(sample_rate, self.sample_rate) {
+            return Err(GrapeFileError::InvalidStreamRates);
+        }
+
+        self = self.add_stream(stream, tag);
+        *self
+            .stream_rates
+            .last_mut()
+            .expect("a stream was just added") = Some(sample_rate);
+        Ok(self)
+    }
+
+    /// Adds many streams to the builder at once, tagging each stream with the
+    /// tag at the same index in `tags`. Returns
+    /// [GrapeFileError::TagCountMismatch] if `tags` and `streams` are
+    /// different lengths.
+    pub fn add_tagged_streams(
+        mut self,
+        tags: &[GrapeTag],
+        streams: Vec<Vec<f32>>,
+    ) -> Result<Self, GrapeFileError> {
+        if tags.len() != streams.len() {
+            return Err(GrapeFileError::TagCountMismatch);
+        }
+
+        self.streams.extend(tags.iter().cloned().zip(streams));
+        self.stream_rates.resize(self.streams.len(), None);
+        Ok(self)
+    }
+
+    /// Makes [GrapeFileBuilder::build()] check every sample, and fail with
+    /// [GrapeFileError::NonFiniteSample] if any of them are NaN or infinite.
+    /// Otherwise they are stored as is, and will poison any interpolation or
+    /// quantization done on the stream later.
+    pub fn reject_non_finite(mut self) -> Self {
+        self.reject_non_finite = true;
+        self
+    }
+
+    /// Makes the [GrapeFile] follow its samples with a checksum when it is
+    /// written, which is checked when it is read back, returning
+    /// [GrapeFileError::ChecksumMismatch] if the samples were damaged.
+    pub fn with_checksum(mut self) -> Self {
+        self.checksum = true;
         self
     }
 
     /// Removes all streams from the builder
     pub fn clear_streams(mut self) -> Self {
         self.streams.clear();
+        self.stream_rates.clear();
         self
     }
 
     /// Builds a [GrapeFile] from the builder, truncating all streams to the
     /// length of the shortest stream.
     pub fn build_truncate(self) -> GrapeFile {
+        if self.has_own_rates() {
+            return self.build_own_rates();
+        }
+
         let tags: Vec<GrapeTag> = self
             .streams
             .iter()
@@ -321,31 +1025,30 @@ impl GrapeFileBuilder {
             .collect();
         let sample_vecs: Vec<Vec<f32>> = self.streams.into_iter().map(|(_tag, vec)| vec).collect();
 
-        let mut samples = Vec::new();
-
-        let shortest = sample_vecs.iter().map(|v| v.len()).min();
-        if let Some(shortest) = shortest {
-            samples.reserve_exact(shortest * sample_vecs.len());
-            for sample_idx in 0..shortest {
-                for stream in &sample_vecs {
-                    samples.push(stream[sample_idx]);
-                }
-            }
-        };
+        let shortest = sample_vecs.iter().map(|v| v.len()).min().unwrap_or(0);
+        let samples = interleave(&sample_vecs, shortest);
 
-        GrapeFile {
-            header: GrapeFileHeader {
+        GrapeFile::new(
+            GrapeFileHeader {
+                version: FORMAT_VERSION,
                 n_streams: sample_vecs.len() as u64,
                 sample_rate: self.sample_rate,
                 tags,
+                checksum: self.checksum,
+                stream_rates: Vec::new(),
+                stream_lens: Vec::new(),
             },
             samples,
-        }
+        )
     }
 
     /// Builds a [GrapeFile] from the builder, extending all streams with the
     /// last value recorded in each stream.
     pub fn build_extend(self) -> GrapeFile {
+        if self.has_own_rates() {
+            return self.build_own_rates();
+        }
+
         let tags: Vec<GrapeTag> = self
             .streams
             .iter()
@@ -354,44 +1057,90 @@ impl GrapeFileBuilder {
             .collect();
         let sample_vecs: Vec<Vec<f32>> = self.streams.into_iter().map(|(_tag, vec)| vec).collect();
 
-        let mut samples = Vec::new();
-
-        let longest = sample_vecs.iter().map(|v| v.len()).max();
-        if let Some(longest) = longest {
-            let lasts: Vec<f32> = sample_vecs
-                .iter()
-                .map(|v| v.last().unwrap_or(&0.0))
-                .cloned()
-                .collect();
-            samples.reserve_exact(longest * sample_vecs.len());
-            for sample_idx in 0..longest {
-                for stream_idx in 0..sample_vecs.len() {
-                    samples.push(
-                        *sample_vecs[stream_idx]
-                            .get(sample_idx)
-                            .unwrap_or(&lasts[stream_idx]),
-                    );
-                }
-            }
-        };
+        let longest = sample_vecs.iter().map(|v| v.len()).max().unwrap_or(0);
+        let samples = interleave(&sample_vecs, longest);
 
-        GrapeFile {
-            header: GrapeFileHeader {
+        GrapeFile::new(
+            GrapeFileHeader {
+                version: FORMAT_VERSION,
                 n_streams: sample_vecs.len() as u64,
                 sample_rate: self.sample_rate,
                 tags,
+                checksum: self.checksum,
+                stream_rates: Vec::new(),
+                stream_lens: Vec::new(),
             },
             samples,
-        }
+        )
+    }
+
+    /// Whether any of the streams has a sample rate other than the builder's.
+    fn has_own_rates(&self) -> bool {
+        self.stream_rates
+            .iter()
+            .any(|rate| rate.is_some_and(|rate| rate != self.sample_rate))
+    }
+
+    /// Builds a [GrapeFile] whose streams have their own sample rates,
+    /// storing each stream whole, one after another.
+    fn build_own_rates(self) -> GrapeFile {
+        let stream_rates = self
+            .stream_rates
+            .iter()
+            .map(|rate| rate.unwrap_or(self.sample_rate))
+            .collect();
+        let stream_lens = self.streams.iter().map(|(_, v)| v.len() as u64).collect();
+        let tags = self.streams.iter().map(|(tag, _)| *tag).collect();
+        let samples = self.streams.into_iter().flat_map(|(_, v)| v).collect();
+
+        GrapeFile::new(
+            GrapeFileHeader {
+                version: FORMAT_VERSION,
+                n_streams: self.stream_rates.len() as u64,
+                sample_rate: self.sample_rate,
+                tags,
+                checksum: self.checksum,
+                stream_rates,
+                stream_lens,
+            },
+            samples,
+        )
     }
 
     /// Builds a [GrapeFile] from a builder, returning the [GrapeFile] if
-    /// all streams are of the same length, and
-    /// [GrapeFileError::UnequalSampleBufferLengths] otherwise.
+    /// all streams are of the same length (or have their own sample
+    /// rates), and
+    /// [GrapeFileError::UnequalSampleBufferLengths] otherwise. If
+    /// [GrapeFileBuilder::reject_non_finite()] was set, also returns
+    /// [GrapeFileError::NonFiniteSample] for streams with NaN or infinite
+    /// samples. Returns [GrapeFileError::InvalidStreamRates] if the sample
+    /// rate was changed after adding a stream with its own rate, and the two
+    /// no longer fit together.
     pub fn build(self) -> Result<GrapeFile, GrapeFileError> {
+        if self.has_own_rates()
+            && !self
+                .stream_rates
+                .iter()
+                .flatten()
+                .all(|&rate| rates_fit(rate, self.sample_rate))
+        {
+            return Err(GrapeFileError::InvalidStreamRates);
+        }
+
+        if self.reject_non_finite {
+            let bad_stream = self
+                .streams
+                .iter()
+                .find(|(_tag, v)| v.iter().any(|s| !s.is_finite()));
+            if let Some((tag, _)) = bad_stream {
+                return Err(GrapeFileError::NonFiniteSample(*tag));
+            }
+        }
+
         let lens: Vec<usize> = self.streams.iter().map(|(_tag, v)| v.len()).collect();
 
-        if lens.windows(2).all(|w| w[0] == w[1]) {
+        // Streams at different rates have different lengths
+        if self.has_own_rates() || lens.windows(2).all(|w| w[0] == w[1]) {
             Ok(self.build_truncate())
         } else {
             Err(GrapeFileError::UnequalSampleBufferLengths)
@@ -399,18 +1148,340 @@ impl GrapeFileBuilder {
     }
 }
 
-#[cfg(test)]
-const A_FLOAT: f32 = 12.078_431;
-mod tests {
-    use super::*;
-    use rand::distributions::{Distribution, Uniform};
-    use std::io::Cursor;
+/// Turns the streams of a [GrapeFile] back into the positions of each tag,
+/// one time slice for each sample in the file, ready to be binauralized.
+///
+/// The streams are read in order, and each tag's streams follow one
+/// another, like the azimuth and elevation streams written by a serial run.
+/// A tag's streams end where a stream repeats a tag that the tag already
+/// has, so `[Azimuth, Elevation, Azimuth, Range]` is two tags, the second of
+/// which has no elevation. Elevations that aren't given are 0, and ranges
+/// and gains are 1. A [GrapeTag::Gain] stream automates its tag's gain over
+/// time. Streams that don't describe a [BufferMetadata], like [GrapeTag::X],
+/// are skipped.
+pub fn grape_file_to_metadata(file: &GrapeFile) -> Vec<Vec<BufferMetadata>> {
+    let tag_idxs = file.tag_groups();
+    let num_tags = file.num_tag_groups();
+
+    let unplaced = BufferMetadata {
+        azimuth: 0.0,
+        elevation: 0.0,
+        range: 1.0,
+        gain: 1.0,
+    };
+
+    file.rows()
+        .map(|row| {
+            let mut metadata = vec![unplaced; num_tags];
+            for ((tag, value), tag_idx) in row.into_iter().zip(&tag_idxs) {
+                let metadata = &mut metadata[tag_idx - 1];
+                match tag {
+                    GrapeTag::Azimuth => metadata.azimuth = value,
+                    GrapeTag::Elevation => metadata.elevation = value,
+                    GrapeTag::Range => metadata.range = value,
+                    GrapeTag::Gain => metadata.gain = value,
+                    _ => {}
+                }
+            }
+            metadata
+        })
+        .collect()
+}
 
-    #[test]
-    fn write_and_read_path() {
-        let tempfile = tempfile::NamedTempFile::new().unwrap();
-        let path = tempfile.path();
-        let data = GrapeFile::builder()
+/// Records the azimuth and elevation of `num_tags` tags, one time slice of
+/// `spatial_data` every `1 / sample_rate` seconds, as a [GrapeFile] with a
+/// [GrapeTag::Azimuth] and a [GrapeTag::Elevation] stream for each tag. This
+/// is how serial runs are recorded, and [grape_file_to_metadata] reads them
+/// back.
+pub fn metadata_to_grape_file(
+    spatial_data: &[Vec<BufferMetadata>],
+    num_tags: usize,
+    sample_rate: u64,
+) -> GrapeFile {
+    (0..num_tags)
+        .fold(
+            GrapeFile::builder().set_samplerate(sample_rate),
+            |builder, tag| {
+                let (azms, elvs): (Vec<f32>, Vec<f32>) = spatial_data
+                    .iter()
+                    .map(|slice| (slice[tag].azimuth, slice[tag].elevation))
+                    .unzip();
+                builder
+                    .add_stream(&azms, GrapeTag::Azimuth)
+                    .add_stream(&elvs, GrapeTag::Elevation)
+            },
+        )
+        .build_truncate()
+}
+
+/// Records a track of localized [Point]s, one frame of points every
+/// `1 / sample_rate` seconds, as a [GrapeFile] with an [GrapeTag::X] and a
+/// [GrapeTag::Y] stream for each tag, in order of the tags' ids.
+///
+/// Each point comes with the id of the tag it was localized for, which is
+/// how the points are matched up between frames. A frame that is missing
+/// some of the tags holds them where they last were, or at the origin if
+/// they haven't been seen yet.
+pub fn points_to_grape_file(frames: &[Vec<(Id, Point)>], sample_rate: u64) -> GrapeFile {
+    let mut ids = frames
+        .iter()
+        .flatten()
+        .map(|&(id, _)| id)
+        .collect::<Vec<_>>();
+    ids.sort_unstable();
+    ids.dedup();
+
+    let mut last = vec![Point::new(0.0, 0.0); ids.len()];
+    let mut xs = vec![Vec::with_capacity(frames.len()); ids.len()];
+    let mut ys = vec![Vec::with_capacity(frames.len()); ids.len()];
+    for frame in frames {
+        for &(id, point) in frame {
+            let i = ids.binary_search(&id).expect("every id was collected");
+            last[i] = point;
+        }
+        for (i, last) in last.iter().enumerate() {
+            xs[i].push(last.x as f32);
+            ys[i].push(last.y as f32);
+        }
+    }
+
+    xs.iter()
+        .zip(&ys)
+        .fold(
+            GrapeFile::builder().set_samplerate(sample_rate),
+            |builder, (x, y)| {
+                builder
+                    .add_stream(x, GrapeTag::X)
+                    .add_stream(y, GrapeTag::Y)
+            },
+        )
+        .build_truncate()
+}
+
+/// Interleaves the first `len` samples of every stream, one time step after
+/// another. Streams shorter than `len` are extended with their last sample,
+/// or with 0 if they are empty.
+fn interleave(streams: &[Vec<f32>], len: usize) -> Vec<f32> {
+    let mut samples = vec![0.0; len * streams.len()];
+    if streams.is_empty() {
+        return samples;
+    }
+
+    let lasts: Vec<f32> = streams
+        .iter()
+        .map(|v| v.last().copied().unwrap_or(0.0))
+        .collect();
+
+    // Filling in whole rows at once writes the output in order, which is
+    // much kinder to the cache than filling in one stream at a time
+    for (sample_idx, row) in samples.chunks_exact_mut(streams.len()).enumerate() {
+        for ((sample, stream), last) in row.iter_mut().zip(streams).zip(&lasts) {
+            *sample = *stream.get(sample_idx).unwrap_or(last);
+        }
+    }
+
+    samples
+}
+
+/// The CRC32 (as used by zip and PNG) of `bytes`.
+fn crc32(bytes: &[u8]) -> u32 {
+    !crc32_update(CRC32_INIT, bytes)
+}
+
+/// Where a running CRC32 starts, before any bytes have been added to it.
+const CRC32_INIT: u32 = !0;
+
+/// Adds `bytes` to a running CRC32, which starts at [CRC32_INIT]. Once every
+/// byte has been added, the CRC32 is the bitwise NOT of the running value.
+fn crc32_update(crc: u32, bytes: &[u8]) -> u32 {
+    bytes.iter().fold(crc, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| {
+            let mask = (crc & 1).wrapping_neg();
+            (crc >> 1) ^ (0xEDB8_8320 & mask)
+        })
+    })
+}
+
+#[cfg(test)]
+const A_FLOAT: f32 = 12.078_431;
+mod tests {
+    use super::*;
+    use crate::TransposableIter;
+    use rand::distributions::{Distribution, Uniform};
+    use std::io::Cursor;
+
+    /// The way that the builders used to interleave streams, one sample at a
+    /// time, to check [interleave()] against.
+    fn interleave_by_index(sample_vecs: &[Vec<f32>], len: usize) -> Vec<f32> {
+        let mut samples = Vec::new();
+        for sample_idx in 0..len {
+            for stream in sample_vecs {
+                samples.push(*stream.get(sample_idx).or(stream.last()).unwrap_or(&0.0));
+            }
+        }
+        samples
+    }
+
+    #[test]
+    fn interleave_ragged_streams() {
+        let streams = vec![
+            vec![1.0, 2.0, 3.0, 4.0, 5.0],
+            vec![6.0, 7.0],
+            vec![],
+            vec![8.0, 9.0, 10.0],
+        ];
+
+        let mut builder = GrapeFile::builder();
+        for stream in &streams {
+            builder = builder.add_stream(stream, GrapeTag::X);
+        }
+
+        assert_eq!(
+            interleave_by_index(&streams, 0),
+            builder.clone().build_truncate().samples
+        );
+        assert_eq!(
+            interleave_by_index(&streams, 5),
+            builder.clone().build_extend().samples
+        );
+        for len in 0..7 {
+            assert_eq!(
+                interleave_by_index(&streams, len),
+                interleave(&streams, len)
+            );
+        }
+
+        let streams = &streams[..2];
+        assert_eq!(
+            vec![1.0, 6.0, 2.0, 7.0],
+            GrapeFile::builder()
+                .add_tagged_streams(&[GrapeTag::X, GrapeTag::Y], streams.to_vec())
+                .unwrap()
+                .build_truncate()
+                .samples
+        );
+        assert!(interleave(&[], 10).is_empty());
+    }
+
+    #[test]
+    fn metadata_from_file() {
+        let positions = (0..3)
+            .map(|i| {
+                vec![
+                    BufferMetadata {
+                        azimuth: 0.5 * i as f32,
+                        elevation: 0.1,
+                        range: 2.0,
+                        gain: 1.0,
+                    },
+                    BufferMetadata {
+                        azimuth: 6.0 - i as f32,
+                        elevation: -0.2 * i as f32,
+                        range: 3.0 + i as f32,
+                        gain: 1.0 - 0.25 * i as f32,
+                    },
+                ]
+            })
+            .collect::<Vec<_>>();
+
+        let builder = positions.iter().cloned().transpose().fold(
+            GrapeFile::builder().set_samplerate(40),
+            |b, v| {
+                let azms: Vec<f32> = v.iter().map(|e| e.azimuth).collect();
+                let elvs: Vec<f32> = v.iter().map(|e| e.elevation).collect();
+                let rngs: Vec<f32> = v.iter().map(|e| e.range).collect();
+                let gains: Vec<f32> = v.iter().map(|e| e.gain).collect();
+                b.add_stream(&azms, GrapeTag::Azimuth)
+                    .add_stream(&elvs, GrapeTag::Elevation)
+                    .add_stream(&rngs, GrapeTag::Range)
+                    .add_stream(&gains, GrapeTag::Gain)
+            },
+        );
+        let data = builder.build().unwrap();
+
+        assert_eq!(positions, grape_file_to_metadata(&data));
+    }
+
+    #[test]
+    fn gain_and_timestamp_tags_round_trip() {
+        let data = GrapeFile::builder()
+            .set_samplerate(10)
+            .add_stream(&[1.0, 0.5, 0.25], GrapeTag::Gain)
+            .add_stream(&[0.0, 0.1, 0.21], GrapeTag::Timestamp)
+            .with_checksum()
+            .build()
+            .unwrap();
+
+        let bytes = data.to_bytes().unwrap();
+        let header =
+            String::from_utf8_lossy(&bytes[..bytes.iter().position(|&b| b == 0xFF).unwrap()]);
+        assert!(header.contains("version:4"), "{}", header);
+        assert!(header.contains("tags:[Gain,Timestamp]"), "{}", header);
+
+        let read = GrapeFile::from_bytes(&bytes).unwrap();
+        assert_eq!(data, read);
+        assert_eq!(
+            vec![
+                (GrapeTag::Gain, vec![1.0, 0.5, 0.25]),
+                (GrapeTag::Timestamp, vec![0.0, 0.1, 0.21]),
+            ],
+            read.streams_native_sample_rate().1
+        );
+
+        let mut bytes = b"(version:4,n_streams:2,sample_rate:10,tags:[Timestamp,Gain])".to_vec();
+        bytes.push(0xFF);
+        for sample in [0.5_f32, 0.75] {
+            bytes.extend(sample.to_be_bytes());
+        }
+        let read = GrapeFile::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            vec![
+                (GrapeTag::Timestamp, vec![0.5]),
+                (GrapeTag::Gain, vec![0.75])
+            ],
+            read.streams_native_sample_rate().1
+        );
+    }
+
+    #[test]
+    fn metadata_fills_in_missing_streams() {
+        let data = GrapeFile::builder()
+            .add_stream(&[1.0, 2.0], GrapeTag::Azimuth)
+            .add_stream(&[0.5, 0.5], GrapeTag::X)
+            .add_stream(&[3.0, 4.0], GrapeTag::Azimuth)
+            .add_stream(&[0.3, 0.4], GrapeTag::Elevation)
+            .build()
+            .unwrap();
+
+        assert_eq!(2, data.num_tag_groups());
+        let metadata = grape_file_to_metadata(&data);
+        assert_eq!(2, metadata.len());
+        assert_eq!(
+            vec![
+                BufferMetadata {
+                    azimuth: 2.0,
+                    elevation: 0.0,
+                    range: 1.0,
+                    gain: 1.0,
+                },
+                BufferMetadata {
+                    azimuth: 4.0,
+                    elevation: 0.4,
+                    range: 1.0,
+                    gain: 1.0,
+                },
+            ],
+            metadata[1]
+        );
+
+        assert!(grape_file_to_metadata(&GrapeFile::builder().build().unwrap()).is_empty());
+    }
+
+    #[test]
+    fn write_and_read_path() {
+        let tempfile = tempfile::NamedTempFile::new().unwrap();
+        let path = tempfile.path();
+        let data = GrapeFile::builder()
             .set_samplerate(1000)
             .add_stream(&[A_FLOAT; 4], GrapeTag::X)
             .add_stream(&[A_FLOAT; 4], GrapeTag::Y)
@@ -455,6 +1526,474 @@ mod tests {
         assert_eq!(data, read_data);
     }
 
+    #[test]
+    fn bytes_round_trip() {
+        let data = GrapeFile::builder()
+            .set_samplerate(1000)
+            .add_stream(&[A_FLOAT; 4], GrapeTag::X)
+            .add_stream(&[-A_FLOAT; 4], GrapeTag::Y)
+            .build()
+            .unwrap();
+
+        let bytes = data.to_bytes().unwrap();
+        assert_eq!(data, GrapeFile::from_bytes(&bytes).unwrap());
+
+        let mut buf = Vec::new();
+        data.to_file(&mut buf).unwrap();
+        assert_eq!(bytes, buf);
+    }
+
+    /// The way that files used to be written, all in one go, to check
+    /// [GrapeFile::to_file()] against.
+    fn to_bytes_at_once(data: &GrapeFile) -> Vec<u8> {
+        let h_str = ron::ser::to_string(&data.header).unwrap();
+        let mut buf = h_str.as_bytes().to_vec();
+        buf.push(0xFF);
+        buf.extend(data.samples.iter().flat_map(|f| f.to_be_bytes()));
+        if data.header.checksum {
+            let crc = crc32(&buf[h_str.len() + 1..]);
+            buf.extend_from_slice(&crc.to_be_bytes());
+        }
+        buf
+    }
+
+    #[test]
+    fn chunked_writes_match() {
+        let between = Uniform::from(-100.0..100.0);
+        let mut rng = rand::thread_rng();
+        // Less than a chunk, exactly one, and a few and a bit
+        for len in [0, 3, WRITE_CHUNK_SAMPLES / 2, WRITE_CHUNK_SAMPLES * 3 + 5] {
+            let x = between
+                .sample_iter(&mut rng)
+                .take(len)
+                .collect::<Vec<f32>>();
+            let y = between
+                .sample_iter(&mut rng)
+                .take(len)
+                .collect::<Vec<f32>>();
+            let builder = GrapeFile::builder()
+                .add_stream(&x, GrapeTag::X)
+                .add_stream(&y, GrapeTag::Y);
+
+            for data in [builder.clone().build(), builder.with_checksum().build()] {
+                let data = data.unwrap();
+                let mut buf = Vec::new();
+                data.to_file(&mut buf).unwrap();
+                assert_eq!(to_bytes_at_once(&data), buf);
+            }
+        }
+    }
+
+    #[test]
+    fn duration_of_known_files() {
+        let data = GrapeFile::builder()
+            .set_samplerate(20)
+            .add_stream(&[A_FLOAT; 50], GrapeTag::Azimuth)
+            .add_stream(&[A_FLOAT; 50], GrapeTag::Elevation)
+            .build()
+            .unwrap();
+        assert_eq!(50, data.num_samples_per_stream());
+        assert_eq!(Duration::from_millis(2500), data.duration());
+
+        let data = GrapeFile::builder()
+            .set_samplerate(1000)
+            .add_stream(&[A_FLOAT; 3], GrapeTag::X)
+            .build()
+            .unwrap();
+        assert_eq!(3, data.num_samples_per_stream());
+        assert_eq!(Duration::from_millis(3), data.duration());
+    }
+
+    #[test]
+    fn duration_of_empty_files() {
+        let data = GrapeFile::builder().build().unwrap();
+        assert_eq!(0, data.num_samples_per_stream());
+        assert_eq!(Duration::ZERO, data.duration());
+
+        let data = GrapeFile::builder()
+            .add_stream(&[], GrapeTag::X)
+            .build()
+            .unwrap();
+        assert_eq!(Duration::ZERO, data.duration());
+
+        // A sample rate of 0 can only come from a file
+        let mut bytes = b"(n_streams:1,sample_rate:0,tags:[X])".to_vec();
+        bytes.push(0xFF);
+        bytes.extend(A_FLOAT.to_be_bytes());
+        let data = GrapeFile::from_bytes(&bytes).unwrap();
+        assert_eq!(1, data.num_samples_per_stream());
+        assert_eq!(Duration::ZERO, data.duration());
+    }
+
+    #[test]
+    fn bytes_without_delimiter() {
+        assert!(matches!(
+            GrapeFile::from_bytes(b"(n_streams:0,sample_rate:1000,tags:[])"),
+            Err(GrapeFileError::NoDelimiter)
+        ));
+    }
+
+    #[test]
+    fn checksum_catches_damage() {
+        let data = GrapeFile::builder()
+            .add_stream(&[A_FLOAT; 4], GrapeTag::X)
+            .add_stream(&[-A_FLOAT; 4], GrapeTag::Y)
+            .with_checksum()
+            .build()
+            .unwrap();
+
+        let bytes = data.to_bytes().unwrap();
+        assert_eq!(data, GrapeFile::from_bytes(&bytes).unwrap());
+
+        let mut damaged = bytes.clone();
+        let last_sample = damaged.len() - 5;
+        damaged[last_sample] ^= 0x01;
+        assert!(matches!(
+            GrapeFile::from_bytes(&damaged),
+            Err(GrapeFileError::ChecksumMismatch)
+        ));
+    }
+
+    #[test]
+    fn known_crc32() {
+        assert_eq!(0, crc32(b""));
+        assert_eq!(0xCBF4_3926, crc32(b"123456789"));
+    }
+
+    #[test]
+    fn read_unversioned() {
+        let mut bytes = b"(n_streams:1,sample_rate:10,tags:[X])".to_vec();
+        bytes.push(0xFF);
+        bytes.extend(A_FLOAT.to_be_bytes());
+
+        let data = GrapeFile::from_bytes(&bytes).unwrap();
+        assert_eq!(1, data.header.version);
+        assert!(!data.header.checksum);
+        assert_eq!(vec![A_FLOAT], data.samples);
+    }
+
+    #[test]
+    fn upgrade_unversioned() {
+        let mut bytes = b"(n_streams:2,sample_rate:10,tags:[X,Y])".to_vec();
+        bytes.push(0xFF);
+        bytes.extend([A_FLOAT, -A_FLOAT].iter().flat_map(|f| f.to_be_bytes()));
+        let old = GrapeFile::from_bytes(&bytes).unwrap();
+        assert_eq!(1, old.version());
+
+        let new = GrapeFile::from_bytes(&old.clone().upgrade().to_bytes().unwrap()).unwrap();
+        assert_eq!(FORMAT_VERSION, new.version());
+        assert_eq!(
+            old.streams_native_sample_rate(),
+            new.streams_native_sample_rate()
+        );
+    }
+
+    /// A second of an azimuth at 20 Hz, alongside a range at 5 Hz
+    fn mixed_rates() -> GrapeFile {
+        let azms = (0..20).map(|i| i as f32 * 0.1).collect::<Vec<_>>();
+        GrapeFile::builder()
+            .set_samplerate(20)
+            .add_stream(&azms, GrapeTag::Azimuth)
+            .add_stream_with_rate(&[1.0, 2.0, 3.0, 4.0, 5.0], GrapeTag::Range, 5)
+            .unwrap()
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn mixed_rates_resampled_to_a_common_rate() {
+        let data = mixed_rates();
+        assert_eq!(vec![20, 5], data.stream_sample_rates());
+        // Nothing is stored twice
+        assert_eq!(25, data.samples.len());
+
+        let streams = data.streams_with_sample_rate(10);
+        assert_eq!(GrapeTag::Azimuth, streams[0].0);
+        let expected = (0..10).map(|i| i as f32 * 0.2 + 0.05).collect::<Vec<_>>();
+        assert_eq!(10, streams[0].1.len());
+        for (azm, expected) in streams[0].1.iter().zip(expected) {
+            assert!((azm - expected).abs() < 1e-5);
+        }
+        assert_eq!(GrapeTag::Range, streams[1].0);
+        assert_eq!(vec![1.0, 1.5, 2.0, 2.5, 3.0, 3.5, 4.0, 4.5], streams[1].1);
+
+        // At the file's own rate, the azimuths are untouched
+        let streams = data.streams_with_sample_rate(20);
+        assert_eq!(data.get_raw_streams()[0], streams[0].1);
+        assert_eq!(16, streams[1].1.len());
+        assert_eq!(1.25, streams[1].1[1]);
+    }
+
+    #[test]
+    fn mixed_rates_round_trip() {
+        let data = mixed_rates();
+        let bytes = data.to_bytes().unwrap();
+        let header = std::str::from_utf8(&bytes[..bytes.iter().position(|&b| b == 0xFF).unwrap()]);
+        assert!(header.unwrap().contains("stream_rates:[20,5]"));
+        assert_eq!(data, GrapeFile::from_bytes(&bytes).unwrap());
+    }
+
+    #[test]
+    fn mixed_rates_row_by_row() {
+        let data = mixed_rates();
+        assert_eq!(20, data.num_samples_per_stream());
+        assert_eq!(Duration::from_secs(1), data.duration());
+
+        // The ranges are held at their last value once they run out
+        let metadata = grape_file_to_metadata(&data);
+        assert_eq!(20, metadata.len());
+        assert_eq!(2.0, metadata[4][0].range);
+        assert_eq!(4.75, metadata[19][0].range);
+        assert!((metadata[19][0].azimuth - 1.9).abs() < 1e-5);
+    }
+
+    #[test]
+    fn reject_bad_stream_rates() {
+        let read = |header: &[u8]| {
+            let mut bytes = header.to_vec();
+            bytes.push(0xFF);
+            bytes.extend(A_FLOAT.to_be_bytes());
+            GrapeFile::from_bytes(&bytes)
+        };
+
+        let ok =
+            b"(version:3,n_streams:1,sample_rate:10,tags:[X],stream_rates:[5],stream_lens:[1])";
+        assert!(read(ok).is_ok());
+        // Too old to have per-stream rates
+        let old =
+            b"(version:2,n_streams:1,sample_rate:10,tags:[X],stream_rates:[5],stream_lens:[1])";
+        assert!(matches!(read(old), Err(GrapeFileError::InvalidStreamRates)));
+        // More samples than the lengths add up to
+        let long =
+            b"(version:3,n_streams:1,sample_rate:10,tags:[X],stream_rates:[5],stream_lens:[0])";
+        assert!(matches!(
+            read(long),
+            Err(GrapeFileError::InvalidStreamRates)
+        ));
+        // Rates without lengths
+        let no_lens = b"(version:3,n_streams:1,sample_rate:10,tags:[X],stream_rates:[5])";
+        assert!(matches!(
+            read(no_lens),
+            Err(GrapeFileError::InvalidStreamRates)
+        ));
+        // A rate that can't be resampled to the file's
+        for rates in ["[0]", "[15]", "[4]"] {
+            let header = format!(
+                "(version:3,n_streams:1,sample_rate:10,tags:[X],stream_rates:{},stream_lens:[1])",
+                rates
+            );
+            assert!(matches!(
+                read(header.as_bytes()),
+                Err(GrapeFileError::InvalidStreamRates)
+            ));
+        }
+    }
+
+    #[test]
+    fn builder_rejects_bad_stream_rates() {
+        for rate in [0, 15] {
+            assert!(matches!(
+                GrapeFile::builder()
+                    .set_samplerate(20)
+                    .add_stream_with_rate(&[1.0], GrapeTag::Range, rate),
+                Err(GrapeFileError::InvalidStreamRates)
+            ));
+        }
+
+        let builder = GrapeFile::builder()
+            .set_samplerate(20)
+            .add_stream_with_rate(&[1.0], GrapeTag::Range, 5)
+            .unwrap();
+        assert!(builder.clone().set_samplerate(10).build().is_ok());
+        assert!(matches!(
+            builder.set_samplerate(12).build(),
+            Err(GrapeFileError::InvalidStreamRates)
+        ));
+    }
+
+    #[test]
+    fn resampled_once() {
+        let data = mixed_rates();
+        assert!(std::ptr::eq(data.uniform(), data.uniform()));
+        assert_eq!(20, data.num_samples_per_stream());
+        // Comparing leaves the copy out
+        assert_eq!(mixed_rates(), data);
+    }
+
+    #[test]
+    fn reject_future_version() {
+        let mut bytes = b"(version:99,n_streams:0,sample_rate:10,tags:[])".to_vec();
+        bytes.push(0xFF);
+        assert!(matches!(
+            GrapeFile::from_bytes(&bytes),
+            Err(GrapeFileError::UnsupportedVersion(99))
+        ));
+    }
+
+    #[test]
+    fn read_truncated_samples() {
+        let mut buf = Cursor::new(Vec::new());
+        let data = GrapeFile::builder()
+            .add_stream(&[A_FLOAT; 4], GrapeTag::X)
+            .build()
+            .unwrap();
+        data.to_file(&mut buf).unwrap();
+
+        let mut bytes = buf.into_inner();
+        bytes.pop();
+        assert!(matches!(
+            GrapeFile::from_file(&mut Cursor::new(bytes)),
+            Err(GrapeFileError::TruncatedSamples)
+        ));
+    }
+
+    #[test]
+    fn validate_good_files() {
+        let data = GrapeFile::builder()
+            .add_stream(&[A_FLOAT; 4], GrapeTag::X)
+            .add_stream(&[-A_FLOAT; 4], GrapeTag::Y)
+            .with_checksum()
+            .build()
+            .unwrap();
+        assert!(data.validate().is_ok());
+        assert!(GrapeFile::validate_bytes(&data.to_bytes().unwrap()).is_ok());
+
+        let data = mixed_rates();
+        assert!(data.validate().is_ok());
+        assert!(GrapeFile::validate_bytes(&data.to_bytes().unwrap()).is_ok());
+        assert!(GrapeFile::builder().build().unwrap().validate().is_ok());
+    }
+
+    #[test]
+    fn validate_reports_every_problem() {
+        let mut bytes = b"(version:99,n_streams:2,sample_rate:10,tags:[X])".to_vec();
+        bytes.push(0xFF);
+        for sample in [f32::NAN, 1.0, 2.0] {
+            bytes.extend(sample.to_be_bytes());
+        }
+        // Along with part of a fourth
+        bytes.push(0x00);
+
+        let problems = GrapeFile::validate_bytes(&bytes).unwrap_err();
+        assert!(
+            matches!(
+                problems.as_slice(),
+                [
+                    GrapeFileError::TruncatedSamples,
+                    GrapeFileError::UnsupportedVersion(99),
+                    GrapeFileError::TagCountMismatch,
+                    GrapeFileError::UnequalSampleBufferLengths,
+                    GrapeFileError::NonFiniteSample(GrapeTag::X),
+                ]
+            ),
+            "{:?}",
+            problems
+        );
+    }
+
+    #[test]
+    fn validate_damaged_files() {
+        let data = GrapeFile::builder()
+            .add_stream(&[1.0, f32::INFINITY], GrapeTag::X)
+            .add_stream(&[f32::NAN, 2.0], GrapeTag::Y)
+            .with_checksum()
+            .build()
+            .unwrap();
+        let mut bytes = data.to_bytes().unwrap();
+        let last_sample = bytes.len() - 5;
+        bytes[last_sample] ^= 0x01;
+        assert!(matches!(
+            GrapeFile::validate_bytes(&bytes).unwrap_err().as_slice(),
+            [
+                GrapeFileError::ChecksumMismatch,
+                GrapeFileError::NonFiniteSample(GrapeTag::X),
+                GrapeFileError::NonFiniteSample(GrapeTag::Y),
+            ]
+        ));
+
+        let mut bytes =
+            b"(version:2,n_streams:1,sample_rate:10,tags:[X],stream_rates:[5],stream_lens:[2])"
+                .to_vec();
+        bytes.push(0xFF);
+        bytes.extend(A_FLOAT.to_be_bytes());
+        assert!(matches!(
+            GrapeFile::validate_bytes(&bytes).unwrap_err().as_slice(),
+            [GrapeFileError::InvalidStreamRates]
+        ));
+
+        // Rates that would divide by zero, or warp time, when resampled
+        for rates in ["[0]", "[15]"] {
+            let mut bytes = format!(
+                "(version:3,n_streams:1,sample_rate:10,tags:[X],stream_rates:{},stream_lens:[1])",
+                rates
+            )
+            .into_bytes();
+            bytes.push(0xFF);
+            bytes.extend(A_FLOAT.to_be_bytes());
+            assert!(matches!(
+                GrapeFile::validate_bytes(&bytes).unwrap_err().as_slice(),
+                [GrapeFileError::InvalidStreamRates]
+            ));
+        }
+
+        assert!(matches!(
+            GrapeFile::validate_bytes(b"(n_streams:0,sample_rate:10,tags:[])")
+                .unwrap_err()
+                .as_slice(),
+            [GrapeFileError::NoDelimiter]
+        ));
+        assert!(matches!(
+            GrapeFile::validate_bytes(b"(n_streams:0\xFF")
+                .unwrap_err()
+                .as_slice(),
+            [GrapeFileError::RonSpannedError(_)]
+        ));
+    }
+
+    #[test]
+    fn add_many_streams() {
+        let tags = [GrapeTag::Azimuth, GrapeTag::Elevation, GrapeTag::Range];
+        let streams = vec![vec![1.0, 2.0], vec![3.0, 4.0], vec![5.0, 6.0]];
+
+        let data = GrapeFile::builder()
+            .add_stream(&[0.0, 0.0], GrapeTag::X)
+            .add_tagged_streams(&tags, streams.clone())
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let (_, read) = data.streams_native_sample_rate();
+        let mut expected = vec![(GrapeTag::X, vec![0.0, 0.0])];
+        expected.extend(tags.into_iter().zip(streams));
+        assert_eq!(expected, read);
+
+        assert!(matches!(
+            GrapeFile::builder().add_tagged_streams(&tags[..2], vec![vec![1.0]; 3]),
+            Err(GrapeFileError::TagCountMismatch)
+        ));
+    }
+
+    #[test]
+    fn reject_nan() {
+        let builder = GrapeFile::builder()
+            .add_stream(&[1.0, 2.0], GrapeTag::X)
+            .add_stream(&[1.0, f32::NAN], GrapeTag::Y);
+
+        let data = builder.clone().build().unwrap();
+        assert!(data.samples[3].is_nan());
+
+        assert!(matches!(
+            builder.reject_non_finite().build(),
+            Err(GrapeFileError::NonFiniteSample(GrapeTag::Y))
+        ));
+
+        assert!(GrapeFile::builder()
+            .add_stream(&[1.0, f32::INFINITY], GrapeTag::X)
+            .reject_non_finite()
+            .build()
+            .is_err());
+    }
+
     #[test]
     fn native_sample_rate_read() {
         let stream_data = vec![
@@ -550,6 +2089,255 @@ mod tests {
         assert_eq!(expected, streams3);
     }
 
+    #[test]
+    fn merge_streams() {
+        let a = GrapeFile::builder()
+            .set_samplerate(100)
+            .add_stream(&[1.0, 2.0], GrapeTag::Azimuth)
+            .add_stream(&[3.0, 4.0], GrapeTag::Elevation)
+            .build()
+            .unwrap();
+        let b = GrapeFile::builder()
+            .set_samplerate(100)
+            .add_stream(&[5.0, 6.0], GrapeTag::Range)
+            .build()
+            .unwrap();
+
+        let merged = GrapeFile::merge(&[a, b]).unwrap();
+        let expected = GrapeFile::builder()
+            .set_samplerate(100)
+            .add_stream(&[1.0, 2.0], GrapeTag::Azimuth)
+            .add_stream(&[3.0, 4.0], GrapeTag::Elevation)
+            .add_stream(&[5.0, 6.0], GrapeTag::Range)
+            .build()
+            .unwrap();
+        assert_eq!(expected, merged);
+    }
+
+    #[test]
+    fn merge_mismatched_rate() {
+        let a = GrapeFile::builder()
+            .set_samplerate(100)
+            .add_stream(&[1.0, 2.0], GrapeTag::X)
+            .build()
+            .unwrap();
+        let b = GrapeFile::builder()
+            .set_samplerate(200)
+            .add_stream(&[1.0, 2.0], GrapeTag::Y)
+            .build()
+            .unwrap();
+
+        assert!(matches!(
+            GrapeFile::merge(&[a, b]),
+            Err(GrapeFileError::MismatchedSampleRates)
+        ));
+    }
+
+    #[test]
+    fn merge_mismatched_length() {
+        let a = GrapeFile::builder()
+            .set_samplerate(100)
+            .add_stream(&[1.0, 2.0], GrapeTag::X)
+            .build()
+            .unwrap();
+        let b = GrapeFile::builder()
+            .set_samplerate(100)
+            .add_stream(&[1.0, 2.0, 3.0], GrapeTag::Y)
+            .build()
+            .unwrap();
+
+        assert!(matches!(
+            GrapeFile::merge(&[a, b]),
+            Err(GrapeFileError::UnequalSampleBufferLengths)
+        ));
+    }
+
+    #[test]
+    fn group_streams_by_source() {
+        let tags = [
+            GrapeTag::Elevation,
+            GrapeTag::Azimuth,
+            GrapeTag::Gain,
+            GrapeTag::Azimuth,
+            GrapeTag::Range,
+            GrapeTag::Elevation,
+        ];
+        let data = GrapeFile::builder()
+            .add_tagged_streams(&tags, vec![vec![0.0; 2]; tags.len()])
+            .unwrap()
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            vec![
+                SourceStreams {
+                    azimuth: Some(1),
+                    elevation: Some(0),
+                    range: None,
+                    gain: Some(2),
+                },
+                SourceStreams {
+                    azimuth: Some(3),
+                    elevation: Some(5),
+                    range: Some(4),
+                    gain: None,
+                },
+            ],
+            data.logical_sources()
+        );
+        assert!(GrapeFile::builder()
+            .build()
+            .unwrap()
+            .logical_sources()
+            .is_empty());
+    }
+
+    #[test]
+    fn split_into_groups() {
+        let data = GrapeFile::builder()
+            .set_samplerate(100)
+            .add_stream(&[1.0, 2.0], GrapeTag::Azimuth)
+            .add_stream(&[3.0, 4.0], GrapeTag::Elevation)
+            .add_stream(&[5.0, 6.0], GrapeTag::Azimuth)
+            .add_stream(&[7.0, 8.0], GrapeTag::Elevation)
+            .build()
+            .unwrap();
+
+        let groups = data.split_by_tag_groups(2);
+        assert_eq!(2, groups.len());
+        for (i, group) in groups.iter().enumerate() {
+            let (sr, streams) = group.streams_native_sample_rate();
+            assert_eq!(100, sr);
+            let first = 1.0 + 4.0 * i as f32;
+            assert_eq!(
+                vec![
+                    (GrapeTag::Azimuth, vec![first, first + 1.0]),
+                    (GrapeTag::Elevation, vec![first + 2.0, first + 3.0]),
+                ],
+                streams
+            );
+        }
+        assert_eq!(data, GrapeFile::merge(&groups).unwrap());
+
+        let uneven = data.split_by_tag_groups(3);
+        assert_eq!(
+            vec![3, 1],
+            uneven
+                .iter()
+                .map(|f| f.header.n_streams)
+                .collect::<Vec<_>>()
+        );
+        assert!(GrapeFile::builder()
+            .build()
+            .unwrap()
+            .split_by_tag_groups(2)
+            .is_empty());
+
+        let checked = GrapeFile::builder()
+            .add_stream(&[1.0, 2.0], GrapeTag::Azimuth)
+            .add_stream(&[3.0, 4.0], GrapeTag::Elevation)
+            .with_checksum()
+            .build()
+            .unwrap();
+        let groups = checked.split_by_tag_groups(1);
+        assert!(groups.iter().all(|group| group.header.checksum));
+        assert_eq!(checked, GrapeFile::merge(&groups).unwrap());
+
+        // One checked file is enough for the merged file to be checked
+        let unchecked = GrapeFile::builder()
+            .add_stream(&[5.0, 6.0], GrapeTag::Range)
+            .build()
+            .unwrap();
+        let mixed = [unchecked, groups[0].clone()];
+        assert!(GrapeFile::merge(&mixed).unwrap().header.checksum);
+    }
+
+    #[test]
+    fn append_in_time() {
+        let a = GrapeFile::builder()
+            .set_samplerate(100)
+            .add_stream(&[1.0, 2.0], GrapeTag::Azimuth)
+            .add_stream(&[3.0, 4.0], GrapeTag::Elevation)
+            .build()
+            .unwrap();
+        let b = GrapeFile::builder()
+            .set_samplerate(100)
+            .add_stream(&[5.0, 6.0, 7.0], GrapeTag::Azimuth)
+            .add_stream(&[8.0, 9.0, 10.0], GrapeTag::Elevation)
+            .build()
+            .unwrap();
+
+        let appended = a.append_time(&b).unwrap();
+        let (sr, streams) = appended.streams_native_sample_rate();
+        assert_eq!(100, sr);
+        assert_eq!(
+            vec![
+                (GrapeTag::Azimuth, vec![1.0, 2.0, 5.0, 6.0, 7.0]),
+                (GrapeTag::Elevation, vec![3.0, 4.0, 8.0, 9.0, 10.0]),
+            ],
+            streams
+        );
+        assert_eq!(a.samples.len() + b.samples.len(), appended.samples.len());
+    }
+
+    #[test]
+    fn append_mismatched_tags() {
+        let a = GrapeFile::builder()
+            .add_stream(&[1.0, 2.0], GrapeTag::X)
+            .build()
+            .unwrap();
+        let b = GrapeFile::builder()
+            .add_stream(&[1.0, 2.0], GrapeTag::Y)
+            .build()
+            .unwrap();
+
+        assert!(matches!(
+            a.append_time(&b),
+            Err(GrapeFileError::MismatchedTags)
+        ));
+    }
+
+    #[test]
+    fn rows_pair_tags_and_values() {
+        let data = GrapeFile::builder()
+            .add_stream(&[1.0, 2.0, 3.0], GrapeTag::Azimuth)
+            .add_stream(&[4.0, 5.0, 6.0], GrapeTag::Elevation)
+            .build()
+            .unwrap();
+
+        let rows: Vec<_> = data.rows().collect();
+        assert_eq!(3, rows.len());
+        assert_eq!(
+            vec![(GrapeTag::Azimuth, 2.0), (GrapeTag::Elevation, 5.0)],
+            rows[1]
+        );
+    }
+
+    #[test]
+    fn rows_from_empty() {
+        let data = GrapeFile::builder().build().unwrap();
+        assert_eq!(0, data.rows().count());
+    }
+
+    #[test]
+    fn display_summary() {
+        let data = GrapeFile::builder()
+            .set_samplerate(1000)
+            .add_stream(&[A_FLOAT; 4], GrapeTag::Azimuth)
+            .add_stream(&[A_FLOAT; 4], GrapeTag::Elevation)
+            .build()
+            .unwrap();
+
+        let summary = data.to_string();
+        assert!(summary.contains("1000 Hz"), "{summary}");
+        assert!(summary.contains("[Azimuth, Elevation]"), "{summary}");
+        assert!(summary.contains("4 samples"), "{summary}");
+        assert!(!summary.contains("12.07"), "{summary}");
+
+        let empty = GrapeFile::builder().build().unwrap();
+        assert!(empty.to_string().contains("0 streams []"));
+    }
+
     #[test]
     fn long_write_read() {
         let rng = rand::thread_rng();
@@ -588,4 +2376,30 @@ mod tests {
         let read_data = GrapeFile::from_file(&mut buf).unwrap();
         assert_eq!(data, read_data);
     }
+
+    #[test]
+    fn points_to_grape_file_holds_missing_points() {
+        // The tags come in any order, and the first is missing a frame
+        let frames = vec![
+            vec![(1, Point::new(1.0, 2.0)), (2, Point::new(-3.0, 4.0))],
+            vec![(2, Point::new(-3.5, 4.5))],
+            vec![(2, Point::new(-4.0, 5.0)), (1, Point::new(2.0, 3.0))],
+        ];
+        let file = points_to_grape_file(&frames, 4);
+
+        let (sample_rate, streams) = file.streams_native_sample_rate();
+        assert_eq!(4, sample_rate);
+        assert_eq!(
+            vec![
+                (GrapeTag::X, vec![1.0, 1.0, 2.0]),
+                (GrapeTag::Y, vec![2.0, 2.0, 3.0]),
+                (GrapeTag::X, vec![-3.0, -3.5, -4.0]),
+                (GrapeTag::Y, vec![4.0, 4.5, 5.0]),
+            ],
+            streams
+        );
+
+        let empty = points_to_grape_file(&[], 4);
+        assert_eq!(0, empty.num_tag_groups());
+    }
 }
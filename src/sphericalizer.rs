@@ -1,9 +1,12 @@
 //! The system for converting readings from two antenna into one spherical
-//! coordinate.
+//! coordinate. Setups with more antennas can describe them with
+//! [`Sphericalizer::with_antennas`], and their readings are fused together.
 
-use std::f32::consts::PI;
+use std::collections::BTreeMap;
+use std::f32::consts::{PI, TAU};
+use std::sync::{Arc, Mutex};
 
-use crate::hdm::Hdm;
+use crate::hardware_data_manager::{normalize_azimuth_f32, HardwareDataManager, Id, Update};
 use crate::saf::BufferMetadata;
 use crate::update_accumulator::UpdateAccumulator;
 
@@ -13,48 +16,239 @@ use crate::update_accumulator::UpdateAccumulator;
 // Tag 1 = 118875764010724
 // Tag 2 = 118875764011634
 
-const BACK_ANTENNA: usize = 118875763481542;
-const FRONT_ANTENNA: usize = 118875763481510;
+/// The ID of the antenna at the back of the listener block
+pub const BACK_ANTENNA: usize = 118875763481542;
+/// The ID of the antenna at the front of the listener block
+pub const FRONT_ANTENNA: usize = 118875763481510;
 
-// A tuple of the gain and range of the tags
-type TagSetting = (f32, f32);
+// From observation, the antennas report angles in the range of -70 to 70 degrees
+const ANTENNA_FOV: f32 = 1.22173;
+
+/// A tuple of the gain and range of a tag
+pub type TagSetting = (f32, f32);
+
+/// The yaw of the listener's head, in radians to the left, kept up to date
+/// by a head tracker while the [`Sphericalizer`] reads it.
+pub type HeadYaw = Arc<Mutex<f32>>;
+
+/// How far apart, in radians, two antennas' bearings to a tag can be while
+/// still agreeing with each other.
+const AGREEMENT: f32 = PI / 8.0;
+
+/// An antenna on the listener block, for setups with more than the usual two.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Antenna {
+    /// The ID of the antenna
+    pub id: Id,
+    /// The azimuth, in radians to the left of straight ahead, that the
+    /// antenna reads as 0. An antenna can't tell which side of it a tag is
+    /// on, so a reading of `a` puts the tag either at `orientation + a` or at
+    /// `orientation + PI - a`.
+    pub orientation: f32,
+}
+
+/// How the azimuths read by the [`BACK_ANTENNA`] turn into azimuths around the
+/// listener, which depends on how the listener block is put together. The
+/// world azimuth is `offset` plus the reading, or minus the reading if
+/// `flipped` is set, wrapped to 0 to 2PI.
+///
+/// The default fits our listener block, where the back antenna reads 0 for a
+/// tag straight out to the listener's left, and larger angles as the tag
+/// moves round towards the back. So the offset is -1.5PI (a quarter turn to
+/// the left) and it isn't flipped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AzimuthConvention {
+    /// The world azimuth, in radians to the left of straight ahead, of a
+    /// reading of 0
+    pub offset: f32,
+    /// Whether the antenna's readings increase to the right, rather than to
+    /// the left like world azimuths do
+    pub flipped: bool,
+}
+
+impl Default for AzimuthConvention {
+    fn default() -> Self {
+        Self {
+            offset: -1.5 * PI,
+            flipped: false,
+        }
+    }
+}
+
+impl AzimuthConvention {
+    /// Turns an azimuth read by the back antenna into one around the listener
+    /// block. Not wrapped, [`Sphericalizer::face_listener`] does that.
+    fn apply(&self, azimuth: f32) -> f32 {
+        let azimuth = if self.flipped { -azimuth } else { azimuth };
+        azimuth + self.offset
+    }
+}
 
 /// Converts from raw antenna measurements into a spherical coordinate and bundles
 /// range and gain into a [`BufferMetadata`] struct to pass into a [`Binauraliser`](crate::saf::Binauraliser).
 pub struct Sphericalizer {
     tag_settings: Vec<TagSetting>,
+    tag_ids: Option<Vec<Id>>,
+    listener_yaw: f32,
+    head_yaw: Option<HeadYaw>,
+    antennas: Option<Vec<Antenna>>,
+    convention: AzimuthConvention,
 }
 
 impl Sphericalizer {
     /// Instantiates a new `Sphericalizer`, storing the gains and ranges of the
     /// tags that we will be looking for.
     pub fn new(tag_settings: Vec<TagSetting>) -> Self {
-        Self { tag_settings }
+        Self {
+            tag_settings,
+            tag_ids: None,
+            listener_yaw: 0.0,
+            head_yaw: None,
+            antennas: None,
+            convention: AzimuthConvention::default(),
+        }
+    }
+
+    /// Matches up the tags with their settings by ID, so that the settings
+    /// at each index belong to the tag with the ID at the same index. By
+    /// default, the settings are matched up with the tags in order of ID.
+    pub fn with_tag_ids(mut self, tag_ids: Vec<Id>) -> Self {
+        self.tag_ids = Some(tag_ids);
+        self
+    }
+
+    /// Turns the whole scene to make up for a listener block that isn't
+    /// facing straight ahead. `yaw` is how far the listener block is turned
+    /// to the left, in radians, so every source ends up that much further to
+    /// the right.
+    pub fn with_listener_yaw(mut self, yaw: f32) -> Self {
+        self.listener_yaw = yaw;
+        self
+    }
+
+    /// Follows the listener's head as it turns, so that the sources stay put
+    /// while the listener looks around. `head_yaw` is read on every query and
+    /// adds to the yaw of the listener block.
+    pub fn with_head_tracker(mut self, head_yaw: HeadYaw) -> Self {
+        self.head_yaw = Some(head_yaw);
+        self
+    }
+
+    /// Maps the back antenna's readings onto the world with `convention`,
+    /// for listener blocks that aren't put together like ours. Setups given
+    /// [`Sphericalizer::with_antennas`] describe which way each antenna faces
+    /// instead, and ignore this.
+    pub fn with_azimuth_convention(mut self, convention: AzimuthConvention) -> Self {
+        self.convention = convention;
+        self
+    }
+
+    /// Reads from any number of antennas, rather than just the
+    /// [`BACK_ANTENNA`] and [`FRONT_ANTENNA`]. Each tag's bearing is the one
+    /// that the most antennas agree on, so a single antenna that disagrees
+    /// with the rest is outvoted. Readings pinned to the edge of an antenna's
+    /// field of view count for less. A tag only needs to be heard
+    /// by one antenna, but then which side of it the tag is on is a guess.
+    pub fn with_antennas(mut self, antennas: Vec<Antenna>) -> Self {
+        self.antennas = Some(antennas);
+        self
+    }
+
+    /// The number of tags that we are looking for.
+    pub fn num_tags(&self) -> usize {
+        self.tag_settings.len()
+    }
+
+    /// The gain of the given tag, if we are looking for that tag.
+    pub fn gain(&self, tag: usize) -> Option<f32> {
+        self.tag_settings.get(tag).map(|&(gain, _)| gain)
+    }
+
+    /// Changes the gain of the given tag. It will be used from the next query
+    /// onwards. Does nothing if we aren't looking for that tag.
+    pub fn set_gain(&mut self, tag: usize, gain: f32) {
+        if let Some((old_gain, _)) = self.tag_settings.get_mut(tag) {
+            *old_gain = gain;
+        }
     }
 
     /// From observation, azimuth and elevation are in the range of -70 to 70 degrees (-1.22173 to 1.22173 rad)
     /// This function scales them to the range -90 to 90 degrees (-PI/2 to PI/2 rad)
     fn scale_angle(azm: f32) -> f32 {
         let pi_2 = PI / 2.0;
-        let scaled = azm * pi_2 / 1.22173;
+        let scaled = azm * pi_2 / ANTENNA_FOV;
         scaled.clamp(-pi_2, pi_2)
     }
 
+    /// How far the listener is turned to the left of the listener block
+    /// right now, in radians.
+    fn yaw(&self) -> f32 {
+        let head_yaw = self
+            .head_yaw
+            .as_ref()
+            .map_or(0.0, |head_yaw| *head_yaw.lock().unwrap());
+        self.listener_yaw + head_yaw
+    }
+
+    /// Turns an azimuth relative to the listener block into one relative to
+    /// where the listener is facing, given the listener's `yaw`, in the range
+    /// 0 to 2PI.
+    fn face_listener(azimuth: f32, yaw: f32) -> f32 {
+        normalize_azimuth_f32(azimuth - yaw)
+    }
+
+    /// The inverse of the transform applied by [`Sphericalizer::query`], with
+    /// the default [`AzimuthConvention`]. Given
+    /// the azimuth of a tag, returns the azimuths that the back and front
+    /// antennas would report for it. This lets us simulate the listener block.
+    pub fn antenna_azimuths(azimuth: f32) -> (f32, f32) {
+        // Only the sign of the front antenna's reading matters, it tells us
+        // whether the tag is in front of or behind the back antenna
+        let azimuth = normalize_azimuth_f32(azimuth);
+        let (scaled, front) = if azimuth <= PI {
+            (azimuth - PI / 2.0, -PI / 4.0)
+        } else {
+            (1.5 * PI - azimuth, PI / 4.0)
+        };
+
+        (scaled * ANTENNA_FOV / (PI / 2.0), front)
+    }
+
     /// Pulls updates out of the [`UpdateAccumulator`], sphericalizes them, bundles
     /// the associated gain and range, generating a vec of [`BufferMetadata`].
-    pub fn query(&self, acc: &mut UpdateAccumulator<Hdm>) -> Option<Vec<BufferMetadata>> {
+    pub fn query<H>(&self, acc: &mut UpdateAccumulator<H>) -> Option<Vec<BufferMetadata>>
+    where
+        H: HardwareDataManager + ?Sized,
+    {
         let mut updates = acc.get_status();
+        if let Some(antennas) = &self.antennas {
+            return self.fuse_updates(&updates, antennas);
+        }
         // There should be two updates for each tag since there are two antennas
         // If there are not, then we must wait until more updates come in
         if updates.len() != self.tag_settings.len() * 2 {
             return None;
         }
         // Sort by tag ID
-        updates.sort_by(|a, b| a.dst.cmp(&b.dst));
+        updates.sort_by_key(|u| u.dst);
         // Group updates into pairs, one for each tag, where each pair is from the back and front antennas
-        let grouped_updates = updates.chunks(2);
+        let mut grouped_updates = updates.chunks(2).collect::<Vec<_>>();
+        // Put the pairs in the same order as the tag settings, if we know
+        // which tag each setting belongs to
+        if let Some(tag_ids) = &self.tag_ids {
+            grouped_updates = tag_ids
+                .iter()
+                .map(|&id| {
+                    grouped_updates
+                        .iter()
+                        .find(|pair| pair[0].dst == id)
+                        .copied()
+                })
+                .collect::<Option<_>>()?;
+        }
         // For each pair, derive a single BufferMetadata
         grouped_updates
+            .into_iter()
             .enumerate()
             .map(|(i, pair)| {
                 let back_ant = pair
@@ -65,26 +259,463 @@ impl Sphericalizer {
                     .iter()
                     .find(|u| u.src == FRONT_ANTENNA)
                     .expect("Missing an update from the front antenna");
-                let (gain, range) = self.tag_settings[i];
-                let mut metadata = BufferMetadata {
-                    azimuth: Sphericalizer::scale_angle(back_ant.azm as f32),
-                    elevation: Sphericalizer::scale_angle(back_ant.elv as f32),
-                    range,
-                    gain,
-                };
-                // The front antenna informs whether the tag is in front or behind the base antenna, since the base itself cannot tell
-                if front_ant.azm > 0.0 {
-                    metadata.azimuth = PI - metadata.azimuth;
-                };
-
-                metadata.azimuth -= 1.5 * PI;
-
-                if metadata.azimuth < 0.0 {
-                    metadata.azimuth += 2.0 * PI;
-                }
-                metadata
+                self.fuse_pair(back_ant, front_ant, self.tag_settings[i])
             })
             .collect::<Vec<_>>()
             .into()
     }
+
+    /// Turns one tag's readings from the [`BACK_ANTENNA`] and
+    /// [`FRONT_ANTENNA`] into a [`BufferMetadata`] with the tag's gain and
+    /// range from `setting`, as seen by the listener. This is the geometry
+    /// behind [`Sphericalizer::query`], without the accumulator.
+    pub fn fuse_pair(&self, back: &Update, front: &Update, setting: TagSetting) -> BufferMetadata {
+        let (gain, range) = setting;
+        let mut azimuth = Sphericalizer::scale_angle(back.azm as f32);
+        // The front antenna informs whether the tag is in front or behind the base antenna, since the base itself cannot tell
+        if front.azm > 0.0 {
+            azimuth = PI - azimuth;
+        };
+
+        BufferMetadata {
+            azimuth: Sphericalizer::face_listener(self.convention.apply(azimuth), self.yaw()),
+            elevation: Sphericalizer::scale_angle(back.elv as f32),
+            range,
+            gain,
+        }
+    }
+
+    /// Like [`Sphericalizer::query`], but fuses the readings of every one of
+    /// `antennas` that can hear each tag.
+    fn fuse_updates(
+        &self,
+        updates: &[Update],
+        antennas: &[Antenna],
+    ) -> Option<Vec<BufferMetadata>> {
+        // Each tag's readings, as (orientation, azimuth, elevation)
+        let mut by_tag: BTreeMap<Id, Vec<(f32, f32, f32)>> = BTreeMap::new();
+        for update in updates {
+            if let Some(antenna) = antennas.iter().find(|a| a.id == update.src) {
+                by_tag.entry(update.dst).or_default().push((
+                    antenna.orientation,
+                    Sphericalizer::scale_angle(update.azm as f32),
+                    Sphericalizer::scale_angle(update.elv as f32),
+                ));
+            }
+        }
+
+        let tag_ids = match &self.tag_ids {
+            Some(tag_ids) => tag_ids.clone(),
+            None => by_tag.keys().copied().collect(),
+        };
+        // We must wait until we've heard about every tag
+        if tag_ids.len() != self.tag_settings.len() {
+            return None;
+        }
+
+        let yaw = self.yaw();
+        tag_ids
+            .iter()
+            .zip(&self.tag_settings)
+            .map(|(id, &(gain, range))| {
+                let (azimuth, elevation) = fuse_bearings(by_tag.get(id)?)?;
+                Some(BufferMetadata {
+                    azimuth: Sphericalizer::face_listener(azimuth, yaw),
+                    elevation,
+                    range,
+                    gain,
+                })
+            })
+            .collect()
+    }
+}
+
+/// The distance between two angles, the short way around.
+fn angle_between(a: f32, b: f32) -> f32 {
+    ((a - b + PI).rem_euclid(TAU) - PI).abs()
+}
+
+/// Fuses several antennas' readings of one tag, as (orientation, azimuth,
+/// elevation), into a single azimuth and elevation. See
+/// [`Sphericalizer::with_antennas`].
+fn fuse_bearings(readings: &[(f32, f32, f32)]) -> Option<(f32, f32)> {
+    // Each antenna could be seeing the tag on either side of it
+    let candidates = readings
+        .iter()
+        .map(|&(orientation, azimuth, _)| {
+            [
+                normalize_azimuth_f32(orientation + azimuth),
+                normalize_azimuth_f32(orientation + PI - azimuth),
+            ]
+        })
+        .collect::<Vec<_>>();
+    // Readings pinned to the edge of an antenna's field of view only tell us
+    // that the tag is somewhere past it
+    let weights = readings
+        .iter()
+        .map(|&(_, azimuth, _)| {
+            if azimuth.abs() >= PI / 2.0 - 1e-3 {
+                0.25
+            } else {
+                1.0
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // How far each antenna's closest candidate is from `bearing`
+    let nearest = |bearing: f32| {
+        candidates
+            .iter()
+            .map(move |pair| {
+                pair.iter()
+                    .map(|&c| (c, angle_between(c, bearing)))
+                    .min_by(|a, b| a.1.total_cmp(&b.1))
+                    .expect("there are always two candidates")
+            })
+            .zip(&weights)
+    };
+    let support = |bearing: f32| {
+        nearest(bearing)
+            .map(|((_, distance), weight)| weight * (1.0 - distance / AGREEMENT).max(0.0))
+            .sum::<f32>()
+    };
+
+    let best = candidates
+        .iter()
+        .flatten()
+        .copied()
+        .max_by(|&a, &b| support(a).total_cmp(&support(b)))?;
+
+    // Average the antennas that agree with the best candidate
+    let (mut sin, mut cos, mut elevation, mut total) = (0.0, 0.0, 0.0, 0.0);
+    for (((candidate, distance), &weight), &(_, _, elv)) in nearest(best).zip(readings) {
+        if distance <= AGREEMENT {
+            sin += weight * candidate.sin();
+            cos += weight * candidate.cos();
+            elevation += weight * elv;
+            total += weight;
+        }
+    }
+
+    Some((normalize_azimuth_f32(sin.atan2(cos)), elevation / total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dummy_hdm::DummyHdm;
+    use crate::hardware_message_decoder::UUDFEvent;
+    use crate::hdm::Hdm;
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn query_recovers_simulated_azimuths() {
+        let hdm = DummyHdm::builder()
+            .num_points(4)
+            .delay(0.01)
+            .antennas(BACK_ANTENNA, FRONT_ANTENNA)
+            .build();
+        let true_azimuths: Vec<f32> = hdm
+            .get_debug_locations()
+            .iter()
+            .skip(1)
+            .map(|p| p.y.atan2(p.x) as f32)
+            .collect();
+
+        let hdm = Arc::new(Mutex::new(hdm));
+        let mut acc = UpdateAccumulator::new(hdm.clone());
+        thread::sleep(Duration::from_millis(50));
+        hdm.lock().unwrap().stop();
+
+        let sphericalizer = Sphericalizer::new(vec![(1.0, 1.0); 4]);
+        let metadata = sphericalizer.query(&mut acc).unwrap();
+
+        for (m, azimuth) in metadata.iter().zip(true_azimuths) {
+            let diff = (m.azimuth - azimuth + PI).rem_euclid(2.0 * PI) - PI;
+            assert!(diff.abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn query_orders_by_tag_id() {
+        let hdm = Arc::new(Mutex::new(
+            DummyHdm::builder()
+                .num_points(2)
+                .delay(0.01)
+                .antennas(BACK_ANTENNA, FRONT_ANTENNA)
+                .build(),
+        ));
+        let mut acc = UpdateAccumulator::new(hdm.clone());
+        thread::sleep(Duration::from_millis(50));
+        hdm.lock().unwrap().stop();
+
+        let by_id = Sphericalizer::new(vec![(1.0, 1.0); 2])
+            .query(&mut acc)
+            .unwrap();
+
+        // The DummyHdm numbers its tags from 1
+        let reordered = Sphericalizer::new(vec![(0.5, 1.0), (1.0, 1.0)])
+            .with_tag_ids(vec![2, 1])
+            .query(&mut acc)
+            .unwrap();
+        assert_eq!(0.5, reordered[0].gain);
+        assert_eq!(by_id[1].azimuth, reordered[0].azimuth);
+        assert_eq!(by_id[0].azimuth, reordered[1].azimuth);
+
+        let missing = Sphericalizer::new(vec![(1.0, 1.0); 2])
+            .with_tag_ids(vec![1, 3])
+            .query(&mut acc);
+        assert_eq!(None, missing);
+    }
+
+    /// An accumulator that has heard one reading of tag 1 from the back
+    /// antenna, at `back` degrees, and the front antenna, at `front` degrees
+    fn one_reading(back: i32, front: i32) -> UpdateAccumulator<Hdm> {
+        let hdm = Hdm::new();
+        for (anchor, angle) in [(BACK_ANTENNA, back), (FRONT_ANTENNA, front)] {
+            hdm.add_update(UUDFEvent {
+                tag_id: 1,
+                rssi: -40,
+                angle_1: angle,
+                angle_2: 0,
+                reserved: -40,
+                channel: 37,
+                anchor_id: anchor as u64,
+                user_defined: String::new(),
+                timestamp: 0,
+                sequence: 0,
+            });
+        }
+        UpdateAccumulator::new(Arc::new(Mutex::new(hdm)))
+    }
+
+    #[test]
+    fn azimuth_conventions() {
+        // 35 degrees is halfway to the edge of the back antenna's view, and
+        // the front antenna puts the tag behind it
+        let mut acc = one_reading(35, -10);
+        let mut azimuth = |convention| {
+            Sphericalizer::new(vec![(1.0, 1.0)])
+                .with_azimuth_convention(convention)
+                .query(&mut acc)
+                .unwrap()[0]
+                .azimuth
+        };
+
+        let default = azimuth(AzimuthConvention::default());
+        assert!((default - 0.75 * PI).abs() < 1e-3, "{default}");
+
+        let flipped = azimuth(AzimuthConvention {
+            flipped: true,
+            ..Default::default()
+        });
+        assert!((flipped - 0.25 * PI).abs() < 1e-3, "{flipped}");
+
+        let front_is_zero = azimuth(AzimuthConvention {
+            offset: 0.0,
+            flipped: false,
+        });
+        assert!((front_is_zero - 0.25 * PI).abs() < 1e-3, "{front_is_zero}");
+    }
+
+    /// The back and front antennas' readings of a tag at `azimuth`
+    fn pair(azimuth: f32) -> (Update, Update) {
+        let (back, front) = Sphericalizer::antenna_azimuths(azimuth);
+        let update = |src, azm: f32| Update {
+            src,
+            dst: 1,
+            elv: 0.0,
+            azm: azm as f64,
+            rssi: None,
+        };
+        (update(BACK_ANTENNA, back), update(FRONT_ANTENNA, front))
+    }
+
+    #[test]
+    fn fuse_pair_around_the_listener() {
+        let sphericalizer = Sphericalizer::new(vec![]);
+        for (name, azimuth) in [
+            ("ahead", 0.0),
+            ("left", PI / 2.0),
+            ("behind", PI),
+            ("right", 1.5 * PI),
+        ] {
+            let (back, front) = pair(azimuth);
+            let metadata = sphericalizer.fuse_pair(&back, &front, (0.5, 2.0));
+            assert!(
+                angle_between(azimuth, metadata.azimuth) < 1e-3,
+                "{name} came out at {}",
+                metadata.azimuth
+            );
+            assert!((0.0..TAU).contains(&metadata.azimuth));
+            assert_eq!((0.5, 2.0), (metadata.gain, metadata.range));
+        }
+    }
+
+    #[test]
+    fn fuse_pair_clamps_past_the_field_of_view() {
+        // Beyond the edge of the back antenna's view still means straight
+        // ahead, rather than wrapping round
+        let (mut back, front) = pair(0.0);
+        back.azm *= 1.5;
+        let metadata = Sphericalizer::new(vec![]).fuse_pair(&back, &front, (1.0, 1.0));
+        assert!(angle_between(0.0, metadata.azimuth) < 1e-3);
+    }
+
+    #[test]
+    fn listener_yaw_turns_front_to_side() {
+        let yaw = Sphericalizer::new(vec![]).with_listener_yaw(PI / 2.0).yaw();
+        // Turning the listener to the left puts what's in front of the
+        // listener block on the right
+        assert!((Sphericalizer::face_listener(0.0, yaw) - 1.5 * PI).abs() < 1e-6);
+        assert!(Sphericalizer::face_listener(PI / 2.0, yaw).abs() < 1e-6);
+    }
+
+    #[test]
+    fn listener_yaw_rotates_every_tag() {
+        let hdm = Arc::new(Mutex::new(
+            DummyHdm::builder()
+                .num_points(3)
+                .delay(0.01)
+                .antennas(BACK_ANTENNA, FRONT_ANTENNA)
+                .build(),
+        ));
+        let mut acc = UpdateAccumulator::new(hdm.clone());
+        thread::sleep(Duration::from_millis(50));
+        hdm.lock().unwrap().stop();
+
+        let straight = Sphericalizer::new(vec![(1.0, 1.0); 3])
+            .query(&mut acc)
+            .unwrap();
+        let turned = Sphericalizer::new(vec![(1.0, 1.0); 3])
+            .with_listener_yaw(PI / 2.0)
+            .query(&mut acc)
+            .unwrap();
+
+        for (s, t) in straight.iter().zip(&turned) {
+            let diff = (s.azimuth - t.azimuth - PI / 2.0 + PI).rem_euclid(2.0 * PI) - PI;
+            assert!(diff.abs() < 1e-3, "{} -> {}", s.azimuth, t.azimuth);
+            assert!((0.0..2.0 * PI).contains(&t.azimuth));
+            assert_eq!(s.elevation, t.elevation);
+        }
+    }
+
+    #[test]
+    fn head_tracker_counter_rotates() {
+        let hdm = Arc::new(Mutex::new(
+            DummyHdm::builder()
+                .num_points(1)
+                .delay(0.01)
+                .antennas(BACK_ANTENNA, FRONT_ANTENNA)
+                .build(),
+        ));
+        let mut acc = UpdateAccumulator::new(hdm.clone());
+        thread::sleep(Duration::from_millis(50));
+        hdm.lock().unwrap().stop();
+
+        let head_yaw = HeadYaw::default();
+        let sphericalizer = Sphericalizer::new(vec![(1.0, 1.0)])
+            .with_listener_yaw(0.1)
+            .with_head_tracker(head_yaw.clone());
+
+        let start = sphericalizer.query(&mut acc).unwrap()[0].azimuth;
+        // As the head turns left, the source, which isn't moving, should
+        // swing around to the right by just as much
+        for step in 1..=8 {
+            let turn = step as f32 * PI / 4.0;
+            *head_yaw.lock().unwrap() = turn;
+
+            let azimuth = sphericalizer.query(&mut acc).unwrap()[0].azimuth;
+            let diff = (start - azimuth - turn + PI).rem_euclid(2.0 * PI) - PI;
+            assert!(diff.abs() < 1e-3, "turned {turn}, {start} -> {azimuth}");
+        }
+    }
+
+    /// The azimuth that an antenna at `orientation` reads for a tag at
+    /// `bearing`, scaled like [`Sphericalizer::scale_angle`]'s output
+    fn reading(orientation: f32, bearing: f32) -> f32 {
+        let offset = (bearing - orientation + PI).rem_euclid(TAU) - PI;
+        if offset > PI / 2.0 {
+            PI - offset
+        } else if offset < -PI / 2.0 {
+            -PI - offset
+        } else {
+            offset
+        }
+    }
+
+    #[test]
+    fn fuse_outvotes_one_antenna() {
+        let orientations = [0.0, TAU / 3.0, 2.0 * TAU / 3.0];
+        for bearing in [0.3, 1.0, 2.5, 4.0, 5.9] {
+            let mut readings = orientations
+                .iter()
+                .map(|&o| (o, reading(o, bearing), 0.1))
+                .collect::<Vec<_>>();
+            // The third antenna is having a bad day
+            readings[2].1 = reading(orientations[2], bearing + 2.0);
+            readings[2].2 = 0.9;
+
+            let (azimuth, elevation) = fuse_bearings(&readings).unwrap();
+            assert!(
+                angle_between(bearing, azimuth) < 1e-3,
+                "{bearing} fused to {azimuth}"
+            );
+            assert!((0.1 - elevation).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn query_fuses_three_antennas() {
+        let antennas = [(1_usize, 0.0), (2, TAU / 3.0), (3, 2.0 * TAU / 3.0)];
+        let tags = [(10_usize, 1.0), (11, 4.0)];
+
+        let hdm = Hdm::new();
+        for &(tag, bearing) in &tags {
+            for &(anchor, orientation) in &antennas {
+                // Undo the scaling the sphericalizer does, and the antenna
+                // only reports whole degrees
+                let raw = reading(orientation, bearing) * ANTENNA_FOV / (PI / 2.0);
+                hdm.add_update(UUDFEvent {
+                    tag_id: tag as u64,
+                    rssi: -40,
+                    angle_1: raw.to_degrees().round() as i32,
+                    angle_2: 0,
+                    reserved: -40,
+                    channel: 37,
+                    anchor_id: anchor as u64,
+                    user_defined: String::new(),
+                    timestamp: 0,
+                    sequence: 0,
+                });
+            }
+        }
+        let mut acc = UpdateAccumulator::new(Arc::new(Mutex::new(hdm)));
+
+        let sphericalizer = Sphericalizer::new(vec![(1.0, 1.0); 2]).with_antennas(
+            antennas
+                .iter()
+                .map(|&(id, orientation)| Antenna { id, orientation })
+                .collect(),
+        );
+        let metadata = sphericalizer.query(&mut acc).unwrap();
+
+        for (m, (_, bearing)) in metadata.iter().zip(tags) {
+            assert!(
+                angle_between(bearing, m.azimuth) < 2_f32.to_radians(),
+                "{bearing} fused to {}",
+                m.azimuth
+            );
+        }
+
+        // Nobody has heard of a third tag
+        let missing = Sphericalizer::new(vec![(1.0, 1.0); 2])
+            .with_tag_ids(vec![10, 12])
+            .with_antennas(vec![Antenna {
+                id: 1,
+                orientation: 0.0,
+            }])
+            .query(&mut acc);
+        assert_eq!(None, missing);
+    }
 }
@@ -1,13 +1,29 @@
 //! Where we store our time-domain spatial data.
 
+use crate::hardware_data_manager::normalize_azimuth_f32;
 use crate::saf::BufferMetadata;
 
+use log::warn;
+use std::f32::consts::TAU;
+use std::fmt::Display;
+
+/// Where a tag that hasn't been heard from yet sits: straight ahead, and
+/// silent, until we know where it really is.
+const UNHEARD: BufferMetadata = BufferMetadata {
+    azimuth: 0.0,
+    elevation: 0.0,
+    range: 1.0,
+    gain: 0.0,
+};
+
 /// A buffer to store our time-domain spatial data. Ensures that we always
 /// have data for each tag for each time slice.
 #[derive(Debug, Clone)]
 pub struct TDBufMeta {
-    data: Vec<Vec<BufferMetadata>>,
+    data: Vec<Vec<Option<BufferMetadata>>>,
     num_tags: usize,
+    dropped: Vec<usize>,
+    interpolate: bool,
 }
 
 impl TDBufMeta {
@@ -17,25 +33,409 @@ impl TDBufMeta {
         Self {
             data: Vec::new(),
             num_tags,
+            dropped: vec![0; num_tags],
+            interpolate: false,
         }
     }
 
-    /// Insert a time-slice's worth of metadata into the buffer. Panics if
-    /// there is the wrong number of metadata entries.
+    /// Fill in the gaps left by dropped metadata by gliding between the
+    /// positions on either side of the gap, rather than holding the last
+    /// position. Time slices dropped with [`TDBufMeta::drop_slice`] are kept
+    /// and filled in too, rather than skipped.
+    pub fn with_interpolation(mut self) -> Self {
+        self.interpolate = true;
+        self
+    }
+
+    /// Insert a time-slice's worth of metadata into the buffer. If some tags
+    /// are missing from the end of the slice, they are filled in when the
+    /// buffer is dumped, and count as dropped. Extra entries are thrown away.
     pub fn add(&mut self, data: Vec<BufferMetadata>) {
-        assert_eq!(data.len(), self.num_tags);
-        self.data.push(data);
+        if data.len() > self.num_tags {
+            warn!(
+                "expected {} tags but got {}, ignoring the rest",
+                self.num_tags,
+                data.len()
+            );
+        }
+
+        let mut slice = data.into_iter().map(Some).collect::<Vec<_>>();
+        slice.resize(self.num_tags, None);
+
+        for (dropped, metadata) in self.dropped.iter_mut().zip(&slice) {
+            if metadata.is_none() {
+                *dropped += 1;
+            }
+        }
+
+        self.data.push(slice);
+    }
+
+    /// Record that a time-slice's worth of metadata never arrived.
+    pub fn drop_slice(&mut self) {
+        for dropped in self.dropped.iter_mut() {
+            *dropped += 1;
+        }
+        if self.interpolate {
+            self.data.push(vec![None; self.num_tags]);
+        }
+    }
+
+    /// The number of time slices in the buffer.
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Whether there are no time slices in the buffer.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Copies the time slices from `start` onwards into a new buffer, so that
+    /// they can be [`TDBufMeta::append`]ed to a copy of this one kept
+    /// elsewhere without copying the whole buffer again.
+    pub fn tail(&self, start: usize) -> Self {
+        let data = self.data[start.min(self.data.len())..].to_vec();
+        let mut dropped = vec![0; self.num_tags];
+        for slice in &data {
+            for (dropped, metadata) in dropped.iter_mut().zip(slice) {
+                if metadata.is_none() {
+                    *dropped += 1;
+                }
+            }
+        }
+
+        Self {
+            data,
+            dropped,
+            ..*self
+        }
+    }
+
+    /// Adds the time slices in `other` to the end of this buffer.
+    pub fn append(&mut self, other: Self) {
+        for (dropped, more) in self.dropped.iter_mut().zip(other.dropped) {
+            *dropped += more;
+        }
+        self.data.extend(other.data);
     }
 
-    /// Return all of the metadata that we have collected, consuming the buffer.
+    /// Summarizes how each tag moved over everything collected so far, along
+    /// with how many time slices were dropped. Only the metadata that
+    /// actually arrived counts towards the statistics.
+    pub fn stats(&self) -> Vec<TagStats> {
+        self.dropped
+            .iter()
+            .enumerate()
+            .map(|(tag, &dropped)| TagStats {
+                dropped,
+                ..column_stats(self.data.iter().filter_map(|slice| slice[tag].as_ref()))
+            })
+            .collect()
+    }
+
+    /// Return all of the metadata that we have collected, consuming the
+    /// buffer. A tag that is missing from a time slice stays where it was in
+    /// the last slice, or glides towards where it is in the next one if we
+    /// are interpolating. Until a tag is first heard from, it sits straight
+    /// ahead and silent (or, if we are interpolating, wherever it first
+    /// turns up).
     pub fn dump(self) -> Vec<Vec<BufferMetadata>> {
-        self.data
+        let mut filled = vec![Vec::with_capacity(self.num_tags); self.data.len()];
+
+        for tag in 0..self.num_tags {
+            let column = self.data.iter().map(|slice| slice[tag]).collect::<Vec<_>>();
+            for (slice, metadata) in filled.iter_mut().zip(fill_gaps(&column, self.interpolate)) {
+                slice.push(metadata);
+            }
+        }
+
+        filled
+    }
+}
+
+/// Fills in the gaps in one tag's metadata, see [`TDBufMeta::dump`].
+fn fill_gaps(column: &[Option<BufferMetadata>], interpolate: bool) -> Vec<BufferMetadata> {
+    let mut last_known: Option<(usize, BufferMetadata)> = None;
+
+    // Where each gap ends, found in one pass from the back, if we're going to
+    // glide across it
+    let mut next_known = vec![None; column.len()];
+    if interpolate {
+        let mut next = None;
+        for (i, metadata) in column.iter().enumerate().rev() {
+            next_known[i] = next;
+            if let Some(metadata) = *metadata {
+                next = Some((i, metadata));
+            }
+        }
+    }
+
+    column
+        .iter()
+        .enumerate()
+        .map(|(i, metadata)| {
+            if let Some(metadata) = *metadata {
+                last_known = Some((i, metadata));
+                return metadata;
+            }
+
+            match (last_known, next_known[i]) {
+                (Some((before_i, before)), Some((after_i, after))) => {
+                    let t = (i - before_i) as f32 / (after_i - before_i) as f32;
+                    before.lerp(&after, t)
+                }
+                (Some((_, before)), None) => before,
+                (None, Some((_, after))) => after,
+                (None, None) => UNHEARD,
+            }
+        })
+        .collect()
+}
+
+/// The spread of a set of angles.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Summary {
+    /// The average
+    pub mean: f32,
+    /// The smallest value
+    pub min: f32,
+    /// The largest value
+    pub max: f32,
+    /// The standard deviation
+    pub std_dev: f32,
+}
+
+/// How one tag moved over a capture.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct TagStats {
+    /// The circular statistics of the azimuth, in the range 0 to 2PI. The
+    /// min and max are the furthest that the tag strayed from the mean
+    /// clockwise and anticlockwise, so they may wrap around past 0
+    pub azimuth: Option<Summary>,
+    /// The statistics of the elevation
+    pub elevation: Option<Summary>,
+    /// How many time slices we have for the tag
+    pub frames: usize,
+    /// How many time slices the tag was missing from
+    pub dropped: usize,
+}
+
+impl Display for TagStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} frames, {} dropped", self.frames, self.dropped)?;
+        for (name, summary) in [("azimuth", self.azimuth), ("elevation", self.elevation)] {
+            if let Some(s) = summary {
+                write!(
+                    f,
+                    ", {name} {:.1}°±{:.1}° ({:.1}° to {:.1}°)",
+                    s.mean.to_degrees(),
+                    s.std_dev.to_degrees(),
+                    s.min.to_degrees(),
+                    s.max.to_degrees(),
+                )?;
+            }
+        }
+        Ok(())
     }
 }
 
+/// Summarizes how each tag moved over `data`, where each time slice has the
+/// metadata of every tag. Doesn't know about dropped time slices, see
+/// [`TDBufMeta::stats`] for that.
+pub fn tag_stats(data: &[Vec<BufferMetadata>]) -> Vec<TagStats> {
+    let num_tags = data.iter().map(Vec::len).max().unwrap_or(0);
+
+    (0..num_tags)
+        .map(|tag| column_stats(data.iter().filter_map(|slice| slice.get(tag))))
+        .collect()
+}
+
+/// Summarizes one tag's metadata.
+fn column_stats<'a>(column: impl Iterator<Item = &'a BufferMetadata> + Clone) -> TagStats {
+    let azimuths = column.clone().map(|m| m.azimuth).collect::<Vec<_>>();
+    let elevations = column.map(|m| m.elevation).collect::<Vec<_>>();
+
+    TagStats {
+        azimuth: circular_summary(&azimuths),
+        elevation: linear_summary(&elevations),
+        frames: azimuths.len(),
+        dropped: 0,
+    }
+}
+
+fn linear_summary(values: &[f32]) -> Option<Summary> {
+    if values.is_empty() {
+        return None;
+    }
+
+    let n = values.len() as f32;
+    let mean = values.iter().sum::<f32>() / n;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / n;
+
+    Some(Summary {
+        mean,
+        min: values.iter().copied().fold(f32::INFINITY, f32::min),
+        max: values.iter().copied().fold(f32::NEG_INFINITY, f32::max),
+        std_dev: variance.sqrt(),
+    })
+}
+
+fn circular_summary(angles: &[f32]) -> Option<Summary> {
+    if angles.is_empty() {
+        return None;
+    }
+
+    let n = angles.len() as f32;
+    let sin = angles.iter().map(|a| a.sin()).sum::<f32>() / n;
+    let cos = angles.iter().map(|a| a.cos()).sum::<f32>() / n;
+    let mean = normalize_azimuth_f32(sin.atan2(cos));
+    // The mean resultant length, which is 1 when every angle agrees
+    let resultant = sin.hypot(cos).min(1.0);
+
+    // How far each angle is from the mean, the short way around
+    let offsets = angles
+        .iter()
+        .map(|a| (a - mean + TAU / 2.0).rem_euclid(TAU) - TAU / 2.0);
+    let min_offset = offsets.clone().fold(f32::INFINITY, f32::min);
+    let max_offset = offsets.fold(f32::NEG_INFINITY, f32::max);
+
+    Some(Summary {
+        mean,
+        min: normalize_azimuth_f32(mean + min_offset),
+        max: normalize_azimuth_f32(mean + max_offset),
+        std_dev: (-2.0 * resultant.ln()).sqrt(),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::f32::consts::PI;
+
+    fn at(azimuth: f32, elevation: f32) -> BufferMetadata {
+        BufferMetadata {
+            azimuth,
+            elevation,
+            range: 1.0,
+            gain: 1.0,
+        }
+    }
+
+    fn assert_close(expected: f32, actual: f32) {
+        assert!((expected - actual).abs() < 1e-4, "{expected} != {actual}");
+    }
+
+    #[test]
+    fn stats_over_known_values() {
+        let mut buf = TDBufMeta::new(2);
+        // The first tag wobbles either side of straight ahead, the second
+        // holds still
+        buf.add(vec![at(TAU - 0.2, 0.1), at(PI, 0.0)]);
+        buf.add(vec![at(0.0, 0.2), at(PI, 0.0)]);
+        buf.add(vec![at(0.2, 0.3), at(PI, 0.0)]);
+        buf.drop_slice();
+
+        let stats = buf.stats();
+        assert_eq!(2, stats.len());
+
+        let wobbly = stats[0];
+        assert_eq!(3, wobbly.frames);
+        assert_eq!(1, wobbly.dropped);
+        let azimuth = wobbly.azimuth.unwrap();
+        // Not PI, which is what a naive mean would say
+        assert_close(0.0, (azimuth.mean + PI).rem_euclid(TAU) - PI);
+        assert_close(TAU - 0.2, azimuth.min);
+        assert_close(0.2, azimuth.max);
+        assert!(azimuth.std_dev > 0.15 && azimuth.std_dev < 0.2);
+
+        let elevation = wobbly.elevation.unwrap();
+        assert_close(0.2, elevation.mean);
+        assert_close(0.1, elevation.min);
+        assert_close(0.3, elevation.max);
+        assert_close((0.02_f32 / 3.0).sqrt(), elevation.std_dev);
+
+        let still = stats[1].azimuth.unwrap();
+        assert_close(PI, still.mean);
+        assert_close(0.0, still.std_dev);
+    }
+
+    #[test]
+    fn carry_short_rows_forward() {
+        let mut buf = TDBufMeta::new(3);
+        buf.add(vec![at(0.1, 0.0), at(0.2, 0.0)]);
+        buf.add(vec![at(0.3, 0.0), at(0.4, 0.0), at(0.5, 0.0)]);
+        buf.add(vec![at(0.6, 0.0)]);
+
+        let stats = buf.stats();
+        assert_eq!(
+            vec![0, 1, 2],
+            stats.iter().map(|s| s.dropped).collect::<Vec<_>>()
+        );
+
+        let data = buf.dump();
+        // The third tag is silent until we hear from it
+        assert_eq!(vec![at(0.1, 0.0), at(0.2, 0.0), UNHEARD], data[0]);
+        assert_eq!(vec![at(0.6, 0.0), at(0.4, 0.0), at(0.5, 0.0)], data[2]);
+    }
+
+    #[test]
+    fn interpolate_missing_frames() {
+        let mut buf = TDBufMeta::new(2).with_interpolation();
+        buf.add(vec![at(TAU - 0.2, 0.0), at(1.0, 0.2)]);
+        buf.drop_slice();
+        buf.add(vec![at(0.2, 0.0)]);
+        buf.add(vec![at(0.4, 0.0), at(1.5, 0.6)]);
+
+        let dropped = buf.stats().iter().map(|s| s.dropped).collect::<Vec<_>>();
+        assert_eq!(vec![1, 2], dropped);
+
+        let data = buf.dump();
+        assert_eq!(4, data.len());
+
+        // The first tag crosses 0 the short way around, rather than sweeping
+        // back through the whole scene
+        let between = data[1][0];
+        assert!(between.azimuth < 0.01 || between.azimuth > TAU - 0.01);
+
+        // The second tag is missing from two slices in a row
+        assert_close(1.0 + 0.5 / 3.0, data[1][1].azimuth);
+        assert_close(1.0 + 1.0 / 3.0, data[2][1].azimuth);
+        assert_close(0.2 + 0.4 * 2.0 / 3.0, data[2][1].elevation);
+        assert!(data[1][1].azimuth > data[0][1].azimuth);
+        assert!(data[2][1].azimuth < data[3][1].azimuth);
+    }
+
+    #[test]
+    fn trim_long_rows() {
+        let mut buf = TDBufMeta::new(1);
+        buf.add(vec![at(0.1, 0.0), at(0.2, 0.0)]);
+
+        assert_eq!(vec![vec![at(0.1, 0.0)]], buf.dump());
+    }
+
+    #[test]
+    fn stats_without_data() {
+        let stats = TDBufMeta::new(1).stats();
+        assert_eq!(vec![TagStats::default()], stats);
+    }
+
+    #[test]
+    fn append_tails() {
+        let mut buf = TDBufMeta::new(2).with_interpolation();
+        buf.add(vec![at(0.1, 0.0), at(0.2, 0.0)]);
+        buf.add(vec![at(0.3, 0.0)]);
+        let mut copy = buf.tail(0);
+
+        buf.drop_slice();
+        buf.add(vec![at(0.4, 0.0), at(0.5, 0.0)]);
+        copy.append(buf.tail(copy.len()));
+        assert!(buf.tail(buf.len()).is_empty());
+
+        assert_eq!(buf.len(), copy.len());
+        assert_eq!(buf.stats(), copy.stats());
+        assert_eq!(buf.dump(), copy.dump());
+    }
 
     #[test]
     fn test_buf_init() {
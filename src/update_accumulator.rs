@@ -4,10 +4,15 @@ use crate::hardware_data_manager::{HardwareDataManager, Id, Update};
 use std::{
     collections::{HashMap, VecDeque},
     sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
 const BUFFER_SIZE: usize = 5;
 
+/// The fewest median absolute deviations that outlier rejection allows, so
+/// that the readings closest to the median are never left out.
+const MIN_OUTLIER_THRESHOLD: f64 = 1.0;
+
 /// The `UpdateAccumulator` consumes updates from a [`HardwareDataManager`], and
 /// accumulates them. It can be queried for the most recent updates using [`get_status`](UpdateAccumulator::get_status).
 // The <Hdm> means that we are allowed to use `Hdm` as a type within `UpdateAccumulator`.
@@ -15,16 +20,30 @@ const BUFFER_SIZE: usize = 5;
 pub struct UpdateAccumulator<Hdm>
 where
     // Then this binding ensures that `Hdm` implements `HardwareDataManager`.
-    Hdm: HardwareDataManager,
-{   
+    // `?Sized` lets `Hdm` be a trait object, like `dyn HardwareDataManager`.
+    Hdm: HardwareDataManager + ?Sized,
+{
     /// A handle to communicate with the [`Hdm`]
     // `Rc` means this is a "reference-counted" smart pointer, and `RefCell` means we
     // are going to enforce the borrow checking rules at runtime instead of
     // compile time. This way we can keep references to the HDM in several scopes.
     hdm_handle: Arc<Mutex<Hdm>>,
 
-    /// A HashMap mapping `(Id, Id)` pairs to `Update`s.
-    accumulated_updates: HashMap<(Id, Id), VecDeque<Update>>,
+    /// A HashMap mapping `(Id, Id)` pairs to `Update`s, along with when we
+    /// got each of them.
+    accumulated_updates: HashMap<(Id, Id), VecDeque<(Instant, Update)>>,
+
+    /// How many median absolute deviations from the median an update can be
+    /// before we leave it out of the average, if we leave any out at all
+    outlier_threshold: Option<f64>,
+
+    /// How long an update counts for, if it doesn't count forever
+    max_age: Option<Duration>,
+
+    /// How many updates we keep for each pair. Only the most recent
+    /// [`BUFFER_SIZE`] are averaged, the rest are kept for
+    /// [`recent_track`](UpdateAccumulator::recent_track).
+    track_len: usize,
 }
 
 // We see `Hdm` in three places here. First, it is declared as a type for use
@@ -33,56 +52,354 @@ where
 // `HardwareDataManager` trait.
 impl<Hdm> UpdateAccumulator<Hdm>
 where
-    Hdm: HardwareDataManager,
+    Hdm: HardwareDataManager + ?Sized,
 {
     /// Instantiates a new [`UpdateAccumulator`] attached to a [`Hdm`]
     pub fn new(hdm_handle: Arc<Mutex<Hdm>>) -> Self {
         Self {
             hdm_handle,
             accumulated_updates: HashMap::new(),
+            outlier_threshold: None,
+            max_age: None,
+            track_len: BUFFER_SIZE,
         }
     }
 
+    /// Leaves updates whose azimuth or elevation is more than `k` median
+    /// absolute deviations from the median of their pair's window out of
+    /// the average, so that a single bad reflection doesn't drag it around.
+    /// `k` is raised to at least [`MIN_OUTLIER_THRESHOLD`], and a `k` that
+    /// isn't finite turns the rejection off.
+    pub fn with_outlier_rejection(mut self, k: f64) -> Self {
+        self.outlier_threshold = k.is_finite().then(|| k.max(MIN_OUTLIER_THRESHOLD));
+        self
+    }
+
+    /// Forgets updates that we got more than `max_age` ago, so that a tag
+    /// that stops reporting drops out of [`get_status`](Self::get_status)
+    /// rather than staying stuck where it was last heard from. The
+    /// [`Update`]s don't say when they were measured, so this goes by when
+    /// they came out of the HDM.
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Keeps the last `track_len` updates for each pair around for
+    /// [`recent_track`](Self::recent_track), rather than just the ones that
+    /// are averaged. Never keeps fewer than are averaged.
+    pub fn with_track_length(mut self, track_len: usize) -> Self {
+        self.track_len = track_len.max(BUFFER_SIZE);
+        self
+    }
+
+    /// Returns the updates that we're keeping for the `(src, dst)` pair,
+    /// oldest first, as of the last time that the accumulator was queried.
+    /// This is the path that a tag took recently, for drawing on a live
+    /// display; see [`with_track_length`](Self::with_track_length) and
+    /// [`with_max_age`](Self::with_max_age) for how long it is.
+    pub fn recent_track(&self, pair: (Id, Id)) -> Vec<Update> {
+        self.accumulated_updates
+            .get(&pair)
+            .map(|window| window.iter().map(|(_, update)| update.clone()).collect())
+            .unwrap_or_default()
+    }
+
     /// Returns a vec contatining the most recent [`Update`]s for all pairs
     /// of blocks. Essentially, the most updated data available.
     pub fn get_status(&mut self) -> Vec<Update> {
-        for update in self.hdm_handle.lock().unwrap().by_ref() {
-            self.accumulated_updates
-                .entry((update.src, update.dst))
-                .and_modify(|v| v.push_back(update.clone()))
-                .or_insert_with(|| VecDeque::from(vec![update.clone()]));
-        }
+        self.status_at(Instant::now())
+    }
+
+    /// [`get_status`](Self::get_status), as if the updates still in the HDM
+    /// came out of it at `now`.
+    fn status_at(&mut self, now: Instant) -> Vec<Update> {
+        self.drain_hdm(now);
 
         // Return a copy of the most recent updates, in a Vec rather than a HashMap
-        let res = self
-            .accumulated_updates
+        self.accumulated_updates
             .values()
-            .map(|v| {
-                let taken = v.iter().rev().take(BUFFER_SIZE);
-                let len = taken.len() as f64;
-                let sum = taken
-                    .cloned()
-                    .reduce(|l, r| Update {
-                        elv: l.elv + r.elv,
-                        azm: l.azm + r.azm,
-                        ..l
-                    })
-                    .expect("There should be some elements here");
-
-                Update {
-                    elv: sum.elv / len,
-                    azm: sum.azm / len,
-                    ..sum
+            .map(|v| self.average(v))
+            .collect()
+    }
+
+    /// Returns the most recent [`Update`] measured from `src` to `dst`, if
+    /// there has been one. Updates for every other pair are still
+    /// accumulated, but not averaged.
+    pub fn get_status_for(&mut self, src: Id, dst: Id) -> Option<Update> {
+        self.drain_hdm(Instant::now());
+
+        self.accumulated_updates
+            .get(&(src, dst))
+            .map(|v| self.average(v))
+    }
+
+    /// Moves every update out of the HDM and into its pair's window, keeping
+    /// just the most recent `track_len` of each, then forgets any that are
+    /// too old as of `now`.
+    fn drain_hdm(&mut self, now: Instant) {
+        for update in &mut *self.hdm_handle.lock().unwrap() {
+            let window = self
+                .accumulated_updates
+                .entry((update.src, update.dst))
+                .or_default();
+            window.push_back((now, update));
+            if window.len() > self.track_len {
+                window.pop_front();
+            }
+        }
+
+        if let Some(max_age) = self.max_age {
+            self.accumulated_updates.retain(|_, window| {
+                while window
+                    .front()
+                    .is_some_and(|(received, _)| now.duration_since(*received) > max_age)
+                {
+                    window.pop_front();
                 }
-            })
-            .collect();
+                !window.is_empty()
+            });
+        }
+    }
 
-        for v in self.accumulated_updates.values_mut() {
-            if v.len() > BUFFER_SIZE {
-                v.pop_front();
+    /// Averages the most recent updates in a pair's window, leaving out any
+    /// outliers if we're rejecting them.
+    fn average(&self, window: &VecDeque<(Instant, Update)>) -> Update {
+        let mut taken = window
+            .iter()
+            .rev()
+            .take(BUFFER_SIZE)
+            .map(|(_, update)| update.clone())
+            .collect::<Vec<_>>();
+
+        if let Some(k) = self.outlier_threshold {
+            let azm_inliers = inliers(&taken, k, |u| u.azm);
+            let elv_inliers = inliers(&taken, k, |u| u.elv);
+            let kept = taken
+                .iter()
+                .zip(azm_inliers.into_iter().zip(elv_inliers))
+                .filter(|(_, (azm, elv))| *azm && *elv)
+                .map(|(u, _)| u.clone())
+                .collect::<Vec<_>>();
+            // The azimuth and elevation can each pick out different readings,
+            // in which case none of them stands out, so they're all kept
+            if !kept.is_empty() {
+                taken = kept;
             }
         }
 
-        res
+        let len = taken.len() as f64;
+        let sum = taken
+            .iter()
+            .cloned()
+            .reduce(|l, r| Update {
+                elv: l.elv + r.elv,
+                azm: l.azm + r.azm,
+                ..l
+            })
+            .expect("There should be some elements here");
+
+        // The signal strength is averaged over just the updates that have one
+        let rssis = taken.iter().filter_map(|u| u.rssi).collect::<Vec<_>>();
+        let rssi = (!rssis.is_empty())
+            .then(|| (rssis.iter().sum::<i32>() as f64 / rssis.len() as f64).round() as i32);
+
+        Update {
+            elv: sum.elv / len,
+            azm: sum.azm / len,
+            rssi,
+            ..sum
+        }
+    }
+}
+
+/// Whether each update's `field` is within `k` median absolute deviations of
+/// the median. As long as `k` is at least [`MIN_OUTLIER_THRESHOLD`], the
+/// reading (or, for an even number, the two readings) closest to the median
+/// are always inliers, so there's always at least one.
+fn inliers(updates: &[Update], k: f64, field: impl Fn(&Update) -> f64) -> Vec<bool> {
+    let values = updates.iter().map(&field).collect::<Vec<_>>();
+    let middle = median(values.clone());
+    let mad = median(values.iter().map(|v| (v - middle).abs()).collect());
+
+    values
+        .iter()
+        .map(|v| (v - middle).abs() <= k * mad)
+        .collect()
+}
+
+fn median(mut values: Vec<f64>) -> f64 {
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dummy_hdm::ScriptedHdm;
+
+    fn update(src: Id, dst: Id, azm: f64) -> Update {
+        Update {
+            src,
+            dst,
+            elv: 0.0,
+            azm,
+            rssi: None,
+        }
+    }
+
+    fn accumulator(updates: Vec<Update>) -> UpdateAccumulator<ScriptedHdm> {
+        UpdateAccumulator::new(Arc::new(Mutex::new(ScriptedHdm::new(updates))))
+    }
+
+    #[test]
+    fn averages_the_window() {
+        let mut acc = accumulator(vec![
+            update(1, 2, 0.1),
+            update(1, 2, 0.2),
+            update(1, 2, 3.0),
+        ]);
+        let status = acc.get_status();
+        assert_eq!(1, status.len());
+        assert!((status[0].azm - 1.1).abs() < 1e-9);
+    }
+
+    #[test]
+    fn averages_the_signal_strength() {
+        let with_rssi = |rssi| Update {
+            rssi,
+            ..update(1, 2, 0.0)
+        };
+        let mut acc = accumulator(vec![
+            with_rssi(Some(-40)),
+            with_rssi(None),
+            with_rssi(Some(-51)),
+        ]);
+        assert_eq!(Some(-46), acc.get_status()[0].rssi);
+
+        let mut acc = accumulator(vec![with_rssi(None), with_rssi(None)]);
+        assert_eq!(None, acc.get_status()[0].rssi);
+    }
+
+    #[test]
+    fn recent_track_in_order() {
+        let updates = (0..8).map(|i| update(1, 2, i as f64)).collect::<Vec<_>>();
+        let mut acc = accumulator(updates.clone()).with_track_length(6);
+        acc.get_status_for(1, 2);
+
+        assert_eq!(updates[2..].to_vec(), acc.recent_track((1, 2)));
+        assert!(acc.recent_track((2, 1)).is_empty());
+
+        // Only the most recent updates are averaged
+        let average = acc.get_status_for(1, 2).unwrap();
+        assert!((average.azm - 5.0).abs() < 1e-9);
+
+        let mut acc = accumulator(updates.clone()).with_track_length(1);
+        acc.get_status();
+        assert_eq!(updates[3..].to_vec(), acc.recent_track((1, 2)));
+    }
+
+    #[test]
+    fn status_for_one_pair() {
+        let mut acc = accumulator(vec![
+            update(1, 2, 0.1),
+            update(1, 3, 2.0),
+            update(1, 2, 0.3),
+            update(1, 3, 2.2),
+        ]);
+
+        let status = acc.get_status_for(1, 2).unwrap();
+        assert_eq!((1, 2), (status.src, status.dst));
+        assert!((status.azm - 0.2).abs() < 1e-9);
+        assert_eq!(None, acc.get_status_for(2, 1));
+
+        // The other pair is still there
+        let status = acc.get_status_for(1, 3).unwrap();
+        assert!((status.azm - 2.1).abs() < 1e-9);
+        assert_eq!(2, acc.get_status().len());
+    }
+
+    #[test]
+    fn forget_stale_pairs() {
+        let hdm = Arc::new(Mutex::new(ScriptedHdm::new(vec![
+            update(1, 2, 0.1),
+            update(1, 3, 0.2),
+        ])));
+        let mut acc = UpdateAccumulator::new(hdm.clone()).with_max_age(Duration::from_millis(50));
+        let start = Instant::now();
+        assert_eq!(2, acc.status_at(start).len());
+
+        // Still young enough
+        let status = acc.status_at(start + Duration::from_millis(50));
+        assert_eq!(2, status.len());
+
+        // Only one of the tags is still reporting
+        hdm.lock().unwrap().push(update(1, 3, 0.3));
+
+        let status = acc.status_at(start + Duration::from_millis(100));
+        assert_eq!(1, status.len());
+        assert_eq!(3, status[0].dst);
+        // The old reading of the live tag is gone too
+        assert!((status[0].azm - 0.3).abs() < 1e-9);
+        assert!(acc.recent_track((1, 2)).is_empty());
+        assert_eq!(vec![update(1, 3, 0.3)], acc.recent_track((1, 3)));
+    }
+
+    #[test]
+    fn reject_a_spike() {
+        let good = [0.50, 0.52, 0.48, 0.51];
+        let mut updates = good
+            .iter()
+            .map(|&azm| update(1, 2, azm))
+            .collect::<Vec<_>>();
+        updates.insert(2, update(1, 2, 2.5));
+
+        let mut acc = accumulator(updates).with_outlier_rejection(3.0);
+        let status = acc.get_status();
+
+        let expected = good.iter().sum::<f64>() / good.len() as f64;
+        assert!(
+            (status[0].azm - expected).abs() < 1e-9,
+            "{} != {expected}",
+            status[0].azm
+        );
+    }
+
+    #[test]
+    fn keep_readings_with_no_clear_outlier() {
+        let reading = |azm, elv| Update {
+            elv,
+            ..update(1, 2, azm)
+        };
+
+        // Even windows, where the median isn't one of the readings, with a
+        // threshold too small to keep either of them
+        let mut acc =
+            accumulator(vec![reading(0.0, 0.0), reading(1.0, 0.0)]).with_outlier_rejection(0.5);
+        assert!((acc.get_status()[0].azm - 0.5).abs() < 1e-9);
+
+        // The azimuth and elevation each leave out the readings that the
+        // other keeps
+        let mut acc = accumulator(vec![
+            reading(0.0, 1.0),
+            reading(1.0, 0.0),
+            reading(10.0, 11.0),
+            reading(11.0, 10.0),
+        ])
+        .with_outlier_rejection(1.0);
+        let status = acc.get_status();
+        assert!((status[0].azm - 5.5).abs() < 1e-9);
+        assert!((status[0].elv - 5.5).abs() < 1e-9);
+
+        for k in [f64::NAN, f64::INFINITY] {
+            let mut acc =
+                accumulator(vec![reading(0.0, 0.0), reading(1.0, 0.0)]).with_outlier_rejection(k);
+            assert!((acc.get_status()[0].azm - 0.5).abs() < 1e-9);
+        }
     }
 }
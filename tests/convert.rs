@@ -0,0 +1,74 @@
+//! Runs the `convert` subcommand over a GrapeFile from before the format had
+//! a version, and checks that it comes out in the current version with the
+//! same streams.
+
+use cybergrape::spatial_data_format::{GrapeFile, FORMAT_VERSION};
+use std::fs;
+use std::process::Command;
+use tempfile::tempdir;
+
+#[test]
+fn convert_version_one() {
+    let dir = tempdir().unwrap();
+    let infile = dir.path().join("old.grape");
+    let outfile = dir.path().join("new.grape");
+
+    // A version 1 header has no version field
+    let mut bytes = b"(n_streams:2,sample_rate:20,tags:[Azimuth,Elevation])".to_vec();
+    bytes.push(0xFF);
+    for i in 0..20 {
+        bytes.extend((i as f32 * 0.1).to_be_bytes());
+        bytes.extend((i as f32 * -0.05).to_be_bytes());
+    }
+    fs::write(&infile, &bytes).unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_cybergrape"))
+        .args(["--update", "20", "convert", "--grape"])
+        .arg(&infile)
+        .arg("--out")
+        .arg(&outfile)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let old = GrapeFile::from_path(&infile).unwrap();
+    let new = GrapeFile::from_path(&outfile).unwrap();
+    assert_eq!(1, old.version());
+    assert_eq!(FORMAT_VERSION, new.version());
+    assert_eq!(
+        old.streams_native_sample_rate(),
+        new.streams_native_sample_rate()
+    );
+}
+
+#[test]
+fn convert_needs_force_to_overwrite() {
+    let dir = tempdir().unwrap();
+    let grape = dir.path().join("positions.grape");
+    GrapeFile::builder()
+        .build()
+        .unwrap()
+        .to_path(&grape)
+        .unwrap();
+    let convert = |force: bool| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_cybergrape"));
+        command.args(["--update", "20"]);
+        if force {
+            command.arg("--force");
+        }
+        command
+            .args(["convert", "--grape"])
+            .arg(&grape)
+            .arg("--out")
+            .arg(&grape)
+            .status()
+            .unwrap()
+    };
+
+    assert!(!convert(false).success());
+    assert!(convert(true).success());
+    assert_eq!(
+        FORMAT_VERSION,
+        GrapeFile::from_path(&grape).unwrap().version()
+    );
+}
@@ -0,0 +1,104 @@
+//! Drives the whole binaural pipeline, from a scripted HDM through the
+//! accumulator, sphericalizer, time domain buffer and binauraliser, out to a
+//! WAV file, and checks what comes out the other end.
+
+use cybergrape::dummy_hdm::ScriptedHdm;
+use cybergrape::hardware_data_manager::Update;
+use cybergrape::hound_helpers::hound_writer;
+use cybergrape::pipeline::{capture, render_parallel};
+use cybergrape::saf::{DummyBinauraliser, FRAME_SIZE};
+use cybergrape::sphericalizer::{Sphericalizer, BACK_ANTENNA, FRONT_ANTENNA};
+use cybergrape::update_accumulator::UpdateAccumulator;
+use hound::WavReader;
+use std::f64::consts::FRAC_PI_4;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tempfile::tempdir;
+
+/// The readings that both antennas would give for a tag at `azm`
+fn readings(dst: usize, azm: f64) -> [Update; 2] {
+    [BACK_ANTENNA, FRONT_ANTENNA].map(|src| Update {
+        src,
+        dst,
+        elv: 0.0,
+        azm,
+        rssi: None,
+    })
+}
+
+#[test]
+fn scripted_hdm_to_wav() {
+    let hdm = Arc::new(Mutex::new(ScriptedHdm::new(
+        [readings(1, FRAC_PI_4), readings(2, -FRAC_PI_4)].concat(),
+    )));
+    let mut acc = UpdateAccumulator::new(hdm.clone());
+    // Full gain for the first tag, and half for the second
+    let sphericalizer = Sphericalizer::new(vec![(1.0, 1.0), (0.5, 2.0)]);
+
+    let mut spatial_data = capture(
+        &mut acc,
+        &sphericalizer,
+        2,
+        3,
+        Duration::ZERO,
+        None,
+        false,
+        |_, _| {},
+    )
+    .unwrap();
+    // Then both tags move, halfway through
+    for update in [readings(1, -FRAC_PI_4), readings(2, FRAC_PI_4)].concat() {
+        hdm.lock().unwrap().push(update);
+    }
+    spatial_data.extend(
+        capture(
+            &mut acc,
+            &sphericalizer,
+            2,
+            3,
+            Duration::ZERO,
+            None,
+            false,
+            |_, _| {},
+        )
+        .unwrap(),
+    );
+
+    assert_eq!(6, spatial_data.len());
+    assert!(spatial_data.iter().all(|slice| slice.len() == 2));
+    assert_eq!(
+        [0.5, 2.0],
+        [spatial_data[0][1].gain, spatial_data[0][1].range]
+    );
+    assert_ne!(spatial_data[0][0].azimuth, spatial_data[5][0].azimuth);
+
+    let samples_per_update = FRAME_SIZE * 2;
+    let sound_data = vec![
+        vec![1000.0; samples_per_update * 6],
+        vec![2000.0; samples_per_update * 6],
+    ];
+    let (left, right) = render_parallel(
+        || DummyBinauraliser,
+        &spatial_data,
+        &sound_data,
+        samples_per_update,
+        true,
+        |_, _| {},
+    );
+
+    let dir = tempdir().unwrap();
+    let outfile = dir.path().join("out.wav");
+    hound_writer(left, right, &outfile);
+
+    let mut reader = WavReader::open(&outfile).unwrap();
+    assert_eq!(2, reader.spec().channels);
+    assert_eq!(samples_per_update as u32 * 6, reader.duration());
+    let samples = reader
+        .samples::<i16>()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert!(samples.iter().any(|&s| s != 0));
+    // The dummy binauraliser puts the first tag in the left ear and the
+    // second, at half gain, in the right
+    assert!(samples.iter().all(|&s| s == 1000));
+}
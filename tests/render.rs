@@ -0,0 +1,199 @@
+//! Runs the `render` subcommand over a GrapeFile of positions, as if it had
+//! been recorded by a serial run, and checks that it produces some binaural
+//! audio.
+
+use cybergrape::hound_helpers::f32_to_i16;
+use cybergrape::spatial_data_format::{GrapeFile, GrapeTag};
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+use std::f32::consts::PI;
+use std::path::Path;
+use std::process::Command;
+use tempfile::tempdir;
+
+const SAMP_RATE: u32 = 44100;
+
+/// Writes half a second of a tone at `freq` to `path`
+fn write_tone(path: &Path, freq: f32) {
+    let spec = WavSpec {
+        channels: 1,
+        sample_rate: SAMP_RATE,
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    };
+    let mut writer = WavWriter::create(path, spec).unwrap();
+    for i in 0..SAMP_RATE / 2 {
+        let t = i as f32 / SAMP_RATE as f32;
+        let sample = (t * freq * 2.0 * PI).sin() / 2.0;
+        writer.write_sample(f32_to_i16(sample)).unwrap();
+    }
+    writer.finalize().unwrap();
+}
+
+/// Writes a second of two tags circling in opposite directions, at 20
+/// positions per second
+fn write_positions(path: &Path) {
+    let azms = (0..20).map(|i| i as f32 * PI / 10.0).collect::<Vec<_>>();
+    let reversed = azms.iter().rev().copied().collect::<Vec<_>>();
+    let elvs = vec![0.0; 20];
+
+    GrapeFile::builder()
+        .set_samplerate(20)
+        .add_stream(&azms, GrapeTag::Azimuth)
+        .add_stream(&elvs, GrapeTag::Elevation)
+        .add_stream(&reversed, GrapeTag::Azimuth)
+        .add_stream(&elvs, GrapeTag::Elevation)
+        .build()
+        .unwrap()
+        .to_path(path)
+        .unwrap();
+}
+
+#[test]
+#[cfg(feature = "saf")]
+fn render_produces_audio() {
+    let dir = tempdir().unwrap();
+    let grape = dir.path().join("positions.grape");
+    let low = dir.path().join("low.wav");
+    let high = dir.path().join("high.wav");
+    let outfile = dir.path().join("out.wav");
+
+    write_positions(&grape);
+    write_tone(&low, 220.0);
+    write_tone(&high, 880.0);
+
+    let status = Command::new(env!("CARGO_BIN_EXE_cybergrape"))
+        .args(["--update", "40", "render", "--grape"])
+        .arg(&grape)
+        .arg("--files")
+        .args([&low, &high])
+        .arg("--out")
+        .arg(&outfile)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let mut reader = WavReader::open(&outfile).unwrap();
+    assert_eq!(2, reader.spec().channels);
+    let samples: Vec<i16> = reader.samples::<i16>().map(|s| s.unwrap()).collect();
+    assert_eq!(SAMP_RATE as usize, samples.len());
+    assert!(samples.iter().any(|&s| s != 0));
+}
+
+#[test]
+#[cfg(not(feature = "saf"))]
+fn render_without_saf_needs_the_dummy() {
+    let dir = tempdir().unwrap();
+    let grape = dir.path().join("positions.grape");
+    let low = dir.path().join("low.wav");
+    let high = dir.path().join("high.wav");
+    let outfile = dir.path().join("out.wav");
+
+    write_positions(&grape);
+    write_tone(&low, 220.0);
+    write_tone(&high, 880.0);
+
+    let render = |extra: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_cybergrape"))
+            .args(["--update", "40"])
+            .args(extra)
+            .args(["render", "--grape"])
+            .arg(&grape)
+            .arg("--files")
+            .args([&low, &high])
+            .arg("--out")
+            .arg(&outfile)
+            .status()
+            .unwrap()
+    };
+
+    assert!(!render(&[]).success());
+    assert!(!outfile.exists());
+    assert!(render(&["--binauraliser", "dummy"]).success());
+    assert!(outfile.exists());
+}
+
+#[test]
+fn render_needs_a_file_per_tag() {
+    let dir = tempdir().unwrap();
+    let grape = dir.path().join("positions.grape");
+    let low = dir.path().join("low.wav");
+    let outfile = dir.path().join("out.wav");
+
+    write_positions(&grape);
+    write_tone(&low, 220.0);
+
+    let status = Command::new(env!("CARGO_BIN_EXE_cybergrape"))
+        .args(["--update", "40", "render", "--grape"])
+        .arg(&grape)
+        .arg("--files")
+        .arg(&low)
+        .arg("--out")
+        .arg(&outfile)
+        .status()
+        .unwrap();
+    assert!(!status.success());
+    assert!(!outfile.exists());
+}
+
+#[test]
+fn render_follows_gain_automation() {
+    let dir = tempdir().unwrap();
+    let grape = dir.path().join("fade.grape");
+    let level = dir.path().join("level.wav");
+    let outfile = dir.path().join("out.wav");
+
+    // A second at a steady level
+    let spec = WavSpec {
+        channels: 1,
+        sample_rate: SAMP_RATE,
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    };
+    let mut writer = WavWriter::create(&level, spec).unwrap();
+    for _ in 0..SAMP_RATE {
+        writer.write_sample(f32_to_i16(0.5)).unwrap();
+    }
+    writer.finalize().unwrap();
+
+    // Held still, and ducked to a quarter halfway through
+    let gains = [vec![1.0; 10], vec![0.25; 10]].concat();
+    GrapeFile::builder()
+        .set_samplerate(20)
+        .add_stream(&[0.0; 20], GrapeTag::Azimuth)
+        .add_stream(&[0.0; 20], GrapeTag::Elevation)
+        .add_stream(&gains, GrapeTag::Gain)
+        .build()
+        .unwrap()
+        .to_path(&grape)
+        .unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_cybergrape"))
+        .args([
+            "--update",
+            "20",
+            "--binauraliser",
+            "dummy",
+            "render",
+            "--grape",
+        ])
+        .arg(&grape)
+        .arg("--files")
+        .arg(&level)
+        .arg("--out")
+        .arg(&outfile)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // The dummy binauraliser puts the only tag in the left ear
+    let mut reader = WavReader::open(&outfile).unwrap();
+    let left: Vec<i16> = reader
+        .samples::<i16>()
+        .step_by(2)
+        .map(|s| s.unwrap())
+        .collect();
+    let loud = left[SAMP_RATE as usize / 4];
+    let ducked = left[SAMP_RATE as usize * 3 / 4];
+    assert_eq!(f32_to_i16(0.5), loud);
+    assert!((ducked - f32_to_i16(0.125)).abs() <= 1, "{}", ducked);
+}
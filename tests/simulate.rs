@@ -0,0 +1,51 @@
+//! Runs the `simulate` subcommand from start to finish and checks that it
+//! produces some binaural audio. It binauralizes with SAF, so it needs the
+//! `saf` feature.
+
+#![cfg(feature = "saf")]
+
+use cybergrape::hound_helpers::f32_to_i16;
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+use std::f32::consts::PI;
+use std::process::Command;
+use tempfile::tempdir;
+
+const SAMP_RATE: u32 = 44100;
+
+#[test]
+fn simulate_produces_audio() {
+    let dir = tempdir().unwrap();
+    let infile = dir.path().join("tone.wav");
+    let outfile = dir.path().join("out.wav");
+
+    // Half a second of a 440hz tone
+    let spec = WavSpec {
+        channels: 1,
+        sample_rate: SAMP_RATE,
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    };
+    let mut writer = WavWriter::create(&infile, spec).unwrap();
+    for i in 0..SAMP_RATE / 2 {
+        let t = i as f32 / SAMP_RATE as f32;
+        let sample = (t * 440.0 * 2.0 * PI).sin() / 2.0;
+        writer.write_sample(f32_to_i16(sample)).unwrap();
+    }
+    writer.finalize().unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_cybergrape"))
+        .args(["--update", "20", "simulate", "-n", "1", "--gains", "1"])
+        .args(["--ranges", "1", "--files"])
+        .arg(&infile)
+        .arg("--out")
+        .arg(&outfile)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let mut reader = WavReader::open(&outfile).unwrap();
+    assert_eq!(2, reader.spec().channels);
+    let samples: Vec<i16> = reader.samples::<i16>().map(|s| s.unwrap()).collect();
+    assert_eq!(SAMP_RATE as usize, samples.len());
+    assert!(samples.iter().any(|&s| s != 0));
+}
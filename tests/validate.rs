@@ -0,0 +1,52 @@
+//! Runs the `validate` subcommand over a good GrapeFile and a damaged one,
+//! and checks that it reports every problem with the damaged one.
+
+use cybergrape::spatial_data_format::{GrapeFile, GrapeTag};
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+use tempfile::tempdir;
+
+fn validate(grape: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_cybergrape"))
+        .args(["--update", "20", "validate", "--grape"])
+        .arg(grape)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn validate_good_and_damaged_files() {
+    let dir = tempdir().unwrap();
+    let good = dir.path().join("good.grape");
+    GrapeFile::builder()
+        .set_samplerate(20)
+        .add_stream(&[0.1, 0.2, 0.3], GrapeTag::Azimuth)
+        .add_stream(&[0.0, 0.0, 0.0], GrapeTag::Elevation)
+        .with_checksum()
+        .build()
+        .unwrap()
+        .to_path(&good)
+        .unwrap();
+
+    let output = validate(&good);
+    assert!(output.status.success());
+
+    // Damage a sample, and then cut the checksum short
+    let damaged = dir.path().join("damaged.grape");
+    let mut bytes = fs::read(&good).unwrap();
+    let first_sample = bytes.iter().position(|&b| b == 0xFF).unwrap() + 1;
+    bytes[first_sample..first_sample + 4].copy_from_slice(&f32::NAN.to_be_bytes());
+    bytes.pop();
+    fs::write(&damaged, &bytes).unwrap();
+
+    let output = validate(&damaged);
+    assert!(!output.status.success());
+    let report = String::from_utf8(output.stdout).unwrap();
+    // Cutting the file short also leaves one stream a sample short
+    assert_eq!(4, report.lines().count(), "{}", report);
+    assert!(report.contains("checksum"));
+    assert!(report.contains("whole number"));
+    assert!(report.contains("unequal"));
+    assert!(report.contains("Azimuth"));
+}